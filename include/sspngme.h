@@ -0,0 +1,106 @@
+/*
+ * The C ABI of sspngme, generated from src/ffi.rs by `cargo xtask header`. Don't edit it by
+ * hand, change src/ffi.rs and run that again.
+ *
+ * The library is built into libsspngme.so (libsspngme.dylib, sspngme.dll) with
+ * `cargo rustc --release --lib --features cdylib --crate-type cdylib`.
+ *
+ * Every function but the free functions returns SSPNGME_OK or an error code, the message of
+ * the last error on the calling thread comes from sspngme_last_error_message().
+ */
+
+#ifndef SSPNGME_H
+#define SSPNGME_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SSPNGME_OK 0
+#define SSPNGME_ERROR_NULL_POINTER 1
+#define SSPNGME_ERROR_INVALID_ARGUMENT 2
+#define SSPNGME_ERROR_INVALID_PNG 3
+#define SSPNGME_ERROR_INVALID_CHUNK_TYPE 4
+#define SSPNGME_ERROR_NOT_FOUND 5
+#define SSPNGME_ERROR_INDEX_OUT_OF_BOUNDS 6
+#define SSPNGME_ERROR_ORDERING 7
+#define SSPNGME_ERROR_PAYLOAD 8
+#define SSPNGME_ERROR_PANIC 9
+#define SSPNGME_ERROR_OTHER 10
+
+/* A parsed PNG file, only ever handled through a pointer */
+typedef struct SspngmePng SspngmePng;
+
+/* Bytes owned by the library, given back with sspngme_buffer_free() */
+typedef struct SspngmeBuffer {
+    uint8_t *data;
+    size_t len;
+} SspngmeBuffer;
+
+/* Parses a PNG file from a buffer into a new handle, freed with sspngme_png_free() */
+int sspngme_png_parse(const uint8_t *data, size_t len, SspngmePng **out);
+
+/* Frees a handle, null is ignored */
+void sspngme_png_free(SspngmePng *png);
+
+/* The number of chunks, those after IEND included */
+int sspngme_png_chunk_count(const SspngmePng *png, size_t *count);
+
+/*
+ * Writes the type of a chunk as a null terminated string to out, which has room for 5
+ * bytes
+ */
+int sspngme_png_chunk_type(const SspngmePng *png, size_t index, char *out);
+
+/*
+ * Points data at the data of a chunk. The bytes belong to the handle and stay valid
+ * until it is changed or freed.
+ */
+int sspngme_png_chunk_data(const SspngmePng *png, size_t index, const uint8_t **data, size_t *len);
+
+/*
+ * Points data at the data of the first chunk of a type, the way sspngme_png_chunk_data()
+ * does
+ */
+int sspngme_png_get_chunk(const SspngmePng *png, const char *chunk_type, const uint8_t **data,
+                          size_t *len);
+
+/* Adds a chunk as late in the file as the ordering rules allow */
+int sspngme_png_add_chunk(SspngmePng *png, const char *chunk_type, const uint8_t *data, size_t len);
+
+/* Removes the first chunk of a type */
+int sspngme_png_remove_chunk(SspngmePng *png, const char *chunk_type);
+
+/*
+ * Hides a payload in a chunk of its own, sealed in an envelope like the encode command
+ * does. content_type may be null.
+ */
+int sspngme_png_encode(SspngmePng *png, const char *chunk_type, const uint8_t *payload, size_t len,
+                       const char *content_type);
+
+/*
+ * Finds the payload in the first chunk of a type, opening its envelope if it has one.
+ * The payload is written to out and given back with sspngme_buffer_free().
+ */
+int sspngme_png_decode(const SspngmePng *png, const char *chunk_type, SspngmeBuffer *out);
+
+/* Writes the bytes of the file to out, given back with sspngme_buffer_free() */
+int sspngme_png_serialize(const SspngmePng *png, SspngmeBuffer *out);
+
+/* Frees the bytes of a buffer and empties it, an empty buffer is ignored */
+void sspngme_buffer_free(SspngmeBuffer *buffer);
+
+/*
+ * The message of the last error on this thread, empty if there was none. The string stays
+ * valid until the next error on the same thread.
+ */
+const char *sspngme_last_error_message(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SSPNGME_H */
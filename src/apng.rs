@@ -0,0 +1,615 @@
+use std::fmt::Display;
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Error};
+
+/// The `acTL` chunk: how many frames the animation has and how often it loops
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// 0 means the animation loops forever
+    pub num_plays: u32,
+}
+
+/// What happens to a frame's region before the next frame is rendered
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DisposeOp {
+    None,
+    Background,
+    Previous,
+}
+
+/// How a frame is combined with the output buffer
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+/// The `fcTL` chunk: the size, position and timing of a single frame
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+/// The `fdAT` chunk: a piece of a frame's compressed image data
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FrameData {
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+}
+
+/// A single frame of an animation
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub control: FrameControl,
+    /// Indices of the chunks holding the frame's image data, these are IDAT chunks for
+    /// the default image and fdAT chunks for every other frame.
+    pub data_chunks: Vec<usize>,
+    /// Whether the frame is the static image that non-APNG decoders show
+    pub default_image: bool,
+}
+
+impl AnimationControl {
+    pub fn to_chunk(self) -> Chunk {
+        let data = [self.num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat();
+        Chunk::new(ChunkType::new(*b"acTL"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = expect_chunk(chunk, b"acTL", 8)?;
+
+        Ok(AnimationControl {
+            num_frames: read_u32(data, 0),
+            num_plays: read_u32(data, 4),
+        })
+    }
+}
+
+impl FrameControl {
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(26);
+        data.extend_from_slice(&self.sequence_number.to_be_bytes());
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&self.x_offset.to_be_bytes());
+        data.extend_from_slice(&self.y_offset.to_be_bytes());
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(self.dispose_op as u8);
+        data.push(self.blend_op as u8);
+
+        Chunk::new(ChunkType::new(*b"fcTL"), data)
+    }
+
+    /// The delay of the frame in seconds, a denominator of 0 means 1/100ths of a second
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = expect_chunk(chunk, b"fcTL", 26)?;
+
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            _ => return Err(ApngError::InvalidDisposeOp(data[24]).into()),
+        };
+
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            _ => return Err(ApngError::InvalidBlendOp(data[25]).into()),
+        };
+
+        Ok(FrameControl {
+            sequence_number: read_u32(data, 0),
+            width: read_u32(data, 4),
+            height: read_u32(data, 8),
+            x_offset: read_u32(data, 12),
+            y_offset: read_u32(data, 16),
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op,
+            blend_op,
+        })
+    }
+}
+
+impl FrameData {
+    pub fn to_chunk(&self) -> Chunk {
+        let data = self
+            .sequence_number
+            .to_be_bytes()
+            .iter()
+            .chain(self.data.iter())
+            .copied()
+            .collect();
+
+        Chunk::new(ChunkType::new(*b"fdAT"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameData {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = expect_chunk(chunk, b"fdAT", 4)?;
+
+        Ok(FrameData {
+            sequence_number: read_u32(data, 0),
+            data: data[4..].to_vec(),
+        })
+    }
+}
+
+impl Display for DisposeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisposeOp::None => write!(f, "none"),
+            DisposeOp::Background => write!(f, "background"),
+            DisposeOp::Previous => write!(f, "previous"),
+        }
+    }
+}
+
+impl Display for BlendOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlendOp::Source => write!(f, "source"),
+            BlendOp::Over => write!(f, "over"),
+        }
+    }
+}
+
+/// Checks the type of a chunk and the length of its data, fdAT chunks only need to be
+/// at least `length` bytes long since the frame data follows the sequence number.
+fn expect_chunk<'a>(
+    chunk: &'a Chunk,
+    chunk_type: &[u8; 4],
+    length: usize,
+) -> Result<&'a [u8], Error> {
+    if &chunk.chunk_type().bytes() != chunk_type {
+        return Err(ApngError::WrongChunkType(chunk.chunk_type().to_string()).into());
+    }
+
+    let data = chunk.data();
+    let valid = match chunk_type {
+        b"fdAT" => data.len() >= length,
+        _ => data.len() == length,
+    };
+
+    if !valid {
+        return Err(ApngError::InvalidLength(chunk.chunk_type().to_string()).into());
+    }
+
+    Ok(data)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Whether the PNG file is animated
+pub fn is_animated(png: &Png) -> bool {
+    png.chunk_by_type("acTL").is_some()
+}
+
+/// Returns the animation control chunk of an animated PNG file
+pub fn animation_control(png: &Png) -> Result<Option<AnimationControl>, Error> {
+    png.chunk_by_type("acTL")
+        .map(AnimationControl::try_from)
+        .transpose()
+}
+
+/// Splits the chunks of an animated PNG file into its frames
+pub fn frames(png: &Png) -> Result<Vec<Frame>, Error> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut seen_idat = false;
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        match &chunk.chunk_type().bytes() {
+            b"fcTL" => frames.push(Frame {
+                control: FrameControl::try_from(chunk)?,
+                data_chunks: Vec::new(),
+                default_image: !seen_idat,
+            }),
+            b"IDAT" => {
+                seen_idat = true;
+
+                // The IDAT chunks only belong to a frame if an fcTL comes before them
+                if let Some(frame) = frames.last_mut().filter(|frame| frame.default_image) {
+                    frame.data_chunks.push(index);
+                }
+            }
+            b"fdAT" => match frames.last_mut() {
+                Some(frame) if !frame.default_image => frame.data_chunks.push(index),
+                _ => return Err(ApngError::OrphanedFrameData(index).into()),
+            },
+            _ => {}
+        }
+    }
+
+    Ok(frames)
+}
+
+/// Checks that the sequence numbers of the fcTL and fdAT chunks count up from 0 without
+/// gaps, that acTL agrees with the number of frames and that every frame fits the image.
+pub fn validate(png: &Png) -> Result<(), Error> {
+    let animation = match animation_control(png)? {
+        Some(animation) => animation,
+        None => return Err(ApngError::NotAnimated.into()),
+    };
+
+    let mut expected: u32 = 0;
+    for chunk in png.chunks() {
+        let sequence_number = match &chunk.chunk_type().bytes() {
+            b"fcTL" => FrameControl::try_from(chunk)?.sequence_number,
+            b"fdAT" => FrameData::try_from(chunk)?.sequence_number,
+            _ => continue,
+        };
+
+        if sequence_number != expected {
+            return Err(ApngError::BadSequenceNumber {
+                expected,
+                found: sequence_number,
+            }
+            .into());
+        }
+        expected += 1;
+    }
+
+    let frames = frames(png)?;
+    if frames.len() as u32 != animation.num_frames {
+        return Err(ApngError::FrameCountMismatch {
+            declared: animation.num_frames,
+            found: frames.len() as u32,
+        }
+        .into());
+    }
+
+    let (width, height) = image_size(png)?;
+    for (index, frame) in frames.iter().enumerate() {
+        let control = &frame.control;
+
+        if frame.data_chunks.is_empty() {
+            return Err(ApngError::EmptyFrame(index).into());
+        }
+
+        let fits = control.width > 0
+            && control.height > 0
+            && control.x_offset as u64 + control.width as u64 <= width as u64
+            && control.y_offset as u64 + control.height as u64 <= height as u64;
+
+        let covers_image = control.x_offset == 0
+            && control.y_offset == 0
+            && control.width == width
+            && control.height == height;
+
+        if !fits || (index == 0 && !covers_image) {
+            return Err(ApngError::FrameOutOfBounds(index).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites the sequence numbers of every fcTL and fdAT chunk in file order and updates
+/// the frame count in acTL. The `Png` methods that add or remove frame chunks call this.
+pub fn renumber(png: &mut Png) -> Result<(), Error> {
+    let mut sequence_number: u32 = 0;
+    let mut num_frames: u32 = 0;
+
+    for index in 0..png.chunks().len() {
+        let chunk = &png.chunks()[index];
+
+        let renumbered = match &chunk.chunk_type().bytes() {
+            b"fcTL" => {
+                num_frames += 1;
+                let mut control = FrameControl::try_from(chunk)?;
+                control.sequence_number = sequence_number;
+                control.to_chunk()
+            }
+            b"fdAT" => {
+                let mut frame_data = FrameData::try_from(chunk)?;
+                frame_data.sequence_number = sequence_number;
+                frame_data.to_chunk()
+            }
+            _ => continue,
+        };
+
+        sequence_number += 1;
+        png.replace_chunk(index, renumbered);
+    }
+
+    if let Some(mut animation) = animation_control(png)? {
+        animation.num_frames = num_frames;
+
+        let index = chunk_index(png, b"acTL").unwrap();
+        png.replace_chunk(index, animation.to_chunk());
+    }
+
+    Ok(())
+}
+
+/// Returns the compressed image data of a frame as a single zlib stream
+pub fn frame_data(png: &Png, frame: usize) -> Result<Vec<u8>, Error> {
+    let frames = frames(png)?;
+    let frame = frames.get(frame).ok_or(ApngError::NoSuchFrame(frame))?;
+
+    let mut data = Vec::new();
+    for &index in &frame.data_chunks {
+        let chunk = &png.chunks()[index];
+
+        match &chunk.chunk_type().bytes() {
+            b"fdAT" => data.extend_from_slice(&chunk.data()[4..]),
+            _ => data.extend_from_slice(chunk.data()),
+        }
+    }
+
+    Ok(data)
+}
+
+/// Replaces the compressed image data of a frame, `pieces` becomes one IDAT or fdAT chunk
+/// each. The copy rules for modified image data apply to the default image (see
+/// [`Png::replace_image_data`]), and `Png::splice_chunks` renumbers the sequence numbers.
+/// Returns the chunks dropped by the copy rules.
+pub fn replace_frame_data(
+    png: &mut Png,
+    frame: usize,
+    pieces: Vec<Vec<u8>>,
+    keep: &[ChunkType],
+) -> Result<Vec<Chunk>, Error> {
+    let frames = frames(png)?;
+    let target = frames.get(frame).ok_or(ApngError::NoSuchFrame(frame))?;
+
+    if pieces.is_empty() {
+        return Err(ApngError::EmptyFrame(frame).into());
+    }
+
+    let dropped = if target.default_image {
+        let chunks = pieces
+            .into_iter()
+            .map(|piece| Chunk::new(ChunkType::new(*b"IDAT"), piece))
+            .collect();
+
+        png.replace_image_data(None, chunks, keep)?
+    } else {
+        let chunks = pieces
+            .into_iter()
+            .map(|data| {
+                FrameData {
+                    sequence_number: 0,
+                    data,
+                }
+                .to_chunk()
+            })
+            .collect();
+
+        let first = target.data_chunks[0];
+        let last = target.data_chunks[target.data_chunks.len() - 1];
+        png.splice_chunks(first..last + 1, chunks)?;
+
+        Vec::new()
+    };
+
+    Ok(dropped)
+}
+
+fn chunk_index(png: &Png, chunk_type: &[u8; 4]) -> Option<usize> {
+    png.chunks()
+        .iter()
+        .position(|chunk| &chunk.chunk_type().bytes() == chunk_type)
+}
+
+fn image_size(png: &Png) -> Result<(u32, u32), Error> {
+    match png.chunk_by_type("IHDR") {
+        Some(ihdr) if ihdr.data().len() >= 8 => {
+            Ok((read_u32(ihdr.data(), 0), read_u32(ihdr.data(), 4)))
+        }
+        _ => Err(ApngError::MissingHeader.into()),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApngError {
+    #[error("The file is not an animated PNG, it has no acTL chunk.")]
+    NotAnimated,
+
+    #[error("The file has no valid IHDR chunk.")]
+    MissingHeader,
+
+    #[error("Expected a different chunk type than '{0}'.")]
+    WrongChunkType(String),
+
+    #[error("The '{0}' chunk has an invalid length.")]
+    InvalidLength(String),
+
+    #[error("Invalid dispose operation {0}.")]
+    InvalidDisposeOp(u8),
+
+    #[error("Invalid blend operation {0}.")]
+    InvalidBlendOp(u8),
+
+    #[error("The fdAT chunk at index {0} does not belong to any frame.")]
+    OrphanedFrameData(usize),
+
+    #[error("Expected sequence number {expected} but found {found}.")]
+    BadSequenceNumber { expected: u32, found: u32 },
+
+    #[error("acTL declares {declared} frames but the file has {found}.")]
+    FrameCountMismatch { declared: u32, found: u32 },
+
+    #[error("Frame {0} has no image data.")]
+    EmptyFrame(usize),
+
+    #[error("Frame {0} does not fit inside the image.")]
+    FrameOutOfBounds(usize),
+
+    #[error("Frame {0} does not exist.")]
+    NoSuchFrame(usize),
+}
+
+#[cfg(test)]
+mod apng_tests {
+    use super::*;
+
+    fn frame_control(sequence_number: u32, width: u32, height: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn testing_apng() -> Png {
+        let ihdr = [4u32.to_be_bytes(), 4u32.to_be_bytes()].concat();
+
+        Png::from_chunks(vec![
+            Chunk::new(
+                ChunkType::new(*b"IHDR"),
+                [ihdr, vec![8, 6, 0, 0, 0]].concat(),
+            ),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .to_chunk(),
+            frame_control(0, 4, 4).to_chunk(),
+            Chunk::new(ChunkType::new(*b"IDAT"), b"default image".to_vec()),
+            frame_control(1, 2, 2).to_chunk(),
+            FrameData {
+                sequence_number: 2,
+                data: b"second frame".to_vec(),
+            }
+            .to_chunk(),
+            Chunk::new(ChunkType::new(*b"IEND"), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_frame_control_round_trip() {
+        let control = frame_control(7, 3, 5);
+        let parsed = FrameControl::try_from(&control.to_chunk()).unwrap();
+        assert_eq!(control, parsed);
+        assert_eq!(parsed.delay(), 0.1);
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = testing_apng();
+        let frames = frames(&png).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].default_image);
+        assert_eq!(frames[0].data_chunks, vec![3]);
+        assert!(!frames[1].default_image);
+        assert_eq!(frames[1].data_chunks, vec![5]);
+
+        assert_eq!(frame_data(&png, 1).unwrap(), b"second frame".to_vec());
+        assert!(validate(&png).is_ok());
+    }
+
+    #[test]
+    fn test_bad_sequence_number() {
+        let mut chunks = testing_apng().chunks().to_vec();
+        chunks[4] = frame_control(3, 2, 2).to_chunk();
+        let mut png = Png::from_chunks(chunks);
+        assert!(validate(&png).is_err());
+
+        renumber(&mut png).unwrap();
+        assert!(validate(&png).is_ok());
+    }
+
+    #[test]
+    fn test_edits_renumber_frames() {
+        let mut png = testing_apng();
+
+        // A third frame goes in before IEND with a made up sequence number
+        png.insert_chunk(6, frame_control(9, 1, 1).to_chunk())
+            .unwrap();
+        let frame_data = FrameData {
+            sequence_number: 9,
+            data: b"third frame".to_vec(),
+        };
+        png.insert_chunk(7, frame_data.to_chunk()).unwrap();
+
+        assert!(validate(&png).is_ok());
+        assert_eq!(animation_control(&png).unwrap().unwrap().num_frames, 3);
+        assert_eq!(
+            FrameData::try_from(&png.chunks()[7])
+                .unwrap()
+                .sequence_number,
+            4
+        );
+
+        // Removing the second frame moves the third one up
+        png.splice_chunks(4..6, Vec::new()).unwrap();
+        assert!(validate(&png).is_ok());
+        assert_eq!(frames(&png).unwrap().len(), 2);
+        assert_eq!(
+            FrameData::try_from(&png.chunks()[5])
+                .unwrap()
+                .sequence_number,
+            2
+        );
+    }
+
+    #[test]
+    fn test_replace_frame_data() {
+        let mut png = testing_apng();
+        let pieces = vec![b"first half".to_vec(), b"second half".to_vec()];
+        replace_frame_data(&mut png, 1, pieces, &[]).unwrap();
+
+        assert!(validate(&png).is_ok());
+        assert_eq!(
+            frame_data(&png, 1).unwrap(),
+            b"first halfsecond half".to_vec()
+        );
+
+        let last = FrameData::try_from(&png.chunks()[6]).unwrap();
+        assert_eq!(last.sequence_number, 3);
+    }
+
+    #[test]
+    fn test_frame_out_of_bounds() {
+        let mut png = testing_apng();
+        let mut control = frame_control(1, 2, 2);
+        control.x_offset = 3;
+        png.splice_chunks(4..5, vec![control.to_chunk()]).unwrap();
+
+        assert!(validate(&png).is_err());
+    }
+}
@@ -1,13 +1,151 @@
     use colored::Colorize;
+    use serde_json::json;
 
-    use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Error};
+    use crate::{
+        apng,
+        batch::{self, BatchOptions, Outcome},
+        chunk::{Chunk, ChunkError},
+        chunk_type::ChunkType,
+        color::ColorState,
+        deflate_slack::DeflateSlackError,
+        diff::{self, ChunkChange, PixelDiff},
+        dump, emit, encoder,
+        envelope,
+        exif::{self, Exif},
+        ihdr::ImageHeader,
+        meta::{
+            ImageOffset, ModificationTime, OffsetUnit, PhysicalScale, PixelDensity, ScaleUnit,
+        },
+        optimize, ordering,
+        output::{self, Format},
+        palette,
+        palette_stego::PaletteStegoError,
+        payload::{self, Finding, Method, Payload, PrepareOptions, RevealOptions, Target},
+        png::{Png, PngError},
+        xmp::{self, Xmp},
+        Error,
+    };
     use std::{
         fs::OpenOptions,
-        io::{Read, Seek, SeekFrom, Write}, str::FromStr,
+        io::{Read, Seek, SeekFrom, Write},
     };
 
-    /// Encode a payload to a file
-    pub fn encode(file_name: &str, chunk_type: &str, payload: &str) -> Result<(), Error> {
+    /// Hides a prepared payload in a file with the method of the target
+    pub fn encode(
+        file_name: &str,
+        target: &Target,
+        payload: &[u8],
+        keep: &[ChunkType],
+    ) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let hidden = match payload::hide(&png, target, payload, keep) {
+            Ok(hidden) => hidden,
+            Err(error) => {
+                emit!("{} {}", "Failed to hide the payload:".red().bold(), error);
+                if matches!(
+                    error.downcast_ref(),
+                    Some(PaletteStegoError::PayloadTooLarge(_))
+                ) || matches!(
+                    error.downcast_ref(),
+                    Some(DeflateSlackError::PayloadTooLarge(_))
+                ) {
+                    report_envelope_overhead(payload);
+                }
+                return Err(error);
+            }
+        };
+
+        write_png(file_name, &hidden.png)?;
+        output::record("payload_size", json!(payload.len()));
+
+        match target.method {
+            Method::Chunk => {
+                let chunk_type = target.chunk_type()?;
+                emit!(
+                    "{} '{}'",
+                    "Hid the payload in a chunk with type".green().bold(),
+                    chunk_type.white().bold()
+                );
+                output::record("chunk_type", json!(chunk_type));
+            }
+            Method::Palette | Method::DeflateSlack => {
+                report_unsafe_to_copy(&hidden.dropped);
+
+                let capacity = hidden.capacity.unwrap_or_default();
+                let done = match (target.method, target.frame) {
+                    (Method::Palette, _) => "Hid the payload in the palette order".to_string(),
+                    (_, Some(frame)) => {
+                        format!("Hid the payload in the image data of frame {}", frame)
+                    }
+                    (_, None) => "Hid the payload in the image data".to_string(),
+                };
+                emit!(
+                    "{} ({} of {} bytes used)",
+                    done.green().bold(),
+                    payload.len(),
+                    capacity
+                );
+                output::record("capacity", json!(capacity));
+            }
+            Method::Trailer => {
+                let trailing_bytes = hidden.png.trailing_bytes().len();
+                emit!(
+                    "{} ({} bytes after IEND)",
+                    "Appended the payload".green().bold(),
+                    trailing_bytes
+                );
+                output::record("trailing_bytes", json!(trailing_bytes));
+            }
+            Method::Camouflage => {
+                emit!(
+                    "{} {}",
+                    "Hid the payload in the".green().bold(),
+                    target.carrier.to_string().green().bold()
+                );
+                output::record(
+                    "carrier",
+                    json!(ChunkType::new(target.carrier.chunk_type()).to_string()),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the payload hidden in a file with the method of the target and prints it
+    pub fn decode(file_name: &str, target: &Target, options: &DecodeOptions) -> Result<(), Error> {
+        let (png, damaged) = read_png_for_decode(file_name)?;
+
+        let found = match payload::find(&png, &damaged, target, options.ecc) {
+            Ok(found) => found,
+            Err(error) => {
+                if let Some(ChunkError::InvalidCRC) = error.downcast_ref() {
+                    emit!(
+                        "{}",
+                        "The CRC of the chunk doesn't match, use --ecc if the payload has error correction"
+                            .red()
+                            .bold()
+                    );
+                } else {
+                    emit!("{} {}", "Failed to find a payload:".red().bold(), error);
+                }
+                output::record("found", json!(false));
+                return Err(error);
+            }
+        };
+
+        if target.method == Method::Chunk {
+            emit!(
+                "{} '{}'",
+                "Found chunk with type".green().bold(),
+                target.chunk_type()?.white().bold()
+            );
+        }
+        print_payload(&found, target.method, options)
+    }
+
+    pub fn remove(file_name: &str, chunk_type: &str) -> Result<(), Error> {
         let temp_file_path = format!("{}.temp", file_name);
 
         // Move the contents of the file to a temporary location
@@ -25,7 +163,7 @@
 
             // Tell user there is an error
             Err(error) => {
-                println!(
+                emit!(
                     "{} '{}'",
                     "Failed to open file".red().bold(),
                     file_name.bold()
@@ -34,185 +172,1319 @@
             }
         };
 
-        // Read the contents of the file to the `data` vec
+        let mut data = Vec::new();
+
+        file.read_to_end(&mut data)?;
+
+        let mut png = Png::try_from(&data[..])?;
+
+
+
+        if let Err(error) = png.remove_chunk(chunk_type) {
+            // Ordering refusals speak for themselves
+            if matches!(
+                error.downcast_ref::<PngError>(),
+                Some(PngError::NonExistentChunk)
+            ) {
+                emit!(
+                    "'{}' {}",
+                    chunk_type.white().bold(),
+                    "was not found".white().bold()
+                );
+            }
+            return Err(error);
+        }
+
+        emit!("{}", "Removed chunk from file successfully".green().bold());
+        output::record("removed", json!(chunk_type));
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+
+
+        file.write_all(&png.as_bytes()[..])?;
+
+
+        // Write the contents of the temp file to the PNG file
+        std::fs::remove_file(file_name)?;
+        std::fs::rename(&temp_file_path, file_name)?;
+
+        Ok(())
+    }
+
+    /// Looks for payloads hidden with every method, see `payload::scan`
+    pub fn scan(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+        let findings = payload::scan(&png);
+
+        for finding in &findings {
+            emit!("{} {}", "Found".yellow().bold(), describe_finding(finding));
+        }
+        if findings.is_empty() {
+            emit!("{}", "Found nothing that holds a payload".green().bold());
+        }
+
+        output::record("suspicious", json!(!findings.is_empty()));
+        output::record("findings", json!(findings));
+
+        Ok(())
+    }
+
+    /// Removes whatever could hold a payload, see `payload::sanitize`
+    pub fn sanitize(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let sanitized = match payload::sanitize(&png) {
+            Ok(sanitized) => sanitized,
+            Err(error) => {
+                emit!("{} {}", "Failed to sanitize the file:".red().bold(), error);
+                return Err(error);
+            }
+        };
+        write_png(file_name, &sanitized)?;
+
+        let findings = payload::scan(&png);
+        for finding in &findings {
+            emit!("{} {}", "Removed".yellow().bold(), describe_finding(finding));
+        }
+
+        let original_size = png.as_bytes().len();
+        let sanitized_size = sanitized.as_bytes().len();
+        emit!(
+            "{} {} -> {} bytes",
+            "Sanitized".green().bold(),
+            original_size,
+            sanitized_size
+        );
+        output::record("removed", json!(findings));
+        output::record("original_size", json!(original_size));
+        output::record("sanitized_size", json!(sanitized_size));
+
+        Ok(())
+    }
 
-        let mut data: Vec<u8> = Vec::new();
+    /// Lists the chunks of a PNG file, the order problems and the data after IEND
+    pub fn inspect(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
 
-        if let Err(error) = file.read_to_end(&mut data) {
-            println!(
-                "{}",
-                "An internal error has occured, please try again."
-                    .red()
-                    .bold()
+        if let Ok(header) = ImageHeader::from_png(&png) {
+            output::record(
+                "header",
+                json!({
+                    "width": header.width,
+                    "height": header.height,
+                    "color_type": format!("{:?}", header.color_type),
+                    "bit_depth": header.bit_depth,
+                    "interlaced": header.interlaced,
+                }),
             );
-            return Err(error.into());
+            emit!(
+                "{} {}x{}, {:?} at {} bits{}",
+                "Image:".white().bold(),
+                header.width,
+                header.height,
+                header.color_type,
+                header.bit_depth,
+                if header.interlaced { ", interlaced" } else { "" }
+            );
+        }
+
+        let mut after_iend = false;
+        let mut chunks = Vec::new();
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            let chunk_type = chunk.chunk_type();
+
+            chunks.push(json!({
+                "index": index,
+                "type": chunk_type.to_string(),
+                "length": chunk.length(),
+                "crc": chunk.crc(),
+                "critical": chunk_type.is_critical(),
+                "safe_to_copy": chunk_type.is_safe_to_copy(),
+                "standard": chunk_type.is_standard(),
+                "after_iend": after_iend,
+            }));
+
+            emit!(
+                "{} {} {} bytes, {}, {}{}{}",
+                format!("#{}", index).white().bold(),
+                chunk_type.to_string().bold(),
+                chunk.length(),
+                if chunk_type.is_critical() { "critical" } else { "ancillary" },
+                if chunk_type.is_safe_to_copy() { "safe to copy" } else { "unsafe to copy" },
+                if chunk_type.is_standard() { "" } else { ", custom" },
+                if after_iend { ", after IEND" } else { "" }
+            );
+
+            after_iend |= &chunk_type.bytes() == b"IEND";
+        }
+        output::record("chunks", json!(chunks));
+
+        let mut warnings = Vec::new();
+        for violation in ordering::violations(png.chunks()) {
+            emit!("{} {}", "Warning:".yellow().bold(), violation);
+            warnings.push(violation.to_string());
         }
 
-        // Try to convert the bytes into a `Png` struct
-        let mut png = match Png::try_from(&data[..]) {
-            Ok(png) => png,
+        match ColorState::from_png(&png) {
+            Ok(color) => print_color(&color, ImageHeader::from_png(&png).ok().as_ref()),
             Err(error) => {
-                println!(
-                    "{}",
-                    "A bad PNG file has been given, the given PNG file may be corrupted."
-                        .red()
-                        .bold()
-                );
+                emit!("{} {}", "Warning:".yellow().bold(), error);
+                warnings.push(error.to_string());
+            }
+        }
+
+        if let Err(error) = palette::validate(&png) {
+            emit!("{} {}", "Warning:".yellow().bold(), error);
+            warnings.push(error.to_string());
+        }
+        output::record("warnings", json!(warnings));
+        output::record("trailing_bytes", json!(png.trailing_bytes().len()));
+
+        match png.trailing_bytes().len() {
+            0 => emit!("{}", "No trailing data".green().bold()),
+            length => emit!(
+                "{} {} bytes after the last chunk",
+                "Trailing data:".yellow().bold(),
+                length
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Writes every chunk to a file of its own, along with the manifest
+    pub fn extract(file_name: &str, directory: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let manifest = match dump::extract(&png, std::path::Path::new(directory)) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                emit!("{} {}", "Failed to extract the chunks:".red().bold(), error);
                 return Err(error);
             }
         };
 
-        // Try to create a ChunkType struct from the chunk type
-        let chunk_type = match ChunkType::from_str(chunk_type) {
-            Ok(chunk_type) => chunk_type,
+        output::record("chunks", json!(manifest.chunks.len()));
+        output::record("trailing", json!(manifest.trailing.is_some()));
+        emit!(
+            "{} {} chunks to '{}'{}",
+            "Extracted".green().bold(),
+            manifest.chunks.len(),
+            directory.white().bold(),
+            if manifest.trailing.is_some() { ", with the trailing data" } else { "" }
+        );
+
+        Ok(())
+    }
+
+    /// Rebuilds a PNG file from the chunk files of a directory
+    pub fn assemble(directory: &str, file_name: &str) -> Result<(), Error> {
+        let assembled = match dump::assemble(std::path::Path::new(directory)) {
+            Ok(assembled) => assembled,
             Err(error) => {
-                println!(
-                    "{}",
-                    "A bad chunk type has been given, make sure it is all valid ASCII and that the third char is uppercase."
-                        .red()
-                        .bold()
-                );
+                emit!("{} {}", "Failed to assemble the chunks:".red().bold(), error);
                 return Err(error);
             }
         };
 
-        // Create a new chunk from the chunk type and the payload (converted to a Vec<u8>)
-        let chunk = Chunk::new(chunk_type, payload.as_bytes().to_vec());
+        for index in &assembled.edited {
+            let chunk_type = assembled.png.chunks()[*index].chunk_type().to_string();
+            emit!(
+                "{} {} {}, its CRC was computed again",
+                "Edited".white().bold(),
+                format!("#{}", index).white().bold(),
+                chunk_type.bold()
+            );
+        }
 
-        // Add the chunk to the PNG file
-        png.append_chunk(chunk);
+        for violation in ordering::violations(assembled.png.chunks()) {
+            emit!("{} {}", "Warning:".yellow().bold(), violation);
+        }
 
-        file.seek(SeekFrom::Start(0))?;
+        write_png(file_name, &assembled.png)?;
 
-        file.write_all(&png.as_bytes()[..])?;
+        output::record("chunks", json!(assembled.png.chunks().len()));
+        output::record("edited", json!(assembled.edited));
+        emit!(
+            "{} {} chunks into '{}'",
+            "Assembled".green().bold(),
+            assembled.png.chunks().len(),
+            file_name.white().bold()
+        );
 
-        // Write the contents of the temp file to the PNG file
-        std::fs::remove_file(file_name)?;
-        std::fs::rename(&temp_file_path, file_name)?;
+        Ok(())
+    }
+
+    /// Shows the chunk level differences between two PNG files
+    pub fn diff(old_file_name: &str, new_file_name: &str, pixels: bool) -> Result<(), Error> {
+        let old = read_png(old_file_name)?;
+        let new = read_png(new_file_name)?;
+
+        let png_diff = diff::diff(&old, &new);
+
+        output::record(
+            "changes",
+            json!(png_diff.changes.iter().map(change_json).collect::<Vec<_>>()),
+        );
+        output::record("unchanged", json!(png_diff.unchanged));
+        output::record("trailing_bytes_changed", json!(png_diff.trailing_bytes_changed));
+
+        for change in &png_diff.changes {
+            match change {
+                ChunkChange::Added { to, chunk_type } => {
+                    emit!("{} #{} {}", "+ Added".green().bold(), to, chunk_type.bold())
+                }
+                ChunkChange::Removed { from, chunk_type } => {
+                    emit!("{} #{} {}", "- Removed".red().bold(), from, chunk_type.bold())
+                }
+                ChunkChange::Moved { from, to, chunk_type } => emit!(
+                    "{} #{} -> #{} {}",
+                    "> Moved".cyan().bold(),
+                    from,
+                    to,
+                    chunk_type.bold()
+                ),
+                ChunkChange::Modified { from, to, chunk_type, bytes } => {
+                    emit!(
+                        "{} #{} -> #{} {}, the data differs in {} places",
+                        "~ Modified".yellow().bold(),
+                        from,
+                        to,
+                        chunk_type.bold(),
+                        bytes.len()
+                    );
+                    for change in bytes {
+                        emit!(
+                            "    at {}: {} -> {}",
+                            change.offset,
+                            hex_preview(&change.old),
+                            hex_preview(&change.new)
+                        );
+                    }
+                }
+            }
+        }
+
+        if png_diff.trailing_bytes_changed {
+            emit!(
+                "{} {} -> {} bytes",
+                "~ Trailing data".yellow().bold(),
+                old.trailing_bytes().len(),
+                new.trailing_bytes().len()
+            );
+        }
+
+        if png_diff.is_empty() {
+            emit!("{}", "The files have the same chunks".green().bold());
+        } else {
+            emit!("{} {} chunks", "Unchanged:".white().bold(), png_diff.unchanged);
+        }
+
+        if pixels {
+            let pixel_diff = diff::diff_pixels(&old, &new);
+            if let Ok(pixel_diff) = &pixel_diff {
+                output::record("pixels", pixel_json(pixel_diff));
+            }
+
+            match pixel_diff {
+                Ok(PixelDiff::SizeMismatch { old, new }) => emit!(
+                    "{} {}x{} -> {}x{}",
+                    "The images differ in size:".yellow().bold(),
+                    old.0,
+                    old.1,
+                    new.0,
+                    new.1
+                ),
+                Ok(PixelDiff::Compared { differing: 0, .. }) => {
+                    emit!("{}", "The pixels are the same".green().bold())
+                }
+                Ok(PixelDiff::Compared { differing, max_delta }) => emit!(
+                    "{} {} pixels differ, by at most {} in 16 bit samples",
+                    "Pixels:".yellow().bold(),
+                    differing,
+                    max_delta
+                ),
+                Err(error) => {
+                    emit!("{} {}", "Failed to compare the pixels:".red().bold(), error);
+                    return Err(error);
+                }
+            }
+        }
 
         Ok(())
     }
 
-    pub fn decode(file_name: &str, chunk_type: &str) -> Result<(), Error> {
-        // Try to read the file
-        let mut file = match OpenOptions::new().read(true).open(file_name) {
-            Ok(file) => file,
+    /// Runs a command for a single file, or for every file of a directory or glob pattern
+    /// in parallel, followed by a summary. Fails if any of the files failed.
+    pub fn batch<J>(input: &str, options: &BatchOptions, job: J) -> Result<(), Error>
+    where
+        J: Fn(&str) -> Result<(), Error> + Sync,
+    {
+        if !batch::is_batch(input) {
+            return job(input);
+        }
+
+        let files = match batch::expand(input) {
+            Ok(files) => files,
             Err(error) => {
-                println!(
-                    "{} '{}'",
-                    "Failed to read file".red().bold(),
-                    file_name.white().bold()
-                );
-                return Err(error.into());
+                emit!("{} {}", "Failed to find the files:".red().bold(), error);
+                return Err(error);
             }
         };
 
-        // Create a data vec and try to write to it.
-        let mut data: Vec<u8> = Vec::new();
+        let json = output::format() == Format::Json;
+
+        // Whole files at a time, so their output stays together
+        let printing = std::sync::Mutex::new(());
+        let outcomes = batch::run(&files, options, job, |file, outcome| {
+            let _lock = printing.lock();
+            match outcome {
+                Outcome::Succeeded { output } | Outcome::Failed { output, .. } if json => {
+                    let mut line = json!({
+                        "file": file.display().to_string(),
+                        "ok": matches!(outcome, Outcome::Succeeded { .. }),
+                        "messages": output::messages(&output.text),
+                        "data": output.data,
+                    });
+                    if let Outcome::Failed { error, code, .. } = outcome {
+                        line["error"] = json!({ "code": code, "message": error });
+                    }
+                    output::stream(&line);
+                }
+                Outcome::Succeeded { output } | Outcome::Failed { output, .. } => {
+                    emit!("{} {}", "==>".white().bold(), file.display().to_string().bold());
+                    print!("{}", output.text);
+                }
+                Outcome::Skipped => {}
+            }
+        });
+
+        let failed = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, Outcome::Failed { .. }))
+            .count();
+        let skipped = outcomes
+            .iter()
+            .filter(|outcome| **outcome == Outcome::Skipped)
+            .count();
+
+        if json {
+            let skipped_files: Vec<String> = files
+                .iter()
+                .zip(&outcomes)
+                .filter(|(_, outcome)| **outcome == Outcome::Skipped)
+                .map(|(file, _)| file.display().to_string())
+                .collect();
+
+            output::stream(&json!({
+                "summary": {
+                    "succeeded": files.len() - failed - skipped,
+                    "failed": failed,
+                    "skipped": skipped_files,
+                }
+            }));
+        } else {
+            emit!();
+            for (file, outcome) in files.iter().zip(&outcomes) {
+                match outcome {
+                    Outcome::Succeeded { .. } => {
+                        emit!("{} {}", "ok".green().bold(), file.display())
+                    }
+                    Outcome::Failed { error, .. } => {
+                        emit!("{} {}: {}", "failed".red().bold(), file.display(), error)
+                    }
+                    Outcome::Skipped => emit!("{} {}", "skipped".yellow().bold(), file.display()),
+                }
+            }
 
-        if let Err(error) = file.read_to_end(&mut data) {
-            println!(
-                "{}",
-                "An internal error has occurred, please try again."
-                    .red()
-                    .bold()
+            emit!(
+                "{} {} succeeded, {} failed, {} skipped",
+                "Summary:".white().bold(),
+                files.len() - failed - skipped,
+                failed,
+                skipped
             );
-            return Err(error.into());
         }
 
-        // Try to create a PNG struct from the file
-        let png = match Png::try_from(&data[..]) {
-            Ok(png) => png,
-            Err(error) => {
-                println!(
-                    "{}",
-                    "A bad PNG file has been given, the given PNG file may be corrupted."
-                        .red()
-                        .bold()
-                );
-                return Err(error);
+        if failed > 0 {
+            return Err(batch::BatchError::Failed(failed, files.len()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Lists the tags of the eXIf chunk
+    pub fn exif_list(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let exif = match read_exif(&png)? {
+            Some(exif) => exif,
+            None => return Ok(()),
+        };
+
+        output::record(
+            "tags",
+            json!(exif
+                .entries()
+                .iter()
+                .map(|entry| (exif::tag_name(entry.directory, entry.tag), json!(entry.value.to_string())))
+                .collect::<serde_json::Map<_, _>>()),
+        );
+        output::record("thumbnail", json!(exif.thumbnail().map(|thumbnail| thumbnail.len())));
+
+        for entry in exif.entries() {
+            emit!(
+                "{} {}",
+                format!("{}:", exif::tag_name(entry.directory, entry.tag)).white().bold(),
+                entry.value
+            );
+        }
+
+        if let Some(thumbnail) = exif.thumbnail() {
+            emit!("{} {} bytes", "Thumbnail:".white().bold(), thumbnail.len());
+        }
+
+        Ok(())
+    }
+
+    /// Shows the value of a single EXIF tag
+    pub fn exif_get(file_name: &str, tag: &str) -> Result<(), Error> {
+        let (directory, number) = exif::parse_tag(tag)?;
+        let png = read_png(file_name)?;
+
+        if let Some(exif) = read_exif(&png)? {
+            let value = exif.get(directory, number);
+            output::record("value", json!(value.map(|value| value.to_string())));
+
+            match value {
+                Some(value) => emit!("{}", value),
+                None => emit!(
+                    "{} '{}'",
+                    "The EXIF data has no tag".red().bold(),
+                    tag.white().bold()
+                ),
             }
+        }
+
+        Ok(())
+    }
+
+    /// Sets an EXIF tag, the eXIf chunk is written again with a new CRC
+    pub fn exif_set(file_name: &str, tag: &str, value: &str) -> Result<(), Error> {
+        let (directory, number) = exif::parse_tag(tag)?;
+        let mut png = read_png(file_name)?;
+
+        let mut exif = Exif::from_png(&png)?.unwrap_or_default();
+        if let Err(error) = exif.set_text(directory, number, value) {
+            emit!("{} {}", "Failed to set the tag:".red().bold(), error);
+            return Err(error);
+        }
+        exif.write_to(&mut png)?;
+
+        write_png(file_name, &png)?;
+
+        output::record("tag", json!(exif::tag_name(directory, number)));
+        emit!(
+            "{} {}",
+            "Set".green().bold(),
+            exif::tag_name(directory, number).white().bold()
+        );
+
+        Ok(())
+    }
+
+    /// Removes EXIF tags, the GPS directory or the whole eXIf chunk
+    pub fn exif_strip(file_name: &str, tags: &[String], gps: bool) -> Result<(), Error> {
+        let tags = tags
+            .iter()
+            .map(|tag| exif::parse_tag(tag))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut png = read_png(file_name)?;
+
+        let mut exif = match read_exif(&png)? {
+            Some(exif) => exif,
+            None => return Ok(()),
         };
 
-        match png.chunk_by_type(chunk_type) {
-            Some(chunk) => {
-                println!(
+        if tags.is_empty() && !gps {
+            png.remove_chunk("eXIf")?;
+            write_png(file_name, &png)?;
+            emit!("{}", "Removed the EXIF data".green().bold());
+            output::record("removed", json!("eXIf"));
+            return Ok(());
+        }
+
+        let mut removed = 0;
+        if gps {
+            removed += exif.strip_gps();
+        }
+        for (directory, number) in tags {
+            removed += exif.remove(directory, number) as usize;
+        }
+
+        exif.write_to(&mut png)?;
+        write_png(file_name, &png)?;
+
+        emit!("{} {} tags", "Removed".green().bold(), removed);
+        output::record("removed", json!(removed));
+
+        Ok(())
+    }
+
+    /// Prints the XMP packet as it is stored
+    pub fn xmp_dump(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let packet = xmp::packet(&png)?;
+        output::record("packet", json!(packet));
+
+        match packet {
+            Some(packet) => emit!("{}", packet),
+            None => emit!("{}", "The file has no XMP metadata".yellow().bold()),
+        }
+
+        Ok(())
+    }
+
+    /// Shows an XMP property, or all of them
+    pub fn xmp_get(file_name: &str, property: Option<&str>) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let xmp = match read_xmp(&png)? {
+            Some(xmp) => xmp,
+            None => return Ok(()),
+        };
+
+        match property {
+            Some(key) => match xmp.get(key)? {
+                Some(value) => {
+                    output::record("value", json!(value.to_string()));
+                    emit!("{}", value)
+                }
+                None => emit!(
                     "{} '{}'",
-                    "Found chunk with type".green().bold(),
-                    chunk_type.white().bold()
+                    "The XMP packet has no property".red().bold(),
+                    key.white().bold()
+                ),
+            },
+            None => {
+                output::record(
+                    "properties",
+                    json!(xmp
+                        .properties()
+                        .iter()
+                        .map(|property| (xmp.key(property), json!(property.value.to_string())))
+                        .collect::<serde_json::Map<_, _>>()),
                 );
+                for property in xmp.properties() {
+                    emit!(
+                        "{} {}",
+                        format!("{}:", xmp.key(property)).white().bold(),
+                        property.value
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets an XMP property, the iTXt chunk is written again with a new CRC
+    pub fn xmp_set(file_name: &str, property: &str, value: &str, namespace: Option<&str>) -> Result<(), Error> {
+        let mut png = read_png(file_name)?;
+
+        let mut xmp = Xmp::from_png(&png)?.unwrap_or_default();
+        if let (Some(uri), Some((prefix, _))) = (namespace, property.split_once(':')) {
+            xmp.declare(prefix, uri);
+        }
+
+        if let Err(error) = xmp.set_text(property, value) {
+            emit!("{} {}", "Failed to set the property:".red().bold(), error);
+            return Err(error);
+        }
+        xmp.write_to(&mut png)?;
 
-                print!("{} ", "Message:".white().bold());
-                println!("{}", chunk.data_as_string()?);
+        write_png(file_name, &png)?;
+
+        output::record("property", json!(property));
+        emit!("{} {}", "Set".green().bold(), property.white().bold());
+
+        Ok(())
+    }
+
+    /// Shows the physical and timing chunks
+    pub fn meta_show(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let density: Option<PixelDensity> = png.typed_chunk("pHYs")?;
+        let time: Option<ModificationTime> = png.typed_chunk("tIME")?;
+        let offset: Option<ImageOffset> = png.typed_chunk("oFFs")?;
+        let scale: Option<PhysicalScale> = png.typed_chunk("sCAL")?;
+
+        let show = |label: &str, value: Option<String>| {
+            let key = label.trim_end_matches(':').to_lowercase();
+            output::record(&key, json!(value));
+            emit!(
+                "{} {}",
+                label.white().bold(),
+                value.unwrap_or_else(|| "not set".to_string())
+            )
+        };
+
+        show("Density:", density.map(|density| density.to_string()));
+        show("Modified:", time.map(|time| time.to_string()));
+        show("Offset:", offset.map(|offset| offset.to_string()));
+        show("Scale:", scale.map(|scale| scale.to_string()));
+
+        Ok(())
+    }
+
+    /// Sets the pixel density from dots per inch
+    pub fn meta_set_dpi(file_name: &str, x: f64, y: f64) -> Result<(), Error> {
+        let density = match PixelDensity::from_dpi(x, y) {
+            Ok(density) => density,
+            Err(error) => {
+                emit!("{} {}", "Failed to set the density:".red().bold(), error);
+                return Err(error);
+            }
+        };
+        set_meta_chunk(file_name, density.to_chunk(), &density.to_string())
+    }
+
+    /// Sets the modification time to now
+    pub fn meta_touch(file_name: &str) -> Result<(), Error> {
+        let time = ModificationTime::now();
+        set_meta_chunk(file_name, time.to_chunk(), &time.to_string())
+    }
+
+    /// Sets the position of the image on the page
+    pub fn meta_set_offset(file_name: &str, x: i32, y: i32, micrometers: bool) -> Result<(), Error> {
+        let offset = ImageOffset {
+            x,
+            y,
+            unit: if micrometers { OffsetUnit::Micrometer } else { OffsetUnit::Pixel },
+        };
+        set_meta_chunk(file_name, offset.to_chunk(), &offset.to_string())
+    }
+
+    /// Sets the physical size of a pixel
+    pub fn meta_set_scale(file_name: &str, width: f64, height: f64, radians: bool) -> Result<(), Error> {
+        let unit = if radians { ScaleUnit::Radian } else { ScaleUnit::Meter };
+        let scale = match PhysicalScale::new(unit, width, height) {
+            Ok(scale) => scale,
+            Err(error) => {
+                emit!("{} {}", "Failed to set the scale:".red().bold(), error);
+                return Err(error);
             }
+        };
+        set_meta_chunk(file_name, scale.to_chunk(), &scale.to_string())
+    }
+
+    /// Lists the frames of an animated PNG file
+    pub fn frames(file_name: &str) -> Result<(), Error> {
+        let png = read_png(file_name)?;
+
+        let animation = match apng::animation_control(&png)? {
+            Some(animation) => animation,
             None => {
-                println!(
-                    "{} '{}'",
-                    "Failed to find chunk with type".red().bold(),
-                    chunk_type.white().bold()
+                emit!(
+                    "'{}' {}",
+                    file_name.white().bold(),
+                    "is not an animated PNG file".red().bold()
                 );
+                output::record("animated", json!(false));
+                return Ok(());
             }
         };
 
+        let plays = match animation.num_plays {
+            0 => "forever".to_string(),
+            plays => format!("{} times", plays),
+        };
+
+        emit!(
+            "{} {} {}",
+            format!("{} frames,", animation.num_frames).green().bold(),
+            "plays".white().bold(),
+            plays.white().bold()
+        );
+
+        output::record("animated", json!(true));
+        output::record("num_frames", json!(animation.num_frames));
+        output::record("num_plays", json!(animation.num_plays));
+
+        let mut frames = Vec::new();
+        for (index, frame) in apng::frames(&png)?.iter().enumerate() {
+            let control = &frame.control;
+            let size: usize = frame
+                .data_chunks
+                .iter()
+                .map(|&index| png.chunks()[index].data().len())
+                .sum();
+
+            frames.push(json!({
+                "width": control.width,
+                "height": control.height,
+                "x_offset": control.x_offset,
+                "y_offset": control.y_offset,
+                "delay": control.delay(),
+                "dispose_op": control.dispose_op.to_string(),
+                "blend_op": control.blend_op.to_string(),
+                "data_chunks": frame.data_chunks.len(),
+                "size": size,
+                "default_image": frame.default_image,
+            }));
+
+            emit!(
+                "{} {}x{} at ({}, {}), {:.3}s, dispose {}, blend {}, {} chunk(s), {} bytes{}",
+                format!("#{}", index).white().bold(),
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay(),
+                control.dispose_op,
+                control.blend_op,
+                frame.data_chunks.len(),
+                size,
+                if frame.default_image { " (default image)" } else { "" }
+            );
+        }
+
+        output::record("frames", json!(frames));
+
+        if let Err(error) = apng::validate(&png) {
+            emit!("{} {}", "Warning:".yellow().bold(), error);
+            output::record("warning", json!(error.to_string()));
+        }
+
         Ok(())
     }
 
-    pub fn remove(file_name: &str, chunk_type: &str) -> Result<(), Error> {
-        let temp_file_path = format!("{}.temp", file_name);
+    /// Losslessly shrinks a PNG file
+    /// Shrinks a PNG file. Unknown chunks are all kept unless `drop_unknown` lets the copy
+    /// rules drop the unsafe-to-copy ones, raw payloads don't look like payloads to them.
+    pub fn optimize(file_name: &str, drop_unknown: bool, keep: &[ChunkType]) -> Result<(), Error> {
+        let png = read_png(file_name)?;
 
-        // Move the contents of the file to a temporary location
-        std::fs::File::create(&temp_file_path)?;
-        std::fs::copy(file_name, &temp_file_path)?;
+        let keep = match drop_unknown {
+            true => encoder::payload_chunk_types(&png, keep),
+            false => png
+                .chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().clone())
+                .filter(|chunk_type| !chunk_type.is_standard())
+                .collect(),
+        };
+        let (optimized, report) = match optimize::optimize(&png, &keep) {
+            Ok(result) => result,
+            Err(error) => {
+                emit!("{} {}", "Failed to optimize the file:".red().bold(), error);
+                return Err(error);
+            }
+        };
 
-        // The PNG file to encode to
-        let mut file = match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&temp_file_path)
-        {
-            // Return file if ok
-            Ok(file) => file,
+        output::record("original_size", json!(report.original_size));
 
-            // Tell user there is an error
+        // Nothing was dropped or reduced when the file is left as it is
+        if report.optimized_size >= report.original_size {
+            output::record("optimized_size", json!(report.original_size));
+            emit!(
+                "{} ({} bytes)",
+                "The file is already as small as it gets".green().bold(),
+                report.original_size
+            );
+            return Ok(());
+        }
+
+        write_png(file_name, &optimized)?;
+
+        output::record("optimized_size", json!(report.optimized_size));
+        output::record("dropped", json!(report.dropped));
+        output::record("unsafe_to_copy", json!(report.unsafe_to_copy));
+        output::record(
+            "reductions",
+            json!(report.reductions.iter().map(|reduction| reduction.to_string()).collect::<Vec<_>>()),
+        );
+        output::record("filter", json!(report.filter.to_string()));
+
+        for chunk_type in &report.dropped {
+            emit!("{} '{}'", "Dropped redundant chunk".white().bold(), chunk_type);
+        }
+
+        for chunk_type in &report.unsafe_to_copy {
+            emit!("{} '{}'", "Dropped unsafe-to-copy chunk".yellow().bold(), chunk_type);
+        }
+
+        for reduction in &report.reductions {
+            emit!("{} {}", "Reduction:".white().bold(), reduction);
+        }
+
+        let saved = report.original_size - report.optimized_size;
+        emit!(
+            "{} {} -> {} bytes, saved {} bytes ({:.1}%) using the {} filter",
+            "Optimized".green().bold(),
+            report.original_size,
+            report.optimized_size,
+            saved,
+            saved as f64 * 100.0 / report.original_size as f64,
+            report.filter
+        );
+
+        Ok(())
+    }
+
+    /// How the payload argument of `encode` becomes the bytes to hide
+    pub struct PayloadOptions {
+        /// Reed-Solomon parity bytes per block, no error correction if `None`
+        pub parity: Option<usize>,
+        pub compress: bool,
+        /// Hide the bytes as they are, without an envelope
+        pub raw: bool,
+        /// Use a compact envelope, for methods that hide only a few bytes
+        pub compact: bool,
+        /// The payload argument is the name of a file to hide
+        pub from_file: bool,
+        /// Encrypt the payload into a slot of a deniable container
+        pub passphrase: Option<String>,
+        /// More payloads for other slots, as `PASSPHRASE=PAYLOAD`
+        pub also: Vec<String>,
+        /// The number of slots in the container
+        pub slots: usize,
+    }
+
+    /// How `decode` handles what it finds
+    pub struct DecodeOptions {
+        /// Correct a raw payload hidden with error correction
+        pub ecc: bool,
+        /// Don't look for an envelope
+        pub raw: bool,
+        /// Write the payload to this file instead of printing it
+        pub output: Option<String>,
+        /// Open the slot of a deniable container this passphrase belongs to
+        pub passphrase: Option<String>,
+    }
+
+    /// Turns the payload argument, and the extra ones, into the bytes to hide, see
+    /// `payload::prepare`
+    pub fn prepare_payload(payload: &str, options: &PayloadOptions) -> Result<Vec<u8>, Error> {
+        let main = read_payload(payload, options.from_file)?;
+
+        let mut also = Vec::new();
+        for extra in &options.also {
+            let (passphrase, payload) = match payload::split_also(extra) {
+                Ok(parts) => parts,
+                Err(error) => {
+                    emit!("{} {}", "Failed to prepare the payload:".red().bold(), error);
+                    return Err(error);
+                }
+            };
+            also.push((passphrase, read_payload(payload, options.from_file)?));
+        }
+
+        let prepare_options = PrepareOptions {
+            parity: options.parity,
+            compress: options.compress,
+            raw: options.raw,
+            compact: options.compact,
+            slots: options.slots,
+        };
+        let prepared =
+            payload::prepare(&main, options.passphrase.as_deref(), &also, &prepare_options);
+        if let Err(error) = &prepared {
+            emit!("{} {}", "Failed to prepare the payload:".red().bold(), error);
+        }
+        prepared
+    }
+
+    /// The payload argument as text, or the contents of the file it names
+    fn read_payload(payload: &str, from_file: bool) -> Result<Payload, Error> {
+        if !from_file {
+            return Ok(Payload::text(payload));
+        }
+
+        let bytes = match std::fs::read(payload) {
+            Ok(bytes) => bytes,
             Err(error) => {
-                println!(
-                    "{} '{}'",
-                    "Failed to open file".red().bold(),
-                    file_name.bold()
-                );
+                emit!("{} '{}'", "Failed to read file".red().bold(), payload.white().bold());
                 return Err(error.into());
             }
         };
+        let file_name = std::path::Path::new(payload)
+            .file_name()
+            .map_or(payload.to_string(), |name| name.to_string_lossy().to_string());
 
-        let mut data = Vec::new();
+        Ok(Payload::file(&file_name, bytes))
+    }
 
-        file.read_to_end(&mut data)?;
+    /// Prints a hidden payload, or writes it to a file. Envelopes are opened and their
+    /// metadata shown, see `payload::reveal`.
+    fn print_payload(found: &[u8], method: Method, options: &DecodeOptions) -> Result<(), Error> {
+        let reveal_options = RevealOptions {
+            ecc: options.ecc,
+            raw: options.raw,
+            passphrase: options.passphrase.clone(),
+            ..Default::default()
+        };
+        let revealed = match payload::reveal(found, method, &reveal_options) {
+            Ok(revealed) => revealed,
+            Err(error) => {
+                match payload::is_no_payload(error.as_ref()) {
+                    true => {
+                        emit!("{} {}", "Failed to find a payload:".red().bold(), error);
+                        output::record("found", json!(false));
+                    }
+                    false => emit!("{} {}", "Failed to open the payload:".red().bold(), error),
+                }
+                return Err(error);
+            }
+        };
 
-        let mut png = Png::try_from(&data[..])?;
+        if let Some(metadata) = &revealed.metadata {
+            output::record("content_type", json!(metadata.content_type));
+            output::record("file_name", json!(metadata.file_name));
+            output::record("created", json!((!revealed.compact).then_some(metadata.created)));
+            emit!("{} {}", "Content type:".white().bold(), metadata.content_type);
+            if let Some(file_name) = &metadata.file_name {
+                emit!("{} {}", "File name:".white().bold(), file_name);
+            }
+            if !revealed.compact {
+                emit!(
+                    "{} {}",
+                    "Created:".white().bold(),
+                    envelope::format_timestamp(metadata.created)
+                );
+            }
+            emit!(
+                "{} {} bytes{}{}",
+                "Size:".white().bold(),
+                revealed.payload.len(),
+                if revealed.flags & envelope::FLAG_COMPRESSED != 0 { ", compressed" } else { "" },
+                if revealed.flags & envelope::FLAG_ECC != 0 { ", error correction" } else { "" }
+            );
+        }
+        if let Some(corrected) = revealed.corrected {
+            report_corrected(corrected);
+        }
 
+        let text = revealed.is_text();
+        let payload = revealed.payload;
 
+        output::record("found", json!(true));
+        output::record("size", json!(payload.len()));
 
-        if let Err(error) = png.remove_chunk(chunk_type) {
-            println!(
-                "'{}' {}",
-                chunk_type.white().bold(),
-                "was not found".white().bold()
+        if let Some(output) = &options.output {
+            std::fs::write(output, &payload)?;
+            output::record("saved_to", json!(output));
+            emit!(
+                "{} {} bytes to '{}'",
+                "Saved".green().bold(),
+                payload.len(),
+                output.white().bold()
+            );
+        } else if text {
+            output::record("message", json!(String::from_utf8_lossy(&payload)));
+            emit!(
+                "{} {}",
+                "Message:".white().bold(),
+                String::from_utf8_lossy(&payload)
+            );
+        } else {
+            emit!(
+                "{} {} bytes, use --output to save it",
+                "Binary payload of".white().bold(),
+                payload.len()
             );
-            return Err(error);
         }
 
-        println!("{}", "Removed chunk from file successfully".green().bold());
+        Ok(())
+    }
 
-        file.set_len(0)?;
-        file.seek(SeekFrom::Start(0))?;
+    fn report_corrected(corrected: usize) {
+        output::record("corrected", json!(corrected));
+        if corrected > 0 {
+            emit!("{} {} damaged bytes", "Recovered".yellow().bold(), corrected);
+        }
+    }
 
+    /// Tells how much of a payload that doesn't fit is its envelope
+    fn report_envelope_overhead(payload: &[u8]) {
+        if let Ok(opened) = envelope::open(payload) {
+            let overhead = payload.len().saturating_sub(opened.payload.len());
+            output::record("envelope_overhead", json!(overhead));
+            emit!(
+                "{} of the {} bytes, {} are the envelope, --raw hides the payload without it",
+                "Note:".yellow().bold(),
+                payload.len(),
+                overhead
+            );
+        }
+    }
 
-        file.write_all(&png.as_bytes()[..])?;
+    /// Where a finding of `scan` is and what it holds
+    fn describe_finding(finding: &Finding) -> String {
+        let place = match (finding.method, finding.carrier, &finding.chunk_type) {
+            (_, Some(carrier), _) => format!("in the {}", carrier),
+            (_, None, Some(chunk_type)) => format!("in a chunk with type '{}'", chunk_type),
+            (Method::Palette, ..) => "in the palette order".to_string(),
+            (Method::DeflateSlack, ..) => "in the image data".to_string(),
+            _ => "after IEND".to_string(),
+        };
 
+        format!(
+            "{} bytes {}{}",
+            finding.size,
+            place,
+            if finding.envelope { ", an envelope" } else { "" }
+        )
+    }
+
+    /// Tells which chunks the copy rules dropped when the image data changed
+    fn report_unsafe_to_copy(dropped: &[Chunk]) {
+        let types: Vec<String> = dropped
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+
+        for chunk_type in &types {
+            emit!("{} '{}'", "Dropped unsafe-to-copy chunk".yellow().bold(), chunk_type);
+        }
+        output::record("unsafe_to_copy", json!(types));
+    }
+
+    /// Writes a PNG file through a temporary file, so the original stays intact if
+    /// anything goes wrong
+    fn write_png(file_name: &str, png: &Png) -> Result<(), Error> {
+        let temp_file_path = format!("{}.temp", file_name);
+
+        if let Err(error) = std::fs::write(&temp_file_path, png.as_bytes()) {
+            emit!(
+                "{} '{}'",
+                "Failed to write file".red().bold(),
+                file_name.white().bold()
+            );
+            let _ = std::fs::remove_file(&temp_file_path);
+            return Err(error.into());
+        }
 
-        // Write the contents of the temp file to the PNG file
-        std::fs::remove_file(file_name)?;
         std::fs::rename(&temp_file_path, file_name)?;
 
         Ok(())
     }
+
+    /// Reads the eXIf chunk, telling the user if there is none
+    fn read_exif(png: &Png) -> Result<Option<Exif>, Error> {
+        match Exif::from_png(png) {
+            Ok(Some(exif)) => Ok(Some(exif)),
+            Ok(None) => {
+                emit!("{}", "The file has no EXIF data".yellow().bold());
+                Ok(None)
+            }
+            Err(error) => {
+                emit!("{} {}", "Failed to read the EXIF data:".red().bold(), error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Reads the XMP packet, telling the user if there is none
+    fn read_xmp(png: &Png) -> Result<Option<Xmp>, Error> {
+        match Xmp::from_png(png) {
+            Ok(Some(xmp)) => Ok(Some(xmp)),
+            Ok(None) => {
+                emit!("{}", "The file has no XMP metadata".yellow().bold());
+                Ok(None)
+            }
+            Err(error) => {
+                emit!("{} {}", "Failed to read the XMP metadata:".red().bold(), error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Shows which color chunks there are, which one applies and how they disagree
+    fn print_color(color: &ColorState, header: Option<&ImageHeader>) {
+        let label = |chunk_type: &str| format!("{}:", chunk_type).white().bold();
+
+        match color.effective() {
+            Some(effective) => emit!("{} {}", "Color described by".white().bold(), effective),
+            None => emit!("{}", "No color chunks".white().bold()),
+        }
+
+        if let Some(code_points) = color.code_points {
+            emit!("  {} {}", label("cICP"), code_points);
+        }
+        if let Some(icc) = &color.icc {
+            match icc.header() {
+                Ok(icc_header) => emit!("  {} '{}', {}", label("iCCP"), icc.name, icc_header),
+                Err(error) => emit!("  {} '{}', {}", label("iCCP"), icc.name, error),
+            }
+        }
+        if let Some(intent) = color.srgb {
+            emit!("  {} {:?} rendering intent", label("sRGB"), intent);
+        }
+        if let Some(gamma) = color.gamma {
+            emit!("  {} {}", label("gAMA"), gamma);
+        }
+        if let Some(chromaticities) = color.chromaticities {
+            emit!("  {} {}", label("cHRM"), chromaticities);
+        }
+        if let Some(display) = color.mastering_display {
+            emit!("  {} {}", label("mDCV"), display);
+        }
+        if let Some(light_level) = color.light_level {
+            emit!("  {} {}", label("cLLI"), light_level);
+        }
+
+        let superseded = color.superseded();
+        if !superseded.is_empty() {
+            emit!("  {} {}", "Overridden:".white().bold(), superseded.join(", "));
+        }
+
+        for warning in color.warnings(header) {
+            emit!("{} {}", "Warning:".yellow().bold(), warning);
+        }
+    }
+
+    /// Replaces a chunk or adds it where the ordering rules allow
+    fn set_meta_chunk(file_name: &str, chunk: Chunk, description: &str) -> Result<(), Error> {
+        let mut png = read_png(file_name)?;
+        let chunk_type = chunk.chunk_type().to_string();
+
+        if let Err(error) = png.set_chunk(chunk) {
+            emit!("{} {}", "Failed to set the chunk:".red().bold(), error);
+            return Err(error);
+        }
+
+        write_png(file_name, &png)?;
+
+        output::record("chunk_type", json!(chunk_type));
+        output::record("value", json!(description));
+        emit!("{} {} to {}", "Set".green().bold(), chunk_type.white().bold(), description);
+
+        Ok(())
+    }
+
+    /// A chunk change for the JSON output, with the changed bytes in full
+    fn change_json(change: &ChunkChange) -> serde_json::Value {
+        match change {
+            ChunkChange::Added { to, chunk_type } => {
+                json!({ "change": "added", "type": chunk_type, "to": to })
+            }
+            ChunkChange::Removed { from, chunk_type } => {
+                json!({ "change": "removed", "type": chunk_type, "from": from })
+            }
+            ChunkChange::Moved { from, to, chunk_type } => {
+                json!({ "change": "moved", "type": chunk_type, "from": from, "to": to })
+            }
+            ChunkChange::Modified { from, to, chunk_type, bytes } => json!({
+                "change": "modified",
+                "type": chunk_type,
+                "from": from,
+                "to": to,
+                "bytes": bytes
+                    .iter()
+                    .map(|change| json!({
+                        "offset": change.offset,
+                        "old": hex(&change.old),
+                        "new": hex(&change.new),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
+
+    fn pixel_json(pixel_diff: &PixelDiff) -> serde_json::Value {
+        match pixel_diff {
+            PixelDiff::SizeMismatch { old, new } => json!({ "size_mismatch": { "old": old, "new": new } }),
+            PixelDiff::Compared { differing, max_delta } => {
+                json!({ "differing": differing, "max_delta": max_delta })
+            }
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// The first bytes of some data in hex, with the length when there is more
+    fn hex_preview(bytes: &[u8]) -> String {
+        const PREVIEW: usize = 16;
+
+        let hex: Vec<String> = bytes
+            .iter()
+            .take(PREVIEW)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        match bytes.len() {
+            0 => "nothing".to_string(),
+            length if length > PREVIEW => format!("{} ... ({} bytes)", hex.join(" "), length),
+            _ => hex.join(" "),
+        }
+    }
+
+    /// Reads and parses a PNG file, telling the user what went wrong
+    fn read_png(file_name: &str) -> Result<Png, Error> {
+        let data = read_file(file_name)?;
+
+        match Png::try_from(&data[..]) {
+            Ok(png) => Ok(png),
+            Err(error) => {
+                emit!(
+                    "{}",
+                    "A bad PNG file has been given, the given PNG file may be corrupted."
+                        .red()
+                        .bold()
+                );
+                Err(error)
+            }
+        }
+    }
+
+    /// Reads a PNG file to decode a payload from. Chunks whose CRC doesn't match are kept
+    /// and reported, error correction may still repair the payload in them. Returns their
+    /// indices too.
+    fn read_png_for_decode(file_name: &str) -> Result<(Png, Vec<usize>), Error> {
+        let data = read_file(file_name)?;
+
+        let (png, damaged) = match Png::try_from_lenient(&data[..]) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                emit!(
+                    "{}",
+                    "A bad PNG file has been given, the given PNG file may be corrupted."
+                        .red()
+                        .bold()
+                );
+                return Err(error);
+            }
+        };
+
+        let types: Vec<String> = damaged
+            .iter()
+            .map(|&index| png.chunks()[index].chunk_type().to_string())
+            .collect();
+        for chunk_type in &types {
+            emit!("{} '{}'", "Bad CRC in chunk".yellow().bold(), chunk_type);
+        }
+        if !types.is_empty() {
+            output::record("damaged_chunks", json!(types));
+        }
+
+        Ok((png, damaged))
+    }
+
+    fn read_file(file_name: &str) -> Result<Vec<u8>, Error> {
+        match std::fs::read(file_name) {
+            Ok(data) => Ok(data),
+            Err(error) => {
+                emit!(
+                    "{} '{}'",
+                    "Failed to read file".red().bold(),
+                    file_name.white().bold()
+                );
+                Err(error.into())
+            }
+        }
+    }
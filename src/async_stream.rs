@@ -0,0 +1,460 @@
+use std::str::FromStr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    envelope::{self, Metadata, Opened, SealOptions},
+    ordering::{self, Placement},
+    stream::{self, StreamError},
+};
+
+// The chunk reader and writer of `stream` on top of tokio's `AsyncRead` and `AsyncWrite`, for
+// services that process uploads as they come in. Only the chunk at hand is held in memory.
+
+/// Reads the chunks of a PNG file from an async stream. Like `Png::try_from`, whole chunks
+/// with a matching CRC are still read after IEND, whatever follows them is left to
+/// `trailing_bytes` or `copy_rest`.
+pub struct AsyncChunkReader<R> {
+    reader: R,
+    ended: bool,
+    /// What was read after IEND that isn't a chunk, the start of the trailing bytes
+    rest: Vec<u8>,
+}
+
+/// Writes a PNG file to an async stream a chunk at a time
+pub struct AsyncChunkWriter<W> {
+    writer: W,
+}
+
+impl<R: AsyncRead + Unpin> AsyncChunkReader<R> {
+    /// Reads and checks the signature
+    pub async fn new(mut reader: R) -> Result<AsyncChunkReader<R>, StreamError> {
+        let mut signature = [0; 8];
+        reader.read_exact(&mut signature).await?;
+        stream::check_signature(&signature)?;
+
+        Ok(AsyncChunkReader {
+            reader,
+            ended: false,
+            rest: Vec::new(),
+        })
+    }
+
+    /// The next chunk, or `None` once there are no more
+    pub async fn next_chunk(&mut self) -> Result<Option<Chunk>, StreamError> {
+        if self.ended {
+            return self.next_trailing_chunk().await;
+        }
+
+        let mut head = [0; 8];
+        self.reader
+            .read_exact(&mut head)
+            .await
+            .map_err(stream::missing_iend)?;
+        let (length, chunk_type) = stream::parse_head(&head)?;
+
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)
+            .await?;
+        if data.len() < length as usize {
+            return Err(StreamError::Truncated);
+        }
+
+        let mut crc = [0; 4];
+        self.reader.read_exact(&mut crc).await?;
+
+        let chunk = stream::finish_chunk(chunk_type, data, crc)?;
+        self.ended = &chunk.chunk_type().bytes() == b"IEND";
+        Ok(Some(chunk))
+    }
+
+    /// Reads whatever is left after the chunks
+    pub async fn trailing_bytes(mut self) -> Result<Vec<u8>, StreamError> {
+        let mut trailing_bytes = self.rest;
+        self.reader.read_to_end(&mut trailing_bytes).await?;
+        Ok(trailing_bytes)
+    }
+
+    /// Copies whatever is left after the chunks to a writer, without holding it in memory
+    pub async fn copy_rest<W: AsyncWrite + Unpin>(
+        mut self,
+        writer: &mut AsyncChunkWriter<W>,
+    ) -> Result<u64, StreamError> {
+        writer.write_bytes(&self.rest).await?;
+        let copied = tokio::io::copy(&mut self.reader, &mut writer.writer).await?;
+        Ok(self.rest.len() as u64 + copied)
+    }
+
+    /// A chunk after IEND. It is read into `rest` first, so it stays trailing data if it
+    /// turns out not to be one.
+    async fn next_trailing_chunk(&mut self) -> Result<Option<Chunk>, StreamError> {
+        if !self.rest.is_empty() {
+            return Ok(None);
+        }
+
+        (&mut self.reader)
+            .take(8)
+            .read_to_end(&mut self.rest)
+            .await?;
+        if let Some(length) = stream::trailing_length(&self.rest) {
+            (&mut self.reader)
+                .take(length as u64 + 4)
+                .read_to_end(&mut self.rest)
+                .await?;
+        }
+
+        let chunk = stream::trailing_chunk(&self.rest);
+        if chunk.is_some() {
+            self.rest.clear();
+        }
+        Ok(chunk)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncChunkWriter<W> {
+    /// Writes the signature
+    pub async fn new(mut writer: W) -> Result<AsyncChunkWriter<W>, StreamError> {
+        writer.write_all(&stream::SIGNATURE).await?;
+        Ok(AsyncChunkWriter { writer })
+    }
+
+    pub async fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), StreamError> {
+        self.writer.write_all(&chunk.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Writes bytes as they are, for data after IEND
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), StreamError> {
+        self.writer.write_all(bytes).await?;
+        Ok(())
+    }
+
+    /// Flushes the stream and gives it back
+    pub async fn finish(mut self) -> Result<W, StreamError> {
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+}
+
+/// Copies a PNG file from one stream to another, hiding the payload in an envelope (see
+/// `envelope::seal`) in a chunk right before IEND, the way the encode command does
+pub async fn encode<R, W>(
+    reader: R,
+    writer: W,
+    chunk_type: &str,
+    payload: &[u8],
+    metadata: &Metadata,
+    options: &SealOptions,
+) -> Result<W, StreamError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let sealed = envelope::seal(payload, metadata, options)
+        .map_err(|error| StreamError::Envelope(error.to_string()))?;
+    encode_raw(reader, writer, chunk_type, &sealed).await
+}
+
+/// Opens the envelope in the first chunk of a type (see `envelope::open`), reading the
+/// stream no further than that chunk
+pub async fn decode<R: AsyncRead + Unpin>(
+    reader: R,
+    chunk_type: &str,
+) -> Result<Option<Opened>, StreamError> {
+    match decode_raw(reader, chunk_type).await? {
+        Some(data) => match envelope::open(&data) {
+            Ok(opened) => Ok(Some(opened)),
+            Err(error) => Err(StreamError::Envelope(error.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Copies a PNG file from one stream to another, hiding the payload as it is given in a
+/// chunk right before IEND
+pub async fn encode_raw<R, W>(
+    reader: R,
+    writer: W,
+    chunk_type: &str,
+    payload: &[u8],
+) -> Result<W, StreamError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let hidden = payload_chunk(chunk_type, payload)?;
+
+    let mut reader = AsyncChunkReader::new(reader).await?;
+    let mut writer = AsyncChunkWriter::new(writer).await?;
+
+    // Only the first IEND counts, chunks may follow it
+    let mut hidden = Some(hidden);
+    let mut seen = Vec::new();
+    while let Some(chunk) = reader.next_chunk().await? {
+        if &chunk.chunk_type().bytes() == b"IEND" {
+            if let Some(hidden) = hidden.take() {
+                check_late_chunk(&hidden, &seen)?;
+                writer.write_chunk(&hidden).await?;
+            }
+        }
+
+        seen.push(chunk.chunk_type().bytes());
+        writer.write_chunk(&chunk).await?;
+    }
+
+    reader.copy_rest(&mut writer).await?;
+    writer.finish().await
+}
+
+/// The data of the first chunk of a type, as it is, reading the stream no further than
+/// that chunk
+pub async fn decode_raw<R: AsyncRead + Unpin>(
+    reader: R,
+    chunk_type: &str,
+) -> Result<Option<Vec<u8>>, StreamError> {
+    let mut reader = AsyncChunkReader::new(reader).await?;
+
+    while let Some(chunk) = reader.next_chunk().await? {
+        if chunk.chunk_type().to_string() == chunk_type {
+            return Ok(Some(chunk.data().to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// A chunk holding a payload, of a type given by name
+fn payload_chunk(chunk_type: &str, payload: &[u8]) -> Result<Chunk, StreamError> {
+    match ChunkType::from_str(chunk_type) {
+        Ok(parsed) => Ok(Chunk::new(parsed, payload.to_vec())),
+        Err(_) => Err(StreamError::InvalidChunkType(chunk_type.to_string())),
+    }
+}
+
+/// Checks that a chunk can go right before IEND, the only place a stream can still add it
+/// to. `seen` are the types of the chunks before it.
+fn check_late_chunk(chunk: &Chunk, seen: &[[u8; 4]]) -> Result<(), StreamError> {
+    let chunk_type = chunk.chunk_type().bytes();
+    let rule = ordering::rule_for(&chunk_type);
+
+    let placed = matches!(rule.placement, Placement::Anywhere | Placement::AfterIdat);
+    if !placed || (!rule.multiple && seen.contains(&chunk_type)) {
+        return Err(StreamError::Placement(chunk.chunk_type().to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod async_stream_tests {
+    use super::*;
+    use crate::png::Png;
+
+    fn testing_bytes() -> Vec<u8> {
+        include_bytes!("../sss.png").to_vec()
+    }
+
+    fn testing_metadata() -> Metadata {
+        Metadata::new("text/plain", None)
+    }
+
+    #[tokio::test]
+    async fn test_read_and_write() {
+        let bytes = testing_bytes();
+        let png = Png::try_from(&bytes[..]).unwrap();
+
+        let mut reader = AsyncChunkReader::new(&bytes[..]).await.unwrap();
+        let mut writer = AsyncChunkWriter::new(Vec::new()).await.unwrap();
+        let mut chunks = 0;
+        while let Some(chunk) = reader.next_chunk().await.unwrap() {
+            writer.write_chunk(&chunk).await.unwrap();
+            chunks += 1;
+        }
+        reader.copy_rest(&mut writer).await.unwrap();
+
+        // The chunks sss.png has after IEND are read as chunks, like `Png::try_from` does
+        assert_eq!(chunks, png.chunks().len());
+        assert_eq!(writer.finish().await.unwrap(), bytes);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_bytes() {
+        let mut bytes = testing_bytes();
+        bytes.extend_from_slice(b"\0\0\0\x02RuSthi!!");
+
+        let mut reader = AsyncChunkReader::new(&bytes[..]).await.unwrap();
+        while reader.next_chunk().await.unwrap().is_some() {}
+        assert_eq!(
+            reader.trailing_bytes().await.unwrap(),
+            Png::try_from(&bytes[..]).unwrap().trailing_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_and_decode() {
+        let bytes = testing_bytes();
+
+        let encoded = encode(
+            &bytes[..],
+            Vec::new(),
+            "ruSt",
+            b"streamed",
+            &testing_metadata(),
+            &SealOptions::default(),
+        )
+        .await
+        .unwrap();
+        let opened = decode(&encoded[..], "ruSt").await.unwrap().unwrap();
+        assert_eq!(opened.payload, b"streamed");
+        assert_eq!(opened.metadata, testing_metadata());
+
+        // The chunk went in before IEND, the chunks and data after it stayed
+        let png = Png::try_from(&encoded[..]).unwrap();
+        let original = Png::try_from(&bytes[..]).unwrap();
+        let iend = png
+            .chunks()
+            .iter()
+            .position(|chunk| &chunk.chunk_type().bytes() == b"IEND")
+            .unwrap();
+        assert_eq!(png.chunks()[iend - 1].chunk_type().to_string(), "ruSt");
+        assert_eq!(png.chunks().len(), original.chunks().len() + 1);
+        assert_eq!(png.trailing_bytes(), original.trailing_bytes());
+
+        assert!(decode(&bytes[..], "ruSt").await.unwrap().is_none());
+        assert!(matches!(
+            decode(&bytes[..], "RuSt").await,
+            Err(StreamError::Envelope(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_raw() {
+        let bytes = testing_bytes();
+
+        // sss.png has a chunk after IEND with a raw payload
+        assert_eq!(
+            decode_raw(&bytes[..], "RuSt").await.unwrap(),
+            Some(b"hi".to_vec())
+        );
+
+        let encoded = encode_raw(&bytes[..], Vec::new(), "ruSt", b"as it is")
+            .await
+            .unwrap();
+        assert_eq!(
+            decode_raw(&encoded[..], "ruSt").await.unwrap(),
+            Some(b"as it is".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_stops_at_the_chunk() {
+        let mut bytes = encode_raw(&testing_bytes()[..], Vec::new(), "ruSt", b"found")
+            .await
+            .unwrap();
+
+        // Nothing after the chunk is read, so a broken end doesn't matter
+        let iend = bytes
+            .windows(4)
+            .position(|window| window == b"IEND")
+            .unwrap();
+        bytes.truncate(iend - 2);
+        assert_eq!(
+            decode_raw(&bytes[..], "ruSt").await.unwrap(),
+            Some(b"found".to_vec())
+        );
+        assert!(matches!(
+            decode_raw(&bytes[..], "zzZz").await,
+            Err(StreamError::MissingIend)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_envelope_with_ecc() {
+        let options = SealOptions {
+            compress: true,
+            parity: Some(8),
+            compact: false,
+        };
+        let mut encoded = encode(
+            &testing_bytes()[..],
+            Vec::new(),
+            "ruSt",
+            b"sealed",
+            &testing_metadata(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        // The CRC is fixed up, error correction repairs the data
+        let png = Png::try_from(&encoded[..]).unwrap();
+        let start = encoded
+            .windows(4)
+            .position(|window| window == b"ruSt")
+            .unwrap()
+            + 4;
+        let length = png.chunk_by_type("ruSt").unwrap().length() as usize;
+        encoded[start + 10] ^= 0xFF;
+        let damaged = Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            encoded[start..start + length].to_vec(),
+        );
+        encoded[start + length..start + length + 4].copy_from_slice(&damaged.crc().to_be_bytes());
+
+        let opened = decode(&encoded[..], "ruSt").await.unwrap().unwrap();
+        assert_eq!(opened.payload, b"sealed");
+        assert_eq!(opened.corrected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_encode_errors() {
+        let bytes = testing_bytes();
+
+        assert!(matches!(
+            encode_raw(&bytes[..], Vec::new(), "ru", b"").await,
+            Err(StreamError::InvalidChunkType(_))
+        ));
+        assert!(matches!(
+            encode_raw(&bytes[..], Vec::new(), "pHYs", b"").await,
+            Err(StreamError::Placement(_))
+        ));
+        assert!(matches!(
+            encode_raw(&bytes[1..], Vec::new(), "ruSt", b"").await,
+            Err(StreamError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_futures_are_send() {
+        let bytes = testing_bytes();
+
+        let encoded = tokio::spawn(async move {
+            let metadata = testing_metadata();
+            encode(
+                &bytes[..],
+                Vec::new(),
+                "ruSt",
+                b"sent",
+                &metadata,
+                &SealOptions::default(),
+            )
+            .await
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(encoded.len() > testing_bytes().len());
+    }
+
+    #[test]
+    fn test_check_late_chunk() {
+        let chunk = |chunk_type: &[u8; 4]| Chunk::new(ChunkType::new(*chunk_type), Vec::new());
+
+        assert!(check_late_chunk(&chunk(b"ruSt"), &[*b"ruSt"]).is_ok());
+        assert!(check_late_chunk(&chunk(b"tIME"), &[*b"IHDR"]).is_ok());
+        assert!(check_late_chunk(&chunk(b"tIME"), &[*b"tIME"]).is_err());
+        assert!(check_late_chunk(&chunk(b"pHYs"), &[]).is_err());
+    }
+}
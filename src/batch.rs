@@ -0,0 +1,254 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    output::{self, Captured},
+    Error,
+};
+
+// Commands that take a file name also take a directory, which is searched recursively for
+// PNG files, or a glob pattern. The files are processed in parallel on a thread pool.
+
+/// How a batch of files is processed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchOptions {
+    /// Don't start on more files once one has failed
+    pub fail_fast: bool,
+    /// The number of threads, all cores when `None`
+    pub jobs: Option<usize>,
+}
+
+/// What happened to a file of a batch, with what the command emitted for it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Succeeded {
+        output: Captured,
+    },
+    Failed {
+        output: Captured,
+        error: String,
+        /// The typed code of the error, see `output::error_code`
+        code: String,
+    },
+    /// Not processed because another file failed first
+    Skipped,
+}
+
+/// Whether the input names several files: a directory or a glob pattern. A file that
+/// exists is never a pattern, whatever characters its name has.
+pub fn is_batch(input: &str) -> bool {
+    let path = Path::new(input);
+    if path.exists() {
+        return path.is_dir();
+    }
+    input.contains(['*', '?', '['])
+}
+
+/// The files a directory or glob pattern stands for, sorted
+pub fn expand(input: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+
+    if Path::new(input).is_dir() {
+        find_png_files(Path::new(input), &mut files)?;
+    } else {
+        for path in glob::glob(input)? {
+            let path = path?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+
+    if files.is_empty() {
+        return Err(BatchError::NoFiles(input.to_string()).into());
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Runs a job for every file in parallel. `done` is called as soon as a file is finished,
+/// from the thread that processed it.
+pub fn run<J, D>(files: &[PathBuf], options: &BatchOptions, job: J, done: D) -> Vec<Outcome>
+where
+    J: Fn(&str) -> Result<(), Error> + Sync,
+    D: Fn(&Path, &Outcome) + Sync,
+{
+    let failed = AtomicBool::new(false);
+
+    let process = |file: &PathBuf| {
+        if options.fail_fast && failed.load(Ordering::Relaxed) {
+            return Outcome::Skipped;
+        }
+
+        let (result, output) = output::capture(|| job(&file.to_string_lossy()));
+        let outcome = match result {
+            Ok(()) => Outcome::Succeeded { output },
+            Err(error) => {
+                failed.store(true, Ordering::Relaxed);
+                Outcome::Failed {
+                    output,
+                    error: error.to_string(),
+                    code: output::error_code(error.as_ref()),
+                }
+            }
+        };
+
+        done(file, &outcome);
+        outcome
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build();
+
+    match pool {
+        Ok(pool) => pool.install(|| files.par_iter().map(process).collect()),
+        // Without a pool of our own the global one does the job
+        Err(_) => files.par_iter().map(process).collect(),
+    }
+}
+
+/// Adds the PNG files in a directory and its subdirectories
+fn find_png_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_png_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BatchError {
+    #[error("No files match '{0}'.")]
+    NoFiles(String),
+
+    #[error("{0} of {1} files failed.")]
+    Failed(usize, usize),
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::*;
+
+    /// A directory with PNG files in it and in a subdirectory
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("sspngme_batch_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        std::fs::create_dir_all(directory.join("nested")).unwrap();
+        for file in ["a.png", "b.PNG", "notes.txt", "nested/c.png"] {
+            std::fs::write(directory.join(file), file).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn test_expand() {
+        let directory = directory("expand");
+        let name = |path: &PathBuf| path.strip_prefix(&directory).unwrap().to_path_buf();
+
+        let files = expand(&directory.to_string_lossy()).unwrap();
+        assert_eq!(
+            files.iter().map(name).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("a.png"),
+                PathBuf::from("b.PNG"),
+                PathBuf::from("nested/c.png")
+            ]
+        );
+
+        let pattern = directory.join("*.png");
+        let files = expand(&pattern.to_string_lossy()).unwrap();
+        assert_eq!(
+            files.iter().map(name).collect::<Vec<_>>(),
+            vec![PathBuf::from("a.png")]
+        );
+
+        assert!(expand(&directory.join("*.gif").to_string_lossy()).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_is_batch() {
+        assert!(is_batch("images/*.png"));
+        assert!(is_batch(&std::env::temp_dir().to_string_lossy()));
+        assert!(!is_batch("image.png"));
+
+        // A file whose name looks like a pattern is still a file
+        let file = std::env::temp_dir().join(format!("sspngme_[{}]?.png", std::process::id()));
+        std::fs::write(&file, b"").unwrap();
+        assert!(!is_batch(&file.to_string_lossy()));
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_run() {
+        let files: Vec<PathBuf> = ["one", "two", "three"].iter().map(PathBuf::from).collect();
+
+        let outcomes = run(
+            &files,
+            &BatchOptions::default(),
+            |file| {
+                crate::emit!("processing {}", file);
+                match file {
+                    "two" => Err("broken".into()),
+                    _ => Ok(()),
+                }
+            },
+            |_, _| {},
+        );
+
+        let texts: Vec<&str> = outcomes
+            .iter()
+            .map(|outcome| match outcome {
+                Outcome::Succeeded { output } | Outcome::Failed { output, .. } => &output.text[..],
+                Outcome::Skipped => "",
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["processing one\n", "processing two\n", "processing three\n"]
+        );
+
+        match &outcomes[1] {
+            Outcome::Failed { error, code, .. } => {
+                assert_eq!(error, "broken");
+                assert_eq!(code, "Error");
+            }
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+        assert!(matches!(outcomes[2], Outcome::Succeeded { .. }));
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let files: Vec<PathBuf> = (0..20).map(|index| PathBuf::from(index.to_string())).collect();
+        let options = BatchOptions {
+            fail_fast: true,
+            jobs: Some(1),
+        };
+
+        let outcomes = run(&files, &options, |_| Err("broken".into()), |_, _| {});
+
+        assert!(matches!(outcomes[0], Outcome::Failed { .. }));
+        assert!(outcomes[1..]
+            .iter()
+            .all(|outcome| *outcome == Outcome::Skipped));
+    }
+}
@@ -0,0 +1,537 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    exif::{Directory, Exif, Value},
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    xmp::{self, PropertyValue, Xmp},
+    Error,
+};
+
+// Payloads hidden in chunks that ordinary PNG files carry, instead of a custom chunk type
+// that gives itself away:
+//
+//   comment  a zTXt "Comment" holding the payload in base64
+//   xmp      an iTXt XMP packet with the payload in base64 in a property of our namespace,
+//            added to the packet the file has already
+//   exif     the MakerNote tag of an eXIf chunk, which is opaque vendor data anyway. It is
+//            added to the EXIF data the file has already, unless that has a maker note.
+//   icc      a private tag of a small monitor profile in an iCCP chunk, gray or RGB like
+//            the image. Files with a color profile or sRGB chunk already are refused.
+//
+// The payload is whitened first in every carrier, so the envelope magic and content type
+// don't show through. Whitening only hides the structure, a passphrase protects it.
+
+const COMMENT_KEYWORD: &str = "Comment";
+
+const XMP_NAMESPACE: &str = "http://ns.softsquirrel.net/sspngme/1.0/";
+const XMP_PREFIX: &str = "sspm";
+const XMP_PROPERTY: &str = "Data";
+
+const MAKER_NOTE: u16 = 0x927C;
+
+const ICC_PROFILE_NAME: &str = "ICC Profile";
+const ICC_PRIVATE_TAG: [u8; 4] = *b"sspm";
+
+const WHITENING_KEY: &[u8] = b"sspngme camouflage";
+
+/// The D50 white point in s15Fixed16Number
+const D50: [u8; 12] = [0, 0, 0xF6, 0xD6, 0, 1, 0, 0, 0, 0, 0xD3, 0x2D];
+
+/// The sRGB primaries adapted to D50, in s15Fixed16Number
+const PRIMARIES: [[u8; 12]; 3] = [
+    [0, 0, 0x6F, 0xA2, 0, 0, 0x38, 0xF5, 0, 0, 0x03, 0x90],
+    [0, 0, 0x62, 0x99, 0, 0, 0xB7, 0x85, 0, 0, 0x18, 0xDA],
+    [0, 0, 0x24, 0xA0, 0, 0, 0x0F, 0x84, 0, 0, 0xB6, 0xCF],
+];
+
+/// A gamma of 2.2 in u8Fixed8Number
+const GAMMA: u16 = 0x0233;
+
+/// A chunk a payload can hide in
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Carrier {
+    /// A compressed text comment (zTXt)
+    Comment,
+    /// An XMP metadata packet (iTXt)
+    Xmp,
+    /// The maker note of EXIF data (eXIf)
+    Exif,
+    /// A private tag of an embedded color profile (iCCP)
+    Icc,
+}
+
+impl Carrier {
+    pub fn chunk_type(self) -> [u8; 4] {
+        match self {
+            Carrier::Comment => *b"zTXt",
+            Carrier::Xmp => *b"iTXt",
+            Carrier::Exif => *b"eXIf",
+            Carrier::Icc => *b"iCCP",
+        }
+    }
+}
+
+impl std::fmt::Display for Carrier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Carrier::Comment => "zTXt comment",
+            Carrier::Xmp => "XMP packet",
+            Carrier::Exif => "EXIF maker note",
+            Carrier::Icc => "ICC profile",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Builds a carrier chunk holding the payload, for an image of the given color type
+pub fn wrap(carrier: Carrier, color_type: ColorType, payload: &[u8]) -> Result<Chunk, Error> {
+    let payload = whiten(payload);
+
+    match carrier {
+        Carrier::Comment => Chunk::ztxt(COMMENT_KEYWORD, STANDARD.encode(payload).as_bytes()),
+        Carrier::Xmp => {
+            let mut xmp = Xmp::new();
+            set_xmp_data(&mut xmp, &payload)?;
+            xmp.to_chunk()
+        }
+        Carrier::Exif => {
+            let mut exif = Exif::new();
+            exif.set(Directory::Exif, MAKER_NOTE, Value::Undefined(payload))?;
+            Ok(Chunk::new(
+                ChunkType::new(carrier.chunk_type()),
+                exif.to_bytes(),
+            ))
+        }
+        Carrier::Icc => {
+            let gray = matches!(color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha);
+            Chunk::iccp(ICC_PROFILE_NAME, &icc_profile(&payload, gray)?)
+        }
+    }
+}
+
+/// Reads the payload from a carrier chunk, `None` if the chunk doesn't hold one
+pub fn unwrap(carrier: Carrier, chunk: &Chunk) -> Result<Option<Vec<u8>>, Error> {
+    if chunk.chunk_type().bytes() != carrier.chunk_type() {
+        return Ok(None);
+    }
+
+    let payload = match carrier {
+        Carrier::Comment => {
+            let (keyword, text) = chunk.text()?;
+            if keyword != COMMENT_KEYWORD {
+                return Ok(None);
+            }
+            STANDARD.decode(text).ok()
+        }
+        Carrier::Xmp => {
+            let (keyword, text) = chunk.text()?;
+            if keyword != xmp::KEYWORD {
+                return Ok(None);
+            }
+
+            let xmp = match Xmp::parse(&String::from_utf8_lossy(&text)) {
+                Ok(xmp) => xmp,
+                Err(_) => return Ok(None),
+            };
+            let data = xmp.properties().iter().find(|property| {
+                property.namespace == XMP_NAMESPACE && property.name == XMP_PROPERTY
+            });
+
+            match data.map(|property| &property.value) {
+                Some(PropertyValue::Text(data)) => STANDARD.decode(data.trim()).ok(),
+                _ => None,
+            }
+        }
+        Carrier::Exif => maker_note(chunk.data()),
+        Carrier::Icc => {
+            let (_, profile) = chunk.icc_profile()?;
+            icc_private_tag(&profile)
+        }
+    };
+
+    Ok(payload.map(|payload| whiten(&payload)))
+}
+
+/// Hides the payload in a carrier of a PNG file. XMP and EXIF data go into the packet or
+/// the IFDs the file has already, a second one of those would give the payload away.
+pub fn embed(png: &Png, carrier: Carrier, payload: &[u8]) -> Result<Png, Error> {
+    let mut embedded = png.clone();
+
+    match carrier {
+        Carrier::Xmp => {
+            let mut xmp = Xmp::from_png(png)?.unwrap_or_default();
+            set_xmp_data(&mut xmp, &whiten(payload))?;
+            xmp.write_to(&mut embedded)?;
+        }
+        Carrier::Exif => {
+            let mut exif = Exif::from_png(png)?.unwrap_or_default();
+            // The maker note of the camera would be lost
+            if exif.get(Directory::Exif, MAKER_NOTE).is_some() {
+                return Err(CamouflageError::HasMakerNote.into());
+            }
+            exif.set(
+                Directory::Exif,
+                MAKER_NOTE,
+                Value::Undefined(whiten(payload)),
+            )?;
+            exif.write_to(&mut embedded)?;
+        }
+        Carrier::Icc => {
+            // A second profile would contradict the one that applies
+            if png.chunk_by_type("iCCP").is_some() || png.chunk_by_type("sRGB").is_some() {
+                return Err(CamouflageError::HasColorProfile.into());
+            }
+            let color_type = ImageHeader::from_png(png)?.color_type;
+            embedded.append_chunk(wrap(carrier, color_type, payload)?)?;
+        }
+        Carrier::Comment => {
+            let color_type = ImageHeader::from_png(png)?.color_type;
+            embedded.append_chunk(wrap(carrier, color_type, payload)?)?;
+        }
+    }
+
+    Ok(embedded)
+}
+
+/// Finds the first carrier chunk of a PNG file that holds a payload
+pub fn extract(png: &Png, carrier: Carrier) -> Result<Vec<u8>, Error> {
+    for chunk in png.chunks() {
+        if let Some(payload) = unwrap(carrier, chunk)? {
+            return Ok(payload);
+        }
+    }
+
+    Err(CamouflageError::NotFound(carrier).into())
+}
+
+/// Puts the payload in base64 in a property of our namespace
+fn set_xmp_data(xmp: &mut Xmp, payload: &[u8]) -> Result<(), Error> {
+    xmp.declare(XMP_PREFIX, XMP_NAMESPACE);
+    xmp.set(
+        &format!("{}:{}", XMP_PREFIX, XMP_PROPERTY),
+        PropertyValue::Text(STANDARD.encode(payload)),
+    )
+}
+
+/// The MakerNote of EXIF data, `None` if the data can't be read or has none
+fn maker_note(tiff: &[u8]) -> Option<Vec<u8>> {
+    match Exif::parse(tiff).ok()?.get(Directory::Exif, MAKER_NOTE)? {
+        Value::Undefined(bytes) => Some(bytes.clone()),
+        _ => None,
+    }
+}
+
+/// XORs the payload with a keystream of SHA-256 blocks, doing it twice gives the payload
+/// back
+fn whiten(payload: &[u8]) -> Vec<u8> {
+    payload
+        .chunks(32)
+        .enumerate()
+        .flat_map(|(index, block)| {
+            let key = Sha256::new()
+                .chain_update(WHITENING_KEY)
+                .chain_update((index as u64).to_be_bytes())
+                .finalize();
+            block
+                .iter()
+                .zip(key)
+                .map(|(byte, key)| byte ^ key)
+                .collect::<Vec<u8>>()
+        })
+        .collect()
+}
+
+/// A small ICC v2 monitor profile, gray or RGB with the sRGB primaries and a gamma of 2.2,
+/// with the payload in a private tag
+fn icc_profile(payload: &[u8], gray: bool) -> Result<Vec<u8>, Error> {
+    if payload.len() > u32::MAX as usize - 1024 {
+        return Err(CamouflageError::TooLarge.into());
+    }
+
+    let xyz = |value: &[u8; 12]| [&b"XYZ \0\0\0\0"[..], value].concat();
+
+    let description = if gray { "Gray 2.2" } else { "RGB 2.2" };
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend_from_slice(&(description.len() as u32 + 1).to_be_bytes());
+    desc.extend_from_slice(description.as_bytes());
+    // The null terminator, empty Unicode and ScriptCode descriptions
+    desc.extend(std::iter::repeat_n(0, 1 + 8 + 3 + 67));
+
+    let mut copyright = b"text\0\0\0\0No copyright, use freely".to_vec();
+    copyright.push(0);
+
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend_from_slice(&1u32.to_be_bytes());
+    curve.extend_from_slice(&GAMMA.to_be_bytes());
+
+    // Binary data type, the flag 1 marks it as binary
+    let mut private = b"data\0\0\0\0".to_vec();
+    private.extend_from_slice(&1u32.to_be_bytes());
+    private.extend_from_slice(payload);
+
+    let white_point = xyz(&D50);
+    let colorants: Vec<Vec<u8>> = PRIMARIES.iter().map(xyz).collect();
+
+    let mut tags: Vec<(&[u8; 4], &[u8])> =
+        vec![(b"desc", &desc), (b"cprt", &copyright), (b"wtpt", &white_point)];
+    if gray {
+        tags.push((b"kTRC", &curve));
+    } else {
+        tags.extend([
+            (b"rXYZ", &colorants[0][..]),
+            (b"gXYZ", &colorants[1][..]),
+            (b"bXYZ", &colorants[2][..]),
+            (b"rTRC", &curve[..]),
+            (b"gTRC", &curve[..]),
+            (b"bTRC", &curve[..]),
+        ]);
+    }
+    tags.push((&ICC_PRIVATE_TAG, &private));
+
+    // Tag data starts after the header, the tag count and the tag table
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut offset = 128 + 4 + tags.len() * 12;
+    for (signature, tag) in tags {
+        table.extend_from_slice(signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+
+        // Tags are aligned to 4 bytes
+        data.extend_from_slice(tag);
+        let padding = (4 - tag.len() % 4) % 4;
+        data.extend(std::iter::repeat_n(0, padding));
+        offset += tag.len() + padding;
+    }
+
+    let mut header = vec![0u8; 128];
+    header[0..4].copy_from_slice(&(offset as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(if gray { b"GRAY" } else { b"RGB " });
+    header[20..24].copy_from_slice(b"XYZ ");
+    for (index, value) in [2022u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        header[24 + index * 2..26 + index * 2].copy_from_slice(&value.to_be_bytes());
+    }
+    header[36..40].copy_from_slice(b"acsp");
+    header[68..80].copy_from_slice(&D50);
+
+    let mut profile = header;
+    profile.extend(table);
+    profile.extend(data);
+    Ok(profile)
+}
+
+/// Returns the data of the private tag of a profile
+fn icc_private_tag(profile: &[u8]) -> Option<Vec<u8>> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        let bytes = profile.get(offset..offset + 4)?.try_into().ok()?;
+        Some(u32::from_be_bytes(bytes) as usize)
+    };
+
+    let count = u32_at(128)?;
+    let entry = (0..count.min(1024))
+        .map(|index| 132 + index * 12)
+        .find(|&entry| profile.get(entry..entry + 4) == Some(&ICC_PRIVATE_TAG[..]))?;
+
+    let offset = u32_at(entry + 4)?;
+    let size = u32_at(entry + 8)?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+
+    match tag.get(0..4) {
+        Some(b"data") => Some(tag.get(12..)?.to_vec()),
+        _ => None,
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CamouflageError {
+    #[error("No {0} holds a payload.")]
+    NotFound(Carrier),
+
+    #[error("The payload is too large for the carrier.")]
+    TooLarge,
+
+    #[error("The image has a color profile or an sRGB chunk already, a second profile would contradict it.")]
+    HasColorProfile,
+
+    #[error("The EXIF data has a maker note already, it would be lost.")]
+    HasMakerNote,
+}
+
+#[cfg(test)]
+mod camouflage_tests {
+    use super::*;
+
+    const CARRIERS: [Carrier; 4] = [Carrier::Comment, Carrier::Xmp, Carrier::Exif, Carrier::Icc];
+
+    /// The testing image without its sRGB chunk, so every carrier fits
+    fn testing_png() -> Png {
+        let mut png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        png.remove_chunk("sRGB").unwrap();
+        png
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap() {
+        for carrier in CARRIERS {
+            for payload in [
+                &b""[..],
+                b"abc",
+                b"a longer payload \0 with binary \xff bytes",
+            ] {
+                let chunk = wrap(carrier, ColorType::Rgb, payload).unwrap();
+                assert_eq!(chunk.chunk_type().bytes(), carrier.chunk_type());
+                assert_eq!(unwrap(carrier, &chunk).unwrap().unwrap(), payload);
+            }
+        }
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let png = testing_png();
+
+        for carrier in CARRIERS {
+            let embedded = embed(&png, carrier, b"hidden in plain sight").unwrap();
+            assert_eq!(
+                extract(&embedded, carrier).unwrap(),
+                b"hidden in plain sight"
+            );
+            assert!(extract(&png, carrier).is_err());
+        }
+    }
+
+    #[test]
+    fn test_merges_into_existing_xmp() {
+        let mut png = testing_png();
+        let mut xmp = Xmp::new();
+        xmp.set("xmp:Label", PropertyValue::Text("Sunset".to_string()))
+            .unwrap();
+        xmp.write_to(&mut png).unwrap();
+
+        let embedded = embed(&png, Carrier::Xmp, b"merged").unwrap();
+        let packets = embedded
+            .chunks()
+            .iter()
+            .filter(|chunk| matches!(chunk.text(), Ok((keyword, _)) if keyword == xmp::KEYWORD))
+            .count();
+        assert_eq!(packets, 1);
+        assert_eq!(extract(&embedded, Carrier::Xmp).unwrap(), b"merged");
+
+        let merged = Xmp::from_png(&embedded).unwrap().unwrap();
+        assert_eq!(
+            merged.get("xmp:Label").unwrap(),
+            Some(&PropertyValue::Text("Sunset".to_string()))
+        );
+
+        // Embedding again replaces the payload
+        let again = embed(&embedded, Carrier::Xmp, b"again").unwrap();
+        assert_eq!(extract(&again, Carrier::Xmp).unwrap(), b"again");
+    }
+
+    #[test]
+    fn test_merges_into_existing_exif() {
+        let mut png = testing_png();
+        let mut exif = Exif::new();
+        exif.set(Directory::Image, 0x010F, Value::Ascii("Camera".to_string()))
+            .unwrap();
+        exif.write_to(&mut png).unwrap();
+
+        let embedded = embed(&png, Carrier::Exif, b"merged").unwrap();
+        let chunks = embedded
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type().to_string() == "eXIf")
+            .count();
+        assert_eq!(chunks, 1);
+        assert_eq!(extract(&embedded, Carrier::Exif).unwrap(), b"merged");
+
+        let merged = Exif::from_png(&embedded).unwrap().unwrap();
+        assert_eq!(
+            merged.get(Directory::Image, 0x010F),
+            Some(&Value::Ascii("Camera".to_string()))
+        );
+
+        // A maker note of the camera isn't overwritten
+        assert!(embed(&embedded, Carrier::Exif, b"again").is_err());
+    }
+
+    #[test]
+    fn test_payload_is_whitened() {
+        use crate::envelope::{self, Metadata, SealOptions};
+
+        let metadata = Metadata::new("text/plain", None);
+        let sealed = envelope::seal(b"hidden", &metadata, &SealOptions::default()).unwrap();
+
+        let exif = wrap(Carrier::Exif, ColorType::Rgb, &sealed).unwrap();
+        let (_, profile) = wrap(Carrier::Icc, ColorType::Rgb, &sealed)
+            .unwrap()
+            .icc_profile()
+            .unwrap();
+        let (_, comment) = wrap(Carrier::Comment, ColorType::Rgb, &sealed)
+            .unwrap()
+            .text()
+            .unwrap();
+
+        let contains = |data: &[u8], needle: &[u8]| {
+            data.windows(needle.len()).any(|window| window == needle)
+        };
+        for data in [exif.data(), &profile[..]] {
+            assert!(!contains(data, b"SSPM"));
+            assert!(!contains(data, b"text/plain"));
+        }
+        assert!(!contains(&comment, STANDARD.encode(b"SSPM").trim_end_matches('=').as_bytes()));
+    }
+
+    #[test]
+    fn test_icc_profile_fits_the_image() {
+        use crate::color::ColorState;
+
+        let png = testing_png();
+        let header = ImageHeader::from_png(&png).unwrap();
+        let embedded = embed(&png, Carrier::Icc, b"profile").unwrap();
+        let state = ColorState::from_png(&embedded).unwrap();
+        assert!(state.warnings(Some(&header)).is_empty());
+
+        for color_type in [ColorType::Grayscale, ColorType::GrayscaleAlpha] {
+            let (_, profile) = wrap(Carrier::Icc, color_type, b"gray")
+                .unwrap()
+                .icc_profile()
+                .unwrap();
+            assert_eq!(&profile[16..20], b"GRAY");
+        }
+
+        // A second profile is refused
+        assert!(embed(&embedded, Carrier::Icc, b"again").is_err());
+        let srgb = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        assert!(embed(&srgb, Carrier::Icc, b"srgb").is_err());
+    }
+
+    #[test]
+    fn test_ignores_other_chunks() {
+        let comment = Chunk::ztxt("Author", b"aGVsbG8=").unwrap();
+        assert_eq!(unwrap(Carrier::Comment, &comment).unwrap(), None);
+
+        let xmp = Chunk::itxt(xmp::KEYWORD, "<x:xmpmeta/>").unwrap();
+        assert_eq!(unwrap(Carrier::Xmp, &xmp).unwrap(), None);
+        assert_eq!(unwrap(Carrier::Exif, &xmp).unwrap(), None);
+    }
+
+    #[test]
+    fn test_little_endian_maker_note() {
+        #[rustfmt::skip]
+        let tiff = [
+            b'I', b'I', 0x2a, 0, 8, 0, 0, 0,
+            // IFD0 with the EXIF IFD pointer
+            1, 0, 0x69, 0x87, 4, 0, 1, 0, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0,
+            // EXIF IFD with a 3 byte MakerNote in the entry
+            1, 0, 0x7c, 0x92, 7, 0, 3, 0, 0, 0, b'x', b'y', b'z', 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(maker_note(&tiff).unwrap(), b"xyz");
+    }
+}
@@ -7,9 +7,9 @@ use crc::{Crc, CRC_32_ISO_HDLC};
 
 const CHECKSUM_ALG: Crc::<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
-use crate::{chunk_type::ChunkType, Error};
+use crate::{chunk_type::ChunkType, decoder, encoder, Error};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
     pub length: u32,
     pub chunk_type: ChunkType,
@@ -69,6 +69,110 @@ impl Chunk {
         Ok(string)
     }
 
+    /// A `zTXt` chunk: a keyword and compressed Latin-1 text
+    pub fn ztxt(keyword: &str, text: &[u8]) -> Result<Chunk, Error> {
+        let mut data = keyword_bytes(keyword)?;
+
+        // Compression method 0 is the only one defined
+        data.push(0);
+        data.extend(encoder::deflate(text, 9)?);
+
+        Ok(Chunk::new(ChunkType::new(*b"zTXt"), data))
+    }
+
+    /// An uncompressed `iTXt` chunk without a language tag: a keyword and UTF-8 text
+    pub fn itxt(keyword: &str, text: &str) -> Result<Chunk, Error> {
+        let mut data = keyword_bytes(keyword)?;
+
+        // Compression flag and method, then empty language tag and translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+
+        Ok(Chunk::new(ChunkType::new(*b"iTXt"), data))
+    }
+
+    /// An `iCCP` chunk: a profile name and a compressed ICC profile
+    pub fn iccp(name: &str, profile: &[u8]) -> Result<Chunk, Error> {
+        let mut data = keyword_bytes(name)?;
+
+        data.push(0);
+        data.extend(encoder::deflate(profile, 9)?);
+
+        Ok(Chunk::new(ChunkType::new(*b"iCCP"), data))
+    }
+
+    /// Reads the keyword and text of a `tEXt`, `zTXt` or `iTXt` chunk, decompressing the
+    /// text if needed
+    pub fn text(&self) -> Result<(String, Vec<u8>), Error> {
+        let (keyword, rest) = self.split_keyword()?;
+
+        let text = match &self.chunk_type.bytes() {
+            b"tEXt" => rest.to_vec(),
+            b"zTXt" => match rest.split_first() {
+                Some((0, compressed)) => decoder::inflate(compressed)?,
+                _ => return Err(ChunkError::InvalidTextChunk.into()),
+            },
+            b"iTXt" => {
+                let (&compressed, rest) = rest.split_first().ok_or(ChunkError::InvalidTextChunk)?;
+                let rest = rest.get(1..).ok_or(ChunkError::InvalidTextChunk)?;
+
+                // Skip the language tag and the translated keyword
+                let mut fields = rest.splitn(3, |&byte| byte == 0);
+                let text = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(_), Some(_), Some(text)) => text,
+                    _ => return Err(ChunkError::InvalidTextChunk.into()),
+                };
+
+                match compressed {
+                    0 => text.to_vec(),
+                    _ => decoder::inflate(text)?,
+                }
+            }
+            _ => return Err(ChunkError::InvalidTextChunk.into()),
+        };
+
+        Ok((keyword, text))
+    }
+
+    /// Reads the profile name and the decompressed profile of an `iCCP` chunk
+    pub fn icc_profile(&self) -> Result<(String, Vec<u8>), Error> {
+        if &self.chunk_type.bytes() != b"iCCP" {
+            return Err(ChunkError::InvalidTextChunk.into());
+        }
+
+        let (name, rest) = self.split_keyword()?;
+        match rest.split_first() {
+            Some((0, compressed)) => Ok((name, decoder::inflate(compressed)?)),
+            _ => Err(ChunkError::InvalidTextChunk.into()),
+        }
+    }
+
+    /// Checks that the chunk has the given type and data length, typed chunks use this
+    /// before reading their fields
+    pub fn expect(&self, chunk_type: &[u8; 4], length: usize) -> Result<(), Error> {
+        let name = ChunkType::new(*chunk_type).to_string();
+
+        if &self.chunk_type.bytes() != chunk_type {
+            return Err(ChunkError::UnexpectedType(name).into());
+        }
+        if self.data.len() != length {
+            return Err(ChunkError::UnexpectedLength(name, length).into());
+        }
+
+        Ok(())
+    }
+
+    /// Splits the data at the null byte that ends the keyword, keywords are Latin-1
+    fn split_keyword(&self) -> Result<(String, &[u8]), Error> {
+        match self.data.iter().position(|&byte| byte == 0) {
+            Some(end) => Ok((
+                self.data[..end].iter().map(|&byte| byte as char).collect(),
+                &self.data[end + 1..],
+            )),
+            None => Err(ChunkError::InvalidTextChunk.into()),
+        }
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         // Merge all of the data into a single byte slice
         self.length()
@@ -82,11 +186,10 @@ impl Chunk {
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
-
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-
+impl Chunk {
+    /// Reads a chunk like `try_from`, but keeps it when its CRC doesn't match and tells
+    /// whether it does. Damaged payloads can still be corrected this way.
+    pub fn try_from_lenient(value: &[u8]) -> Result<(Chunk, bool), Error> {
         let mut cursor = Cursor::new(value);
         
         // Read the first 4 bytes (length of a u32) to length
@@ -117,17 +220,49 @@ impl TryFrom<&[u8]> for Chunk {
             .collect();
 
 
-        if crc != CHECKSUM_ALG.checksum(&datae) {
-            return Err(ChunkError::InvalidCRC.into())
+        let valid = crc == CHECKSUM_ALG.checksum(&datae);
+
+        Ok((
+            Chunk {
+                length,
+                chunk_type,
+                data,
+                crc,
+            },
+            valid,
+        ))
+    }
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match Chunk::try_from_lenient(value)? {
+            (chunk, true) => Ok(chunk),
+            (_, false) => Err(ChunkError::InvalidCRC.into()),
         }
+    }
+}
 
-        Ok(Chunk {
-            length,
-            chunk_type,
-            data,
-            crc,
-        })
+/// Checks a keyword and returns it with its null separator. Keywords are 1 to 79
+/// printable Latin-1 characters without leading, trailing or double spaces.
+fn keyword_bytes(keyword: &str) -> Result<Vec<u8>, Error> {
+    let valid = (1..=79).contains(&keyword.chars().count())
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ")
+        && keyword
+            .chars()
+            .all(|character| matches!(character as u32, 32..=126 | 161..=255));
+
+    if !valid {
+        return Err(ChunkError::InvalidKeyword(keyword.to_string()).into());
     }
+
+    let mut bytes: Vec<u8> = keyword.chars().map(|character| character as u8).collect();
+    bytes.push(0);
+    Ok(bytes)
 }
 
 impl Display for Chunk {
@@ -139,7 +274,19 @@ impl Display for Chunk {
 #[derive(thiserror::Error, Debug)]
 pub enum ChunkError {
     #[error("The crc does not match the generated CRC, the data may be corrupted.")]
-    InvalidCRC
+    InvalidCRC,
+
+    #[error("'{0}' is not a valid keyword, it must be 1 to 79 printable Latin-1 characters.")]
+    InvalidKeyword(String),
+
+    #[error("The chunk is not a valid text or profile chunk.")]
+    InvalidTextChunk,
+
+    #[error("The chunk is not a {0} chunk.")]
+    UnexpectedType(String),
+
+    #[error("The {0} chunk must be {1} bytes long.")]
+    UnexpectedLength(String, usize),
 }
 
 
@@ -273,4 +420,34 @@ mod chunk_tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    pub fn test_text_chunks() {
+        let ztxt = Chunk::ztxt("Comment", b"compressed text").unwrap();
+        assert_eq!(&ztxt.chunk_type().to_string(), "zTXt");
+        assert_eq!(
+            ztxt.text().unwrap(),
+            ("Comment".to_string(), b"compressed text".to_vec())
+        );
+
+        let itxt = Chunk::itxt("Title", "ünïcode").unwrap();
+        assert_eq!(
+            itxt.text().unwrap(),
+            ("Title".to_string(), "ünïcode".as_bytes().to_vec())
+        );
+
+        let iccp = Chunk::iccp("ICC Profile", &[1, 2, 3]).unwrap();
+        assert_eq!(
+            iccp.icc_profile().unwrap(),
+            ("ICC Profile".to_string(), vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    pub fn test_invalid_keyword() {
+        assert!(Chunk::ztxt("", b"text").is_err());
+        assert!(Chunk::ztxt(" Comment", b"text").is_err());
+        assert!(Chunk::itxt("Two  spaces", "text").is_err());
+        assert!(Chunk::itxt(&"k".repeat(80), "text").is_err());
+    }
 }
@@ -3,7 +3,14 @@ use std::str::FromStr;
 
 use crate::Error;
 
-#[derive(Debug, Eq, PartialEq)]
+/// Chunk types defined by the PNG specification (including the APNG and PNG 3rd edition chunks)
+pub const STANDARD_CHUNK_TYPES: [&[u8; 4]; 30] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP",
+    b"mDCV", b"cLLI", b"bKGD", b"hIST", b"tRNS", b"eXIf", b"pHYs", b"sPLT", b"tIME", b"iTXt",
+    b"tEXt", b"zTXt", b"oFFs", b"pCAL", b"sCAL", b"gIFg", b"gIFx", b"acTL", b"fcTL", b"fdAT",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ChunkType {
     pub chunk_type: [u8; 4],
 }
@@ -32,6 +39,18 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         self.bytes()[3].is_ascii_lowercase()
     }
+
+    /// Whether the chunk type is defined by the PNG specification
+    pub fn is_standard(&self) -> bool {
+        STANDARD_CHUNK_TYPES.contains(&&self.chunk_type)
+    }
+
+    /// Whether a chunk of this type may be kept after IDAT or PLTE have been modified.
+    /// Unknown ancillary chunks that are unsafe to copy must be dropped, unless they
+    /// are listed in `keep`.
+    pub fn survives_critical_edit(&self, keep: &[ChunkType]) -> bool {
+        self.is_critical() || self.is_safe_to_copy() || self.is_standard() || keep.contains(self)
+    }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
@@ -166,6 +185,23 @@ mod chunk_type_tests {
         assert!(!chunk.is_safe_to_copy());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("tRNS").unwrap().is_standard());
+        assert!(!ChunkType::from_str("ruST").unwrap().is_standard());
+    }
+
+    #[test]
+    pub fn test_chunk_type_survives_critical_edit() {
+        let keep = [ChunkType::from_str("ruST").unwrap()];
+
+        assert!(ChunkType::from_str("ruSt").unwrap().survives_critical_edit(&[]));
+        assert!(ChunkType::from_str("tRNS").unwrap().survives_critical_edit(&[]));
+        assert!(ChunkType::from_str("RuST").unwrap().survives_critical_edit(&[]));
+        assert!(!ChunkType::from_str("ruST").unwrap().survives_critical_edit(&[]));
+        assert!(ChunkType::from_str("ruST").unwrap().survives_critical_edit(&keep));
+    }
+
     #[test]
     pub fn test_valid_chunk_is_valid() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -0,0 +1,877 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    Error,
+};
+
+// The chunks that tell how the samples of an image map to colors. When several are present
+// the most specific one wins:
+//
+//   cICP        coding-independent code points (PNG 3rd edition), over everything else
+//   iCCP        an embedded ICC profile
+//   sRGB        the image is in sRGB, over gAMA and cHRM
+//   gAMA, cHRM  the gamma and the chromaticities of the primaries
+//
+// mDCV and cLLI describe the mastering display and the light level of HDR content and only
+// mean something alongside cICP.
+
+/// The chunk types of the color chunks
+pub const CHUNK_TYPES: [&str; 7] = ["cICP", "iCCP", "sRGB", "gAMA", "cHRM", "mDCV", "cLLI"];
+
+/// The gAMA value sRGB implies, 1 / 2.2 times 100000
+const SRGB_GAMMA: u32 = 45455;
+
+/// The cHRM values sRGB implies: white point, red, green and blue, times 100000
+const SRGB_CHROMATICITIES: Chromaticities = Chromaticities {
+    white: (31270, 32900),
+    red: (64000, 33000),
+    green: (30000, 60000),
+    blue: (15000, 6000),
+};
+
+/// The `gAMA` chunk: the gamma of the image times 100000
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Gamma(pub u32);
+
+/// The `cHRM` chunk: x and y of the white point and the primaries, times 100000
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+/// The `sRGB` chunk: the image is in sRGB, meant to be shown with this intent
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+/// The `iCCP` chunk: a named ICC profile, stored compressed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IccProfile {
+    pub name: String,
+    pub profile: Vec<u8>,
+}
+
+/// The parts of the 128 byte ICC profile header worth showing
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IccHeader {
+    pub size: u32,
+    pub version: (u8, u8),
+    pub class: String,
+    pub color_space: String,
+    pub connection_space: String,
+    pub intent: u32,
+}
+
+/// The `cICP` chunk: code points from ITU-T H.273
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CodePoints {
+    pub primaries: u8,
+    pub transfer: u8,
+    /// Always 0 in PNG, the samples are RGB
+    pub matrix: u8,
+    pub full_range: bool,
+}
+
+/// The `mDCV` chunk: the display the content was mastered on. Chromaticities are in units
+/// of 0.00002, luminances of 0.0001 cd/m².
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MasteringDisplay {
+    pub red: (u16, u16),
+    pub green: (u16, u16),
+    pub blue: (u16, u16),
+    pub white: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+/// The `cLLI` chunk: the light level of the content in units of 0.0001 cd/m²
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ContentLightLevel {
+    pub max_content: u32,
+    pub max_frame_average: u32,
+}
+
+/// Every color chunk of an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColorState {
+    pub gamma: Option<Gamma>,
+    pub chromaticities: Option<Chromaticities>,
+    pub srgb: Option<RenderingIntent>,
+    pub icc: Option<IccProfile>,
+    pub code_points: Option<CodePoints>,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub light_level: Option<ContentLightLevel>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+impl TryFrom<&Chunk> for Gamma {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"gAMA", 4)?;
+
+        match u32_at(chunk.data(), 0) {
+            0 => Err(ColorError::ZeroGamma.into()),
+            gamma => Ok(Gamma(gamma)),
+        }
+    }
+}
+
+impl Gamma {
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::new(*b"gAMA"), self.0.to_be_bytes().to_vec())
+    }
+}
+
+impl TryFrom<&Chunk> for Chromaticities {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"cHRM", 32)?;
+
+        let data = chunk.data();
+        let point = |index: usize| (u32_at(data, index * 8), u32_at(data, index * 8 + 4));
+
+        Ok(Chromaticities {
+            white: point(0),
+            red: point(1),
+            green: point(2),
+            blue: point(3),
+        })
+    }
+}
+
+impl Chromaticities {
+    pub fn to_chunk(self) -> Chunk {
+        let data = [self.white, self.red, self.green, self.blue]
+            .iter()
+            .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+            .collect();
+
+        Chunk::new(ChunkType::new(*b"cHRM"), data)
+    }
+
+    /// Whether every value is within 0.001 of the other
+    fn close_to(&self, other: &Chromaticities) -> bool {
+        let pairs = [
+            (self.white, other.white),
+            (self.red, other.red),
+            (self.green, other.green),
+            (self.blue, other.blue),
+        ];
+
+        pairs.iter().all(|((x, y), (other_x, other_y))| {
+            x.abs_diff(*other_x) <= 100 && y.abs_diff(*other_y) <= 100
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for RenderingIntent {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"sRGB", 1)?;
+
+        match chunk.data()[0] {
+            0 => Ok(RenderingIntent::Perceptual),
+            1 => Ok(RenderingIntent::RelativeColorimetric),
+            2 => Ok(RenderingIntent::Saturation),
+            3 => Ok(RenderingIntent::AbsoluteColorimetric),
+            intent => Err(ColorError::InvalidIntent(intent).into()),
+        }
+    }
+}
+
+impl RenderingIntent {
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::new(*b"sRGB"), vec![self as u8])
+    }
+}
+
+impl TryFrom<&Chunk> for IccProfile {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let (name, profile) = chunk.icc_profile()?;
+        let icc = IccProfile { name, profile };
+
+        // Fails on a profile too short for its header
+        icc.header()?;
+
+        Ok(icc)
+    }
+}
+
+impl IccProfile {
+    pub fn to_chunk(&self) -> Result<Chunk, Error> {
+        Chunk::iccp(&self.name, &self.profile)
+    }
+
+    pub fn header(&self) -> Result<IccHeader, Error> {
+        let profile = &self.profile;
+        if profile.len() < 128 || &profile[36..40] != b"acsp" {
+            return Err(ColorError::InvalidProfile.into());
+        }
+
+        let signature = |offset: usize| {
+            String::from_utf8_lossy(&profile[offset..offset + 4])
+                .trim_end()
+                .to_string()
+        };
+
+        Ok(IccHeader {
+            size: u32_at(profile, 0),
+            // The minor version and the bug fix level share a byte
+            version: (profile[8], profile[9] >> 4),
+            class: signature(12),
+            color_space: signature(16),
+            connection_space: signature(20),
+            intent: u32_at(profile, 64),
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for CodePoints {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"cICP", 4)?;
+
+        let data = chunk.data();
+        if data[2] != 0 {
+            return Err(ColorError::InvalidMatrix(data[2]).into());
+        }
+
+        let full_range = match data[3] {
+            0 => false,
+            1 => true,
+            flag => return Err(ColorError::InvalidRangeFlag(flag).into()),
+        };
+
+        Ok(CodePoints {
+            primaries: data[0],
+            transfer: data[1],
+            matrix: data[2],
+            full_range,
+        })
+    }
+}
+
+impl CodePoints {
+    pub fn to_chunk(self) -> Chunk {
+        let data = vec![
+            self.primaries,
+            self.transfer,
+            self.matrix,
+            self.full_range as u8,
+        ];
+        Chunk::new(ChunkType::new(*b"cICP"), data)
+    }
+
+    /// The name of the transfer function, for the common ones
+    pub fn transfer_name(&self) -> Option<&'static str> {
+        match self.transfer {
+            1 | 6 | 14 | 15 => Some("BT.709"),
+            8 => Some("linear"),
+            13 => Some("sRGB"),
+            16 => Some("PQ"),
+            18 => Some("HLG"),
+            _ => None,
+        }
+    }
+
+    /// The name of the primaries, for the common ones
+    pub fn primaries_name(&self) -> Option<&'static str> {
+        match self.primaries {
+            1 => Some("BT.709"),
+            9 => Some("BT.2020"),
+            11 => Some("DCI-P3"),
+            12 => Some("Display P3"),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for MasteringDisplay {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"mDCV", 24)?;
+
+        let data = chunk.data();
+        let point = |offset: usize| (u16_at(data, offset), u16_at(data, offset + 2));
+
+        let display = MasteringDisplay {
+            red: point(0),
+            green: point(4),
+            blue: point(8),
+            white: point(12),
+            max_luminance: u32_at(data, 16),
+            min_luminance: u32_at(data, 20),
+        };
+
+        if display.min_luminance >= display.max_luminance {
+            return Err(ColorError::InvalidLuminance.into());
+        }
+
+        Ok(display)
+    }
+}
+
+impl MasteringDisplay {
+    pub fn to_chunk(self) -> Chunk {
+        let mut data: Vec<u8> = [self.red, self.green, self.blue, self.white]
+            .iter()
+            .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+            .collect();
+        data.extend_from_slice(&self.max_luminance.to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.to_be_bytes());
+
+        Chunk::new(ChunkType::new(*b"mDCV"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for ContentLightLevel {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"cLLI", 8)?;
+
+        let data = chunk.data();
+        Ok(ContentLightLevel {
+            max_content: u32_at(data, 0),
+            max_frame_average: u32_at(data, 4),
+        })
+    }
+}
+
+impl ContentLightLevel {
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.max_content.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.max_frame_average.to_be_bytes());
+
+        Chunk::new(ChunkType::new(*b"cLLI"), data)
+    }
+}
+
+#[allow(dead_code)]
+impl ColorState {
+    /// Reads the color chunks of a PNG file, the first chunk of each type counts
+    pub fn from_png(png: &Png) -> Result<ColorState, Error> {
+        Ok(ColorState {
+            gamma: png.typed_chunk("gAMA")?,
+            chromaticities: png.typed_chunk("cHRM")?,
+            srgb: png.typed_chunk("sRGB")?,
+            icc: png.typed_chunk("iCCP")?,
+            code_points: png.typed_chunk("cICP")?,
+            mastering_display: png.typed_chunk("mDCV")?,
+            light_level: png.typed_chunk("cLLI")?,
+        })
+    }
+
+    /// Reads the color chunks of a PNG file like `from_png`, leaving out the ones that are
+    /// malformed
+    pub fn from_png_lenient(png: &Png) -> ColorState {
+        ColorState {
+            gamma: png.typed_chunk("gAMA").ok().flatten(),
+            chromaticities: png.typed_chunk("cHRM").ok().flatten(),
+            srgb: png.typed_chunk("sRGB").ok().flatten(),
+            icc: png.typed_chunk("iCCP").ok().flatten(),
+            code_points: png.typed_chunk("cICP").ok().flatten(),
+            mastering_display: png.typed_chunk("mDCV").ok().flatten(),
+            light_level: png.typed_chunk("cLLI").ok().flatten(),
+        }
+    }
+
+    /// The state without the chunks `warnings` finds fault with. Next to an ICC profile the
+    /// sRGB chunk goes, unless the profile is for the wrong color space and goes itself.
+    /// gAMA and cHRM that agree with sRGB stay for decoders that don't know it, the others
+    /// go, and so do mDCV and cLLI without cICP.
+    pub fn consistent(&self, header: Option<&ImageHeader>) -> ColorState {
+        let warnings = self.warnings(header);
+        let wrong_profile = warnings
+            .iter()
+            .any(|warning| matches!(warning, ColorWarning::ProfileColorSpace(..)));
+
+        let mut state = self.clone();
+        for warning in warnings {
+            match warning {
+                ColorWarning::IccWithSrgb if !wrong_profile => state.srgb = None,
+                ColorWarning::IccWithSrgb => {}
+                ColorWarning::GammaDisagreesWithSrgb(_) => state.gamma = None,
+                ColorWarning::ChromaticitiesDisagreeWithSrgb => state.chromaticities = None,
+                ColorWarning::ProfileColorSpace(..) => state.icc = None,
+                ColorWarning::HdrWithoutCodePoints => {
+                    state.mastering_display = None;
+                    state.light_level = None;
+                }
+                ColorWarning::FrameAverageAboveMaximum => state.light_level = None,
+            }
+        }
+        state
+    }
+
+    /// The chunks of the state, in the order the specification lists them
+    pub fn to_chunks(&self) -> Result<Vec<Chunk>, Error> {
+        let mut chunks = Vec::new();
+
+        if let Some(code_points) = self.code_points {
+            chunks.push(code_points.to_chunk());
+        }
+        if let Some(icc) = &self.icc {
+            chunks.push(icc.to_chunk()?);
+        }
+        if let Some(intent) = self.srgb {
+            chunks.push(intent.to_chunk());
+        }
+        if let Some(gamma) = self.gamma {
+            chunks.push(gamma.to_chunk());
+        }
+        if let Some(chromaticities) = self.chromaticities {
+            chunks.push(chromaticities.to_chunk());
+        }
+        if let Some(display) = self.mastering_display {
+            chunks.push(display.to_chunk());
+        }
+        if let Some(light_level) = self.light_level {
+            chunks.push(light_level.to_chunk());
+        }
+
+        Ok(chunks)
+    }
+
+    /// The chunk type whose color description applies, `None` if the image has none
+    pub fn effective(&self) -> Option<&'static str> {
+        if self.code_points.is_some() {
+            Some("cICP")
+        } else if self.icc.is_some() {
+            Some("iCCP")
+        } else if self.srgb.is_some() {
+            Some("sRGB")
+        } else if self.gamma.is_some() || self.chromaticities.is_some() {
+            Some("gAMA/cHRM")
+        } else {
+            None
+        }
+    }
+
+    /// The chunk types that are present but overridden by a more specific one. Decoders
+    /// that don't know the winner still use them, so they are worth keeping.
+    pub fn superseded(&self) -> Vec<&'static str> {
+        let present = [
+            ("iCCP", self.icc.is_some()),
+            ("sRGB", self.srgb.is_some()),
+            ("gAMA", self.gamma.is_some()),
+            ("cHRM", self.chromaticities.is_some()),
+        ];
+
+        // Everything after the winner in the list above is overridden
+        let skip = match self.effective() {
+            Some("cICP") => 0,
+            Some("iCCP") => 1,
+            Some("sRGB") => 2,
+            _ => return Vec::new(),
+        };
+
+        present
+            .iter()
+            .skip(skip)
+            .filter(|(_, present)| *present)
+            .map(|(chunk_type, _)| *chunk_type)
+            .collect()
+    }
+
+    /// Checks that the chunks agree with each other and with the image
+    pub fn warnings(&self, header: Option<&ImageHeader>) -> Vec<ColorWarning> {
+        let mut warnings = Vec::new();
+
+        if self.icc.is_some() && self.srgb.is_some() {
+            warnings.push(ColorWarning::IccWithSrgb);
+        }
+
+        if self.srgb.is_some() {
+            if let Some(Gamma(gamma)) = self.gamma {
+                if gamma.abs_diff(SRGB_GAMMA) > 1000 {
+                    warnings.push(ColorWarning::GammaDisagreesWithSrgb(gamma));
+                }
+            }
+            if let Some(chromaticities) = self.chromaticities {
+                if !chromaticities.close_to(&SRGB_CHROMATICITIES) {
+                    warnings.push(ColorWarning::ChromaticitiesDisagreeWithSrgb);
+                }
+            }
+        }
+
+        if let (Some(icc), Some(header)) = (&self.icc, header) {
+            if let Ok(icc_header) = icc.header() {
+                let expected = match header.color_type {
+                    ColorType::Grayscale | ColorType::GrayscaleAlpha => "GRAY",
+                    _ => "RGB",
+                };
+                if icc_header.color_space != expected {
+                    warnings.push(ColorWarning::ProfileColorSpace(
+                        icc_header.color_space,
+                        expected,
+                    ));
+                }
+            }
+        }
+
+        if self.code_points.is_none()
+            && (self.mastering_display.is_some() || self.light_level.is_some())
+        {
+            warnings.push(ColorWarning::HdrWithoutCodePoints);
+        }
+
+        if let Some(light_level) = self.light_level {
+            if light_level.max_frame_average > light_level.max_content {
+                warnings.push(ColorWarning::FrameAverageAboveMaximum);
+            }
+        }
+
+        warnings
+    }
+}
+
+impl std::fmt::Display for Gamma {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gamma {:.5} (display gamma {:.2})",
+            self.0 as f64 / 100000.0,
+            100000.0 / self.0 as f64
+        )
+    }
+}
+
+impl std::fmt::Display for Chromaticities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let point =
+            |(x, y): (u32, u32)| format!("{:.4}, {:.4}", x as f64 / 100000.0, y as f64 / 100000.0);
+        write!(
+            f,
+            "white {}, red {}, green {}, blue {}",
+            point(self.white),
+            point(self.red),
+            point(self.green),
+            point(self.blue)
+        )
+    }
+}
+
+impl std::fmt::Display for IccHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "version {}.{}, class '{}', {} to {}, {} bytes",
+            self.version.0,
+            self.version.1,
+            self.class,
+            self.color_space,
+            self.connection_space,
+            self.size
+        )
+    }
+}
+
+impl std::fmt::Display for CodePoints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = |name: Option<&str>, code: u8| match name {
+            Some(name) => format!("{} ({})", name, code),
+            None => code.to_string(),
+        };
+        write!(
+            f,
+            "primaries {}, transfer {}, {} range",
+            name(self.primaries_name(), self.primaries),
+            name(self.transfer_name(), self.transfer),
+            if self.full_range { "full" } else { "narrow" }
+        )
+    }
+}
+
+impl std::fmt::Display for MasteringDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let point =
+            |(x, y): (u16, u16)| format!("{:.4}, {:.4}", x as f64 * 0.00002, y as f64 * 0.00002);
+        write!(
+            f,
+            "red {}, green {}, blue {}, white {}, {:.4} to {:.4} cd/m²",
+            point(self.red),
+            point(self.green),
+            point(self.blue),
+            point(self.white),
+            self.min_luminance as f64 / 10000.0,
+            self.max_luminance as f64 / 10000.0
+        )
+    }
+}
+
+impl std::fmt::Display for ContentLightLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "maximum {:.4} cd/m², frame average {:.4} cd/m²",
+            self.max_content as f64 / 10000.0,
+            self.max_frame_average as f64 / 10000.0
+        )
+    }
+}
+
+/// Color chunks that are valid on their own but don't fit together
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ColorWarning {
+    #[error("There is both an iCCP and an sRGB chunk, only one of them should be present.")]
+    IccWithSrgb,
+
+    #[error("The gAMA chunk ({0}) disagrees with sRGB, which overrides it.")]
+    GammaDisagreesWithSrgb(u32),
+
+    #[error("The cHRM chunk disagrees with sRGB, which overrides it.")]
+    ChromaticitiesDisagreeWithSrgb,
+
+    #[error("The ICC profile is for '{0}' data but the image is {1}.")]
+    ProfileColorSpace(String, &'static str),
+
+    #[error("mDCV or cLLI without a cICP chunk has no effect.")]
+    HdrWithoutCodePoints,
+
+    #[error("The cLLI frame average is above the maximum light level.")]
+    FrameAverageAboveMaximum,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ColorError {
+    #[error("A gamma of 0 is not allowed.")]
+    ZeroGamma,
+
+    #[error("Invalid rendering intent {0}.")]
+    InvalidIntent(u8),
+
+    #[error("The ICC profile has no valid header.")]
+    InvalidProfile,
+
+    #[error("The cICP matrix coefficients must be 0 in PNG, not {0}.")]
+    InvalidMatrix(u8),
+
+    #[error("Invalid cICP range flag {0}.")]
+    InvalidRangeFlag(u8),
+
+    #[error("The mDCV minimum luminance must be below the maximum.")]
+    InvalidLuminance,
+}
+
+#[cfg(test)]
+mod color_tests {
+    use super::*;
+
+    fn testing_profile(color_space: &[u8; 4]) -> Vec<u8> {
+        let mut profile = vec![0u8; 132];
+        profile[0..4].copy_from_slice(&132u32.to_be_bytes());
+        profile[8] = 4;
+        profile[9] = 0x30;
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(color_space);
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile
+    }
+
+    fn testing_state() -> ColorState {
+        ColorState {
+            gamma: Some(Gamma(SRGB_GAMMA)),
+            chromaticities: Some(SRGB_CHROMATICITIES),
+            srgb: Some(RenderingIntent::Perceptual),
+            icc: Some(IccProfile {
+                name: "Display".to_string(),
+                profile: testing_profile(b"RGB "),
+            }),
+            code_points: Some(CodePoints {
+                primaries: 9,
+                transfer: 16,
+                matrix: 0,
+                full_range: true,
+            }),
+            mastering_display: Some(MasteringDisplay {
+                red: (35400, 14600),
+                green: (8500, 39850),
+                blue: (6550, 2300),
+                white: (15635, 16450),
+                max_luminance: 10_000_000,
+                min_luminance: 1,
+            }),
+            light_level: Some(ContentLightLevel {
+                max_content: 10_000_000,
+                max_frame_average: 4_000_000,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = testing_state();
+        let png = Png::from_chunks(state.to_chunks().unwrap());
+
+        assert_eq!(ColorState::from_png(&png).unwrap(), state);
+    }
+
+    #[test]
+    fn test_icc_header() {
+        let header = testing_state().icc.unwrap().header().unwrap();
+        assert_eq!(header.version, (4, 3));
+        assert_eq!(header.class, "mntr");
+        assert_eq!(header.color_space, "RGB");
+        assert_eq!(header.connection_space, "XYZ");
+
+        let broken = IccProfile {
+            name: "Broken".to_string(),
+            profile: vec![0; 64],
+        };
+        assert!(IccProfile::try_from(&broken.to_chunk().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let chunk = |chunk_type: &[u8; 4], data: &[u8]| {
+            Chunk::new(ChunkType::new(*chunk_type), data.to_vec())
+        };
+
+        assert!(Gamma::try_from(&chunk(b"gAMA", &[0, 0, 0, 0])).is_err());
+        assert!(Gamma::try_from(&chunk(b"gAMA", &[0, 1])).is_err());
+        assert!(Gamma::try_from(&chunk(b"sRGB", &[0, 0, 0, 1])).is_err());
+        assert!(RenderingIntent::try_from(&chunk(b"sRGB", &[4])).is_err());
+        assert!(CodePoints::try_from(&chunk(b"cICP", &[9, 16, 1, 1])).is_err());
+        assert!(CodePoints::try_from(&chunk(b"cICP", &[9, 16, 0, 2])).is_err());
+
+        let mut display = testing_state().mastering_display.unwrap();
+        display.min_luminance = display.max_luminance;
+        assert!(MasteringDisplay::try_from(&display.to_chunk()).is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut state = testing_state();
+        assert_eq!(state.effective(), Some("cICP"));
+        assert_eq!(state.superseded(), vec!["iCCP", "sRGB", "gAMA", "cHRM"]);
+
+        state.code_points = None;
+        state.icc = None;
+        assert_eq!(state.effective(), Some("sRGB"));
+        assert_eq!(state.superseded(), vec!["gAMA", "cHRM"]);
+
+        assert_eq!(ColorState::default().effective(), None);
+        assert!(ColorState::default().superseded().is_empty());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let header = ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+
+        let state = testing_state();
+        assert_eq!(
+            state.warnings(Some(&header)),
+            vec![
+                ColorWarning::IccWithSrgb,
+                ColorWarning::ProfileColorSpace("RGB".to_string(), "GRAY")
+            ]
+        );
+
+        let state = ColorState {
+            gamma: Some(Gamma(100000)),
+            srgb: Some(RenderingIntent::Saturation),
+            light_level: Some(ContentLightLevel {
+                max_content: 1,
+                max_frame_average: 2,
+            }),
+            ..ColorState::default()
+        };
+        assert_eq!(
+            state.warnings(None),
+            vec![
+                ColorWarning::GammaDisagreesWithSrgb(100000),
+                ColorWarning::HdrWithoutCodePoints,
+                ColorWarning::FrameAverageAboveMaximum
+            ]
+        );
+    }
+
+    #[test]
+    fn test_consistent() {
+        let header = ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+
+        // The profile applies, sRGB contradicts it
+        let state = testing_state().consistent(Some(&header));
+        assert_eq!(state.srgb, None);
+        assert!(state.icc.is_some());
+        assert!(state.warnings(Some(&header)).is_empty());
+
+        // A gray image keeps sRGB rather than a profile for RGB data
+        let gray = ImageHeader {
+            color_type: ColorType::Grayscale,
+            ..header
+        };
+        let state = testing_state().consistent(Some(&gray));
+        assert_eq!(state.icc, None);
+        assert!(state.srgb.is_some());
+
+        let state = ColorState {
+            gamma: Some(Gamma(100000)),
+            chromaticities: Some(SRGB_CHROMATICITIES),
+            srgb: Some(RenderingIntent::Perceptual),
+            light_level: Some(ContentLightLevel {
+                max_content: 2,
+                max_frame_average: 1,
+            }),
+            ..ColorState::default()
+        };
+        assert_eq!(
+            state.consistent(None),
+            ColorState {
+                chromaticities: Some(SRGB_CHROMATICITIES),
+                srgb: Some(RenderingIntent::Perceptual),
+                ..ColorState::default()
+            }
+        );
+
+        let mut png = Png::from_chunks(testing_state().to_chunks().unwrap());
+        png.remove_chunk("gAMA").unwrap();
+        let mut chunks = png.chunks().to_vec();
+        chunks.push(Chunk::new(ChunkType::new(*b"gAMA"), vec![0, 0, 0, 0]));
+        let png = Png::from_chunks(chunks);
+        assert!(ColorState::from_png(&png).is_err());
+        assert_eq!(ColorState::from_png_lenient(&png).gamma, None);
+    }
+}
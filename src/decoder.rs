@@ -0,0 +1,555 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    filter::{self, FilterType},
+    ihdr::{ColorType, ImageHeader},
+    palette::Transparency,
+    png::Png,
+    Error,
+};
+
+/// The Adam7 passes as (x start, y start, x step, y step)
+pub const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The most pixels an image may have to be decoded, which keeps the pixel data below
+/// 512 MiB at any format
+pub const MAX_PIXELS: u64 = 1 << 26;
+
+/// The most bytes `inflate` produces, so a small stream can't claim all the memory
+pub const MAX_INFLATED_SIZE: usize = 1 << 29;
+
+/// Decoded image data. The rows are stored the way PNG stores them without filter bytes
+/// and without interlacing: samples are big endian and packed at the image's bit depth,
+/// and every row starts on a new byte.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PixelBuffer {
+    pub header: ImageHeader,
+    pub data: Vec<u8>,
+    /// The PLTE entries of an indexed image
+    pub palette: Option<Vec<[u8; 3]>>,
+}
+
+#[allow(dead_code)]
+impl PixelBuffer {
+    pub fn width(&self) -> usize {
+        self.header.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.header.height as usize
+    }
+
+    /// The number of bytes in a row
+    pub fn stride(&self) -> usize {
+        self.header.stride(self.width())
+    }
+
+    pub fn row(&self, y: usize) -> &[u8] {
+        let stride = self.stride();
+        &self.data[y * stride..(y + 1) * stride]
+    }
+
+    /// Reads a single sample (a channel of a pixel, or a palette index)
+    pub fn sample(&self, x: usize, y: usize, channel: usize) -> u16 {
+        let index = x * self.header.color_type.channels() + channel;
+        read_sample(self.row(y), index, self.header.bit_depth)
+    }
+
+    /// Writes a single sample, values that don't fit the bit depth are truncated
+    pub fn set_sample(&mut self, x: usize, y: usize, channel: usize, value: u16) {
+        let stride = self.stride();
+        let index = x * self.header.color_type.channels() + channel;
+        let row = &mut self.data[y * stride..(y + 1) * stride];
+        write_sample(row, index, self.header.bit_depth, value);
+    }
+
+    /// The color of a pixel as RGBA scaled to 16 bits, palette indices are looked up in
+    /// the palette. Transparency from tRNS is not applied, see `transparent_rgba16`.
+    pub fn rgba16(&self, x: usize, y: usize) -> [u16; 4] {
+        let max = (1u32 << self.header.bit_depth) - 1;
+        let scale = |sample: u16| (sample as u32 * 65535 / max) as u16;
+        let sample = |channel: usize| scale(self.sample(x, y, channel));
+
+        match self.header.color_type {
+            ColorType::Grayscale => [sample(0), sample(0), sample(0), 65535],
+            ColorType::GrayscaleAlpha => [sample(0), sample(0), sample(0), sample(1)],
+            ColorType::Rgb => [sample(0), sample(1), sample(2), 65535],
+            ColorType::Rgba => [sample(0), sample(1), sample(2), sample(3)],
+            ColorType::Indexed => {
+                let index = self.sample(x, y, 0) as usize;
+                let entry = self
+                    .palette
+                    .as_ref()
+                    .and_then(|palette| palette.get(index))
+                    .copied()
+                    .unwrap_or([0, 0, 0]);
+
+                [
+                    entry[0] as u16 * 257,
+                    entry[1] as u16 * 257,
+                    entry[2] as u16 * 257,
+                    65535,
+                ]
+            }
+        }
+    }
+
+    /// Like `rgba16`, with the alpha a tRNS chunk gives the pixel
+    pub fn transparent_rgba16(
+        &self,
+        x: usize,
+        y: usize,
+        transparency: Option<&Transparency>,
+    ) -> [u16; 4] {
+        let mut rgba = self.rgba16(x, y);
+        let samples = |count: usize| -> Vec<u16> {
+            (0..count).map(|channel| self.sample(x, y, channel)).collect()
+        };
+
+        match (transparency, self.header.color_type) {
+            (Some(transparency @ Transparency::Indexed(_)), ColorType::Indexed) => {
+                rgba[3] = transparency.alpha(self.sample(x, y, 0) as usize) as u16 * 257;
+            }
+            (Some(Transparency::Grayscale(gray)), ColorType::Grayscale)
+                if samples(1) == [*gray] =>
+            {
+                rgba[3] = 0;
+            }
+            (Some(Transparency::Rgb(red, green, blue)), ColorType::Rgb)
+                if samples(3) == [*red, *green, *blue] =>
+            {
+                rgba[3] = 0;
+            }
+            _ => {}
+        }
+
+        rgba
+    }
+}
+
+/// Reads the sample at `index` from a packed row
+pub fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            let mask = (1u8 << bit_depth) - 1;
+            ((row[bit / 8] >> shift) & mask) as u16
+        }
+    }
+}
+
+/// Writes the sample at `index` into a packed row
+pub fn write_sample(row: &mut [u8], index: usize, bit_depth: u8, value: u16) {
+    match bit_depth {
+        16 => row[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+        8 => row[index] = value as u8,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            let mask = ((1u8 << bit_depth) - 1) << shift;
+            row[bit / 8] = (row[bit / 8] & !mask) | (((value as u8) << shift) & mask);
+        }
+    }
+}
+
+/// The size of an Adam7 pass in pixels
+pub fn pass_size(header: &ImageHeader, pass: usize) -> (usize, usize) {
+    let (x_start, y_start, x_step, y_step) = ADAM7[pass];
+    let width = (header.width as usize + x_step - 1 - x_start) / x_step;
+    let height = (header.height as usize + y_step - 1 - y_start) / y_step;
+
+    if width == 0 || height == 0 {
+        (0, 0)
+    } else {
+        (width, height)
+    }
+}
+
+/// Inflates a zlib stream, refusing streams that inflate to more than `MAX_INFLATED_SIZE`
+/// bytes
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let inflated = inflate_limited(data, MAX_INFLATED_SIZE + 1)?;
+
+    if inflated.len() > MAX_INFLATED_SIZE {
+        return Err(DecodeError::TooLarge.into());
+    }
+    Ok(inflated)
+}
+
+/// Inflates no more than `limit` bytes of a zlib stream, the rest of the stream isn't read
+pub fn inflate_limited(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut inflated = Vec::new();
+
+    let mut decoder = ZlibDecoder::new(data).take(limit as u64);
+    if decoder.read_to_end(&mut inflated).is_err() {
+        return Err(DecodeError::InvalidZlibStream.into());
+    }
+
+    Ok(inflated)
+}
+
+/// The number of bytes the filtered image data of an image or frame inflates to: every
+/// row (of every Adam7 pass) with its filter byte. Images of more than `MAX_PIXELS`
+/// pixels are refused.
+pub fn filtered_size(header: &ImageHeader) -> Result<usize, Error> {
+    if header.width as u64 * header.height as u64 > MAX_PIXELS {
+        return Err(DecodeError::TooLarge.into());
+    }
+
+    if !header.interlaced {
+        return Ok((header.stride(header.width as usize) + 1) * header.height as usize);
+    }
+
+    Ok((0..ADAM7.len())
+        .map(|pass| pass_size(header, pass))
+        .filter(|&(width, _)| width > 0)
+        .map(|(width, height)| (header.stride(width) + 1) * height)
+        .sum())
+}
+
+/// Decodes the pixels of a PNG file
+pub fn decode(png: &Png) -> Result<PixelBuffer, Error> {
+    let header = ImageHeader::from_png(png)?;
+    let palette = read_palette(png, &header)?;
+    let data = decode_image_data(&header, &png.image_data())?;
+
+    Ok(PixelBuffer {
+        header,
+        data,
+        palette,
+    })
+}
+
+/// Decodes a zlib stream of image data (the contents of the IDAT or fdAT chunks) into
+/// packed rows. `header` gives the size of the image or frame.
+pub fn decode_image_data(header: &ImageHeader, compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+
+    // Nothing is allocated for the size the header claims before the stream has shown to
+    // hold that much data, and the stream is never inflated past it
+    let filtered_size = filtered_size(header)?;
+    let inflated = inflate_limited(compressed, filtered_size)?;
+    if inflated.len() < filtered_size {
+        return Err(DecodeError::NotEnoughData.into());
+    }
+
+    let size = header.stride(width) * height;
+
+    if !header.interlaced {
+        let mut data = Vec::with_capacity(size);
+        unfilter_rows(header, width, height, &inflated, &mut data)?;
+        return Ok(data);
+    }
+
+    let mut data = vec![0u8; size];
+    let mut offset = 0;
+    let bits_per_pixel = header.bits_per_pixel();
+
+    for (pass, &(x_start, y_start, x_step, y_step)) in ADAM7.iter().enumerate() {
+        let (pass_width, pass_height) = pass_size(header, pass);
+        if pass_width == 0 {
+            continue;
+        }
+
+        let pass_length = (header.stride(pass_width) + 1) * pass_height;
+        let end = offset + pass_length;
+        if end > inflated.len() {
+            return Err(DecodeError::NotEnoughData.into());
+        }
+
+        let mut pass_data = Vec::with_capacity(pass_length);
+        unfilter_rows(
+            header,
+            pass_width,
+            pass_height,
+            &inflated[offset..end],
+            &mut pass_data,
+        )?;
+        offset = end;
+
+        // Put every pixel of the pass at its place in the full image
+        let pass_stride = header.stride(pass_width);
+        let stride = header.stride(width);
+        for y in 0..pass_height {
+            for x in 0..pass_width {
+                copy_pixel(
+                    &pass_data[y * pass_stride..],
+                    x,
+                    &mut data[(y_start + y * y_step) * stride..],
+                    x_start + x * x_step,
+                    bits_per_pixel,
+                );
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Unfilters `height` rows of `width` pixels and appends them to `output`
+fn unfilter_rows(
+    header: &ImageHeader,
+    width: usize,
+    height: usize,
+    filtered: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let stride = header.stride(width);
+    let distance = header.filter_distance();
+
+    if filtered.len() < (stride + 1) * height {
+        return Err(DecodeError::NotEnoughData.into());
+    }
+
+    let mut previous = vec![0u8; stride];
+    for line in filtered.chunks_exact(stride + 1).take(height) {
+        let filter = FilterType::try_from(line[0])?;
+        let mut row = line[1..].to_vec();
+
+        filter::unfilter(filter, distance, &previous, &mut row);
+        output.extend_from_slice(&row);
+        previous = row;
+    }
+
+    Ok(())
+}
+
+/// Copies a single pixel between two packed rows
+pub fn copy_pixel(from: &[u8], from_x: usize, to: &mut [u8], to_x: usize, bits_per_pixel: usize) {
+    if bits_per_pixel >= 8 {
+        let bytes = bits_per_pixel / 8;
+        to[to_x * bytes..(to_x + 1) * bytes]
+            .copy_from_slice(&from[from_x * bytes..(from_x + 1) * bytes]);
+    } else {
+        let depth = bits_per_pixel as u8;
+        write_sample(to, to_x, depth, read_sample(from, from_x, depth));
+    }
+}
+
+/// Reads the palette of an indexed image
+fn read_palette(png: &Png, header: &ImageHeader) -> Result<Option<Vec<[u8; 3]>>, Error> {
+    let chunk = match png.chunk_by_type("PLTE") {
+        Some(chunk) => chunk,
+        None if header.color_type == ColorType::Indexed => {
+            return Err(DecodeError::MissingPalette.into())
+        }
+        None => return Ok(None),
+    };
+
+    if chunk.data().len() % 3 != 0 {
+        return Err(DecodeError::InvalidPalette.into());
+    }
+
+    Ok(Some(
+        chunk
+            .data()
+            .chunks_exact(3)
+            .map(|entry| [entry[0], entry[1], entry[2]])
+            .collect(),
+    ))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DecodeError {
+    #[error("The image data is not a valid zlib stream.")]
+    InvalidZlibStream,
+
+    #[error("The image data is shorter than the image size requires.")]
+    NotEnoughData,
+
+    #[error("The image is too large to decode, or its data inflates to too many bytes.")]
+    TooLarge,
+
+    #[error("The image is indexed but has no PLTE chunk.")]
+    MissingPalette,
+
+    #[error("The PLTE chunk length must be divisible by 3.")]
+    InvalidPalette,
+}
+
+#[cfg(test)]
+mod decoder_tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn testing_png(header: ImageHeader, filtered: &[u8], palette: Option<&[u8]>) -> Png {
+        let mut chunks = vec![header.to_chunk()];
+        if let Some(palette) = palette {
+            chunks.push(Chunk::new(ChunkType::new(*b"PLTE"), palette.to_vec()));
+        }
+
+        // Split the image data over two IDAT chunks to test the concatenation
+        let compressed = deflate(filtered);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        chunks.push(Chunk::new(ChunkType::new(*b"IDAT"), first.to_vec()));
+        chunks.push(Chunk::new(ChunkType::new(*b"IDAT"), second.to_vec()));
+        chunks.push(Chunk::new(ChunkType::new(*b"IEND"), Vec::new()));
+
+        Png::from_chunks(chunks)
+    }
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_decode_rgb() {
+        #[rustfmt::skip]
+        let filtered = [
+            0, 1, 2, 3, 4, 5, 6,    // None
+            1, 1, 1, 1, 2, 2, 2,    // Sub
+            2, 1, 1, 1, 1, 1, 1,    // Up
+        ];
+
+        let png = testing_png(header(2, 3, 8, ColorType::Rgb), &filtered, None);
+        let pixels = decode(&png).unwrap();
+
+        assert_eq!(pixels.row(0), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(pixels.row(1), &[1, 1, 1, 3, 3, 3]);
+        assert_eq!(pixels.row(2), &[2, 2, 2, 4, 4, 4]);
+        assert_eq!(pixels.rgba16(1, 0), [4 * 257, 5 * 257, 6 * 257, 65535]);
+    }
+
+    #[test]
+    fn test_decode_16_bit() {
+        let filtered = [0, 0x12, 0x34, 0xff, 0xff];
+
+        let png = testing_png(header(1, 1, 16, ColorType::GrayscaleAlpha), &filtered, None);
+        let pixels = decode(&png).unwrap();
+
+        assert_eq!(pixels.sample(0, 0, 0), 0x1234);
+        assert_eq!(pixels.sample(0, 0, 1), 0xffff);
+    }
+
+    #[test]
+    fn test_decode_indexed() {
+        // Four 2 bit indices per byte: 0, 1, 2, 1
+        let filtered = [0, 0b00_01_10_01];
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0];
+
+        let png = testing_png(
+            header(4, 1, 2, ColorType::Indexed),
+            &filtered,
+            Some(&palette),
+        );
+        let pixels = decode(&png).unwrap();
+
+        let indices: Vec<u16> = (0..4).map(|x| pixels.sample(x, 0, 0)).collect();
+        assert_eq!(indices, vec![0, 1, 2, 1]);
+        assert_eq!(pixels.rgba16(2, 0), [0, 65535, 0, 65535]);
+    }
+
+    #[test]
+    fn test_decode_missing_palette() {
+        let png = testing_png(header(1, 1, 8, ColorType::Indexed), &[0, 0], None);
+        assert!(decode(&png).is_err());
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        // A 3x3 grayscale image with the pixel values 0 to 8, the passes hold pixels
+        // (0, 0), (2, 0), (0, 2) (2, 2), (1, 0) (1, 2) and (0, 1) (1, 1) (2, 1)
+        let mut header = header(3, 3, 8, ColorType::Grayscale);
+        header.interlaced = true;
+
+        #[rustfmt::skip]
+        let filtered = [
+            0, 0,           // Pass 1
+            0, 2,           // Pass 4
+            0, 6, 8,        // Pass 5
+            0, 1, 0, 7,     // Pass 6
+            0, 3, 4, 5,     // Pass 7
+        ];
+
+        let png = testing_png(header, &filtered, None);
+        let pixels = decode(&png).unwrap();
+
+        assert_eq!(pixels.data, (0..9).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_decode_not_enough_data() {
+        let png = testing_png(header(2, 2, 8, ColorType::Rgba), &[0, 1, 2, 3], None);
+        assert!(decode(&png).is_err());
+    }
+
+    #[test]
+    fn test_decode_hostile_header() {
+        // A 68 byte file whose header claims 2^51 pixels of 64 bits
+        let png = testing_png(header(0x7fffffff, 0x100000, 16, ColorType::Rgba), &[0], None);
+        assert!(png.as_bytes().len() < 100);
+        assert!(matches!(
+            decode(&png).unwrap_err().downcast_ref::<DecodeError>(),
+            Some(DecodeError::TooLarge)
+        ));
+
+        // Within the pixel limit the stream has to hold the data before anything is
+        // allocated for it
+        let png = testing_png(header(8192, 8192, 16, ColorType::Rgba), &[0; 64], None);
+        assert!(matches!(
+            decode(&png).unwrap_err().downcast_ref::<DecodeError>(),
+            Some(DecodeError::NotEnoughData)
+        ));
+    }
+
+    #[test]
+    fn test_decode_zip_bomb() {
+        // A 2x1 image whose stream inflates to 64 MiB, only the first 7 bytes are read
+        let mut filtered = vec![0, 1, 2, 3, 4, 5, 6];
+        filtered.resize(64 << 20, 0);
+        let png = testing_png(header(2, 1, 8, ColorType::Rgb), &filtered, None);
+        assert!(png.as_bytes().len() < 128 << 10);
+
+        assert_eq!(decode(&png).unwrap().data, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(inflate_limited(&png.image_data(), 7).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_decode_image_file() {
+        let png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        let header = ImageHeader::from_png(&png).unwrap();
+        let pixels = decode(&png).unwrap();
+
+        assert_eq!(
+            pixels.data.len(),
+            header.stride(header.width as usize) * header.height as usize
+        );
+    }
+
+    #[test]
+    fn test_sample_round_trip() {
+        let mut row = vec![0u8; 2];
+        write_sample(&mut row, 3, 4, 0xa);
+        write_sample(&mut row, 0, 4, 0x5);
+        assert_eq!(row, vec![0x50, 0x0a]);
+        assert_eq!(read_sample(&row, 3, 4), 0xa);
+    }
+}
@@ -0,0 +1,997 @@
+use flate2::{Compress, Compression, FlushCompress, Status};
+
+use crate::{
+    apng::{self, ApngError},
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    decoder,
+    encoder::{self, EncoderOptions},
+    ihdr::ImageHeader,
+    png::Png,
+    Error,
+};
+
+// The payload is hidden in choices a decoder can't see. The filtered image data is
+// compressed again with a sync flush after every row, and two kinds of choices carry bits:
+//
+//   block types    every Huffman coded block can use the fixed code (a 0 bit) or a dynamic
+//                  code of its own (a 1 bit), the symbols stay the same
+//   padding        every stored block is padded to a byte boundary after its 3 bit header,
+//                  and those padding bits are never read. The sync flushes end every row
+//                  with an empty stored block, and an extra empty stored block (5 free bits
+//                  each) can be added after any row if more room is needed.
+//
+// The inflated bytes stay the same, so the pixels don't change at all. The hidden message
+// is a big-endian u16 length followed by the payload, every byte stored least significant
+// bit first like deflate does. Its first bits go into the block types in stream order, the
+// rest into the padding.
+
+/// An empty stored block starting at a byte boundary, the top 5 bits of the first byte are
+/// padding
+const EMPTY_STORED_BLOCK: [u8; 5] = [0x00, 0x00, 0x00, 0xFF, 0xFF];
+
+/// The number of payload bits an empty stored block at a byte boundary holds
+const EMPTY_BLOCK_BITS: usize = 5;
+
+/// The extra bits of the length symbols 257 to 285
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The extra bits of the distance symbols 0 to 29
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// A zlib stream of the image data (or of a frame) with a sync flush after every row.
+/// Compressing is the slow part, so the capacity and the embedding share it.
+pub struct SlackStream {
+    frame: Option<usize>,
+    stream: Vec<u8>,
+}
+
+/// The blocks of a deflate stream as far as the payload is concerned
+struct Blocks {
+    /// Whether every Huffman coded block has a dynamic code (a 1 bit) or the fixed one
+    dynamic: Vec<bool>,
+    /// The bit positions of the padding of every stored block, in stream order
+    padding: Vec<usize>,
+    /// The padding bits no choice of block types can take away, those of the stored blocks
+    /// that don't follow a Huffman coded block
+    aligned_padding: usize,
+    /// The number of stored blocks that aren't the last block, an empty stored block can go
+    /// after each of them
+    stored: usize,
+}
+
+/// A symbol of a Huffman coded block with its extra bits, and the distance of a match
+#[derive(Debug, Clone, Copy)]
+struct Code {
+    symbol: u16,
+    extra: u32,
+    distance: Option<(u16, u32)>,
+}
+
+/// The number of payload bytes the image data of a PNG file can hold, or that of a single
+/// frame of an animation
+pub fn capacity(png: &Png, frame: Option<usize>) -> Result<usize, Error> {
+    SlackStream::new(png, frame)?.capacity()
+}
+
+/// Hides a payload in the image data, or in the data of a single frame of an animation.
+/// The copy rules for modified image data apply, unsafe-to-copy chunks are dropped unless
+/// listed in `keep`. Returns the new file and the dropped chunks.
+pub fn embed(
+    png: &Png,
+    payload: &[u8],
+    frame: Option<usize>,
+    keep: &[ChunkType],
+) -> Result<(Png, Vec<Chunk>), Error> {
+    SlackStream::new(png, frame)?.embed(png, payload, keep)
+}
+
+/// Reads a payload hidden in the image data, or in the data of a single frame of an
+/// animation
+pub fn extract(png: &Png, frame: Option<usize>) -> Result<Vec<u8>, Error> {
+    let stream = match frame {
+        Some(frame) => apng::frame_data(png, frame)?,
+        None => png.image_data(),
+    };
+    let blocks = read_blocks(&stream)?;
+
+    let bits: Vec<u8> = blocks
+        .dynamic
+        .iter()
+        .map(|&dynamic| dynamic as u8)
+        .chain(
+            blocks
+                .padding
+                .iter()
+                .map(|&position| (stream[position / 8] >> (position % 8)) & 1),
+        )
+        .collect();
+
+    let bytes: Vec<u8> = bits
+        .chunks_exact(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0, |value, (index, bit)| value | bit << index)
+        })
+        .collect();
+
+    if bytes.len() < 2 {
+        return Err(DeflateSlackError::NoPayload.into());
+    }
+
+    let length = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+    if length == 0 || length > bytes.len() - 2 {
+        return Err(DeflateSlackError::NoPayload.into());
+    }
+
+    Ok(bytes[2..2 + length].to_vec())
+}
+
+impl SlackStream {
+    /// Compresses the filtered image data of a PNG file (or of a frame) again, with a sync
+    /// flush after every row
+    pub fn new(png: &Png, frame: Option<usize>) -> Result<SlackStream, Error> {
+        let mut header = ImageHeader::from_png(png)?;
+        let stream = match frame {
+            Some(index) => {
+                let frames = apng::frames(png)?;
+                let control = frames.get(index).ok_or(ApngError::NoSuchFrame(index))?.control;
+                header.width = control.width;
+                header.height = control.height;
+                apng::frame_data(png, index)?
+            }
+            None => png.image_data(),
+        };
+        let filtered = decoder::inflate_limited(&stream, decoder::filtered_size(&header)?)?;
+        let row_size = header.stride(header.width as usize) + 1;
+
+        let level = EncoderOptions::default().level;
+        let mut compress = Compress::new(Compression::new(level), true);
+        let mut stream = Vec::new();
+
+        let rows: Vec<&[u8]> = filtered.chunks(row_size).collect();
+        if rows.is_empty() {
+            return Err(DeflateSlackError::InvalidStream.into());
+        }
+
+        for (index, row) in rows.iter().enumerate() {
+            let last = index + 1 == rows.len();
+            let flush = if last {
+                FlushCompress::Finish
+            } else {
+                FlushCompress::Sync
+            };
+
+            let mut input = *row;
+            loop {
+                // compress_vec only writes into the spare capacity
+                if stream.capacity() - stream.len() < 64 {
+                    stream.reserve(input.len() + 1024);
+                }
+
+                let consumed = compress.total_in();
+                let status = compress.compress_vec(input, &mut stream, flush)?;
+                input = &input[(compress.total_in() - consumed) as usize..];
+
+                let done = match flush {
+                    FlushCompress::Finish => status == Status::StreamEnd,
+                    _ => input.is_empty() && stream.len() < stream.capacity(),
+                };
+                if done {
+                    break;
+                }
+            }
+        }
+
+        Ok(SlackStream { frame, stream })
+    }
+
+    /// The number of payload bytes the stream can hold
+    pub fn capacity(&self) -> Result<usize, Error> {
+        let blocks = read_blocks(&self.stream)?;
+        let bits =
+            blocks.dynamic.len() + blocks.aligned_padding + blocks.stored * EMPTY_BLOCK_BITS;
+
+        Ok((bits / 8).saturating_sub(2))
+    }
+
+    /// Hides a payload in the stream and puts it in place of the image data of `png`, see
+    /// [`embed`]
+    pub fn embed(
+        &self,
+        png: &Png,
+        payload: &[u8],
+        keep: &[ChunkType],
+    ) -> Result<(Png, Vec<Chunk>), Error> {
+        if payload.is_empty() {
+            return Err(DeflateSlackError::EmptyPayload.into());
+        }
+
+        let capacity = self.capacity()?;
+        if payload.len() > capacity || payload.len() > u16::MAX as usize {
+            return Err(DeflateSlackError::PayloadTooLarge(capacity).into());
+        }
+
+        let mut message = (payload.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(payload);
+        let bits: Vec<bool> = (0..message.len() * 8)
+            .map(|index| (message[index / 8] >> (index % 8)) & 1 == 1)
+            .collect();
+
+        // The block types come first, they decide where the padding bits end up
+        let block_count = read_blocks(&self.stream)?.dynamic.len();
+        let (types, padding) = bits.split_at(bits.len().min(block_count));
+        let (rewritten, boundaries) = rewrite_blocks(&self.stream, types)?;
+
+        // Only add as many empty blocks as the rest of the message needs
+        let available = read_blocks(&rewritten)?.padding.len();
+        let extra_blocks = padding
+            .len()
+            .saturating_sub(available)
+            .div_ceil(EMPTY_BLOCK_BITS)
+            .min(boundaries.len());
+
+        let mut stream = Vec::with_capacity(rewritten.len() + extra_blocks * 5);
+        let mut start = 0;
+        for &boundary in &boundaries[..extra_blocks] {
+            stream.extend_from_slice(&rewritten[start..boundary]);
+            stream.extend_from_slice(&EMPTY_STORED_BLOCK);
+            start = boundary;
+        }
+        stream.extend_from_slice(&rewritten[start..]);
+
+        let positions = read_blocks(&stream)?.padding;
+        if positions.len() < padding.len() {
+            return Err(DeflateSlackError::PayloadTooLarge(capacity).into());
+        }
+        for (&position, &bit) in positions.iter().zip(padding) {
+            stream[position / 8] =
+                stream[position / 8] & !(1 << (position % 8)) | (bit as u8) << (position % 8);
+        }
+
+        let mut embedded = png.clone();
+        let idat_size = EncoderOptions::default().idat_size;
+        let dropped = match self.frame {
+            Some(frame) => {
+                let pieces = stream.chunks(idat_size).map(|piece| piece.to_vec()).collect();
+                apng::replace_frame_data(&mut embedded, frame, pieces, keep)?
+            }
+            None => embedded.replace_image_data(
+                None,
+                encoder::split_image_data(&stream, idat_size),
+                keep,
+            )?,
+        };
+
+        Ok((embedded, dropped))
+    }
+}
+
+/// Walks the blocks of a zlib stream and returns the bit positions of the padding of every
+/// stored block, in stream order. Bit `n` is bit `n % 8` of byte `n / 8`.
+pub fn padding_bits(stream: &[u8]) -> Result<Vec<usize>, Error> {
+    Ok(read_blocks(stream)?.padding)
+}
+
+fn check_header(stream: &[u8]) -> Result<(), Error> {
+    if stream.len() < 2 || stream[0] & 0x0F != 8 || stream[1] & 0x20 != 0 {
+        return Err(DeflateSlackError::InvalidStream.into());
+    }
+    Ok(())
+}
+
+/// Walks the blocks of a zlib stream
+fn read_blocks(stream: &[u8]) -> Result<Blocks, Error> {
+    check_header(stream)?;
+
+    let mut reader = BitReader {
+        data: stream,
+        position: 16,
+    };
+    let mut blocks = Blocks {
+        dynamic: Vec::new(),
+        padding: Vec::new(),
+        aligned_padding: 0,
+        stored: 0,
+    };
+    let mut after_huffman = false;
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                let padding = (8 - reader.position % 8) % 8;
+                blocks
+                    .padding
+                    .extend(reader.position..reader.position + padding);
+                if !after_huffman {
+                    blocks.aligned_padding += padding;
+                }
+                if !last {
+                    blocks.stored += 1;
+                }
+                reader.position += padding;
+                skip_stored(&mut reader)?;
+                after_huffman = false;
+            }
+            kind @ (1 | 2) => {
+                read_huffman_block(&mut reader, kind == 2)?;
+                blocks.dynamic.push(kind == 2);
+                after_huffman = true;
+            }
+            _ => return Err(DeflateSlackError::InvalidStream.into()),
+        }
+
+        if last {
+            return Ok(blocks);
+        }
+    }
+}
+
+/// Copies a zlib stream, giving its Huffman coded blocks the fixed (false) or a dynamic
+/// (true) code as `types` says, in stream order. Blocks past the end of `types` are copied
+/// as they are. Returns the new stream and the byte offsets after its stored blocks but the
+/// last one.
+fn rewrite_blocks(stream: &[u8], types: &[bool]) -> Result<(Vec<u8>, Vec<usize>), Error> {
+    check_header(stream)?;
+
+    let mut reader = BitReader {
+        data: stream,
+        position: 16,
+    };
+    let mut writer = BitWriter::default();
+    writer.bytes(&stream[..2]);
+    let mut boundaries = Vec::new();
+    let mut block = 0;
+
+    loop {
+        let start = reader.position;
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.position = reader.position.div_ceil(8) * 8;
+                let data_start = reader.position / 8;
+                skip_stored(&mut reader)?;
+
+                writer.bits(last as u32, 1);
+                writer.bits(0, 2);
+                writer.align();
+                writer.bytes(&stream[data_start..reader.position / 8]);
+                if !last {
+                    boundaries.push(writer.data.len());
+                }
+            }
+            kind @ (1 | 2) => {
+                let codes = read_huffman_block(&mut reader, kind == 2)?;
+                let dynamic = types.get(block).copied().unwrap_or(kind == 2);
+                block += 1;
+
+                if dynamic == (kind == 2) {
+                    writer.copy(stream, start, reader.position);
+                } else if dynamic {
+                    write_dynamic(&mut writer, &codes, last);
+                } else {
+                    write_fixed(&mut writer, &codes, last);
+                }
+            }
+            _ => return Err(DeflateSlackError::InvalidStream.into()),
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    // The Adler-32 of the inflated data stays the same
+    let end = reader.position.div_ceil(8);
+    let adler = stream
+        .get(end..end + 4)
+        .ok_or(DeflateSlackError::InvalidStream)?;
+    writer.align();
+    writer.bytes(adler);
+
+    Ok((writer.data, boundaries))
+}
+
+/// Skips the length, its complement and the data of a stored block, from a byte boundary
+fn skip_stored(reader: &mut BitReader) -> Result<(), Error> {
+    let length = reader.bits(16)?;
+    let complement = reader.bits(16)?;
+    if length != !complement & 0xFFFF {
+        return Err(DeflateSlackError::InvalidStream.into());
+    }
+    reader.skip(length as usize * 8)
+}
+
+/// The code lengths of the fixed literal/length code
+fn fixed_lengths() -> [u8; 288] {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// Reads a Huffman coded block after its 3 bit header, up to its end of block symbol
+fn read_huffman_block(reader: &mut BitReader, dynamic: bool) -> Result<Vec<Code>, Error> {
+    let (literals, distances) = if dynamic {
+        read_dynamic_tables(reader)?
+    } else {
+        (Huffman::new(&fixed_lengths()), Huffman::new(&[5; 30]))
+    };
+    read_codes(reader, &literals, &distances)
+}
+
+/// Reads the bits of a deflate stream, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        let mut value = 0;
+        for index in 0..count {
+            let byte = match self.data.get(self.position / 8) {
+                Some(byte) => byte,
+                None => return Err(DeflateSlackError::InvalidStream.into()),
+            };
+            value |= ((*byte as u32 >> (self.position % 8)) & 1) << index;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        if self.position + count > self.data.len() * 8 {
+            return Err(DeflateSlackError::InvalidStream.into());
+        }
+        self.position += count;
+        Ok(())
+    }
+}
+
+/// A canonical Huffman code, stored as the number of codes of every length and the symbols
+/// in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, _) in lengths
+                .iter()
+                .enumerate()
+                .filter(|(_, &symbol_length)| symbol_length as usize == length)
+            {
+                symbols.push(symbol as u16);
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Error> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(DeflateSlackError::InvalidStream.into())
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + reader.bits(2)? as usize),
+                None => return Err(DeflateSlackError::InvalidStream.into()),
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err(DeflateSlackError::InvalidStream.into());
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// Reads the symbols of a Huffman coded block up to its end of block symbol, which is left
+/// out
+fn read_codes(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<Vec<Code>, Error> {
+    let mut codes = Vec::new();
+
+    loop {
+        let code = match literals.decode(reader)? {
+            symbol @ 0..=255 => Code {
+                symbol,
+                extra: 0,
+                distance: None,
+            },
+            256 => return Ok(codes),
+            symbol @ 257..=285 => {
+                let extra = reader.bits(LENGTH_EXTRA_BITS[symbol as usize - 257])?;
+
+                let distance = match distances.decode(reader)? {
+                    distance @ 0..=29 => {
+                        (distance, reader.bits(DISTANCE_EXTRA_BITS[distance as usize])?)
+                    }
+                    _ => return Err(DeflateSlackError::InvalidStream.into()),
+                };
+
+                Code {
+                    symbol,
+                    extra,
+                    distance: Some(distance),
+                }
+            }
+            _ => return Err(DeflateSlackError::InvalidStream.into()),
+        };
+        codes.push(code);
+    }
+}
+
+/// Writes the bits of a deflate stream, least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl BitWriter {
+    fn bit(&mut self, bit: bool) {
+        if self.position.is_multiple_of(8) {
+            self.data.push(0);
+        }
+        if bit {
+            *self.data.last_mut().unwrap() |= 1 << (self.position % 8);
+        }
+        self.position += 1;
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for index in 0..count {
+            self.bit((value >> index) & 1 == 1);
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first
+    fn code(&mut self, (code, length): (u32, u8)) {
+        for index in (0..length).rev() {
+            self.bit((code >> index) & 1 == 1);
+        }
+    }
+
+    fn align(&mut self) {
+        self.position = self.data.len() * 8;
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+        self.position = self.data.len() * 8;
+    }
+
+    /// Copies the bits `start..end` of `data`
+    fn copy(&mut self, data: &[u8], start: usize, end: usize) {
+        for position in start..end {
+            self.bit((data[position / 8] >> (position % 8)) & 1 == 1);
+        }
+    }
+}
+
+/// The canonical codes of a list of code lengths, as code and length
+fn canonical_codes(lengths: &[u8]) -> Vec<(u32, u8)> {
+    let mut counts = [0u32; 16];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next = [0u32; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return (0, 0);
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            (code, length)
+        })
+        .collect()
+}
+
+/// Builds Huffman code lengths of at most `limit` bits for a list of symbol frequencies.
+/// At least two symbols get a code, some inflaters refuse a code of a single symbol.
+fn huffman_lengths(frequencies: &[u32], limit: usize) -> Vec<u8> {
+    use std::{cmp::Reverse, collections::BinaryHeap};
+
+    let mut frequencies = frequencies.to_vec();
+    for symbol in 0..2 {
+        if frequencies.iter().filter(|&&frequency| frequency > 0).count() < 2 {
+            frequencies[symbol] = frequencies[symbol].max(1);
+        }
+    }
+
+    loop {
+        let mut heap: BinaryHeap<_> = frequencies
+            .iter()
+            .enumerate()
+            .filter(|(_, &frequency)| frequency > 0)
+            .map(|(symbol, &frequency)| Reverse((frequency as u64, symbol)))
+            .collect();
+        let mut parents = vec![usize::MAX; frequencies.len()];
+
+        while heap.len() > 1 {
+            let Reverse((first, first_node)) = heap.pop().unwrap();
+            let Reverse((second, second_node)) = heap.pop().unwrap();
+            let node = parents.len();
+            parents.push(usize::MAX);
+            parents[first_node] = node;
+            parents[second_node] = node;
+            heap.push(Reverse((first + second, node)));
+        }
+
+        let depths: Vec<usize> = (0..frequencies.len())
+            .map(|symbol| {
+                let mut depth = 0;
+                let mut node = symbol;
+                while parents[node] != usize::MAX {
+                    node = parents[node];
+                    depth += 1;
+                }
+                depth
+            })
+            .collect();
+
+        if depths.iter().all(|&depth| depth <= limit) {
+            return depths.into_iter().map(|depth| depth as u8).collect();
+        }
+
+        // Flatter frequencies make a shallower tree
+        for frequency in frequencies.iter_mut().filter(|frequency| **frequency > 0) {
+            *frequency = frequency.div_ceil(2);
+        }
+    }
+}
+
+/// Writes the symbols of a Huffman coded block and its end of block symbol
+fn write_codes(
+    writer: &mut BitWriter,
+    codes: &[Code],
+    literals: &[(u32, u8)],
+    distances: &[(u32, u8)],
+) {
+    for code in codes {
+        writer.code(literals[code.symbol as usize]);
+        if code.symbol > 256 {
+            writer.bits(code.extra, LENGTH_EXTRA_BITS[code.symbol as usize - 257]);
+        }
+        if let Some((distance, extra)) = code.distance {
+            writer.code(distances[distance as usize]);
+            writer.bits(extra, DISTANCE_EXTRA_BITS[distance as usize]);
+        }
+    }
+    writer.code(literals[256]);
+}
+
+/// Writes a Huffman coded block with the fixed code
+fn write_fixed(writer: &mut BitWriter, codes: &[Code], last: bool) {
+    writer.bits(last as u32, 1);
+    writer.bits(1, 2);
+    write_codes(
+        writer,
+        codes,
+        &canonical_codes(&fixed_lengths()),
+        &canonical_codes(&[5; 30]),
+    );
+}
+
+/// Writes a Huffman coded block with a dynamic code built for its symbols. The code lengths
+/// are stored without the repeat symbols 16 to 18.
+fn write_dynamic(writer: &mut BitWriter, codes: &[Code], last: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+    for code in codes {
+        literal_frequencies[code.symbol as usize] += 1;
+        if let Some((distance, _)) = code.distance {
+            distance_frequencies[distance as usize] += 1;
+        }
+    }
+    literal_frequencies[256] += 1;
+
+    let literal_lengths = huffman_lengths(&literal_frequencies, 15);
+    let distance_lengths = huffman_lengths(&distance_frequencies, 15);
+    let used = |lengths: &[u8]| lengths.iter().rposition(|&length| length > 0).unwrap_or(0) + 1;
+    let literal_count = used(&literal_lengths).max(257);
+    let distance_count = used(&distance_lengths);
+
+    let lengths: Vec<u8> = literal_lengths[..literal_count]
+        .iter()
+        .chain(&distance_lengths[..distance_count])
+        .copied()
+        .collect();
+    let mut code_length_frequencies = [0u32; 19];
+    for &length in &lengths {
+        code_length_frequencies[length as usize] += 1;
+    }
+    let code_lengths = huffman_lengths(&code_length_frequencies, 7);
+    let code_length_count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| code_lengths[symbol] > 0)
+        .unwrap_or(0)
+        .max(3)
+        + 1;
+
+    writer.bits(last as u32, 1);
+    writer.bits(2, 2);
+    writer.bits(literal_count as u32 - 257, 5);
+    writer.bits(distance_count as u32 - 1, 5);
+    writer.bits(code_length_count as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        writer.bits(code_lengths[symbol] as u32, 3);
+    }
+
+    let code_length_codes = canonical_codes(&code_lengths);
+    for &length in &lengths {
+        writer.code(code_length_codes[length as usize]);
+    }
+
+    write_codes(
+        writer,
+        codes,
+        &canonical_codes(&literal_lengths),
+        &canonical_codes(&distance_lengths),
+    );
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeflateSlackError {
+    #[error("The image data is not a valid zlib stream.")]
+    InvalidStream,
+
+    #[error("The payload is too large, the image data can hide at most {0} bytes.")]
+    PayloadTooLarge(usize),
+
+    #[error("There is nothing to hide, the payload is empty.")]
+    EmptyPayload,
+
+    #[error("The image data has no hidden payload.")]
+    NoPayload,
+}
+
+#[cfg(test)]
+mod deflate_slack_tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::try_from(&include_bytes!("../sss.png")[..]).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let png = testing_png();
+        let (embedded, _) = embed(&png, b"slack", None, &[]).unwrap();
+
+        assert_eq!(extract(&embedded, None).unwrap(), b"slack");
+        assert_eq!(
+            decoder::decode(&embedded).unwrap(),
+            decoder::decode(&png).unwrap()
+        );
+        assert_eq!(
+            decoder::inflate(&embedded.image_data()).unwrap(),
+            decoder::inflate(&png.image_data()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_embed_full_capacity() {
+        let png = testing_png();
+        let capacity = capacity(&png, None).unwrap();
+        assert!(capacity > 0);
+
+        let payload: Vec<u8> = (0..capacity).map(|index| index as u8 ^ 0xA5).collect();
+        let (embedded, _) = embed(&png, &payload, None, &[]).unwrap();
+        assert_eq!(extract(&embedded, None).unwrap(), payload);
+
+        assert!(embed(&png, &vec![0; capacity + 1], None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_embed_keeps_chunks() {
+        let png = testing_png();
+        let (embedded, _) = embed(&png, b"x", None, &[]).unwrap();
+
+        let types = |png: &Png| -> Vec<String> {
+            let mut types: Vec<String> = png
+                .chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect();
+            types.dedup();
+            types
+        };
+        assert_eq!(types(&png), types(&embedded));
+    }
+
+    #[test]
+    fn test_embed_in_a_frame() {
+        use crate::apng::{AnimationControl, BlendOp, DisposeOp, FrameControl, FrameData};
+        use crate::ihdr::ColorType;
+
+        let header = ImageHeader {
+            width: 16,
+            height: 16,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let control = |sequence_number, size| FrameControl {
+            sequence_number,
+            width: size,
+            height: size,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        let image_data = |size: u32| {
+            let frame = ImageHeader {
+                width: size,
+                height: size,
+                ..header
+            };
+            let pixels: Vec<u8> = (0..size * size * 4).map(|index| index as u8).collect();
+            encoder::encode_image_data(&frame, &pixels, &EncoderOptions::default()).unwrap()
+        };
+
+        let png = Png::from_chunks(vec![
+            header.to_chunk(),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .to_chunk(),
+            control(0, 16).to_chunk(),
+            Chunk::new(ChunkType::new(*b"IDAT"), image_data(16)),
+            control(1, 12).to_chunk(),
+            FrameData {
+                sequence_number: 2,
+                data: image_data(12),
+            }
+            .to_chunk(),
+            Chunk::new(ChunkType::new(*b"IEND"), Vec::new()),
+        ]);
+
+        let (embedded, _) = embed(&png, b"frame", Some(1), &[]).unwrap();
+        assert!(apng::validate(&embedded).is_ok());
+        assert_eq!(extract(&embedded, Some(1)).unwrap(), b"frame");
+        assert!(extract(&embedded, None).is_err());
+        assert_eq!(
+            decoder::inflate(&apng::frame_data(&embedded, 1).unwrap()).unwrap(),
+            decoder::inflate(&apng::frame_data(&png, 1).unwrap()).unwrap()
+        );
+        assert_eq!(embedded.image_data(), png.image_data());
+
+        assert!(embed(&png, b"frame", Some(2), &[]).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_block_types() {
+        let png = testing_png();
+        let stream = SlackStream::new(&png, None).unwrap().stream;
+        let count = read_blocks(&stream).unwrap().dynamic.len();
+        assert!(count > 1);
+
+        for types in [vec![true; count], vec![false; count], vec![true, false, true]] {
+            let (rewritten, _) = rewrite_blocks(&stream, &types).unwrap();
+            let dynamic = read_blocks(&rewritten).unwrap().dynamic;
+            assert_eq!(&dynamic[..types.len()], &types[..]);
+            assert_eq!(
+                decoder::inflate(&rewritten).unwrap(),
+                decoder::inflate(&stream).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_huffman_lengths() {
+        // Fibonacci frequencies make the deepest tree
+        let mut frequencies = vec![1u32, 1];
+        while frequencies.len() < 19 {
+            let next = frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2];
+            frequencies.push(next);
+        }
+
+        let lengths = huffman_lengths(&frequencies, 7);
+        assert!(lengths.iter().all(|&length| (1..=7).contains(&length)));
+        let kraft: f64 = lengths.iter().map(|&length| 0.5f64.powi(length as i32)).sum();
+        assert!(kraft <= 1.0);
+
+        assert_eq!(huffman_lengths(&[0, 0, 5], 15), vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn test_extract_without_payload() {
+        assert!(extract(&testing_png(), None).is_err());
+    }
+
+    #[test]
+    fn test_padding_bits() {
+        // An empty stored block, then a final fixed block with only the end of block symbol
+        let stream = [0x78, 0x9C, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x03, 0x00];
+        assert_eq!(padding_bits(&stream).unwrap(), vec![19, 20, 21, 22, 23]);
+
+        assert!(padding_bits(&[0x78, 0x9C, 0x00, 0x00, 0x00, 0xFF, 0xFE]).is_err());
+    }
+
+    #[test]
+    fn test_padding_bits_of_compressed_data() {
+        let data: Vec<u8> = (0..5000).map(|index| (index * 7 % 251) as u8).collect();
+        let stream = encoder::deflate(&data, 9).unwrap();
+        assert_eq!(padding_bits(&stream).unwrap(), Vec::<usize>::new());
+    }
+}
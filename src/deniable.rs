@@ -0,0 +1,219 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use rand::{seq::SliceRandom, Rng, RngCore};
+
+use crate::Error;
+
+// Several payloads under different passphrases, where nobody can tell how many slots are
+// in use. The container has no magic or header that isn't random:
+//
+//   salt   16 bytes, random
+//   slots  all the same size, each one:
+//            nonce       12 bytes, random
+//            ciphertext  ChaCha20-Poly1305 of the slot plaintext, with its 16 byte tag
+//
+// A slot plaintext is the payload length (4 bytes, big-endian), the payload and random
+// padding up to the size of the largest payload. Unused slots are random bytes of the same
+// size. The key of a passphrase is Argon2id of the passphrase and the salt, opening tries
+// every slot with it, and the slot count follows from the container length.
+
+/// The slot count if none is given, so a single payload doesn't stand out
+pub const DEFAULT_SLOTS: usize = 4;
+
+/// The most slots a container can have
+pub const MAX_SLOTS: usize = 16;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const LENGTH_SIZE: usize = 4;
+
+/// Hides every payload in a slot of its own, encrypted with its passphrase
+pub fn seal(secrets: &[(&str, &[u8])], slots: usize) -> Result<Vec<u8>, Error> {
+    if !(1..=MAX_SLOTS).contains(&slots) {
+        return Err(DeniableError::InvalidSlotCount(slots).into());
+    }
+    if secrets.is_empty() || secrets.len() > slots {
+        return Err(DeniableError::TooManySecrets(secrets.len(), slots).into());
+    }
+    for (index, (passphrase, _)) in secrets.iter().enumerate() {
+        if secrets[..index]
+            .iter()
+            .any(|(other, _)| other == passphrase)
+        {
+            return Err(DeniableError::DuplicatePassphrase.into());
+        }
+    }
+
+    let largest = secrets
+        .iter()
+        .map(|(_, payload)| payload.len())
+        .max()
+        .unwrap_or(0);
+    if largest > u32::MAX as usize {
+        return Err(DeniableError::TooLarge.into());
+    }
+    let plaintext_size = LENGTH_SIZE + largest;
+    let slot_size = NONCE_SIZE + plaintext_size + TAG_SIZE;
+
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; SALT_SIZE];
+    rng.fill_bytes(&mut salt);
+
+    // Which slot every secret goes in, the rest stay random
+    let mut order: Vec<usize> = (0..slots).collect();
+    order.shuffle(&mut rng);
+
+    let mut container = salt.to_vec();
+    for slot in 0..slots {
+        let secret = order
+            .iter()
+            .position(|&assigned| assigned == slot)
+            .and_then(|index| secrets.get(index));
+
+        let (passphrase, payload) = match secret {
+            Some(secret) => secret,
+            None => {
+                let mut decoy = vec![0u8; slot_size];
+                rng.fill_bytes(&mut decoy);
+                container.extend(decoy);
+                continue;
+            }
+        };
+
+        let mut plaintext = (payload.len() as u32).to_be_bytes().to_vec();
+        plaintext.extend_from_slice(payload);
+        let mut padding = vec![0u8; plaintext_size - plaintext.len()];
+        rng.fill_bytes(&mut padding);
+        plaintext.extend(padding);
+
+        let nonce: [u8; NONCE_SIZE] = rng.gen();
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), &plaintext[..])
+            .map_err(|_| DeniableError::Encryption)?;
+
+        container.extend_from_slice(&nonce);
+        container.extend(ciphertext);
+    }
+
+    Ok(container)
+}
+
+/// Finds the slot the passphrase opens and returns its payload
+pub fn open(container: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if container.len() < SALT_SIZE {
+        return Err(DeniableError::NoSlot.into());
+    }
+
+    let (salt, slots) = container.split_at(SALT_SIZE);
+    let cipher = cipher(passphrase, salt)?;
+
+    for count in 1..=MAX_SLOTS {
+        if slots.len() % count != 0 || slots.len() / count < NONCE_SIZE + LENGTH_SIZE + TAG_SIZE {
+            continue;
+        }
+
+        for slot in slots.chunks_exact(slots.len() / count) {
+            let (nonce, ciphertext) = slot.split_at(NONCE_SIZE);
+
+            if let Ok(plaintext) = cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+                let length = u32::from_be_bytes(plaintext[..LENGTH_SIZE].try_into()?) as usize;
+                return match plaintext.get(LENGTH_SIZE..LENGTH_SIZE + length) {
+                    Some(payload) => Ok(payload.to_vec()),
+                    None => Err(DeniableError::NoSlot.into()),
+                };
+            }
+        }
+    }
+
+    Err(DeniableError::NoSlot.into())
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| DeniableError::KeyDerivation)?;
+
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeniableError {
+    #[error("{0} slots is invalid, it must be 1 to 16.")]
+    InvalidSlotCount(usize),
+
+    #[error("{0} payloads don't fit in {1} slots.")]
+    TooManySecrets(usize, usize),
+
+    #[error("Every payload needs a passphrase of its own.")]
+    DuplicatePassphrase,
+
+    #[error("The payload is too large to encrypt.")]
+    TooLarge,
+
+    #[error("The key could not be derived from the passphrase.")]
+    KeyDerivation,
+
+    #[error("The payload could not be encrypted.")]
+    Encryption,
+
+    #[error("No payload opens with this passphrase.")]
+    NoSlot,
+}
+
+#[cfg(test)]
+mod deniable_tests {
+    use super::*;
+
+    #[test]
+    fn test_every_passphrase_opens_its_own_payload() {
+        let secrets: [(&str, &[u8]); 3] = [
+            ("first", b"the real message"),
+            ("second", b"a decoy"),
+            ("third", b""),
+        ];
+        let container = seal(&secrets, 5).unwrap();
+
+        // Salt, then 5 slots sized for the largest payload
+        assert_eq!(container.len(), 16 + 5 * (12 + 4 + 16 + 16));
+
+        for (passphrase, payload) in secrets {
+            assert_eq!(open(&container, passphrase).unwrap(), payload);
+        }
+        assert!(open(&container, "fourth").is_err());
+    }
+
+    #[test]
+    fn test_containers_look_random() {
+        let secrets: [(&str, &[u8]); 1] = [("key", b"same message")];
+        let first = seal(&secrets, 2).unwrap();
+        let second = seal(&secrets, 2).unwrap();
+
+        assert_eq!(first.len(), second.len());
+        assert_ne!(first, second);
+        assert!(!first.windows(12).any(|window| window == b"same message"));
+    }
+
+    #[test]
+    fn test_invalid_secrets() {
+        let payload: &[u8] = b"message";
+        assert!(seal(&[], 4).is_err());
+        assert!(seal(&[("a", payload), ("b", payload)], 1).is_err());
+        assert!(seal(&[("a", payload), ("a", payload)], 4).is_err());
+        assert!(seal(&[("a", payload)], MAX_SLOTS + 1).is_err());
+    }
+
+    #[test]
+    fn test_open_damaged_container() {
+        let mut container = seal(&[("key", b"message")], 1).unwrap();
+        let last = container.len() - 1;
+        container[last] ^= 1;
+
+        assert!(open(&container, "key").is_err());
+        assert!(open(&container[..8], "key").is_err());
+    }
+}
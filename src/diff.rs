@@ -0,0 +1,468 @@
+use sha2::{Digest, Sha256};
+
+use crate::{chunk::Chunk, decoder, palette::Transparency, png::Png, Error};
+
+// Chunks are matched by their type and a digest of their data. The longest common
+// subsequence of the two chunk lists is what stayed in place, of the rest a chunk found
+// in both files moved, a chunk whose type is left on both sides was modified and the
+// others were added or removed.
+
+/// What happened to a chunk between two PNG files. Indices are positions in the file
+/// the chunk is in, `from` in the old file and `to` in the new one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ChunkChange {
+    Added {
+        to: usize,
+        chunk_type: String,
+    },
+    Removed {
+        from: usize,
+        chunk_type: String,
+    },
+    Moved {
+        from: usize,
+        to: usize,
+        chunk_type: String,
+    },
+    Modified {
+        from: usize,
+        to: usize,
+        chunk_type: String,
+        bytes: Vec<ByteChange>,
+    },
+}
+
+/// A run of bytes that differs between two versions of a chunk's data
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ByteChange {
+    pub offset: usize,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// The chunk level differences between two PNG files
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PngDiff {
+    pub changes: Vec<ChunkChange>,
+    /// The number of chunks that stayed as they were
+    pub unchanged: usize,
+    pub trailing_bytes_changed: bool,
+}
+
+/// How the decoded pixels of two PNG files differ
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PixelDiff {
+    SizeMismatch {
+        old: (usize, usize),
+        new: (usize, usize),
+    },
+    /// The largest difference is of any channel, in 16 bit samples
+    Compared { differing: usize, max_delta: u16 },
+}
+
+impl PngDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && !self.trailing_bytes_changed
+    }
+}
+
+/// Compares the chunks of two PNG files
+pub fn diff(old: &Png, new: &Png) -> PngDiff {
+    let old_keys: Vec<Key> = old.chunks().iter().map(Key::new).collect();
+    let new_keys: Vec<Key> = new.chunks().iter().map(Key::new).collect();
+
+    let matched = longest_common_subsequence(&old_keys, &new_keys);
+
+    let mut removed: Vec<usize> = (0..old_keys.len())
+        .filter(|index| !matched.iter().any(|(from, _)| from == index))
+        .collect();
+    let mut added: Vec<usize> = (0..new_keys.len())
+        .filter(|index| !matched.iter().any(|(_, to)| to == index))
+        .collect();
+
+    let mut changes = Vec::new();
+    let chunk_type = |index: usize| old.chunks()[index].chunk_type().to_string();
+
+    // The same chunk on both sides, but out of order
+    removed.retain(
+        |&from| match added.iter().position(|&to| new_keys[to] == old_keys[from]) {
+            Some(position) => {
+                let to = added.remove(position);
+                changes.push(ChunkChange::Moved {
+                    from,
+                    to,
+                    chunk_type: chunk_type(from),
+                });
+                false
+            }
+            None => true,
+        },
+    );
+
+    // A chunk of the same type on both sides
+    removed.retain(|&from| {
+        match added
+            .iter()
+            .position(|&to| new_keys[to].chunk_type == old_keys[from].chunk_type)
+        {
+            Some(position) => {
+                let to = added.remove(position);
+                changes.push(ChunkChange::Modified {
+                    from,
+                    to,
+                    chunk_type: chunk_type(from),
+                    bytes: diff_bytes(old.chunks()[from].data(), new.chunks()[to].data()),
+                });
+                false
+            }
+            None => true,
+        }
+    });
+
+    changes.extend(removed.into_iter().map(|from| ChunkChange::Removed {
+        from,
+        chunk_type: chunk_type(from),
+    }));
+    changes.extend(added.into_iter().map(|to| ChunkChange::Added {
+        to,
+        chunk_type: new.chunks()[to].chunk_type().to_string(),
+    }));
+
+    PngDiff {
+        changes,
+        unchanged: matched.len(),
+        trailing_bytes_changed: old.trailing_bytes() != new.trailing_bytes(),
+    }
+}
+
+/// The runs of bytes that differ between two versions of some data. Data of the same
+/// length is compared byte by byte, otherwise the common start and end are left out and
+/// the rest is a single change.
+pub fn diff_bytes(old: &[u8], new: &[u8]) -> Vec<ByteChange> {
+    if old.len() != new.len() {
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        return vec![ByteChange {
+            offset: prefix,
+            old: old[prefix..old.len() - suffix].to_vec(),
+            new: new[prefix..new.len() - suffix].to_vec(),
+        }];
+    }
+
+    let mut changes: Vec<ByteChange> = Vec::new();
+    for (offset, (&a, &b)) in old.iter().zip(new).enumerate() {
+        if a == b {
+            continue;
+        }
+
+        match changes.last_mut() {
+            Some(change) if change.offset + change.old.len() == offset => {
+                change.old.push(a);
+                change.new.push(b);
+            }
+            _ => changes.push(ByteChange {
+                offset,
+                old: vec![a],
+                new: vec![b],
+            }),
+        }
+    }
+    changes
+}
+
+/// Decodes both files and compares their pixels as 16 bit RGBA, with the transparency of
+/// their tRNS chunks
+pub fn diff_pixels(old_png: &Png, new_png: &Png) -> Result<PixelDiff, Error> {
+    let old = decoder::decode(old_png)?;
+    let new = decoder::decode(new_png)?;
+    let old_transparency = Transparency::from_png(old_png)?;
+    let new_transparency = Transparency::from_png(new_png)?;
+
+    let old_size = (old.width(), old.height());
+    let new_size = (new.width(), new.height());
+    if old_size != new_size {
+        return Ok(PixelDiff::SizeMismatch {
+            old: old_size,
+            new: new_size,
+        });
+    }
+
+    let mut differing = 0;
+    let mut max_delta = 0;
+    for y in 0..old.height() {
+        for x in 0..old.width() {
+            let delta = old
+                .transparent_rgba16(x, y, old_transparency.as_ref())
+                .iter()
+                .zip(new.transparent_rgba16(x, y, new_transparency.as_ref()))
+                .map(|(&a, b)| a.abs_diff(b))
+                .max()
+                .unwrap_or(0);
+
+            if delta > 0 {
+                differing += 1;
+                max_delta = max_delta.max(delta);
+            }
+        }
+    }
+
+    Ok(PixelDiff::Compared {
+        differing,
+        max_delta,
+    })
+}
+
+/// What two chunks are compared by
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Key {
+    chunk_type: [u8; 4],
+    digest: [u8; 32],
+}
+
+impl Key {
+    fn new(chunk: &Chunk) -> Key {
+        Key {
+            chunk_type: chunk.chunk_type().bytes(),
+            digest: Sha256::digest(chunk.data()).into(),
+        }
+    }
+}
+
+/// The index pairs of the longest common subsequence, in order
+fn longest_common_subsequence(old: &[Key], new: &[Key]) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::{
+        chunk_type::ChunkType,
+        encoder::{self, EncoderOptions},
+    };
+
+    fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::new(*chunk_type), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::try_from(&include_bytes!("../sss.png")[..]).unwrap()
+    }
+
+    #[test]
+    fn test_same_file() {
+        let png = testing_png();
+        let diff = diff(&png, &png);
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, png.chunks().len());
+        assert_eq!(
+            diff_pixels(&png, &png).unwrap(),
+            PixelDiff::Compared {
+                differing: 0,
+                max_delta: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_added_removed_moved_and_modified() {
+        let old = Png::from_chunks(vec![
+            chunk(b"IHDR", b"header"),
+            chunk(b"RuSt", b"moves"),
+            chunk(b"tEXt", b"a\0one"),
+            chunk(b"zTXt", b"gone"),
+            chunk(b"gAMA", b"gamma"),
+            chunk(b"IDAT", b"data"),
+            chunk(b"IEND", b""),
+        ]);
+        let new = Png::from_chunks(vec![
+            chunk(b"IHDR", b"header"),
+            chunk(b"tEXt", b"a\0two"),
+            chunk(b"gAMA", b"gamma"),
+            chunk(b"IDAT", b"data"),
+            chunk(b"RuSt", b"moves"),
+            chunk(b"pHYs", b"new"),
+            chunk(b"IEND", b""),
+        ]);
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.unchanged, 4);
+        assert_eq!(
+            diff.changes,
+            vec![
+                ChunkChange::Moved {
+                    from: 1,
+                    to: 4,
+                    chunk_type: "RuSt".to_string()
+                },
+                ChunkChange::Modified {
+                    from: 2,
+                    to: 1,
+                    chunk_type: "tEXt".to_string(),
+                    bytes: vec![ByteChange {
+                        offset: 2,
+                        old: b"one".to_vec(),
+                        new: b"two".to_vec()
+                    }]
+                },
+                ChunkChange::Removed {
+                    from: 3,
+                    chunk_type: "zTXt".to_string()
+                },
+                ChunkChange::Added {
+                    to: 5,
+                    chunk_type: "pHYs".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_bytes() {
+        assert_eq!(
+            diff_bytes(b"abcdef", b"aXcdYZ"),
+            vec![
+                ByteChange {
+                    offset: 1,
+                    old: b"b".to_vec(),
+                    new: b"X".to_vec()
+                },
+                ByteChange {
+                    offset: 4,
+                    old: b"ef".to_vec(),
+                    new: b"YZ".to_vec()
+                },
+            ]
+        );
+
+        assert_eq!(
+            diff_bytes(b"hello world", b"hello big world"),
+            vec![ByteChange {
+                offset: 6,
+                old: Vec::new(),
+                new: b"big ".to_vec()
+            }]
+        );
+        assert!(diff_bytes(b"same", b"same").is_empty());
+    }
+
+    #[test]
+    fn test_diff_pixels() {
+        let png = testing_png();
+        let mut pixels = decoder::decode(&png).unwrap();
+        let sample = pixels.sample(2, 1, 0);
+        pixels.set_sample(2, 1, 0, sample ^ 0x10);
+
+        let keep: Vec<ChunkType> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().clone())
+            .collect();
+        let (changed, _) =
+            encoder::encode(&png, &pixels, &EncoderOptions::default(), &keep).unwrap();
+
+        let max_delta = match diff_pixels(&png, &changed).unwrap() {
+            PixelDiff::Compared {
+                differing,
+                max_delta,
+            } => {
+                assert_eq!(differing, 1);
+                max_delta
+            }
+            other => panic!("Expected compared pixels, got {:?}", other),
+        };
+        assert!(max_delta > 0);
+
+        // The image data changed, the other chunks stayed
+        assert!(diff(&png, &changed)
+            .changes
+            .iter()
+            .all(|change| matches!(change, ChunkChange::Modified { chunk_type, .. } if chunk_type == "IDAT")));
+    }
+
+    #[test]
+    fn test_diff_pixels_with_transparency() {
+        use crate::palette::Palette;
+
+        // sss.png is indexed, making an entry transparent changes the pixels using it
+        let png = testing_png();
+        let pixels = decoder::decode(&png).unwrap();
+        let index = pixels.sample(0, 0, 0) as usize;
+        let users = (0..pixels.height())
+            .flat_map(|y| (0..pixels.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| pixels.sample(x, y, 0) as usize == index)
+            .count();
+
+        let palette = Palette::from_png(&png).unwrap().unwrap();
+        let mut alphas = vec![255; palette.len()];
+        alphas[index] = 0;
+        let mut transparent = png.clone();
+        transparent
+            .append_chunk(Transparency::Indexed(alphas).to_chunk())
+            .unwrap();
+
+        assert_eq!(
+            diff_pixels(&png, &transparent).unwrap(),
+            PixelDiff::Compared {
+                differing: users,
+                max_delta: 65535,
+            }
+        );
+
+        // An opaque tRNS changes nothing
+        let mut opaque = png.clone();
+        opaque
+            .append_chunk(Transparency::Indexed(vec![255; palette.len()]).to_chunk())
+            .unwrap();
+        assert_eq!(
+            diff_pixels(&png, &opaque).unwrap(),
+            PixelDiff::Compared {
+                differing: 0,
+                max_delta: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let png = testing_png();
+        let mut trailed = png.clone();
+        trailed.set_trailing_bytes(b"extra".to_vec());
+
+        let diff = diff(&png, &trailed);
+        assert!(diff.changes.is_empty());
+        assert!(diff.trailing_bytes_changed);
+    }
+}
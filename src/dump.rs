@@ -0,0 +1,277 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Error};
+
+// A PNG file taken apart for forensic work: every chunk's data goes to a file of its own
+// and a JSON manifest records their order, types and CRCs. The data files can be edited
+// before the file is put back together, the CRCs are computed again on assembly.
+
+pub const MANIFEST: &str = "manifest.json";
+
+/// Where the bytes after the last chunk go, if there are any
+const TRAILING_FILE: &str = "trailing.bin";
+
+/// The order, types and CRCs of the chunks of a taken apart PNG file
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub chunks: Vec<ManifestEntry>,
+    /// The file holding the bytes after the last chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailing: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub index: usize,
+    pub chunk_type: String,
+    /// The file holding the chunk data, relative to the manifest
+    pub file: String,
+    pub length: u32,
+    /// The CRC of the chunk when it was extracted
+    pub crc: u32,
+}
+
+/// A PNG file put back together from its chunk files
+pub struct Assembled {
+    pub png: Png,
+    /// Indices of the chunks whose data no longer matches the CRC in the manifest
+    pub edited: Vec<usize>,
+}
+
+/// Writes every chunk of a PNG file to `<index>_<type>.bin` in a directory, along with
+/// the manifest
+pub fn extract(png: &Png, directory: &Path) -> Result<Manifest, Error> {
+    std::fs::create_dir_all(directory)?;
+
+    let mut chunks = Vec::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type().to_string();
+        let file = format!("{}_{}.bin", index, chunk_type);
+        std::fs::write(directory.join(&file), chunk.data())?;
+
+        chunks.push(ManifestEntry {
+            index,
+            chunk_type,
+            file,
+            length: chunk.length(),
+            crc: chunk.crc(),
+        });
+    }
+
+    let mut trailing = None;
+    if !png.trailing_bytes().is_empty() {
+        std::fs::write(directory.join(TRAILING_FILE), png.trailing_bytes())?;
+        trailing = Some(TRAILING_FILE.to_string());
+    }
+
+    let manifest = Manifest { chunks, trailing };
+    std::fs::write(
+        directory.join(MANIFEST),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    Ok(manifest)
+}
+
+/// Rebuilds a PNG file from a directory written by `extract`. Chunks are put in the order
+/// of their index, so entries can be moved around in the manifest.
+pub fn assemble(directory: &Path) -> Result<Assembled, Error> {
+    let manifest: Manifest =
+        serde_json::from_str(&std::fs::read_to_string(directory.join(MANIFEST))?)?;
+
+    let mut entries: Vec<&ManifestEntry> = manifest.chunks.iter().collect();
+    entries.sort_by_key(|entry| entry.index);
+
+    let mut chunks = Vec::new();
+    let mut edited = Vec::new();
+    for entry in entries {
+        // Chunks with the reserved bit set are kept, they may be the very thing under study
+        let chunk_type = match ChunkType::from_str(&entry.chunk_type) {
+            Ok(chunk_type) => chunk_type,
+            Err(_) => return Err(DumpError::InvalidChunkType(entry.chunk_type.clone()).into()),
+        };
+
+        let data = match std::fs::read(manifest_path(directory, &entry.file)?) {
+            Ok(data) => data,
+            Err(_) => return Err(DumpError::MissingFile(entry.file.clone()).into()),
+        };
+
+        let chunk = Chunk::new(chunk_type, data);
+        if chunk.crc() != entry.crc {
+            edited.push(entry.index);
+        }
+        chunks.push(chunk);
+    }
+
+    let mut png = Png::from_chunks(chunks);
+    if let Some(file) = &manifest.trailing {
+        match std::fs::read(manifest_path(directory, file)?) {
+            Ok(bytes) => png.set_trailing_bytes(bytes),
+            Err(_) => return Err(DumpError::MissingFile(file.clone()).into()),
+        }
+    }
+
+    Ok(Assembled { png, edited })
+}
+
+/// Resolves a file named in the manifest. Only plain relative paths are allowed, so a
+/// manifest can't read files outside its directory.
+fn manifest_path(directory: &Path, file: &str) -> Result<PathBuf, Error> {
+    let path = Path::new(file);
+    let plain = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    if !plain || path.as_os_str().is_empty() {
+        return Err(DumpError::InvalidPath(file.to_string()).into());
+    }
+
+    Ok(directory.join(path))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DumpError {
+    #[error("'{0}' in the manifest is not a valid chunk type.")]
+    InvalidChunkType(String),
+
+    #[error("The chunk file '{0}' can't be read.")]
+    MissingFile(String),
+
+    #[error("'{0}' in the manifest is not a plain relative path.")]
+    InvalidPath(String),
+}
+
+#[cfg(test)]
+mod dump_tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::try_from(&include_bytes!("../sss.png")[..]).unwrap()
+    }
+
+    /// A fresh directory in the temporary directory
+    fn directory(name: &str) -> std::path::PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("sspngme_dump_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_extract_and_assemble() {
+        let mut png = testing_png();
+        png.set_trailing_bytes(b"after the end".to_vec());
+        let directory = directory("round_trip");
+
+        let manifest = extract(&png, &directory).unwrap();
+        assert_eq!(manifest.chunks.len(), png.chunks().len());
+        assert_eq!(manifest.chunks[0].file, "0_IHDR.bin");
+        assert!(directory.join("0_IHDR.bin").exists());
+
+        let assembled = assemble(&directory).unwrap();
+        assert_eq!(assembled.png.as_bytes(), png.as_bytes());
+        assert!(assembled.edited.is_empty());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_assemble_edited_chunk() {
+        let png = testing_png();
+        let directory = directory("edited");
+
+        let manifest = extract(&png, &directory).unwrap();
+        let last = manifest.chunks.last().unwrap();
+        std::fs::write(directory.join(&last.file), b"edited by hand").unwrap();
+
+        let assembled = assemble(&directory).unwrap();
+        let chunk = assembled.png.chunks().last().unwrap();
+        assert_eq!(chunk.data(), b"edited by hand");
+        assert_eq!(assembled.edited, vec![last.index]);
+
+        // The CRC was computed again, so the file reads back
+        let bytes = assembled.png.as_bytes();
+        assert!(Png::try_from(&bytes[..]).is_ok());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_assemble_in_index_order() {
+        let png = testing_png();
+        let directory = directory("reordered");
+
+        let mut manifest = extract(&png, &directory).unwrap();
+        manifest.chunks.reverse();
+        std::fs::write(
+            directory.join(MANIFEST),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        let assembled = assemble(&directory).unwrap();
+        assert_eq!(assembled.png.as_bytes(), png.as_bytes());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        let directory = directory("invalid");
+        let mut manifest = extract(&testing_png(), &directory).unwrap();
+
+        manifest.chunks[0].chunk_type = "IH1".to_string();
+        std::fs::write(
+            directory.join(MANIFEST),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(assemble(&directory).is_err());
+
+        manifest.chunks[0].chunk_type = "IHDR".to_string();
+        manifest.chunks[0].file = "missing.bin".to_string();
+        std::fs::write(
+            directory.join(MANIFEST),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(assemble(&directory).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_paths_stay_inside() {
+        let directory = directory("paths");
+        let manifest = extract(&testing_png(), &directory).unwrap();
+
+        for file in ["../outside.bin", "/etc/passwd", "a/../../b.bin", "./0_IHDR.bin", ""] {
+            let mut edited = manifest.clone();
+            edited.chunks[0].file = file.to_string();
+            std::fs::write(
+                directory.join(MANIFEST),
+                serde_json::to_string(&edited).unwrap(),
+            )
+            .unwrap();
+            assert!(assemble(&directory).is_err(), "{}", file);
+
+            let mut edited = manifest.clone();
+            edited.trailing = Some(file.to_string());
+            std::fs::write(
+                directory.join(MANIFEST),
+                serde_json::to_string(&edited).unwrap(),
+            )
+            .unwrap();
+            assert!(assemble(&directory).is_err(), "{}", file);
+        }
+
+        assert!(manifest_path(&directory, "0_IHDR.bin").is_ok());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use crate::Error;
+
+// Reed-Solomon coding over GF(2^8) with the 0x11d polynomial and the roots α^0 to
+// α^(parity - 1). The payload is split into blocks of at most 255 bytes (data and parity
+// together), so every block can fix up to parity / 2 damaged bytes.
+//
+// The encoded payload starts with a header protected by a code of its own:
+//   parity (1 byte), data length (4 bytes, big-endian), header parity (8 bytes)
+// followed by the blocks, the last one shortened to fit the data.
+
+/// The largest block the field allows
+const BLOCK_SIZE: usize = 255;
+
+/// The parity bytes of the header, it can fix 4 damaged bytes
+const HEADER_PARITY: usize = 8;
+
+const HEADER_LENGTH: usize = 5;
+
+const TABLES: ([u8; 512], [u8; 256]) = tables();
+
+/// The exponent and logarithm tables of the field, the exponent table is doubled so
+/// products don't need a modulo
+const fn tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+
+    let mut value: u16 = 1;
+    let mut index = 0;
+    while index < 255 {
+        exp[index] = value as u8;
+        log[value as usize] = index as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= 0x11d;
+        }
+        index += 1;
+    }
+    while index < 512 {
+        exp[index] = exp[index - 255];
+        index += 1;
+    }
+
+    (exp, log)
+}
+
+fn exp(power: usize) -> u8 {
+    TABLES.0[power % 255]
+}
+
+fn log(value: u8) -> usize {
+    TABLES.1[value as usize] as usize
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.0[log(a) + log(b)]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    TABLES.0[log(a) + 255 - log(b)]
+}
+
+fn inverse(value: u8) -> u8 {
+    TABLES.0[255 - log(value)]
+}
+
+/// Evaluates a polynomial stored lowest degree first
+fn eval(polynomial: &[u8], x: u8) -> u8 {
+    polynomial
+        .iter()
+        .rev()
+        .fold(0, |value, &coefficient| mul(value, x) ^ coefficient)
+}
+
+/// The generator polynomial (x - α^0)...(x - α^(parity - 1)), highest degree first
+fn generator(parity: usize) -> Vec<u8> {
+    let mut generator = vec![1u8];
+
+    for power in 0..parity {
+        let root = exp(power);
+        let mut product = vec![0u8; generator.len() + 1];
+        for (index, &coefficient) in generator.iter().enumerate() {
+            product[index] ^= coefficient;
+            product[index + 1] ^= mul(coefficient, root);
+        }
+        generator = product;
+    }
+
+    generator
+}
+
+/// Appends the parity bytes to a block of data
+fn encode_block(data: &[u8], generator: &[u8]) -> Vec<u8> {
+    let parity = generator.len() - 1;
+    let mut remainder = data.to_vec();
+    remainder.resize(data.len() + parity, 0);
+
+    for index in 0..data.len() {
+        let coefficient = remainder[index];
+        if coefficient != 0 {
+            for (offset, &factor) in generator.iter().enumerate().skip(1) {
+                remainder[index + offset] ^= mul(factor, coefficient);
+            }
+        }
+    }
+
+    let mut block = data.to_vec();
+    block.extend_from_slice(&remainder[data.len()..]);
+    block
+}
+
+/// Fixes a block in place and returns the number of bytes it changed
+fn decode_block(block: &mut [u8], parity: usize) -> Result<usize, Error> {
+    // The first byte of the block is the highest degree coefficient
+    let syndromes: Vec<u8> = (0..parity)
+        .map(|power| {
+            block.iter().fold(0, |value, &coefficient| {
+                mul(value, exp(power)) ^ coefficient
+            })
+        })
+        .collect();
+
+    if syndromes.iter().all(|&syndrome| syndrome == 0) {
+        return Ok(0);
+    }
+
+    // Berlekamp-Massey finds the error locator, lowest degree first
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    let mut errors = 0;
+    let mut shift = 1;
+    let mut previous_discrepancy = 1u8;
+
+    for step in 0..parity {
+        let discrepancy = (1..=errors).fold(syndromes[step], |value, index| {
+            value
+                ^ mul(
+                    locator.get(index).copied().unwrap_or(0),
+                    syndromes[step - index],
+                )
+        });
+
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let factor = div(discrepancy, previous_discrepancy);
+        let mut updated = locator.clone();
+        updated.resize(updated.len().max(previous.len() + shift), 0);
+        for (index, &coefficient) in previous.iter().enumerate() {
+            updated[index + shift] ^= mul(factor, coefficient);
+        }
+
+        if 2 * errors <= step {
+            previous = std::mem::replace(&mut locator, updated);
+            errors = step + 1 - errors;
+            previous_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            locator = updated;
+            shift += 1;
+        }
+    }
+
+    if 2 * errors > parity {
+        return Err(EccError::TooManyErrors.into());
+    }
+
+    // Chien search, byte `index` is the coefficient of x^(length - 1 - index)
+    let length = block.len();
+    let positions: Vec<usize> = (0..length)
+        .filter(|&index| {
+            let power = length - 1 - index;
+            eval(&locator, inverse(exp(power))) == 0
+        })
+        .collect();
+
+    if positions.len() != errors {
+        return Err(EccError::TooManyErrors.into());
+    }
+
+    // Forney: the evaluator is the syndromes times the locator, modulo x^parity
+    let mut evaluator = vec![0u8; parity];
+    for (index, &syndrome) in syndromes.iter().enumerate() {
+        for (offset, &coefficient) in locator.iter().enumerate() {
+            if index + offset < parity {
+                evaluator[index + offset] ^= mul(syndrome, coefficient);
+            }
+        }
+    }
+
+    // The formal derivative only keeps the odd powers in GF(2^8)
+    let derivative: Vec<u8> = locator
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(power, &coefficient)| if power % 2 == 1 { coefficient } else { 0 })
+        .collect();
+
+    for &index in &positions {
+        let location = exp(length - 1 - index);
+        let inverse_location = inverse(location);
+
+        let denominator = eval(&derivative, inverse_location);
+        if denominator == 0 {
+            return Err(EccError::TooManyErrors.into());
+        }
+
+        let magnitude = mul(
+            location,
+            div(eval(&evaluator, inverse_location), denominator),
+        );
+        block[index] ^= magnitude;
+    }
+
+    Ok(positions.len())
+}
+
+/// Protects a payload with Reed-Solomon coding, `parity` bytes in every block
+pub fn encode(payload: &[u8], parity: usize) -> Result<Vec<u8>, Error> {
+    if !(2..BLOCK_SIZE).contains(&parity) {
+        return Err(EccError::InvalidParity(parity).into());
+    }
+
+    if payload.len() > u32::MAX as usize {
+        return Err(EccError::TooLarge.into());
+    }
+
+    let mut header = vec![parity as u8];
+    header.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    let mut encoded = encode_block(&header, &generator(HEADER_PARITY));
+
+    let generator = generator(parity);
+    for data in payload.chunks(BLOCK_SIZE - parity) {
+        encoded.extend(encode_block(data, &generator));
+    }
+
+    Ok(encoded)
+}
+
+/// Corrects and unwraps a payload made by `encode`. Returns the payload and the number
+/// of bytes that had to be fixed.
+pub fn decode(encoded: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    decode_blocks(encoded, usize::MAX)
+}
+
+/// Corrects and unwraps only the first block of a payload made by `encode`, enough to
+/// tell what it holds without decoding all of it
+pub fn decode_start(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(decode_blocks(encoded, 1)?.0)
+}
+
+/// Decodes the header and at most `limit` blocks
+fn decode_blocks(encoded: &[u8], limit: usize) -> Result<(Vec<u8>, usize), Error> {
+    let header_size = HEADER_LENGTH + HEADER_PARITY;
+    if encoded.len() < header_size {
+        return Err(EccError::Truncated.into());
+    }
+
+    let mut header = encoded[..header_size].to_vec();
+    let mut corrected = decode_block(&mut header, HEADER_PARITY)?;
+
+    let parity = header[0] as usize;
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if !(2..BLOCK_SIZE).contains(&parity) {
+        return Err(EccError::InvalidParity(parity).into());
+    }
+
+    let data_size = BLOCK_SIZE - parity;
+    let blocks = length.div_ceil(data_size);
+    if encoded.len() < header_size + length + blocks * parity {
+        return Err(EccError::Truncated.into());
+    }
+
+    let mut payload = Vec::with_capacity(length.min(limit.saturating_mul(data_size)));
+    let mut position = header_size;
+    for block_index in 0..blocks.min(limit) {
+        let data_length = data_size.min(length - block_index * data_size);
+        let mut block = encoded[position..position + data_length + parity].to_vec();
+
+        corrected += decode_block(&mut block, parity)?;
+        payload.extend_from_slice(&block[..data_length]);
+        position += data_length + parity;
+    }
+
+    Ok((payload, corrected))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EccError {
+    #[error("{0} parity bytes per block is invalid, it must be 2 to 254.")]
+    InvalidParity(usize),
+
+    #[error("The payload is too large to encode.")]
+    TooLarge,
+
+    #[error("The encoded payload is cut off.")]
+    Truncated,
+
+    #[error("The payload is damaged beyond what the parity bytes can fix.")]
+    TooManyErrors,
+}
+
+#[cfg(test)]
+mod ecc_tests {
+    use super::*;
+
+    fn testing_payload() -> Vec<u8> {
+        (0..600).map(|index| (index * 31 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = testing_payload();
+        let encoded = encode(&payload, 16).unwrap();
+
+        // 13 header bytes, then 3 blocks with 16 parity bytes each
+        assert_eq!(encoded.len(), 13 + 600 + 3 * 16);
+        assert_eq!(decode(&encoded).unwrap(), (payload, 0));
+    }
+
+    #[test]
+    fn test_decode_start() {
+        let payload = testing_payload();
+        let mut encoded = encode(&payload, 16).unwrap();
+        encoded[20] ^= 0xFF;
+
+        assert_eq!(decode_start(&encoded).unwrap(), &payload[..239]);
+        assert!(decode_start(&payload).is_err());
+    }
+
+    #[test]
+    fn test_corrects_errors() {
+        let payload = testing_payload();
+        let mut encoded = encode(&payload, 16).unwrap();
+
+        // 4 errors in the header, 8 in the first two blocks and 1 in the last
+        for index in (0..4)
+            .chain((20..100).step_by(10))
+            .chain((300..316).step_by(2))
+        {
+            encoded[index] ^= 0x5A;
+        }
+        let last = encoded.len() - 1;
+        encoded[last] = !encoded[last];
+
+        let (decoded, corrected) = decode(&encoded).unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(corrected, 4 + 8 + 8 + 1);
+    }
+
+    #[test]
+    fn test_too_many_errors() {
+        let payload = testing_payload();
+        let mut encoded = encode(&payload, 4).unwrap();
+        for byte in &mut encoded[20..23] {
+            *byte ^= 1;
+        }
+
+        assert!(decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_short_payload() {
+        let encoded = encode(b"hi", 2).unwrap();
+        let mut damaged = encoded.clone();
+        damaged[13] = b'X';
+
+        assert_eq!(decode(&damaged).unwrap(), (b"hi".to_vec(), 1));
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_parity() {
+        assert!(encode(b"hi", 1).is_err());
+        assert!(encode(b"hi", 255).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    decoder::{self, PixelBuffer, ADAM7},
+    envelope,
+    filter::{self, FilterType},
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    Error,
+};
+
+/// How the filter of every row is chosen
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FilterStrategy {
+    /// Use the same filter for every row
+    Fixed(FilterType),
+    /// Pick the filter with the minimum sum of absolute differences for every row
+    Adaptive,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EncoderOptions {
+    pub filter: FilterStrategy,
+    /// The deflate compression level, from 0 (stored) to 9 (best)
+    pub level: u32,
+    /// The maximum number of bytes in a single IDAT chunk
+    pub idat_size: usize,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            filter: FilterStrategy::Adaptive,
+            level: 6,
+            idat_size: 8192,
+        }
+    }
+}
+
+/// Filters every row of the image, handling the Adam7 passes of interlaced images.
+/// Returns the bytes that go into the zlib stream.
+pub fn filter_image(header: &ImageHeader, data: &[u8], strategy: FilterStrategy) -> Vec<u8> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+
+    if !header.interlaced {
+        return filter_rows(header, width, height, data, strategy);
+    }
+
+    let stride = header.stride(width);
+    let bits_per_pixel = header.bits_per_pixel();
+    let mut filtered = Vec::new();
+
+    for (pass, &(x_start, y_start, x_step, y_step)) in ADAM7.iter().enumerate() {
+        let (pass_width, pass_height) = decoder::pass_size(header, pass);
+        if pass_width == 0 {
+            continue;
+        }
+
+        // Collect the pixels of the pass into rows of their own
+        let pass_stride = header.stride(pass_width);
+        let mut pass_data = vec![0u8; pass_stride * pass_height];
+        for y in 0..pass_height {
+            for x in 0..pass_width {
+                decoder::copy_pixel(
+                    &data[(y_start + y * y_step) * stride..],
+                    x_start + x * x_step,
+                    &mut pass_data[y * pass_stride..],
+                    x,
+                    bits_per_pixel,
+                );
+            }
+        }
+
+        filtered.extend(filter_rows(
+            header,
+            pass_width,
+            pass_height,
+            &pass_data,
+            strategy,
+        ));
+    }
+
+    filtered
+}
+
+fn filter_rows(
+    header: &ImageHeader,
+    width: usize,
+    height: usize,
+    data: &[u8],
+    strategy: FilterStrategy,
+) -> Vec<u8> {
+    let stride = header.stride(width);
+    let distance = header.filter_distance();
+
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    let mut previous = vec![0u8; stride];
+
+    for row in data.chunks_exact(stride).take(height) {
+        let (filter_type, bytes) = match strategy {
+            FilterStrategy::Fixed(filter_type) => (
+                filter_type,
+                filter::filter(filter_type, distance, &previous, row),
+            ),
+            FilterStrategy::Adaptive => filter::filter_adaptive(distance, &previous, row),
+        };
+
+        filtered.push(filter_type as u8);
+        filtered.extend_from_slice(&bytes);
+        previous = row.to_vec();
+    }
+
+    filtered
+}
+
+/// Compresses data into a zlib stream at the given level
+pub fn deflate(data: &[u8], level: u32) -> Result<Vec<u8>, Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Splits a zlib stream into IDAT chunks of at most `idat_size` bytes
+pub fn split_image_data(stream: &[u8], idat_size: usize) -> Vec<Chunk> {
+    stream
+        .chunks(idat_size.max(1))
+        .map(|piece| Chunk::new(ChunkType::new(*b"IDAT"), piece.to_vec()))
+        .collect()
+}
+
+/// Filters and compresses packed rows into a zlib stream
+pub fn encode_image_data(
+    header: &ImageHeader,
+    data: &[u8],
+    options: &EncoderOptions,
+) -> Result<Vec<u8>, Error> {
+    if data.len() != header.stride(header.width as usize) * header.height as usize {
+        return Err(EncodeError::SizeMismatch.into());
+    }
+
+    deflate(&filter_image(header, data, options.filter), options.level)
+}
+
+/// Writes pixels back into a PNG file. The result keeps every chunk of `png` in its
+/// place, with new IDAT chunks (and IHDR and PLTE if the pixels changed them). The copy
+/// rules for modified image data apply, unknown unsafe-to-copy chunks are dropped unless
+/// listed in `keep`. Returns the new file and the dropped chunks.
+pub fn encode(
+    png: &Png,
+    pixels: &PixelBuffer,
+    options: &EncoderOptions,
+    keep: &[ChunkType],
+) -> Result<(Png, Vec<Chunk>), Error> {
+    let stream = encode_image_data(&pixels.header, &pixels.data, options)?;
+
+    let mut encoded = png.clone();
+
+    if ImageHeader::from_png(png)? != pixels.header {
+        let index = png
+            .chunks()
+            .iter()
+            .position(|chunk| &chunk.chunk_type().bytes() == b"IHDR")
+            .unwrap();
+        encoded.splice_chunks(index..index + 1, vec![pixels.header.to_chunk()])?;
+    }
+
+    let palette = pixels
+        .palette
+        .as_ref()
+        .map(|palette| Chunk::new(ChunkType::new(*b"PLTE"), palette.concat()));
+
+    let dropped =
+        encoded.replace_image_data(palette, split_image_data(&stream, options.idat_size), keep)?;
+
+    // Grayscale images can't have a palette, not even a suggested one
+    let grayscale = matches!(
+        pixels.header.color_type,
+        ColorType::Grayscale | ColorType::GrayscaleAlpha
+    );
+    if grayscale && encoded.chunk_by_type("PLTE").is_some() {
+        encoded.remove_chunk("PLTE")?;
+    }
+
+    Ok((encoded, dropped))
+}
+
+/// The unsafe-to-copy types the copy rules should spare: those of the chunks holding one of
+/// our envelopes, and the private types given by the user
+pub fn payload_chunk_types(png: &Png, extra: &[ChunkType]) -> Vec<ChunkType> {
+    let mut keep: Vec<ChunkType> = extra.to_vec();
+
+    for chunk in png.chunks() {
+        if envelope::is_envelope(chunk.data()) && !keep.contains(chunk.chunk_type()) {
+            keep.push(chunk.chunk_type().clone());
+        }
+    }
+
+    keep
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EncodeError {
+    #[error("The pixel data does not match the size of the image.")]
+    SizeMismatch,
+}
+
+#[cfg(test)]
+mod encoder_tests {
+    use super::*;
+    use crate::{ihdr::ColorType, ordering};
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::try_from(&include_bytes!("../sss.png")[..]).unwrap()
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let png = testing_png();
+        let pixels = decoder::decode(&png).unwrap();
+
+        for filter in [
+            FilterStrategy::Fixed(FilterType::None),
+            FilterStrategy::Fixed(FilterType::Paeth),
+            FilterStrategy::Adaptive,
+        ] {
+            let options = EncoderOptions {
+                filter,
+                level: 9,
+                idat_size: 1000,
+            };
+
+            let (encoded, _) = encode(&png, &pixels, &options, &[]).unwrap();
+            assert_eq!(decoder::decode(&encoded).unwrap(), pixels);
+            assert_eq!(
+                ordering::violations(encoded.chunks()),
+                ordering::violations(png.chunks())
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_interlaced() {
+        let header = ImageHeader {
+            width: 5,
+            height: 3,
+            bit_depth: 2,
+            color_type: ColorType::Grayscale,
+            interlaced: true,
+        };
+        let data = vec![
+            0b00011011, 0b11000000, 0b01010101, 0b10000000, 0b11100100, 0b01000000,
+        ];
+
+        let stream = encode_image_data(&header, &data, &EncoderOptions::default()).unwrap();
+        assert_eq!(decoder::decode_image_data(&header, &stream).unwrap(), data);
+    }
+
+    #[test]
+    fn test_split_image_data() {
+        let chunks = split_image_data(&[0; 2500], 1000);
+        let lengths: Vec<u32> = chunks.iter().map(|chunk| chunk.length()).collect();
+        assert_eq!(lengths, vec![1000, 1000, 500]);
+    }
+
+    #[test]
+    fn test_encode_keeps_ancillary_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"a\0b".to_vec(),
+        ))
+        .unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("prIv").unwrap(), Vec::new()))
+            .unwrap();
+
+        let pixels = decoder::decode(&png).unwrap();
+        let options = EncoderOptions {
+            idat_size: 100,
+            ..EncoderOptions::default()
+        };
+        let (encoded, dropped) = encode(&png, &pixels, &options, &[]).unwrap();
+
+        assert_eq!(dropped.len(), 0);
+        let types = |png: &Png| -> Vec<String> {
+            let mut types: Vec<String> = png
+                .chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect();
+            types.dedup();
+            types
+        };
+        assert_eq!(types(&png), types(&encoded));
+    }
+
+    #[test]
+    fn test_encode_size_mismatch() {
+        let png = testing_png();
+        let mut pixels = decoder::decode(&png).unwrap();
+        pixels.data.pop();
+
+        assert!(encode(&png, &pixels, &EncoderOptions::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_payload_chunk_types() {
+        let mut png = testing_png();
+        let metadata = envelope::Metadata::new("text/plain", None);
+        let sealed = envelope::seal(b"kept", &metadata, &Default::default()).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruST").unwrap(), sealed))
+            .unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("abCD").unwrap(), b"raw".to_vec()))
+            .unwrap();
+
+        let extra = [ChunkType::from_str("xyZW").unwrap()];
+        let keep = payload_chunk_types(&png, &extra);
+        assert_eq!(
+            keep,
+            vec![extra[0].clone(), ChunkType::from_str("ruST").unwrap()]
+        );
+
+        let pixels = decoder::decode(&png).unwrap();
+        let (encoded, dropped) =
+            encode(&png, &pixels, &EncoderOptions::default(), &keep).unwrap();
+        assert!(encoded.chunk_by_type("ruST").is_some());
+        assert!(encoded.chunk_by_type("abCD").is_none());
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk_type().to_string(), "abCD");
+    }
+}
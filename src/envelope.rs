@@ -0,0 +1,559 @@
+use sha2::{Digest, Sha256};
+
+use crate::{decoder, ecc, encoder, meta::ModificationTime, Error};
+
+// The container every payload is wrapped in, unless raw mode is asked for. All numbers are
+// big-endian.
+//
+//   magic           4 bytes   "SSPM"
+//   version         1 byte    1
+//   flags           1 byte    1 = compressed, 4 = error correction
+//   content type    1 byte length, then UTF-8
+//   file name       2 byte length (0 if there is none), then UTF-8
+//   created         8 bytes   seconds since the Unix epoch
+//   payload length  4 bytes   of the original payload
+//   digest          32 bytes  SHA-256 of the original payload
+//   body length     4 bytes
+//   body            the payload, compressed (zlib) if the flags say so
+//
+// The palette order and the deflate slack only hold a few dozen bytes, too few for that
+// header, so they get a compact envelope without the metadata:
+//
+//   magic           2 bytes   "Sp"
+//   flags           1 byte    as above, and 8 = text
+//   body length     2 bytes
+//   digest          4 bytes   the start of the SHA-256 of the original payload
+//   body            as above
+//
+// With error correction the whole envelope, header and all, is Reed-Solomon coded (see
+// `ecc`), so it starts with the coded header instead of the magic.
+//
+// Flag 2 is kept for encryption, which an envelope never has: a passphrase encrypts the
+// envelope into a slot of a deniable container (see `deniable`), so that nothing, flags
+// included, shows there is a payload at all.
+//
+// Anything after the body is ignored, so an envelope can sit in front of padding.
+
+pub const MAGIC: [u8; 4] = *b"SSPM";
+
+pub const COMPACT_MAGIC: [u8; 2] = *b"Sp";
+
+pub const VERSION: u8 = 1;
+
+pub const FLAG_COMPRESSED: u8 = 1;
+pub const FLAG_ECC: u8 = 4;
+/// Compact envelopes only, the payload is text/plain
+pub const FLAG_TEXT: u8 = 8;
+
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_ECC;
+
+/// The bytes of the digest a compact envelope keeps
+const COMPACT_DIGEST: usize = 4;
+
+/// The content type of compact envelopes without the text flag
+const COMPACT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// What an envelope tells about its payload
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Metadata {
+    pub content_type: String,
+    pub file_name: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+}
+
+/// How the payload is stored in the body
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SealOptions {
+    pub compress: bool,
+    /// Reed-Solomon parity bytes per block, no error correction if `None`
+    pub parity: Option<usize>,
+    /// Leave out the metadata, for methods that can hide only a few bytes. Bodies too
+    /// large for a compact envelope get a full one.
+    pub compact: bool,
+}
+
+/// An unwrapped envelope
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Opened {
+    pub metadata: Metadata,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+    /// The number of bytes error correction fixed
+    pub corrected: usize,
+    /// A compact envelope, its metadata has no file name or creation time
+    pub compact: bool,
+}
+
+impl Metadata {
+    /// Metadata for a payload created now
+    pub fn new(content_type: &str, file_name: Option<String>) -> Metadata {
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        Metadata {
+            content_type: content_type.to_string(),
+            file_name,
+            created,
+        }
+    }
+}
+
+/// Whether the bytes start like an envelope, or like one with error correction. Only the
+/// first block of those is corrected.
+pub fn is_envelope(bytes: &[u8]) -> bool {
+    let starts_like_one =
+        |bytes: &[u8]| bytes.starts_with(&MAGIC) || bytes.starts_with(&COMPACT_MAGIC);
+    starts_like_one(bytes) || ecc::decode_start(bytes).is_ok_and(|start| starts_like_one(&start))
+}
+
+/// Wraps a payload in an envelope
+pub fn seal(payload: &[u8], metadata: &Metadata, options: &SealOptions) -> Result<Vec<u8>, Error> {
+    let file_name = metadata.file_name.as_deref().unwrap_or("");
+    if metadata.content_type.len() > u8::MAX as usize {
+        return Err(EnvelopeError::FieldTooLong("content type").into());
+    }
+    if file_name.len() > u16::MAX as usize {
+        return Err(EnvelopeError::FieldTooLong("file name").into());
+    }
+    if payload.len() > u32::MAX as usize {
+        return Err(EnvelopeError::FieldTooLong("payload").into());
+    }
+
+    let mut flags = 0;
+    let mut body = payload.to_vec();
+
+    if options.compress {
+        body = encoder::deflate(&body, 9)?;
+        flags |= FLAG_COMPRESSED;
+    }
+
+    if options.parity.is_some() {
+        flags |= FLAG_ECC;
+    }
+
+    if body.len() > u32::MAX as usize {
+        return Err(EnvelopeError::FieldTooLong("body").into());
+    }
+
+    let mut sealed = match options.compact && body.len() <= u16::MAX as usize {
+        true => compact_header(payload, body.len(), flags, metadata),
+        false => header(payload, body.len(), flags, metadata),
+    };
+    sealed.extend_from_slice(&body);
+
+    match options.parity {
+        Some(parity) => ecc::encode(&sealed, parity),
+        None => Ok(sealed),
+    }
+}
+
+/// The header of a full envelope, up to the body
+fn header(payload: &[u8], body_length: usize, flags: u8, metadata: &Metadata) -> Vec<u8> {
+    let file_name = metadata.file_name.as_deref().unwrap_or("");
+
+    let mut sealed = MAGIC.to_vec();
+    sealed.push(VERSION);
+    sealed.push(flags);
+    sealed.push(metadata.content_type.len() as u8);
+    sealed.extend_from_slice(metadata.content_type.as_bytes());
+    sealed.extend_from_slice(&(file_name.len() as u16).to_be_bytes());
+    sealed.extend_from_slice(file_name.as_bytes());
+    sealed.extend_from_slice(&metadata.created.to_be_bytes());
+    sealed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    sealed.extend_from_slice(&Sha256::digest(payload));
+    sealed.extend_from_slice(&(body_length as u32).to_be_bytes());
+    sealed
+}
+
+/// The header of a compact envelope, which only tells whether the payload is text
+fn compact_header(payload: &[u8], body_length: usize, flags: u8, metadata: &Metadata) -> Vec<u8> {
+    let flags = match metadata.content_type == "text/plain" {
+        true => flags | FLAG_TEXT,
+        false => flags,
+    };
+
+    let mut sealed = COMPACT_MAGIC.to_vec();
+    sealed.push(flags);
+    sealed.extend_from_slice(&(body_length as u16).to_be_bytes());
+    sealed.extend_from_slice(&Sha256::digest(payload)[..COMPACT_DIGEST]);
+    sealed
+}
+
+/// Unwraps an envelope, correcting it first if it has error correction, and checks the
+/// digest
+pub fn open(bytes: &[u8]) -> Result<Opened, Error> {
+    open_limited(bytes, decoder::MAX_INFLATED_SIZE)
+}
+
+/// Unwraps an envelope like `open`, refusing payloads of more than `limit` bytes before
+/// they are inflated
+pub fn open_limited(bytes: &[u8], limit: usize) -> Result<Opened, Error> {
+    if bytes.starts_with(&MAGIC) {
+        return open_uncoded(bytes, 0, limit);
+    }
+    if bytes.starts_with(&COMPACT_MAGIC) {
+        return open_compact(bytes, 0, limit);
+    }
+
+    match ecc::decode(bytes) {
+        Ok((decoded, corrected)) if decoded.starts_with(&MAGIC) => {
+            open_uncoded(&decoded, corrected, limit)
+        }
+        Ok((decoded, corrected)) if decoded.starts_with(&COMPACT_MAGIC) => {
+            open_compact(&decoded, corrected, limit)
+        }
+        _ => Err(EnvelopeError::NotAnEnvelope.into()),
+    }
+}
+
+/// Unwraps an envelope without error correction around it
+fn open_uncoded(bytes: &[u8], corrected: usize, limit: usize) -> Result<Opened, Error> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(4)? != MAGIC {
+        return Err(EnvelopeError::NotAnEnvelope.into());
+    }
+
+    let version = reader.take(1)?[0];
+    if version != VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(version).into());
+    }
+
+    let flags = reader.take(1)?[0];
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(EnvelopeError::UnknownFlags(flags).into());
+    }
+
+    let length = reader.take(1)?[0] as usize;
+    let content_type = String::from_utf8(reader.take(length)?.to_vec())?;
+
+    let length = reader.u16()? as usize;
+    let file_name = match length {
+        0 => None,
+        _ => Some(String::from_utf8(reader.take(length)?.to_vec())?),
+    };
+
+    let created = reader.u64()?;
+    let payload_length = reader.u32()? as usize;
+    let digest = reader.take(32)?.to_vec();
+    if payload_length > limit {
+        return Err(EnvelopeError::TooLarge(payload_length, limit).into());
+    }
+
+    let length = reader.u32()? as usize;
+    let mut body = reader.take(length)?.to_vec();
+
+    // A body that inflates past the payload length can't match the digest anyway
+    if flags & FLAG_COMPRESSED != 0 {
+        body = decoder::inflate_limited(&body, payload_length + 1)?;
+    }
+
+    if body.len() != payload_length || Sha256::digest(&body)[..] != digest[..] {
+        return Err(EnvelopeError::DigestMismatch.into());
+    }
+
+    Ok(Opened {
+        metadata: Metadata {
+            content_type,
+            file_name,
+            created,
+        },
+        flags,
+        payload: body,
+        corrected,
+        compact: false,
+    })
+}
+
+/// Unwraps a compact envelope without error correction around it
+fn open_compact(bytes: &[u8], corrected: usize, limit: usize) -> Result<Opened, Error> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(2)? != COMPACT_MAGIC {
+        return Err(EnvelopeError::NotAnEnvelope.into());
+    }
+
+    let flags = reader.take(1)?[0];
+    if flags & !(KNOWN_FLAGS | FLAG_TEXT) != 0 {
+        return Err(EnvelopeError::UnknownFlags(flags).into());
+    }
+
+    let length = reader.u16()? as usize;
+    let digest = reader.take(COMPACT_DIGEST)?.to_vec();
+    let mut body = reader.take(length)?.to_vec();
+
+    if flags & FLAG_COMPRESSED != 0 {
+        body = decoder::inflate_limited(&body, limit + 1)?;
+    }
+    if body.len() > limit {
+        return Err(EnvelopeError::TooLarge(body.len(), limit).into());
+    }
+
+    if Sha256::digest(&body)[..COMPACT_DIGEST] != digest[..] {
+        return Err(EnvelopeError::DigestMismatch.into());
+    }
+
+    let content_type = match flags & FLAG_TEXT != 0 {
+        true => "text/plain",
+        false => COMPACT_CONTENT_TYPE,
+    };
+    Ok(Opened {
+        metadata: Metadata {
+            content_type: content_type.to_string(),
+            file_name: None,
+            created: 0,
+        },
+        flags: flags & KNOWN_FLAGS,
+        payload: body,
+        corrected,
+        compact: true,
+    })
+}
+
+/// A content type for a file, going by its extension
+pub fn guess_content_type(file_name: &str) -> &'static str {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("txt" | "md") => "text/plain",
+        Some("html" | "htm") => "text/html",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time
+pub fn format_timestamp(seconds: u64) -> String {
+    ModificationTime::from_unix(seconds).to_string()
+}
+
+/// Reads the fields of an envelope one after another
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        match self.bytes.get(self.position..self.position + length) {
+            Some(bytes) => {
+                self.position += length;
+                Ok(bytes)
+            }
+            None => Err(EnvelopeError::Truncated.into()),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EnvelopeError {
+    #[error("The payload is not in an envelope.")]
+    NotAnEnvelope,
+
+    #[error("Envelope version {0} is not supported.")]
+    UnsupportedVersion(u8),
+
+    #[error("The envelope has unknown flags ({0:#04x}).")]
+    UnknownFlags(u8),
+
+    #[error("The envelope is cut off.")]
+    Truncated,
+
+    #[error("The {0} is too long for an envelope.")]
+    FieldTooLong(&'static str),
+
+    #[error("The payload does not match its SHA-256 digest.")]
+    DigestMismatch,
+
+    #[error("The payload of {0} bytes is larger than the {1} bytes allowed.")]
+    TooLarge(usize, usize),
+}
+
+#[cfg(test)]
+mod envelope_tests {
+    use super::*;
+
+    fn testing_metadata() -> Metadata {
+        Metadata {
+            content_type: "text/plain".to_string(),
+            file_name: Some("notes.txt".to_string()),
+            created: 1_660_000_000,
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealed = seal(b"a message", &testing_metadata(), &SealOptions::default()).unwrap();
+        assert!(is_envelope(&sealed));
+
+        let opened = open(&sealed).unwrap();
+        assert_eq!(opened.metadata, testing_metadata());
+        assert_eq!(opened.payload, b"a message");
+        assert_eq!(opened.flags, 0);
+    }
+
+    #[test]
+    fn test_compressed_with_ecc() {
+        let payload = b"repeated ".repeat(100);
+        let options = SealOptions {
+            compress: true,
+            parity: Some(8),
+            compact: false,
+        };
+        let mut sealed = seal(&payload, &testing_metadata(), &options).unwrap();
+        assert!(sealed.len() < payload.len());
+
+        // Damage the body
+        let last = sealed.len() - 3;
+        sealed[last] ^= 0xFF;
+
+        let opened = open(&sealed).unwrap();
+        assert_eq!(opened.payload, payload);
+        assert_eq!(opened.flags, FLAG_COMPRESSED | FLAG_ECC);
+        assert_eq!(opened.corrected, 1);
+    }
+
+    #[test]
+    fn test_ecc_protects_the_header() {
+        let options = SealOptions {
+            compress: false,
+            parity: Some(16),
+            compact: false,
+        };
+        let mut sealed = seal(b"a message", &testing_metadata(), &options).unwrap();
+        assert!(!sealed.starts_with(&MAGIC));
+        assert!(is_envelope(&sealed));
+
+        // The coded header, then the magic, the flags, the content type length and the
+        // digest of the envelope inside
+        for position in [0, 13, 18, 19, 60] {
+            sealed[position] ^= 0xFF;
+        }
+        assert!(is_envelope(&sealed));
+
+        let opened = open(&sealed).unwrap();
+        assert_eq!(opened.metadata, testing_metadata());
+        assert_eq!(opened.payload, b"a message");
+        assert_eq!(opened.flags, FLAG_ECC);
+        assert_eq!(opened.corrected, 5);
+    }
+
+    #[test]
+    fn test_digest_mismatch() {
+        let mut sealed = seal(b"a message", &testing_metadata(), &SealOptions::default()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        assert!(open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_trailing_padding() {
+        let mut sealed = seal(b"a message", &testing_metadata(), &SealOptions::default()).unwrap();
+        sealed.extend_from_slice(&[0; 16]);
+
+        assert_eq!(open(&sealed).unwrap().payload, b"a message");
+        assert!(open(&sealed[..20]).is_err());
+        assert!(open(b"raw payload").is_err());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let payload = vec![0u8; 1 << 20];
+        let options = SealOptions {
+            compress: true,
+            parity: None,
+            compact: false,
+        };
+        let mut sealed = seal(&payload, &testing_metadata(), &options).unwrap();
+
+        assert_eq!(open_limited(&sealed, 1 << 20).unwrap().payload, payload);
+        assert!(open_limited(&sealed, 1000).is_err());
+
+        // A payload length that lies doesn't let the body inflate past it
+        let length_at = 4 + 1 + 1 + 1 + "text/plain".len() + 2 + "notes.txt".len() + 8;
+        sealed[length_at..length_at + 4].copy_from_slice(&16u32.to_be_bytes());
+        assert!(open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_unknown_flags() {
+        let mut sealed = seal(b"a message", &testing_metadata(), &SealOptions::default()).unwrap();
+        sealed[5] = 0x80;
+
+        assert!(open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let options = SealOptions {
+            compact: true,
+            ..Default::default()
+        };
+        let sealed = seal(b"abc", &testing_metadata(), &options).unwrap();
+        assert_eq!(sealed.len(), 2 + 1 + 2 + COMPACT_DIGEST + 3);
+        assert!(is_envelope(&sealed));
+
+        let opened = open(&sealed).unwrap();
+        assert!(opened.compact);
+        assert_eq!(opened.payload, b"abc");
+        assert_eq!(opened.metadata.content_type, "text/plain");
+        assert_eq!(opened.metadata.file_name, None);
+        assert_eq!(opened.flags, 0);
+
+        let mut damaged = sealed.clone();
+        damaged[9] ^= 1;
+        assert!(open(&damaged).is_err());
+
+        // Error correction and compression work the same way
+        let options = SealOptions {
+            compress: true,
+            parity: Some(8),
+            compact: true,
+        };
+        let payload = b"repeated ".repeat(20);
+        let mut sealed = seal(&payload, &testing_metadata(), &options).unwrap();
+        sealed[3] ^= 0xFF;
+        assert!(is_envelope(&sealed));
+
+        let opened = open(&sealed).unwrap();
+        assert!(opened.compact);
+        assert_eq!(opened.payload, payload);
+        assert_eq!(opened.flags, FLAG_COMPRESSED | FLAG_ECC);
+        assert_eq!(opened.corrected, 1);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_660_000_000), "2022-08-08 23:06:40 UTC");
+    }
+
+    #[test]
+    fn test_guess_content_type() {
+        assert_eq!(guess_content_type("a/b/notes.TXT"), "text/plain");
+        assert_eq!(guess_content_type("archive"), "application/octet-stream");
+    }
+}
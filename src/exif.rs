@@ -0,0 +1,1055 @@
+use std::collections::HashSet;
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Error};
+
+// The eXIf chunk holds TIFF-structured EXIF data, the same bytes a JPEG file carries in its
+// APP1 segment after the "Exif\0\0" prefix:
+//
+//   byte order  "MM" (big-endian) or "II" (little-endian)
+//   magic       42
+//   IFD0        offset of the first directory
+//
+// A directory is an entry count (2 bytes), 12 byte entries (tag, type, count, value or the
+// offset of the value if it takes more than 4 bytes) and the offset of the next directory.
+// IFD0 points to the EXIF and GPS directories, the EXIF directory to the interoperability
+// one, and the directory after IFD0 describes the thumbnail.
+//
+// Parsing keeps the tags of every directory, writing lays them out again from scratch so
+// removed tags leave no data behind.
+
+const EXIF_POINTER: u16 = 0x8769;
+const GPS_POINTER: u16 = 0x8825;
+const INTEROP_POINTER: u16 = 0xA005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+/// The most entries a directory may claim, anything more is corrupt data
+const MAX_ENTRIES: usize = 1024;
+
+/// The directory a tag lives in
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Directory {
+    Image,
+    Exif,
+    Gps,
+    Interop,
+    Thumbnail,
+}
+
+/// The value of a tag, one variant for every TIFF field type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub directory: Directory,
+    pub tag: u16,
+    pub value: Value,
+}
+
+/// Parsed EXIF data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exif {
+    big_endian: bool,
+    entries: Vec<Entry>,
+    thumbnail: Option<Vec<u8>>,
+}
+
+/// The tags we know by name, with the type a new value gets
+const TAGS: &[(Directory, u16, &str, u16)] = &[
+    (Directory::Image, 0x010E, "ImageDescription", 2),
+    (Directory::Image, 0x010F, "Make", 2),
+    (Directory::Image, 0x0110, "Model", 2),
+    (Directory::Image, 0x0112, "Orientation", 3),
+    (Directory::Image, 0x011A, "XResolution", 5),
+    (Directory::Image, 0x011B, "YResolution", 5),
+    (Directory::Image, 0x0128, "ResolutionUnit", 3),
+    (Directory::Image, 0x0131, "Software", 2),
+    (Directory::Image, 0x0132, "DateTime", 2),
+    (Directory::Image, 0x013B, "Artist", 2),
+    (Directory::Image, 0x8298, "Copyright", 2),
+    (Directory::Exif, 0x829A, "ExposureTime", 5),
+    (Directory::Exif, 0x829D, "FNumber", 5),
+    (Directory::Exif, 0x8827, "ISOSpeedRatings", 3),
+    (Directory::Exif, 0x9000, "ExifVersion", 7),
+    (Directory::Exif, 0x9003, "DateTimeOriginal", 2),
+    (Directory::Exif, 0x9004, "DateTimeDigitized", 2),
+    (Directory::Exif, 0x9010, "OffsetTime", 2),
+    (Directory::Exif, 0x920A, "FocalLength", 5),
+    (Directory::Exif, 0x927C, "MakerNote", 7),
+    (Directory::Exif, 0x9286, "UserComment", 7),
+    (Directory::Exif, 0xA002, "PixelXDimension", 4),
+    (Directory::Exif, 0xA003, "PixelYDimension", 4),
+    (Directory::Exif, 0xA420, "ImageUniqueID", 2),
+    (Directory::Exif, 0xA430, "CameraOwnerName", 2),
+    (Directory::Exif, 0xA431, "BodySerialNumber", 2),
+    (Directory::Exif, 0xA433, "LensMake", 2),
+    (Directory::Exif, 0xA434, "LensModel", 2),
+    (Directory::Gps, 0x0000, "GPSVersionID", 1),
+    (Directory::Gps, 0x0001, "GPSLatitudeRef", 2),
+    (Directory::Gps, 0x0002, "GPSLatitude", 5),
+    (Directory::Gps, 0x0003, "GPSLongitudeRef", 2),
+    (Directory::Gps, 0x0004, "GPSLongitude", 5),
+    (Directory::Gps, 0x0005, "GPSAltitudeRef", 1),
+    (Directory::Gps, 0x0006, "GPSAltitude", 5),
+    (Directory::Gps, 0x0007, "GPSTimeStamp", 5),
+    (Directory::Gps, 0x0010, "GPSImgDirectionRef", 2),
+    (Directory::Gps, 0x0011, "GPSImgDirection", 5),
+    (Directory::Gps, 0x001D, "GPSDateStamp", 2),
+    (Directory::Interop, 0x0001, "InteroperabilityIndex", 2),
+];
+
+impl Directory {
+    fn name(self) -> &'static str {
+        match self {
+            Directory::Image => "image",
+            Directory::Exif => "exif",
+            Directory::Gps => "gps",
+            Directory::Interop => "interop",
+            Directory::Thumbnail => "thumbnail",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Directory> {
+        [
+            Directory::Image,
+            Directory::Exif,
+            Directory::Gps,
+            Directory::Interop,
+            Directory::Thumbnail,
+        ]
+        .into_iter()
+        .find(|directory| directory.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Finds a tag by its name, like `Make`, or by directory and number, like `gps:0x0002`.
+/// A number without a directory is looked for in IFD0.
+pub fn parse_tag(tag: &str) -> Result<(Directory, u16), Error> {
+    if let Some(&(directory, number, _, _)) = TAGS
+        .iter()
+        .find(|(_, _, name, _)| name.eq_ignore_ascii_case(tag))
+    {
+        return Ok((directory, number));
+    }
+
+    let (directory, number) = match tag.split_once(':') {
+        Some((directory, number)) => (Directory::from_name(directory), number),
+        None => (Some(Directory::Image), tag),
+    };
+
+    let number = number.trim_start_matches("0x").trim_start_matches("0X");
+    match (directory, u16::from_str_radix(number, 16)) {
+        (Some(directory), Ok(number)) => Ok((directory, number)),
+        _ => Err(ExifError::UnknownTag(tag.to_string()).into()),
+    }
+}
+
+/// The name of a tag, or its directory and number if we don't know it
+pub fn tag_name(directory: Directory, tag: u16) -> String {
+    match TAGS
+        .iter()
+        .find(|&&(known, number, _, _)| known == directory && number == tag)
+    {
+        Some((_, _, name, _)) => name.to_string(),
+        None => format!("{}:{:#06x}", directory.name(), tag),
+    }
+}
+
+/// The field type a new value of a tag gets, ASCII for tags we don't know
+fn default_type(directory: Directory, tag: u16) -> u16 {
+    TAGS.iter()
+        .find(|&&(known, number, _, _)| known == directory && number == tag)
+        .map_or(2, |&(_, _, _, field_type)| field_type)
+}
+
+/// The size of a single value of a field type, `None` for types TIFF doesn't define
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Splits bytes into fixed size values in big-endian order
+fn values<const N: usize>(bytes: &[u8], big_endian: bool) -> Vec<[u8; N]> {
+    bytes
+        .chunks_exact(N)
+        .map(|chunk| {
+            let mut value: [u8; N] = chunk.try_into().unwrap();
+            if !big_endian {
+                value.reverse();
+            }
+            value
+        })
+        .collect()
+}
+
+/// Appends a big-endian value in the byte order of the data
+fn put<const N: usize>(bytes: &mut Vec<u8>, mut value: [u8; N], big_endian: bool) {
+    if !big_endian {
+        value.reverse();
+    }
+    bytes.extend_from_slice(&value);
+}
+
+impl Value {
+    pub fn field_type(&self) -> u16 {
+        match self {
+            Value::Byte(_) => 1,
+            Value::Ascii(_) => 2,
+            Value::Short(_) => 3,
+            Value::Long(_) => 4,
+            Value::Rational(_) => 5,
+            Value::SByte(_) => 6,
+            Value::Undefined(_) => 7,
+            Value::SShort(_) => 8,
+            Value::SLong(_) => 9,
+            Value::SRational(_) => 10,
+            Value::Float(_) => 11,
+            Value::Double(_) => 12,
+        }
+    }
+
+    /// The number of values, for ASCII the length with the terminating NUL
+    pub fn count(&self) -> usize {
+        match self {
+            Value::Byte(values) | Value::Undefined(values) => values.len(),
+            Value::Ascii(text) => text.len() + 1,
+            Value::Short(values) => values.len(),
+            Value::Long(values) => values.len(),
+            Value::Rational(values) => values.len(),
+            Value::SByte(values) => values.len(),
+            Value::SShort(values) => values.len(),
+            Value::SLong(values) => values.len(),
+            Value::SRational(values) => values.len(),
+            Value::Float(values) => values.len(),
+            Value::Double(values) => values.len(),
+        }
+    }
+
+    fn decode(field_type: u16, bytes: &[u8], big_endian: bool) -> Option<Value> {
+        let value = match field_type {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => {
+                let text = bytes.split(|&byte| byte == 0).next().unwrap_or(&[]);
+                Value::Ascii(String::from_utf8_lossy(text).into_owned())
+            }
+            3 => Value::Short(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(u16::from_be_bytes)
+                    .collect(),
+            ),
+            4 => Value::Long(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(u32::from_be_bytes)
+                    .collect(),
+            ),
+            5 => Value::Rational(
+                values::<4>(bytes, big_endian)
+                    .chunks_exact(2)
+                    .map(|pair| (u32::from_be_bytes(pair[0]), u32::from_be_bytes(pair[1])))
+                    .collect(),
+            ),
+            6 => Value::SByte(bytes.iter().map(|&byte| byte as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec()),
+            8 => Value::SShort(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(i16::from_be_bytes)
+                    .collect(),
+            ),
+            9 => Value::SLong(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(i32::from_be_bytes)
+                    .collect(),
+            ),
+            10 => Value::SRational(
+                values::<4>(bytes, big_endian)
+                    .chunks_exact(2)
+                    .map(|pair| (i32::from_be_bytes(pair[0]), i32::from_be_bytes(pair[1])))
+                    .collect(),
+            ),
+            11 => Value::Float(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(f32::from_be_bytes)
+                    .collect(),
+            ),
+            12 => Value::Double(
+                values(bytes, big_endian)
+                    .into_iter()
+                    .map(f64::from_be_bytes)
+                    .collect(),
+            ),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn encode(&self, big_endian: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match self {
+            Value::Byte(values) | Value::Undefined(values) => bytes.extend_from_slice(values),
+            Value::Ascii(text) => {
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.push(0);
+            }
+            Value::Short(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+            Value::Long(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+            Value::Rational(values) => values.iter().for_each(|(numerator, denominator)| {
+                put(&mut bytes, numerator.to_be_bytes(), big_endian);
+                put(&mut bytes, denominator.to_be_bytes(), big_endian);
+            }),
+            Value::SByte(values) => bytes.extend(values.iter().map(|&value| value as u8)),
+            Value::SShort(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+            Value::SLong(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+            Value::SRational(values) => values.iter().for_each(|(numerator, denominator)| {
+                put(&mut bytes, numerator.to_be_bytes(), big_endian);
+                put(&mut bytes, denominator.to_be_bytes(), big_endian);
+            }),
+            Value::Float(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+            Value::Double(values) => values
+                .iter()
+                .for_each(|value| put(&mut bytes, value.to_be_bytes(), big_endian)),
+        }
+
+        bytes
+    }
+
+    /// Reads a value of a field type from text. Numbers are separated by commas or spaces,
+    /// rationals are written as `1/250` or as decimals like `2.8`.
+    pub fn parse(field_type: u16, text: &str) -> Result<Value, Error> {
+        let invalid = || -> Error { ExifError::InvalidValue(text.to_string()).into() };
+        let items: Vec<&str> = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|item| !item.is_empty())
+            .collect();
+
+        fn numbers<T: std::str::FromStr>(items: &[&str]) -> Option<Vec<T>> {
+            items.iter().map(|item| item.parse().ok()).collect()
+        }
+
+        let value = match field_type {
+            1 => numbers(&items).map(Value::Byte),
+            2 => Some(Value::Ascii(text.to_string())),
+            3 => numbers(&items).map(Value::Short),
+            4 => numbers(&items).map(Value::Long),
+            5 => items
+                .iter()
+                .map(|item| parse_rational(item))
+                .collect::<Option<Vec<(i64, i64)>>>()
+                .and_then(|values| {
+                    values
+                        .into_iter()
+                        .map(|(numerator, denominator)| {
+                            Some((
+                                u32::try_from(numerator).ok()?,
+                                u32::try_from(denominator).ok()?,
+                            ))
+                        })
+                        .collect()
+                })
+                .map(Value::Rational),
+            6 => numbers(&items).map(Value::SByte),
+            7 => Some(Value::Undefined(text.as_bytes().to_vec())),
+            8 => numbers(&items).map(Value::SShort),
+            9 => numbers(&items).map(Value::SLong),
+            10 => items
+                .iter()
+                .map(|item| parse_rational(item))
+                .collect::<Option<Vec<(i64, i64)>>>()
+                .and_then(|values| {
+                    values
+                        .into_iter()
+                        .map(|(numerator, denominator)| {
+                            Some((
+                                i32::try_from(numerator).ok()?,
+                                i32::try_from(denominator).ok()?,
+                            ))
+                        })
+                        .collect()
+                })
+                .map(Value::SRational),
+            11 => numbers(&items).map(Value::Float),
+            12 => numbers(&items).map(Value::Double),
+            _ => None,
+        };
+
+        match value {
+            Some(value) if value.count() > 0 => Ok(value),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Reads `1/250` or a decimal like `-2.8` as a fraction
+fn parse_rational(text: &str) -> Option<(i64, i64)> {
+    if let Some((numerator, denominator)) = text.split_once('/') {
+        return Some((numerator.parse().ok()?, denominator.parse().ok()?));
+    }
+
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if fraction.len() > 6 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let denominator = 10i64.pow(fraction.len() as u32);
+    let negative = whole.starts_with('-');
+    let whole: i64 = match whole.trim_start_matches('-') {
+        "" => 0,
+        digits => digits.parse().ok()?,
+    };
+    let fraction: i64 = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse().ok()?
+    };
+
+    let numerator = whole * denominator + fraction;
+    Some((if negative { -numerator } else { numerator }, denominator))
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn join<T: std::fmt::Display>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+
+        match self {
+            Value::Ascii(text) => write!(f, "{}", text),
+            Value::Undefined(bytes) => {
+                if bytes
+                    .iter()
+                    .all(|&byte| byte.is_ascii_graphic() || byte == b' ')
+                {
+                    write!(f, "{}", String::from_utf8_lossy(bytes))
+                } else {
+                    write!(f, "({} bytes)", bytes.len())
+                }
+            }
+            Value::Byte(values) => write!(f, "{}", join(values)),
+            Value::Short(values) => write!(f, "{}", join(values)),
+            Value::Long(values) => write!(f, "{}", join(values)),
+            Value::SByte(values) => write!(f, "{}", join(values)),
+            Value::SShort(values) => write!(f, "{}", join(values)),
+            Value::SLong(values) => write!(f, "{}", join(values)),
+            Value::Float(values) => write!(f, "{}", join(values)),
+            Value::Double(values) => write!(f, "{}", join(values)),
+            Value::Rational(values) => {
+                let fractions: Vec<String> = values
+                    .iter()
+                    .map(|(numerator, denominator)| format!("{}/{}", numerator, denominator))
+                    .collect();
+                write!(f, "{}", fractions.join(", "))
+            }
+            Value::SRational(values) => {
+                let fractions: Vec<String> = values
+                    .iter()
+                    .map(|(numerator, denominator)| format!("{}/{}", numerator, denominator))
+                    .collect();
+                write!(f, "{}", fractions.join(", "))
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Exif {
+    /// Empty big-endian EXIF data
+    pub fn new() -> Exif {
+        Exif {
+            big_endian: true,
+            entries: Vec::new(),
+            thumbnail: None,
+        }
+    }
+
+    /// Reads the eXIf chunk of a PNG file, `None` if there is none
+    pub fn from_png(png: &Png) -> Result<Option<Exif>, Error> {
+        match png.chunk_by_type("eXIf") {
+            Some(chunk) => Ok(Some(Exif::parse(chunk.data())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the eXIf chunk of a PNG file, or adds one before the image data
+    pub fn write_to(&self, png: &mut Png) -> Result<(), Error> {
+        png.set_chunk(self.to_chunk())
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::new(*b"eXIf"), self.to_bytes())
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Exif, Error> {
+        let big_endian = match bytes.get(0..2) {
+            Some(b"MM") => true,
+            Some(b"II") => false,
+            _ => return Err(ExifError::InvalidHeader.into()),
+        };
+
+        let reader = Reader { bytes, big_endian };
+        if reader.u16(2)? != 42 {
+            return Err(ExifError::InvalidHeader.into());
+        }
+
+        let mut exif = Exif {
+            big_endian,
+            entries: Vec::new(),
+            thumbnail: None,
+        };
+        let mut visited = HashSet::new();
+
+        let next = exif.read_directory(
+            &reader,
+            Directory::Image,
+            reader.u32(4)? as usize,
+            &mut visited,
+        )?;
+        if next != 0 {
+            exif.read_directory(&reader, Directory::Thumbnail, next, &mut visited)?;
+        }
+
+        exif.entries
+            .sort_by_key(|entry| (entry.directory, entry.tag));
+
+        Ok(exif)
+    }
+
+    /// Reads a directory and the ones it points to, returns the offset of the next one
+    fn read_directory(
+        &mut self,
+        reader: &Reader,
+        directory: Directory,
+        offset: usize,
+        visited: &mut HashSet<usize>,
+    ) -> Result<usize, Error> {
+        if !visited.insert(offset) {
+            return Err(ExifError::Loop.into());
+        }
+
+        let count = reader.u16(offset)? as usize;
+        if count > MAX_ENTRIES {
+            return Err(ExifError::Truncated.into());
+        }
+
+        let mut thumbnail_offset = None;
+        let mut thumbnail_length = None;
+
+        for index in 0..count {
+            let entry = offset + 2 + index * 12;
+            let tag = reader.u16(entry)?;
+            let field_type = reader.u16(entry + 2)?;
+            let count = reader.u32(entry + 4)? as usize;
+
+            // Types TIFF doesn't define can't be read, so they can't be kept either
+            let size = match type_size(field_type).and_then(|size| size.checked_mul(count)) {
+                Some(size) => size,
+                None => continue,
+            };
+            let data = match size {
+                0..=4 => reader.take(entry + 8, size)?,
+                _ => reader.take(reader.u32(entry + 8)? as usize, size)?,
+            };
+
+            let value = match Value::decode(field_type, data, self.big_endian) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let pointer = match &value {
+                Value::Long(values) if values.len() == 1 => Some(values[0] as usize),
+                _ => None,
+            };
+
+            match (directory, tag, pointer) {
+                (Directory::Image, EXIF_POINTER, Some(pointer)) => {
+                    self.read_directory(reader, Directory::Exif, pointer, visited)?;
+                }
+                (Directory::Image, GPS_POINTER, Some(pointer)) => {
+                    self.read_directory(reader, Directory::Gps, pointer, visited)?;
+                }
+                (Directory::Exif, INTEROP_POINTER, Some(pointer)) => {
+                    self.read_directory(reader, Directory::Interop, pointer, visited)?;
+                }
+                (Directory::Thumbnail, THUMBNAIL_OFFSET, Some(pointer)) => {
+                    thumbnail_offset = Some(pointer)
+                }
+                (Directory::Thumbnail, THUMBNAIL_LENGTH, Some(pointer)) => {
+                    thumbnail_length = Some(pointer)
+                }
+                _ => self.entries.push(Entry {
+                    directory,
+                    tag,
+                    value,
+                }),
+            }
+        }
+
+        if let (Some(offset), Some(length)) = (thumbnail_offset, thumbnail_length) {
+            self.thumbnail = Some(reader.take(offset, length)?.to_vec());
+        }
+
+        reader
+            .u32(offset + 2 + count * 12)
+            .map(|next| next as usize)
+    }
+
+    /// Lays the EXIF data out again, in the byte order it was read in
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            bytes: Vec::new(),
+            big_endian: self.big_endian,
+        };
+
+        writer
+            .bytes
+            .extend_from_slice(if self.big_endian { b"MM" } else { b"II" });
+        writer.u16(42);
+        writer.u32(8);
+
+        let has = |directory: Directory| {
+            self.entries
+                .iter()
+                .any(|entry| entry.directory == directory)
+        };
+        let has_interop = has(Directory::Interop);
+        let has_exif = has(Directory::Exif) || has_interop;
+        let has_gps = has(Directory::Gps);
+        let has_thumbnail = has(Directory::Thumbnail) || self.thumbnail.is_some();
+
+        let pointer = || Value::Long(vec![0]);
+
+        let mut image = self.directory(Directory::Image);
+        if has_exif {
+            image.push((EXIF_POINTER, pointer()));
+        }
+        if has_gps {
+            image.push((GPS_POINTER, pointer()));
+        }
+        let image = writer.directory(image);
+
+        if has_exif {
+            let mut exif = self.directory(Directory::Exif);
+            if has_interop {
+                exif.push((INTEROP_POINTER, pointer()));
+            }
+            let exif = writer.directory(exif);
+            writer.patch(image.value(EXIF_POINTER), exif.start);
+
+            if has_interop {
+                let interop = writer.directory(self.directory(Directory::Interop));
+                writer.patch(exif.value(INTEROP_POINTER), interop.start);
+            }
+        }
+
+        if has_gps {
+            let gps = writer.directory(self.directory(Directory::Gps));
+            writer.patch(image.value(GPS_POINTER), gps.start);
+        }
+
+        if has_thumbnail {
+            let mut thumbnail = self.directory(Directory::Thumbnail);
+            if let Some(data) = &self.thumbnail {
+                thumbnail.push((THUMBNAIL_OFFSET, pointer()));
+                thumbnail.push((THUMBNAIL_LENGTH, Value::Long(vec![data.len() as u32])));
+            }
+            let directory = writer.directory(thumbnail);
+            writer.patch(image.next, directory.start);
+
+            if let Some(data) = &self.thumbnail {
+                let start = writer.bytes.len();
+                writer.bytes.extend_from_slice(data);
+                writer.patch(directory.value(THUMBNAIL_OFFSET), start);
+            }
+        }
+
+        writer.bytes
+    }
+
+    /// The tags of a directory, sorted as TIFF wants them
+    fn directory(&self, directory: Directory) -> Vec<(u16, Value)> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.directory == directory)
+            .map(|entry| (entry.tag, entry.value.clone()))
+            .collect()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get(&self, directory: Directory, tag: u16) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|entry| entry.directory == directory && entry.tag == tag)
+            .map(|entry| &entry.value)
+    }
+
+    /// Sets a tag, replacing the value it had
+    pub fn set(&mut self, directory: Directory, tag: u16, value: Value) -> Result<(), Error> {
+        let pointers = [
+            EXIF_POINTER,
+            GPS_POINTER,
+            INTEROP_POINTER,
+            THUMBNAIL_OFFSET,
+            THUMBNAIL_LENGTH,
+        ];
+        if pointers.contains(&tag) {
+            return Err(ExifError::ReservedTag(tag).into());
+        }
+
+        // The entries stay sorted by directory and tag
+        match self
+            .entries
+            .binary_search_by_key(&(directory, tag), |entry| (entry.directory, entry.tag))
+        {
+            Ok(index) => self.entries[index].value = value,
+            Err(index) => self.entries.insert(
+                index,
+                Entry {
+                    directory,
+                    tag,
+                    value,
+                },
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Sets a tag from text, in the type it has or the one the tag usually has
+    pub fn set_text(&mut self, directory: Directory, tag: u16, text: &str) -> Result<(), Error> {
+        let field_type = self
+            .get(directory, tag)
+            .map_or(default_type(directory, tag), Value::field_type);
+
+        self.set(directory, tag, Value::parse(field_type, text)?)
+    }
+
+    /// Removes a tag, returns whether it was there
+    pub fn remove(&mut self, directory: Directory, tag: u16) -> bool {
+        let length = self.entries.len();
+        self.entries
+            .retain(|entry| entry.directory != directory || entry.tag != tag);
+        self.entries.len() != length
+    }
+
+    /// Removes the GPS directory, returns the number of tags removed
+    pub fn strip_gps(&mut self) -> usize {
+        let length = self.entries.len();
+        self.entries
+            .retain(|entry| entry.directory != Directory::Gps);
+        length - self.entries.len()
+    }
+
+    pub fn thumbnail(&self) -> Option<&[u8]> {
+        self.thumbnail.as_deref()
+    }
+}
+
+impl Default for Exif {
+    fn default() -> Self {
+        Exif::new()
+    }
+}
+
+/// Reads numbers at offsets of the data, in its byte order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&self, offset: usize, length: usize) -> Result<&'a [u8], Error> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or_else(|| ExifError::Truncated.into())
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(
+            values(self.take(offset, 2)?, self.big_endian)[0],
+        ))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(
+            values(self.take(offset, 4)?, self.big_endian)[0],
+        ))
+    }
+}
+
+/// Where a directory ended up, so the offsets pointing to it can be filled in
+struct Written {
+    start: usize,
+    /// The tags of the directory and where their value or value offset is
+    values: Vec<(u16, usize)>,
+    /// Where the offset of the next directory is
+    next: usize,
+}
+
+impl Written {
+    fn value(&self, tag: u16) -> usize {
+        self.values
+            .iter()
+            .find(|&&(written, _)| written == tag)
+            .map(|&(_, position)| position)
+            .expect("the pointer tag was written")
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        put(&mut self.bytes, value.to_be_bytes(), self.big_endian);
+    }
+
+    fn u32(&mut self, value: u32) {
+        put(&mut self.bytes, value.to_be_bytes(), self.big_endian);
+    }
+
+    fn patch(&mut self, position: usize, value: usize) {
+        let mut bytes = Vec::new();
+        put(&mut bytes, (value as u32).to_be_bytes(), self.big_endian);
+        self.bytes[position..position + 4].copy_from_slice(&bytes);
+    }
+
+    /// Writes a directory with the values that don't fit in their entries right after it
+    fn directory(&mut self, mut entries: Vec<(u16, Value)>) -> Written {
+        entries.sort_by_key(|(tag, _)| *tag);
+
+        // Values start at word boundaries
+        if self.bytes.len() % 2 == 1 {
+            self.bytes.push(0);
+        }
+
+        let start = self.bytes.len();
+        let mut data_offset = start + 2 + entries.len() * 12 + 4;
+        let mut data = Vec::new();
+        let mut values = Vec::new();
+
+        self.u16(entries.len() as u16);
+        for (tag, value) in &entries {
+            let encoded = value.encode(self.big_endian);
+
+            self.u16(*tag);
+            self.u16(value.field_type());
+            self.u32(value.count() as u32);
+            values.push((*tag, self.bytes.len()));
+
+            if encoded.len() <= 4 {
+                let mut inline = encoded;
+                inline.resize(4, 0);
+                self.bytes.extend(inline);
+            } else {
+                self.u32(data_offset as u32);
+                data_offset += encoded.len() + encoded.len() % 2;
+                data.extend_from_slice(&encoded);
+                if encoded.len() % 2 == 1 {
+                    data.push(0);
+                }
+            }
+        }
+
+        let next = self.bytes.len();
+        self.u32(0);
+        self.bytes.extend(data);
+
+        Written {
+            start,
+            values,
+            next,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExifError {
+    #[error("The EXIF data doesn't start with a TIFF header.")]
+    InvalidHeader,
+
+    #[error("The EXIF data is cut off.")]
+    Truncated,
+
+    #[error("The EXIF directories point to each other in a loop.")]
+    Loop,
+
+    #[error("'{0}' is not a known EXIF tag, use a name or a number like gps:0x0002.")]
+    UnknownTag(String),
+
+    #[error("'{0}' is not a valid value for this tag.")]
+    InvalidValue(String),
+
+    #[error("Tag {0:#06x} points to other data and can't be set.")]
+    ReservedTag(u16),
+}
+
+#[cfg(test)]
+mod exif_tests {
+    use super::*;
+
+    fn testing_exif(big_endian: bool) -> Exif {
+        let mut exif = Exif {
+            big_endian,
+            ..Exif::new()
+        };
+        exif.set_text(Directory::Image, 0x010F, "Squirrel Optics")
+            .unwrap();
+        exif.set_text(Directory::Image, 0x0112, "1").unwrap();
+        exif.set_text(Directory::Exif, 0x829A, "1/250").unwrap();
+        exif.set_text(Directory::Exif, 0x9000, "0232").unwrap();
+        exif.set_text(Directory::Gps, 0x0001, "N").unwrap();
+        exif.set_text(Directory::Gps, 0x0002, "52/1, 22/1, 1.5")
+            .unwrap();
+        exif.set_text(Directory::Interop, 0x0001, "R98").unwrap();
+        exif
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for big_endian in [true, false] {
+            let exif = testing_exif(big_endian);
+            let parsed = Exif::parse(&exif.to_bytes()).unwrap();
+
+            assert_eq!(parsed, exif);
+            assert_eq!(
+                parsed.get(Directory::Gps, 0x0002),
+                Some(&Value::Rational(vec![(52, 1), (22, 1), (15, 10)]))
+            );
+            assert_eq!(
+                parsed.get(Directory::Image, 0x010F).unwrap().to_string(),
+                "Squirrel Optics"
+            );
+        }
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut exif = testing_exif(true);
+        exif.thumbnail = Some(vec![0xFF, 0xD8, 0xFF, 0xD9]);
+        exif.set(Directory::Thumbnail, 0x0103, Value::Short(vec![6]))
+            .unwrap();
+
+        let parsed = Exif::parse(&exif.to_bytes()).unwrap();
+        assert_eq!(parsed.thumbnail(), Some(&[0xFF, 0xD8, 0xFF, 0xD9][..]));
+        assert_eq!(parsed, exif);
+    }
+
+    #[test]
+    fn test_strip_gps() {
+        let mut exif = testing_exif(false);
+        assert_eq!(exif.strip_gps(), 2);
+        assert!(exif.remove(Directory::Image, 0x010F));
+        assert!(!exif.remove(Directory::Image, 0x010F));
+
+        let bytes = exif.to_bytes();
+        let parsed = Exif::parse(&bytes).unwrap();
+        assert_eq!(parsed.get(Directory::Gps, 0x0001), None);
+        assert!(!bytes.windows(15).any(|window| window == b"Squirrel Optics"));
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(parse_tag("make").unwrap(), (Directory::Image, 0x010F));
+        assert_eq!(parse_tag("GPSLatitude").unwrap(), (Directory::Gps, 0x0002));
+        assert_eq!(parse_tag("exif:0xA001").unwrap(), (Directory::Exif, 0xA001));
+        assert_eq!(parse_tag("0x0100").unwrap(), (Directory::Image, 0x0100));
+        assert!(parse_tag("Nonsense").is_err());
+        assert_eq!(tag_name(Directory::Gps, 0x0002), "GPSLatitude");
+        assert_eq!(tag_name(Directory::Exif, 0xA001), "exif:0xa001");
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(
+            Value::parse(3, "1, 2 3").unwrap(),
+            Value::Short(vec![1, 2, 3])
+        );
+        assert_eq!(
+            Value::parse(10, "-2.25").unwrap(),
+            Value::SRational(vec![(-225, 100)])
+        );
+        assert!(Value::parse(3, "70000").is_err());
+        assert!(Value::parse(5, "-1/2").is_err());
+        assert!(Value::parse(4, "").is_err());
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert!(Exif::parse(b"XX\0\x2a\0\0\0\x08").is_err());
+        assert!(Exif::parse(b"MM\0\x2a\0\0\0\x40").is_err());
+
+        // IFD0 whose next directory is itself
+        let looping = b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\x08";
+        assert!(Exif::parse(looping).is_err());
+    }
+
+    #[test]
+    fn test_write_to_png() {
+        let mut png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        assert_eq!(Exif::from_png(&png).unwrap(), None);
+
+        let mut exif = testing_exif(true);
+        exif.write_to(&mut png).unwrap();
+        exif.strip_gps();
+        exif.write_to(&mut png).unwrap();
+
+        let types: Vec<[u8; 4]> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().bytes())
+            .collect();
+        let exif_chunks: Vec<usize> = (0..types.len())
+            .filter(|&index| &types[index] == b"eXIf")
+            .collect();
+        let first_idat = types.iter().position(|chunk_type| chunk_type == b"IDAT");
+        assert_eq!(exif_chunks.len(), 1);
+        assert!(Some(exif_chunks[0]) < first_idat);
+
+        let reread = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(Exif::from_png(&reread).unwrap(), Some(exif));
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+    str::FromStr,
+};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::{ChunkType, ChunkTypeError},
+    envelope::{self, EnvelopeError, Metadata, SealOptions},
+    ordering::OrderingError,
+    png::{Png, PngError},
+    Error,
+};
+
+// A C ABI over opaque `Png` handles, declared in include/sspngme.h. Every function returns
+// one of the codes below, the message of the last error on the calling thread comes from
+// `sspngme_last_error_message`. Payloads are sealed in the same envelope the command line
+// uses, so files move freely between the two.
+
+pub const SSPNGME_OK: c_int = 0;
+pub const SSPNGME_ERROR_NULL_POINTER: c_int = 1;
+pub const SSPNGME_ERROR_INVALID_ARGUMENT: c_int = 2;
+pub const SSPNGME_ERROR_INVALID_PNG: c_int = 3;
+pub const SSPNGME_ERROR_INVALID_CHUNK_TYPE: c_int = 4;
+pub const SSPNGME_ERROR_NOT_FOUND: c_int = 5;
+pub const SSPNGME_ERROR_INDEX_OUT_OF_BOUNDS: c_int = 6;
+pub const SSPNGME_ERROR_ORDERING: c_int = 7;
+pub const SSPNGME_ERROR_PAYLOAD: c_int = 8;
+pub const SSPNGME_ERROR_PANIC: c_int = 9;
+pub const SSPNGME_ERROR_OTHER: c_int = 10;
+
+/// The content type of payloads encoded without one
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// A parsed PNG file, only ever handled through a pointer
+pub struct SspngmePng {
+    png: Png,
+}
+
+/// Bytes owned by the library, given back with `sspngme_buffer_free`
+#[repr(C)]
+pub struct SspngmeBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Parses a PNG file from a buffer into a new handle, freed with `sspngme_png_free`
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` to a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_parse(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut SspngmePng,
+) -> c_int {
+    guard(|| {
+        let out = writable(out)?;
+        let png = match Png::try_from(bytes(data, len)?) {
+            Ok(png) => png,
+            Err(error) => return Err(FfiError::InvalidPng(error.to_string()).into()),
+        };
+
+        *out = Box::into_raw(Box::new(SspngmePng { png }));
+        Ok(())
+    })
+}
+
+/// Frees a handle, null is ignored
+///
+/// # Safety
+/// `png` must come from `sspngme_png_parse` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_free(png: *mut SspngmePng) {
+    if !png.is_null() {
+        drop(Box::from_raw(png));
+    }
+}
+
+/// The number of chunks, those after IEND included
+///
+/// # Safety
+/// `png` must be a live handle and `count` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_chunk_count(
+    png: *const SspngmePng,
+    count: *mut usize,
+) -> c_int {
+    guard(|| {
+        *writable(count)? = handle(png)?.png.chunks().len();
+        Ok(())
+    })
+}
+
+/// Writes the type of a chunk as a null terminated string to `out`, which has room for 5
+/// bytes
+///
+/// # Safety
+/// `png` must be a live handle and `out` must point to 5 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_chunk_type(
+    png: *const SspngmePng,
+    index: usize,
+    out: *mut c_char,
+) -> c_int {
+    guard(|| {
+        let chunk = chunk_at(handle(png)?, index)?;
+        let out = slice::from_raw_parts_mut(writable(out)?, 5);
+
+        for (out, byte) in out.iter_mut().zip(chunk.chunk_type().bytes()) {
+            *out = byte as c_char;
+        }
+        out[4] = 0;
+        Ok(())
+    })
+}
+
+/// Points `data` at the data of a chunk. The bytes belong to the handle and stay valid
+/// until it is changed or freed.
+///
+/// # Safety
+/// `png` must be a live handle, `data` and `len` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_chunk_data(
+    png: *const SspngmePng,
+    index: usize,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let chunk = chunk_at(handle(png)?, index)?;
+        lend(chunk.data(), data, len)
+    })
+}
+
+/// Points `data` at the data of the first chunk of a type, the way `sspngme_png_chunk_data`
+/// does
+///
+/// # Safety
+/// `png` must be a live handle, `chunk_type` a null terminated string, `data` and `len`
+/// must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_get_chunk(
+    png: *const SspngmePng,
+    chunk_type: *const c_char,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let chunk = find_chunk(handle(png)?, chunk_type)?;
+        lend(chunk.data(), data, len)
+    })
+}
+
+/// Adds a chunk as late in the file as the ordering rules allow
+///
+/// # Safety
+/// `png` must be a live handle, `chunk_type` a null terminated string and `data` must point
+/// to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_add_chunk(
+    png: *mut SspngmePng,
+    chunk_type: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let png = handle_mut(png)?;
+        let chunk = Chunk::new(parse_chunk_type(chunk_type)?, bytes(data, len)?.to_vec());
+        png.png.append_chunk(chunk)
+    })
+}
+
+/// Removes the first chunk of a type
+///
+/// # Safety
+/// `png` must be a live handle and `chunk_type` a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_remove_chunk(
+    png: *mut SspngmePng,
+    chunk_type: *const c_char,
+) -> c_int {
+    guard(|| {
+        let png = handle_mut(png)?;
+        png.png.remove_chunk(string(chunk_type)?)?;
+        Ok(())
+    })
+}
+
+/// Hides a payload in a chunk of its own, sealed in an envelope like the encode command
+/// does. `content_type` may be null.
+///
+/// # Safety
+/// `png` must be a live handle, `chunk_type` a null terminated string, `payload` must point
+/// to `len` readable bytes and `content_type` must be null or a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_encode(
+    png: *mut SspngmePng,
+    chunk_type: *const c_char,
+    payload: *const u8,
+    len: usize,
+    content_type: *const c_char,
+) -> c_int {
+    guard(|| {
+        let png = handle_mut(png)?;
+        let chunk_type = parse_chunk_type(chunk_type)?;
+        let content_type = match content_type.is_null() {
+            true => DEFAULT_CONTENT_TYPE,
+            false => string(content_type)?,
+        };
+
+        let metadata = Metadata::new(content_type, None);
+        let sealed = envelope::seal(bytes(payload, len)?, &metadata, &SealOptions::default())?;
+        png.png.append_chunk(Chunk::new(chunk_type, sealed))
+    })
+}
+
+/// Finds the payload in the first chunk of a type, opening its envelope if it has one.
+/// The payload is written to `out` and given back with `sspngme_buffer_free`.
+///
+/// # Safety
+/// `png` must be a live handle, `chunk_type` a null terminated string and `out` must point
+/// to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_decode(
+    png: *const SspngmePng,
+    chunk_type: *const c_char,
+    out: *mut SspngmeBuffer,
+) -> c_int {
+    guard(|| {
+        let out = writable(out)?;
+        let data = find_chunk(handle(png)?, chunk_type)?.data();
+
+        let payload = match envelope::is_envelope(data) {
+            true => envelope::open(data)?.payload,
+            false => data.to_vec(),
+        };
+        *out = buffer(payload);
+        Ok(())
+    })
+}
+
+/// Writes the bytes of the file to `out`, given back with `sspngme_buffer_free`
+///
+/// # Safety
+/// `png` must be a live handle and `out` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_png_serialize(
+    png: *const SspngmePng,
+    out: *mut SspngmeBuffer,
+) -> c_int {
+    guard(|| {
+        let out = writable(out)?;
+        *out = buffer(handle(png)?.png.as_bytes());
+        Ok(())
+    })
+}
+
+/// Frees the bytes of a buffer and empties it, an empty buffer is ignored
+///
+/// # Safety
+/// `buffer` must be null or point to a buffer the library filled in.
+#[no_mangle]
+pub unsafe extern "C" fn sspngme_buffer_free(buffer: *mut SspngmeBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                buffer.data,
+                buffer.len,
+            )));
+        }
+        buffer.data = ptr::null_mut();
+        buffer.len = 0;
+    }
+}
+
+/// The message of the last error on this thread, empty if there was none. The string stays
+/// valid until the next error on the same thread.
+#[no_mangle]
+pub extern "C" fn sspngme_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Runs the body of an exported function, turning errors and panics into codes
+fn guard(body: impl FnOnce() -> Result<(), Error>) -> c_int {
+    let (code, message) = match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return SSPNGME_OK,
+        Ok(Err(error)) => (code_of(error.as_ref()), error.to_string()),
+        Err(_) => (SSPNGME_ERROR_PANIC, "The library panicked.".to_string()),
+    };
+
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    code
+}
+
+/// The code of an error, by its type and variant
+fn code_of(error: &(dyn std::error::Error + 'static)) -> c_int {
+    if let Some(error) = error.downcast_ref::<FfiError>() {
+        return match error {
+            FfiError::NullPointer => SSPNGME_ERROR_NULL_POINTER,
+            FfiError::InvalidString => SSPNGME_ERROR_INVALID_ARGUMENT,
+            FfiError::InvalidPng(_) => SSPNGME_ERROR_INVALID_PNG,
+        };
+    }
+
+    if let Some(error) = error.downcast_ref::<PngError>() {
+        return match error {
+            PngError::NonExistentChunk | PngError::NoTrailingData => SSPNGME_ERROR_NOT_FOUND,
+            PngError::IndexOutOfBounds => SSPNGME_ERROR_INDEX_OUT_OF_BOUNDS,
+            PngError::InvalidPlacement => SSPNGME_ERROR_ORDERING,
+            PngError::TruncatedChunk | PngError::InvalidChunk(..) => SSPNGME_ERROR_INVALID_PNG,
+        };
+    }
+
+    if error.is::<ChunkTypeError>() {
+        SSPNGME_ERROR_INVALID_CHUNK_TYPE
+    } else if error.is::<OrderingError>() {
+        SSPNGME_ERROR_ORDERING
+    } else if error.is::<EnvelopeError>() {
+        SSPNGME_ERROR_PAYLOAD
+    } else {
+        SSPNGME_ERROR_OTHER
+    }
+}
+
+unsafe fn handle<'a>(png: *const SspngmePng) -> Result<&'a SspngmePng, Error> {
+    png.as_ref().ok_or_else(|| FfiError::NullPointer.into())
+}
+
+unsafe fn handle_mut<'a>(png: *mut SspngmePng) -> Result<&'a mut SspngmePng, Error> {
+    png.as_mut().ok_or_else(|| FfiError::NullPointer.into())
+}
+
+unsafe fn writable<'a, T>(out: *mut T) -> Result<&'a mut T, Error> {
+    out.as_mut().ok_or_else(|| FfiError::NullPointer.into())
+}
+
+/// The bytes behind a pointer, null is fine for no bytes at all
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Error> {
+    match (data.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(FfiError::NullPointer.into()),
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn string<'a>(text: *const c_char) -> Result<&'a str, Error> {
+    if text.is_null() {
+        return Err(FfiError::NullPointer.into());
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|_| FfiError::InvalidString.into())
+}
+
+unsafe fn parse_chunk_type(text: *const c_char) -> Result<ChunkType, Error> {
+    ChunkType::from_str(string(text)?)
+}
+
+unsafe fn find_chunk(png: &SspngmePng, chunk_type: *const c_char) -> Result<&Chunk, Error> {
+    let chunk_type = string(chunk_type)?;
+    png.png
+        .chunk_by_type(chunk_type)
+        .ok_or_else(|| PngError::NonExistentChunk.into())
+}
+
+fn chunk_at(png: &SspngmePng, index: usize) -> Result<&Chunk, Error> {
+    png.png
+        .chunks()
+        .get(index)
+        .ok_or_else(|| PngError::IndexOutOfBounds.into())
+}
+
+/// Points the caller at bytes the handle keeps owning
+unsafe fn lend(bytes: &[u8], data: *mut *const u8, len: *mut usize) -> Result<(), Error> {
+    let data = writable(data)?;
+    let len = writable(len)?;
+
+    *data = bytes.as_ptr();
+    *len = bytes.len();
+    Ok(())
+}
+
+fn buffer(bytes: Vec<u8>) -> SspngmeBuffer {
+    let len = bytes.len();
+    SspngmeBuffer {
+        data: Box::into_raw(bytes.into_boxed_slice()) as *mut u8,
+        len,
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FfiError {
+    #[error("A pointer that can't be null is null.")]
+    NullPointer,
+
+    #[error("A string is not valid UTF-8.")]
+    InvalidString,
+
+    #[error("The buffer is not a PNG file: {0}")]
+    InvalidPng(String),
+}
+
+#[cfg(test)]
+mod ffi_tests {
+    use super::*;
+
+    fn testing_bytes() -> Vec<u8> {
+        include_bytes!("../sss.png").to_vec()
+    }
+
+    fn parse(bytes: &[u8]) -> *mut SspngmePng {
+        let mut png = ptr::null_mut();
+        let code = unsafe { sspngme_png_parse(bytes.as_ptr(), bytes.len(), &mut png) };
+        assert_eq!(code, SSPNGME_OK);
+        png
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(sspngme_last_error_message()) }
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_chunks() {
+        let bytes = testing_bytes();
+        let png = parse(&bytes);
+
+        unsafe {
+            let mut count = 0;
+            assert_eq!(sspngme_png_chunk_count(png, &mut count), SSPNGME_OK);
+            assert_eq!(count, Png::try_from(&bytes[..]).unwrap().chunks().len());
+
+            let mut chunk_type = [0 as c_char; 5];
+            assert_eq!(
+                sspngme_png_chunk_type(png, 0, chunk_type.as_mut_ptr()),
+                SSPNGME_OK
+            );
+            assert_eq!(
+                CStr::from_ptr(chunk_type.as_ptr()).to_str().unwrap(),
+                "IHDR"
+            );
+
+            let (mut data, mut len) = (ptr::null(), 0);
+            assert_eq!(
+                sspngme_png_get_chunk(png, c"IHDR".as_ptr(), &mut data, &mut len),
+                SSPNGME_OK
+            );
+            assert_eq!(len, 13);
+            assert_eq!(
+                sspngme_png_chunk_data(png, count, &mut data, &mut len),
+                SSPNGME_ERROR_INDEX_OUT_OF_BOUNDS
+            );
+
+            sspngme_png_free(png);
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_and_serialize() {
+        let png = parse(&testing_bytes());
+
+        unsafe {
+            let payload = b"hidden from C";
+            assert_eq!(
+                sspngme_png_encode(
+                    png,
+                    c"ruSt".as_ptr(),
+                    payload.as_ptr(),
+                    payload.len(),
+                    ptr::null()
+                ),
+                SSPNGME_OK
+            );
+
+            let mut serialized = SspngmeBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(sspngme_png_serialize(png, &mut serialized), SSPNGME_OK);
+            let reparsed = parse(slice::from_raw_parts(serialized.data, serialized.len));
+            sspngme_buffer_free(&mut serialized);
+            assert!(serialized.data.is_null());
+
+            let mut decoded = SspngmeBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(
+                sspngme_png_decode(reparsed, c"ruSt".as_ptr(), &mut decoded),
+                SSPNGME_OK
+            );
+            assert_eq!(slice::from_raw_parts(decoded.data, decoded.len), payload);
+            sspngme_buffer_free(&mut decoded);
+
+            assert_eq!(
+                sspngme_png_remove_chunk(reparsed, c"ruSt".as_ptr()),
+                SSPNGME_OK
+            );
+            assert_eq!(
+                sspngme_png_decode(reparsed, c"ruSt".as_ptr(), &mut decoded),
+                SSPNGME_ERROR_NOT_FOUND
+            );
+
+            sspngme_png_free(reparsed);
+            sspngme_png_free(png);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut png = ptr::null_mut();
+
+        unsafe {
+            assert_eq!(
+                sspngme_png_parse(b"not a png".as_ptr(), 9, &mut png),
+                SSPNGME_ERROR_INVALID_PNG
+            );
+            assert!(last_error().contains("not a PNG file"));
+            assert!(png.is_null());
+
+            let mut count = 0;
+            assert_eq!(
+                sspngme_png_chunk_count(ptr::null(), &mut count),
+                SSPNGME_ERROR_NULL_POINTER
+            );
+
+            let png = parse(&testing_bytes());
+            assert_eq!(
+                sspngme_png_add_chunk(png, c"ru1t".as_ptr(), ptr::null(), 0),
+                SSPNGME_ERROR_INVALID_CHUNK_TYPE
+            );
+            assert_eq!(
+                sspngme_png_add_chunk(png, c"IHDR".as_ptr(), ptr::null(), 0),
+                SSPNGME_ERROR_ORDERING
+            );
+            assert!(!last_error().is_empty());
+
+            sspngme_png_free(png);
+        }
+    }
+}
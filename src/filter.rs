@@ -0,0 +1,188 @@
+/// The filter applied to a row of image data before compression
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = FilterError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            _ => Err(FilterError::InvalidFilterType(value)),
+        }
+    }
+}
+
+/// The Paeth predictor: picks whichever of left, above and upper left is closest to
+/// `left + above - upper_left`
+pub fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_above = (estimate - above as i16).abs();
+    let distance_upper_left = (estimate - upper_left as i16).abs();
+
+    if distance_left <= distance_above && distance_left <= distance_upper_left {
+        left
+    } else if distance_above <= distance_upper_left {
+        above
+    } else {
+        upper_left
+    }
+}
+
+/// Reverses the filter of a row in place. `previous` is the already unfiltered row above
+/// (all zeroes for the first row) and `distance` is the number of bytes per pixel,
+/// rounded up to 1.
+pub fn unfilter(filter: FilterType, distance: usize, previous: &[u8], row: &mut [u8]) {
+    match filter {
+        FilterType::None => {}
+        FilterType::Sub => {
+            for i in distance..row.len() {
+                row[i] = row[i].wrapping_add(row[i - distance]);
+            }
+        }
+        FilterType::Up => {
+            for (byte, above) in row.iter_mut().zip(previous) {
+                *byte = byte.wrapping_add(*above);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..row.len() {
+                let left = if i >= distance { row[i - distance] } else { 0 };
+                let average = ((left as u16 + previous[i] as u16) / 2) as u8;
+                row[i] = row[i].wrapping_add(average);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..row.len() {
+                let (left, upper_left) = if i >= distance {
+                    (row[i - distance], previous[i - distance])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(left, previous[i], upper_left));
+            }
+        }
+    }
+}
+
+/// Applies a filter to a row, the opposite of [`unfilter`]. Returns the filtered bytes
+/// without the filter type byte.
+pub fn filter(filter: FilterType, distance: usize, previous: &[u8], row: &[u8]) -> Vec<u8> {
+    let left = |i: usize| if i >= distance { row[i - distance] } else { 0 };
+    let upper_left = |i: usize| if i >= distance { previous[i - distance] } else { 0 };
+
+    (0..row.len())
+        .map(|i| {
+            let prediction = match filter {
+                FilterType::None => 0,
+                FilterType::Sub => left(i),
+                FilterType::Up => previous[i],
+                FilterType::Average => ((left(i) as u16 + previous[i] as u16) / 2) as u8,
+                FilterType::Paeth => paeth(left(i), previous[i], upper_left(i)),
+            };
+            row[i].wrapping_sub(prediction)
+        })
+        .collect()
+}
+
+/// Tries every filter on a row and picks the one with the minimum sum of absolute
+/// differences, treating the filtered bytes as signed.
+pub fn filter_adaptive(distance: usize, previous: &[u8], row: &[u8]) -> (FilterType, Vec<u8>) {
+    [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ]
+    .into_iter()
+    .map(|filter_type| (filter_type, filter(filter_type, distance, previous, row)))
+    .min_by_key(|(_, filtered)| {
+        filtered
+            .iter()
+            .map(|&byte| (byte as i8).unsigned_abs() as u64)
+            .sum::<u64>()
+    })
+    .unwrap()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FilterError {
+    #[error("Invalid filter type {0}.")]
+    InvalidFilterType(u8),
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+    }
+
+    #[test]
+    fn test_unfilter_sub() {
+        let mut row = vec![1, 2, 1, 1];
+        unfilter(FilterType::Sub, 2, &[0; 4], &mut row);
+        assert_eq!(row, vec![1, 2, 2, 3]);
+    }
+
+    #[test]
+    fn test_unfilter_up() {
+        let mut row = vec![1, 2, 255];
+        unfilter(FilterType::Up, 1, &[5, 5, 5], &mut row);
+        assert_eq!(row, vec![6, 7, 4]);
+    }
+
+    #[test]
+    fn test_unfilter_average() {
+        let mut row = vec![1, 1];
+        unfilter(FilterType::Average, 1, &[4, 6], &mut row);
+        assert_eq!(row, vec![3, 5]);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let previous = [10, 200, 30, 40, 50, 60];
+        let row = [11, 190, 35, 255, 0, 61];
+
+        for filter_type in [
+            FilterType::None,
+            FilterType::Sub,
+            FilterType::Up,
+            FilterType::Average,
+            FilterType::Paeth,
+        ] {
+            let mut filtered = filter(filter_type, 2, &previous, &row);
+            unfilter(filter_type, 2, &previous, &mut filtered);
+            assert_eq!(filtered, row.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_filter_adaptive() {
+        // A gradient is best predicted from the left
+        let row: Vec<u8> = (0..32).map(|i| i * 3).collect();
+        let (filter_type, _) = filter_adaptive(1, &[0; 32], &row);
+        assert_eq!(filter_type, FilterType::Sub);
+    }
+
+    #[test]
+    fn test_invalid_filter_type() {
+        assert!(FilterType::try_from(5).is_err());
+    }
+}
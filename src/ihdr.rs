@@ -0,0 +1,215 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Error};
+
+/// The color type of an image, which decides the channels every pixel has
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+/// The `IHDR` chunk: the size and pixel format of the image
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl ColorType {
+    /// The number of samples in a single pixel
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// The bit depths the PNG specification allows for the color type
+    pub fn allowed_bit_depths(self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(HeaderError::InvalidColorType(value).into()),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl ImageHeader {
+    /// Reads the header of a PNG file
+    pub fn from_png(png: &Png) -> Result<ImageHeader, Error> {
+        match png.chunk_by_type("IHDR") {
+            Some(chunk) => ImageHeader::try_from(chunk),
+            None => Err(HeaderError::Missing.into()),
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type as u8);
+
+        // Compression and filter method, 0 is the only one defined
+        data.push(0);
+        data.push(0);
+        data.push(self.interlaced as u8);
+
+        Chunk::new(ChunkType::new(*b"IHDR"), data)
+    }
+
+    /// The number of bits a single pixel takes up
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// The number of bytes a row of `width` pixels takes up, without the filter byte
+    pub fn stride(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// The distance in bytes between a byte and the matching byte of the previous pixel,
+    /// used by the filters. Pixels smaller than a byte use a distance of 1.
+    pub fn filter_distance(&self) -> usize {
+        (self.bits_per_pixel() / 8).max(1)
+    }
+}
+
+impl TryFrom<&Chunk> for ImageHeader {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+
+        if &chunk.chunk_type().bytes() != b"IHDR" || data.len() != 13 {
+            return Err(HeaderError::InvalidLength.into());
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+
+        // The dimensions are limited to 2^31 - 1
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(HeaderError::InvalidDimensions(width, height).into());
+        }
+
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(HeaderError::InvalidBitDepth(bit_depth, data[9]).into());
+        }
+
+        if data[10] != 0 || data[11] != 0 {
+            return Err(HeaderError::UnknownMethod.into());
+        }
+
+        let interlaced = match data[12] {
+            0 => false,
+            1 => true,
+            method => return Err(HeaderError::InvalidInterlaceMethod(method).into()),
+        };
+
+        Ok(ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HeaderError {
+    #[error("The file has no IHDR chunk.")]
+    Missing,
+
+    #[error("The IHDR chunk must be 13 bytes long.")]
+    InvalidLength,
+
+    #[error("Invalid image size {0}x{1}.")]
+    InvalidDimensions(u32, u32),
+
+    #[error("Invalid color type {0}.")]
+    InvalidColorType(u8),
+
+    #[error("A bit depth of {0} is not allowed for color type {1}.")]
+    InvalidBitDepth(u8, u8),
+
+    #[error("Unknown compression or filter method.")]
+    UnknownMethod,
+
+    #[error("Invalid interlace method {0}.")]
+    InvalidInterlaceMethod(u8),
+}
+
+#[cfg(test)]
+mod ihdr_tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = ImageHeader {
+            width: 50,
+            height: 20,
+            bit_depth: 4,
+            color_type: ColorType::Indexed,
+            interlaced: true,
+        };
+
+        let parsed = ImageHeader::try_from(&header.to_chunk()).unwrap();
+        assert_eq!(header, parsed);
+        assert_eq!(parsed.bits_per_pixel(), 4);
+        assert_eq!(parsed.stride(5), 3);
+        assert_eq!(parsed.filter_distance(), 1);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth() {
+        let header = ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth: 4,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+
+        assert!(ImageHeader::try_from(&header.to_chunk()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        let header = ImageHeader {
+            width: 0,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+
+        assert!(ImageHeader::try_from(&header.to_chunk()).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+pub mod apng;
+#[cfg(feature = "async")]
+pub mod async_stream;
+pub mod camouflage;
+pub mod chunk;
+pub mod chunk_type;
+pub mod color;
+pub mod decoder;
+pub mod deflate_slack;
+pub mod deniable;
+pub mod diff;
+pub mod dump;
+pub mod ecc;
+pub mod encoder;
+pub mod envelope;
+pub mod exif;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod filter;
+pub mod ihdr;
+pub mod meta;
+pub mod optimize;
+pub mod ordering;
+pub mod palette;
+pub mod palette_stego;
+pub mod payload;
+pub mod png;
+pub mod stream;
+pub mod xmp;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
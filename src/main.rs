@@ -1,11 +1,19 @@
-#![feature(cursor_remaining)]
-
 mod args;
-mod chunk;
-mod chunk_type;
+mod batch;
 mod commands;
-mod png;
-use clap::{Parser, Subcommand};
+mod output;
+mod server;
+use sspngme::{
+    apng, camouflage, chunk, chunk_type, color, decoder, deflate_slack, deniable, diff, dump, ecc,
+    encoder, envelope, exif, filter, ihdr, meta, optimize, ordering, palette, palette_stego,
+    payload, png, stream, xmp,
+};
+use batch::BatchOptions;
+use camouflage::Carrier;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use output::Format;
+use payload::Method;
+use std::io::IsTerminal;
 
 /// Hide messages in PNG files
 #[derive(Parser)]
@@ -13,25 +21,99 @@ use clap::{Parser, Subcommand};
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+
+    /// Stop starting on more files once one has failed, when given a directory or pattern
+    #[clap(long, global = true)]
+    fail_fast: bool,
+
+    /// The number of files processed at the same time, all cores by default
+    #[clap(long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Print text, or a JSON document with the messages, data and typed error of the command
+    #[clap(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Encode a PNG file
+    #[clap(allow_missing_positional = true)]
     Encode {
         #[clap(value_parser)]
         file_name: String,
+        /// The chunk type to hide the payload in, only needed by the chunk method
         #[clap(value_parser)]
-        chunk_type: String,
+        chunk_type: Option<String>,
         #[clap(value_parser)]
         payload: String,
+        /// How the payload is hidden
+        #[clap(long, value_enum, default_value_t = Method::Chunk)]
+        method: Method,
+        /// The metadata chunk the camouflage method hides the payload in
+        #[clap(long, value_enum, default_value_t = Carrier::Comment)]
+        carrier: Carrier,
+        /// Protect the payload with Reed-Solomon coding, PARITY bytes in every 255 byte
+        /// block fix up to PARITY / 2 damaged bytes
+        #[clap(long, value_name = "PARITY")]
+        ecc: Option<usize>,
+        /// Compress the payload inside the envelope
+        #[clap(long, conflicts_with = "raw")]
+        compress: bool,
+        /// Hide the payload as it is, without the envelope that holds its metadata
+        #[clap(long)]
+        raw: bool,
+        /// Hide the contents of the file named by PAYLOAD
+        #[clap(long)]
+        from_file: bool,
+        /// Encrypt the payload, nobody without the passphrase can tell what it holds
+        #[clap(long)]
+        passphrase: Option<String>,
+        /// Hide another payload under a passphrase of its own, holders of one passphrase
+        /// can't tell whether there are others
+        #[clap(long, requires = "passphrase", value_name = "PASSPHRASE=PAYLOAD")]
+        also: Vec<String>,
+        /// The number of slots for encrypted payloads, the unused ones are random decoys
+        #[clap(long, requires = "passphrase", default_value_t = deniable::DEFAULT_SLOTS)]
+        slots: usize,
+        /// A private chunk type the palette and deflate-slack methods keep although it is
+        /// unsafe to copy, chunks holding an envelope are always kept
+        #[clap(long, value_name = "CHUNK_TYPE")]
+        keep: Vec<String>,
+        /// Hide the payload in the image data of this frame of an animated PNG file, only
+        /// for the deflate-slack method
+        #[clap(long, value_name = "INDEX")]
+        frame: Option<usize>,
     },
     /// Decodes a PNG file
     Decode {
         #[clap(value_parser)]
         file_name: String,
+        /// The chunk type the payload is hidden in, only needed by the chunk method
         #[clap(value_parser)]
-        chunk_type: String,
+        chunk_type: Option<String>,
+        /// How the payload is hidden
+        #[clap(long, value_enum, default_value_t = Method::Chunk)]
+        method: Method,
+        /// The metadata chunk the camouflage method hides the payload in
+        #[clap(long, value_enum, default_value_t = Carrier::Comment)]
+        carrier: Carrier,
+        /// A raw payload was hidden with Reed-Solomon coding, fix what was damaged
+        #[clap(long)]
+        ecc: bool,
+        /// Don't look for an envelope, show the hidden bytes as they are
+        #[clap(long)]
+        raw: bool,
+        /// Write the payload to a file instead of printing it
+        #[clap(long, value_name = "PATH")]
+        output: Option<String>,
+        /// Decrypt the payload this passphrase belongs to
+        #[clap(long)]
+        passphrase: Option<String>,
+        /// Read the payload from the image data of this frame of an animated PNG file, only
+        /// for the deflate-slack method
+        #[clap(long, value_name = "INDEX")]
+        frame: Option<usize>,
     },
     /// Removes a chunk given a chunk type
     Remove {
@@ -40,42 +122,476 @@ enum Commands {
         #[clap(value_parser)]
         chunk_type: String,
     },
+    /// Lists the chunks of a PNG file and any data after IEND
+    Inspect {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Looks for payloads hidden with any of the methods
+    Scan {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Removes whatever could hold a hidden payload: chunks of unknown types, carriers that
+    /// hold one, data after IEND, the palette order and the deflate slack
+    Sanitize {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Lists the frames of an animated PNG file
+    Frames {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Losslessly shrinks a PNG file, hidden chunks are kept as they are
+    Optimize {
+        #[clap(value_parser)]
+        file_name: String,
+        /// Let the copy rules drop unknown chunks that are unsafe to copy, all of them are
+        /// kept otherwise
+        #[clap(long)]
+        drop_unknown: bool,
+        /// With --drop-unknown, a private chunk type to keep although it is unsafe to copy,
+        /// chunks holding an envelope are always kept
+        #[clap(long, value_name = "CHUNK_TYPE")]
+        keep: Vec<String>,
+    },
+    /// Writes every chunk to a file of its own in a directory, with a JSON manifest of
+    /// their order, types and CRCs
+    Extract {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        directory: String,
+    },
+    /// Rebuilds a PNG file from a directory written by extract, computing the CRCs again
+    Assemble {
+        #[clap(value_parser)]
+        directory: String,
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Shows which chunks were added, removed, moved or modified between two PNG files
+    Diff {
+        #[clap(value_parser)]
+        old_file_name: String,
+        #[clap(value_parser)]
+        new_file_name: String,
+        /// Also decode both images and compare their pixels
+        #[clap(long)]
+        pixels: bool,
+    },
+    /// Reads and edits the EXIF data of a PNG file
+    Exif {
+        #[clap(subcommand)]
+        command: ExifCommands,
+    },
+    /// Reads and edits the XMP metadata of a PNG file
+    Xmp {
+        #[clap(subcommand)]
+        command: XmpCommands,
+    },
+    /// Reads and edits the pixel density, modification time, offset and scale
+    Meta {
+        #[clap(subcommand)]
+        command: MetaCommands,
+    },
+    /// Serves inspect, encode, decode, remove, scan and sanitize over HTTP
+    Serve {
+        /// The address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080", value_name = "ADDRESS")]
+        bind: String,
+        /// The largest request body accepted, in bytes
+        #[clap(long, default_value_t = server::DEFAULT_MAX_BODY, value_name = "BYTES")]
+        max_body: usize,
+        /// The most bytes the image data of an upload, or a payload, may inflate to
+        #[clap(long, default_value_t = server::DEFAULT_MAX_INFLATED, value_name = "BYTES")]
+        max_inflated: usize,
+        /// The number of requests handled at the same time
+        #[clap(long, default_value_t = server::DEFAULT_WORKERS, value_name = "COUNT")]
+        workers: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum MetaCommands {
+    /// Shows the pHYs, tIME, oFFs and sCAL chunks
+    Show {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Sets the pixel density in dots per inch
+    SetDpi {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        dpi: f64,
+        /// A different vertical density
+        #[clap(long, value_name = "DPI")]
+        vertical: Option<f64>,
+    },
+    /// Sets the modification time to now
+    Touch {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Sets the position of the image on the page
+    SetOffset {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser, allow_hyphen_values = true)]
+        x: i32,
+        #[clap(value_parser, allow_hyphen_values = true)]
+        y: i32,
+        /// The offset is in micrometers instead of pixels
+        #[clap(long)]
+        micrometers: bool,
+    },
+    /// Sets the physical width and height of a pixel in meters
+    SetScale {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        width: f64,
+        #[clap(value_parser)]
+        height: f64,
+        /// The scale is in radians instead of meters
+        #[clap(long)]
+        radians: bool,
+    },
+}
+
+/// Properties are given as prefix:name, like dc:title or xmp:CreatorTool
+#[derive(Subcommand)]
+enum XmpCommands {
+    /// Prints the XMP packet
+    Dump {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Shows the value of a property, or every property if none is given
+    Get {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        property: Option<String>,
+    },
+    /// Sets a property, adding an XMP packet if there is none. Array items are separated
+    /// by semicolons.
+    Set {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        property: String,
+        #[clap(value_parser)]
+        value: String,
+        /// The namespace URI of a prefix the packet doesn't declare yet
+        #[clap(long, value_name = "URI")]
+        namespace: Option<String>,
+    },
+}
 
+/// Tags are given by name, like Make or GPSLatitude, or by directory and number, like
+/// gps:0x0002
+#[derive(Subcommand)]
+enum ExifCommands {
+    /// Lists every EXIF tag
+    List {
+        #[clap(value_parser)]
+        file_name: String,
+    },
+    /// Shows the value of a tag
+    Get {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        tag: String,
+    },
+    /// Sets a tag, adding an eXIf chunk if there is none
+    Set {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        tag: String,
+        #[clap(value_parser)]
+        value: String,
+    },
+    /// Removes the given tags, or all EXIF data if none are given
+    Strip {
+        #[clap(value_parser)]
+        file_name: String,
+        #[clap(value_parser)]
+        tags: Vec<String>,
+        /// Remove the GPS location
+        #[clap(long)]
+        gps: bool,
+    },
 }
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+pub use sspngme::{Error, Result};
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(error) => error.exit(),
+    };
 
+    output::set_format(cli.format);
+    if cli.format == Format::Json || !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+
+    if cli.format == Format::Text {
+        return run(&cli);
+    }
+
+    // The names of the subcommands, like "exif get"
+    let mut command = Vec::new();
+    let mut subcommand = matches.subcommand();
+    while let Some((name, matches)) = subcommand {
+        command.push(name);
+        subcommand = matches.subcommand();
+    }
+
+    let (result, captured) = output::capture(|| run(&cli));
+    if !output::streamed() {
+        println!("{}", output::document(&command.join(" "), &result, captured));
+    }
+
+    if result.is_err() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let batch = BatchOptions {
+        fail_fast: cli.fail_fast,
+        jobs: cli.jobs,
+    };
 
     match &cli.command {
         Commands::Encode {
             file_name,
             chunk_type,
             payload,
+            method,
+            carrier,
+            ecc,
+            compress,
+            raw,
+            from_file,
+            passphrase,
+            also,
+            slots,
+            keep,
+            frame,
         } => {
-            if args::encode(file_name, chunk_type, payload).is_err() {
-                std::fs::remove_file(&format!("{}.temp", file_name))?;
-            }
+            let options = args::PayloadOptions {
+                parity: *ecc,
+                compress: *compress,
+                raw: *raw,
+                compact: method.compact(),
+                from_file: *from_file,
+                passphrase: passphrase.clone(),
+                also: also.clone(),
+                slots: *slots,
+            };
+            let payload = args::prepare_payload(payload, &options)?;
+
+            let target = payload::Target {
+                method: *method,
+                chunk_type: chunk_type.as_deref(),
+                carrier: *carrier,
+                frame: *frame,
+            };
+            check_target(&target)?;
+            let keep = parse_chunk_types(keep)?;
+
+            args::batch(file_name, &batch, |file_name| {
+                args::encode(file_name, &target, &payload, &keep)
+            })?;
         }
         Commands::Decode {
             file_name,
             chunk_type,
+            method,
+            carrier,
+            ecc,
+            raw,
+            output,
+            passphrase,
+            frame,
         } => {
-            args::decode(file_name, chunk_type)?;
+            let target = payload::Target {
+                method: *method,
+                chunk_type: chunk_type.as_deref(),
+                carrier: *carrier,
+                frame: *frame,
+            };
+            check_target(&target)?;
+            let options = args::DecodeOptions {
+                ecc: *ecc,
+                raw: *raw,
+                output: output.clone(),
+                passphrase: passphrase.clone(),
+            };
+
+            // Every file would write its payload over the one before
+            if options.output.is_some() && batch::is_batch(file_name) {
+                return Err("--output can't be used with several files.".into());
+            }
+
+            args::batch(file_name, &batch, |file_name| {
+                args::decode(file_name, &target, &options)
+            })?;
         }
 
         Commands::Remove {
             file_name,
             chunk_type,
         } => {
-            if args::remove(file_name, chunk_type).is_err() {
-                std::fs::remove_file(&format!("{}.temp", file_name))?;
-            }
+            args::batch(file_name, &batch, |file_name| {
+                let result = args::remove(file_name, chunk_type);
+                if result.is_err() {
+                    let _ = std::fs::remove_file(format!("{}.temp", file_name));
+                }
+                result
+            })?;
+        }
+
+        Commands::Inspect { file_name } => {
+            args::batch(file_name, &batch, args::inspect)?;
+        }
+
+        Commands::Scan { file_name } => {
+            args::batch(file_name, &batch, args::scan)?;
+        }
+
+        Commands::Sanitize { file_name } => {
+            args::batch(file_name, &batch, args::sanitize)?;
+        }
+
+        Commands::Frames { file_name } => {
+            args::frames(file_name)?;
+        }
+
+        Commands::Optimize {
+            file_name,
+            drop_unknown,
+            keep,
+        } => {
+            args::optimize(file_name, *drop_unknown, &parse_chunk_types(keep)?)?;
+        }
+
+        Commands::Extract {
+            file_name,
+            directory,
+        } => {
+            args::extract(file_name, directory)?;
+        }
+
+        Commands::Diff {
+            old_file_name,
+            new_file_name,
+            pixels,
+        } => {
+            args::diff(old_file_name, new_file_name, *pixels)?;
+        }
+
+        Commands::Assemble {
+            directory,
+            file_name,
+        } => {
+            args::assemble(directory, file_name)?;
+        }
+
+        Commands::Exif { command } => match command {
+            ExifCommands::List { file_name } => args::exif_list(file_name)?,
+            ExifCommands::Get { file_name, tag } => args::exif_get(file_name, tag)?,
+            ExifCommands::Set {
+                file_name,
+                tag,
+                value,
+            } => args::exif_set(file_name, tag, value)?,
+            ExifCommands::Strip {
+                file_name,
+                tags,
+                gps,
+            } => args::exif_strip(file_name, tags, *gps)?,
+        },
+
+        Commands::Xmp { command } => match command {
+            XmpCommands::Dump { file_name } => args::xmp_dump(file_name)?,
+            XmpCommands::Get {
+                file_name,
+                property,
+            } => args::xmp_get(file_name, property.as_deref())?,
+            XmpCommands::Set {
+                file_name,
+                property,
+                value,
+                namespace,
+            } => args::xmp_set(file_name, property, value, namespace.as_deref())?,
+        },
+
+        Commands::Meta { command } => match command {
+            MetaCommands::Show { file_name } => args::meta_show(file_name)?,
+            MetaCommands::SetDpi {
+                file_name,
+                dpi,
+                vertical,
+            } => args::meta_set_dpi(file_name, *dpi, vertical.unwrap_or(*dpi))?,
+            MetaCommands::Touch { file_name } => args::meta_touch(file_name)?,
+            MetaCommands::SetOffset {
+                file_name,
+                x,
+                y,
+                micrometers,
+            } => args::meta_set_offset(file_name, *x, *y, *micrometers)?,
+            MetaCommands::SetScale {
+                file_name,
+                width,
+                height,
+                radians,
+            } => args::meta_set_scale(file_name, *width, *height, *radians)?,
+        },
+
+        Commands::Serve {
+            bind,
+            max_body,
+            max_inflated,
+            workers,
+        } => {
+            let options = server::ServeOptions {
+                max_body: *max_body,
+                max_inflated: *max_inflated,
+                workers: *workers,
+            };
+            server::serve(bind, &options)?;
         }
     }
 
     Ok(())
 }
+
+/// The chunk method can't do without a chunk type, and only the deflate-slack method can
+/// hide a payload in a single frame
+fn check_target(target: &payload::Target) -> Result<()> {
+    if target.method == Method::Chunk {
+        target.chunk_type()?;
+    }
+    match target.frame.is_some() && target.method != Method::DeflateSlack {
+        true => Err("--frame only works with the deflate-slack method.".into()),
+        false => Ok(()),
+    }
+}
+
+/// The chunk types given to --keep
+fn parse_chunk_types(chunk_types: &[String]) -> Result<Vec<chunk_type::ChunkType>> {
+    chunk_types
+        .iter()
+        .map(|chunk_type| chunk_type.parse())
+        .collect()
+}
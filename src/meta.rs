@@ -0,0 +1,425 @@
+use crate::{
+    chunk::{Chunk, ChunkError},
+    chunk_type::ChunkType,
+    Error,
+};
+
+// The chunks that place the image in the physical world and in time:
+//
+//   pHYs  pixels per unit on both axes, the unit being the meter or unknown (only the
+//         aspect ratio is known then)
+//   tIME  when the image was last modified, in UTC
+//   oFFs  where the image sits on the page, in pixels or micrometers
+//   sCAL  the physical size of a pixel, in meters or radians, as ASCII floating point
+
+const METERS_PER_INCH: f64 = 0.0254;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DensityUnit {
+    Unknown = 0,
+    Meter = 1,
+}
+
+/// The `pHYs` chunk: the pixel density
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PixelDensity {
+    pub x: u32,
+    pub y: u32,
+    pub unit: DensityUnit,
+}
+
+/// The `tIME` chunk: the time of the last modification, in UTC
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ModificationTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Up to 60 for leap seconds
+    pub second: u8,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OffsetUnit {
+    Pixel = 0,
+    Micrometer = 1,
+}
+
+/// The `oFFs` chunk: the position of the image on a page
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ImageOffset {
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ScaleUnit {
+    Meter = 1,
+    Radian = 2,
+}
+
+/// The `sCAL` chunk: the width and the height a pixel covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalScale {
+    pub unit: ScaleUnit,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl TryFrom<&Chunk> for PixelDensity {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"pHYs", 9)?;
+
+        let data = chunk.data();
+        let unit = match data[8] {
+            0 => DensityUnit::Unknown,
+            1 => DensityUnit::Meter,
+            unit => return Err(MetaError::UnknownUnit("pHYs", unit).into()),
+        };
+
+        Ok(PixelDensity {
+            x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            unit,
+        })
+    }
+}
+
+impl PixelDensity {
+    /// The density of the given dots per inch on both axes, rounded to whole pixels per
+    /// meter
+    pub fn from_dpi(x: f64, y: f64) -> Result<PixelDensity, Error> {
+        let per_meter = |dpi: f64| {
+            let value = (dpi / METERS_PER_INCH).round();
+            if dpi > 0.0 && value >= 1.0 && value <= u32::MAX as f64 {
+                Ok(value as u32)
+            } else {
+                Err(MetaError::BadDensity(dpi))
+            }
+        };
+
+        Ok(PixelDensity {
+            x: per_meter(x)?,
+            y: per_meter(y)?,
+            unit: DensityUnit::Meter,
+        })
+    }
+
+    /// The dots per inch on both axes, `None` if the unit is unknown
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            DensityUnit::Meter => Some((
+                self.x as f64 * METERS_PER_INCH,
+                self.y as f64 * METERS_PER_INCH,
+            )),
+            DensityUnit::Unknown => None,
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_be_bytes());
+        data.push(self.unit as u8);
+
+        Chunk::new(ChunkType::new(*b"pHYs"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for ModificationTime {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"tIME", 7)?;
+
+        let data = chunk.data();
+        let time = ModificationTime {
+            year: u16::from_be_bytes([data[0], data[1]]),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6],
+        };
+
+        let valid = (1..=12).contains(&time.month)
+            && (1..=31).contains(&time.day)
+            && time.hour < 24
+            && time.minute < 60
+            && time.second <= 60;
+
+        if !valid {
+            return Err(MetaError::BadTime.into());
+        }
+
+        Ok(time)
+    }
+}
+
+impl ModificationTime {
+    /// The time the given seconds after the Unix epoch
+    pub fn from_unix(seconds: u64) -> ModificationTime {
+        let days = (seconds / 86400) as i64;
+        let time = seconds % 86400;
+
+        // Converts days since the epoch to a civil date, from Howard Hinnant's date algorithms
+        let shifted = days + 719468;
+        let era = shifted.div_euclid(146097);
+        let day_of_era = shifted.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        ModificationTime {
+            year: year.clamp(0, u16::MAX as i64) as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// The current time
+    pub fn now() -> ModificationTime {
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        ModificationTime::from_unix(seconds)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+
+        Chunk::new(ChunkType::new(*b"tIME"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for ImageOffset {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        chunk.expect(b"oFFs", 9)?;
+
+        let data = chunk.data();
+        let unit = match data[8] {
+            0 => OffsetUnit::Pixel,
+            1 => OffsetUnit::Micrometer,
+            unit => return Err(MetaError::UnknownUnit("oFFs", unit).into()),
+        };
+
+        Ok(ImageOffset {
+            x: i32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            y: i32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            unit,
+        })
+    }
+}
+
+impl ImageOffset {
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_be_bytes());
+        data.push(self.unit as u8);
+
+        Chunk::new(ChunkType::new(*b"oFFs"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for PhysicalScale {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if &chunk.chunk_type().bytes() != b"sCAL" {
+            return Err(ChunkError::UnexpectedType("sCAL".to_string()).into());
+        }
+
+        let (&unit, rest) = chunk.data().split_first().ok_or(MetaError::BadScale)?;
+        let unit = match unit {
+            1 => ScaleUnit::Meter,
+            2 => ScaleUnit::Radian,
+            unit => return Err(MetaError::UnknownUnit("sCAL", unit).into()),
+        };
+
+        // Two ASCII floating point numbers separated by a null byte
+        let mut values = rest.split(|&byte| byte == 0);
+        let (width, height) = match (values.next(), values.next(), values.next()) {
+            (Some(width), Some(height), None) => (parse_scale(width)?, parse_scale(height)?),
+            _ => return Err(MetaError::BadScale.into()),
+        };
+
+        Ok(PhysicalScale {
+            unit,
+            width,
+            height,
+        })
+    }
+}
+
+/// Reads a positive number in the floating point syntax of the PNG specification
+fn parse_scale(bytes: &[u8]) -> Result<f64, Error> {
+    let valid_syntax = !bytes.is_empty()
+        && bytes
+            .iter()
+            .all(|byte| byte.is_ascii_digit() || b"+-.eE".contains(byte));
+
+    let value = match std::str::from_utf8(bytes) {
+        Ok(text) if valid_syntax => text.parse::<f64>().ok(),
+        _ => None,
+    };
+
+    match value {
+        Some(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(MetaError::BadScale.into()),
+    }
+}
+
+impl PhysicalScale {
+    pub fn new(unit: ScaleUnit, width: f64, height: f64) -> Result<PhysicalScale, Error> {
+        let valid = |value: f64| value > 0.0 && value.is_finite();
+        if !valid(width) || !valid(height) {
+            return Err(MetaError::BadScale.into());
+        }
+
+        Ok(PhysicalScale {
+            unit,
+            width,
+            height,
+        })
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = vec![self.unit as u8];
+        data.extend_from_slice(format!("{}", self.width).as_bytes());
+        data.push(0);
+        data.extend_from_slice(format!("{}", self.height).as_bytes());
+
+        Chunk::new(ChunkType::new(*b"sCAL"), data)
+    }
+}
+
+impl std::fmt::Display for PixelDensity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.dpi() {
+            Some((x, y)) => write!(
+                f,
+                "{}x{} pixels per meter ({:.0}x{:.0} dpi)",
+                self.x, self.y, x, y
+            ),
+            None => write!(f, "{}:{} pixel aspect ratio", self.x, self.y),
+        }
+    }
+}
+
+impl std::fmt::Display for ModificationTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl std::fmt::Display for ImageOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.unit {
+            OffsetUnit::Pixel => "pixels",
+            OffsetUnit::Micrometer => "micrometers",
+        };
+        write!(f, "{}, {} {}", self.x, self.y, unit)
+    }
+}
+
+impl std::fmt::Display for PhysicalScale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.unit {
+            ScaleUnit::Meter => "meters",
+            ScaleUnit::Radian => "radians",
+        };
+        write!(f, "{} by {} {} per pixel", self.width, self.height, unit)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MetaError {
+    #[error("Unknown {0} unit {1}.")]
+    UnknownUnit(&'static str, u8),
+
+    #[error("{0} dpi is not a valid density.")]
+    BadDensity(f64),
+
+    #[error("The tIME chunk holds an invalid date or time.")]
+    BadTime,
+
+    #[error("The scale must be two positive numbers.")]
+    BadScale,
+}
+
+#[cfg(test)]
+mod meta_tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_density() {
+        let density = PixelDensity::from_dpi(300.0, 72.0).unwrap();
+        assert_eq!((density.x, density.y), (11811, 2835));
+
+        let parsed = PixelDensity::try_from(&density.to_chunk()).unwrap();
+        assert_eq!(parsed, density);
+
+        let (x, y) = parsed.dpi().unwrap();
+        assert_eq!((x.round(), y.round()), (300.0, 72.0));
+
+        assert!(PixelDensity::from_dpi(0.0, 72.0).is_err());
+    }
+
+    #[test]
+    fn test_modification_time() {
+        let time = ModificationTime::from_unix(951_782_400 + 3661);
+        assert_eq!(time.to_string(), "2000-02-29 01:01:01 UTC");
+        assert_eq!(ModificationTime::try_from(&time.to_chunk()).unwrap(), time);
+
+        let invalid = ModificationTime { month: 13, ..time };
+        assert!(ModificationTime::try_from(&invalid.to_chunk()).is_err());
+    }
+
+    #[test]
+    fn test_image_offset() {
+        let offset = ImageOffset {
+            x: -20,
+            y: 300,
+            unit: OffsetUnit::Micrometer,
+        };
+        assert_eq!(ImageOffset::try_from(&offset.to_chunk()).unwrap(), offset);
+    }
+
+    #[test]
+    fn test_physical_scale() {
+        let scale = PhysicalScale::new(ScaleUnit::Meter, 0.00025, 1.5e-4).unwrap();
+        let chunk = scale.to_chunk();
+        assert_eq!(&chunk.data()[1..], b"0.00025\x000.00015");
+        assert_eq!(PhysicalScale::try_from(&chunk).unwrap(), scale);
+
+        let chunk = |data: &[u8]| Chunk::new(ChunkType::new(*b"sCAL"), data.to_vec());
+        assert!(PhysicalScale::try_from(&chunk(b"\x011.5e3\x002")).is_ok());
+        assert!(PhysicalScale::try_from(&chunk(b"\x01-1\x002")).is_err());
+        assert!(PhysicalScale::try_from(&chunk(b"\x011\x002\x003")).is_err());
+        assert!(PhysicalScale::try_from(&chunk(b"\x031\x002")).is_err());
+        assert!(PhysicalScale::try_from(&chunk(b"\x01inf\x002")).is_err());
+        assert!(PhysicalScale::new(ScaleUnit::Radian, 0.0, 1.0).is_err());
+    }
+}
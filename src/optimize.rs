@@ -0,0 +1,555 @@
+use std::fmt::Display;
+
+use crate::{
+    apng,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    decoder::{self, PixelBuffer},
+    encoder::{self, EncoderOptions, FilterStrategy},
+    filter::FilterType,
+    ihdr::{ColorType, ImageHeader},
+    palette::{self, Background},
+    png::Png,
+    Error,
+};
+
+/// The filter strategies tried when re-deflating the image data
+const STRATEGIES: [FilterStrategy; 6] = [
+    FilterStrategy::Fixed(FilterType::None),
+    FilterStrategy::Fixed(FilterType::Sub),
+    FilterStrategy::Fixed(FilterType::Up),
+    FilterStrategy::Fixed(FilterType::Average),
+    FilterStrategy::Fixed(FilterType::Paeth),
+    FilterStrategy::Adaptive,
+];
+
+/// Chunks whose meaning depends on the color type or bit depth, reductions are skipped
+/// when any of them is present so they stay correct. Indexed images are the exception,
+/// their palette is reduced and the chunks are remapped to the new palette.
+const FORMAT_DEPENDENT_CHUNKS: [&str; 4] = ["tRNS", "bKGD", "sBIT", "hIST"];
+
+/// A change to the pixel format that keeps every pixel the same
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Reduction {
+    /// Every 16 bit sample was a repeated byte
+    SixteenToEightBits,
+    /// Every pixel was fully opaque
+    DropAlpha,
+    /// Every pixel was gray
+    RgbToGrayscale,
+    /// Palette entries no pixel used were removed
+    UnusedPaletteEntries { removed: usize },
+    /// The palette indices fit in fewer bits
+    PaletteBitDepth { from: u8, to: u8 },
+}
+
+/// What `optimize` did to a file
+#[derive(Debug, Clone)]
+pub struct OptimizeReport {
+    pub original_size: usize,
+    pub optimized_size: usize,
+    pub reductions: Vec<Reduction>,
+    /// Types of the redundant chunks that were removed
+    pub dropped: Vec<String>,
+    /// Types of the unknown chunks the copy rules dropped
+    pub unsafe_to_copy: Vec<String>,
+    pub filter: FilterStrategy,
+}
+
+impl Display for Reduction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reduction::SixteenToEightBits => write!(f, "reduced 16 bit samples to 8 bits"),
+            Reduction::DropAlpha => write!(f, "removed the unused alpha channel"),
+            Reduction::RgbToGrayscale => write!(f, "converted gray RGB pixels to grayscale"),
+            Reduction::UnusedPaletteEntries { removed } => {
+                write!(f, "removed {} unused palette entries", removed)
+            }
+            Reduction::PaletteBitDepth { from, to } => {
+                write!(f, "reduced palette indices from {} to {} bits", from, to)
+            }
+        }
+    }
+}
+
+impl Display for FilterStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterStrategy::Fixed(filter_type) => write!(f, "{:?}", filter_type),
+            FilterStrategy::Adaptive => write!(f, "Adaptive"),
+        }
+    }
+}
+
+/// Losslessly shrinks a PNG file. The pixels are reduced to the smallest format that
+/// holds them exactly, re-deflated with every filter strategy and the smallest result is
+/// written as a single IDAT chunk. Unknown unsafe-to-copy chunks are dropped by the copy
+/// rules unless listed in `keep`, other chunks (like hidden payloads) are never touched.
+pub fn optimize(png: &Png, keep: &[ChunkType]) -> Result<(Png, OptimizeReport), Error> {
+    let original_size = png.as_bytes().len();
+
+    let (cleaned, dropped) = drop_redundant_chunks(png);
+
+    let mut pixels = decoder::decode(&cleaned)?;
+
+    // Frames of an animation share the format of IHDR, so it has to stay as it is
+    let animated = apng::is_animated(&cleaned);
+    let can_reduce = FORMAT_DEPENDENT_CHUNKS
+        .iter()
+        .all(|chunk_type| cleaned.chunk_by_type(chunk_type).is_none());
+
+    let mut reductions = Vec::new();
+    let mut mapping = None;
+    if !animated && can_reduce {
+        reductions = reduce(&mut pixels, cleaned.chunk_by_type("iCCP").is_some());
+    } else if !animated && pixels.header.color_type == ColorType::Indexed {
+        // The background has to stay in the palette even when no pixel uses it
+        let background = match Background::from_png(&cleaned)? {
+            Some(Background::Indexed(index)) => Some(index),
+            _ => None,
+        };
+        palette::validate(&cleaned)?;
+
+        let (palette_reductions, palette_mapping) = reduce_palette(&mut pixels, background);
+        reductions = palette_reductions;
+        mapping = palette_mapping;
+    }
+
+    let mut best: Option<(Png, FilterStrategy, Vec<Chunk>)> = None;
+    for filter in STRATEGIES {
+        let options = EncoderOptions {
+            filter,
+            level: 9,
+            idat_size: i32::MAX as usize,
+        };
+
+        let (candidate, unsafe_to_copy) = encoder::encode(&cleaned, &pixels, &options, keep)?;
+
+        let smaller = match &best {
+            Some((png, _, _)) => candidate.as_bytes().len() < png.as_bytes().len(),
+            None => true,
+        };
+        if smaller {
+            best = Some((candidate, filter, unsafe_to_copy));
+        }
+    }
+
+    let (mut optimized, filter, unsafe_to_copy) = best.unwrap();
+
+    if let (Some(mapping), Some(entries)) = (mapping, pixels.palette.as_ref()) {
+        palette::remap(&mut optimized, &mapping, entries.len())?;
+    }
+
+    let report = OptimizeReport {
+        original_size,
+        optimized_size: optimized.as_bytes().len(),
+        reductions,
+        dropped,
+        unsafe_to_copy: unsafe_to_copy
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect(),
+        filter,
+    };
+
+    Ok((optimized, report))
+}
+
+/// Removes standard ancillary chunks that are exact copies of an earlier chunk
+fn drop_redundant_chunks(png: &Png) -> (Png, Vec<String>) {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut dropped = Vec::new();
+
+    for chunk in png.chunks() {
+        let redundant = chunk.chunk_type().is_standard()
+            && !chunk.chunk_type().is_critical()
+            && chunks
+                .iter()
+                .any(|kept| kept.chunk_type() == chunk.chunk_type() && kept.data() == chunk.data());
+
+        if redundant {
+            dropped.push(chunk.chunk_type().to_string());
+        } else {
+            chunks.push(chunk.clone());
+        }
+    }
+
+    let mut cleaned = Png::from_chunks(chunks);
+    cleaned.set_trailing_bytes(png.trailing_bytes().to_vec());
+
+    (cleaned, dropped)
+}
+
+/// Applies every reduction that keeps the pixels the same. `keep_color` skips the grayscale
+/// reduction, for images with an RGB ICC profile that a grayscale image can't carry.
+pub fn reduce(pixels: &mut PixelBuffer, keep_color: bool) -> Vec<Reduction> {
+    let mut reductions = Vec::new();
+
+    if pixels.header.bit_depth == 16 && all_samples(pixels, |sample, _| sample % 257 == 0) {
+        *pixels = convert(pixels, pixels.header.color_type, 8, |sample| sample / 257);
+        reductions.push(Reduction::SixteenToEightBits);
+    }
+
+    let max = ((1u32 << pixels.header.bit_depth) - 1) as u16;
+    let alpha_channel = match pixels.header.color_type {
+        ColorType::Rgba => Some((3, ColorType::Rgb)),
+        ColorType::GrayscaleAlpha => Some((1, ColorType::Grayscale)),
+        _ => None,
+    };
+
+    if let Some((alpha, color_type)) = alpha_channel {
+        if all_samples(pixels, |sample, channel| channel != alpha || sample == max) {
+            *pixels = convert(pixels, color_type, pixels.header.bit_depth, |sample| sample);
+            reductions.push(Reduction::DropAlpha);
+        }
+    }
+
+    // Only the red channel is copied, which is all a gray pixel needs
+    if !keep_color && pixels.header.color_type == ColorType::Rgb && all_gray(pixels) {
+        *pixels = convert(
+            pixels,
+            ColorType::Grayscale,
+            pixels.header.bit_depth,
+            |sample| sample,
+        );
+        reductions.push(Reduction::RgbToGrayscale);
+    }
+
+    if pixels.header.color_type == ColorType::Indexed {
+        reductions.extend(reduce_palette(pixels, None).0);
+    }
+
+    reductions
+}
+
+/// Removes unused palette entries and packs the indices into as few bits as possible. The
+/// `reserved` entry is kept even when no pixel uses it. Also returns the new index of every
+/// old entry when entries were removed.
+fn reduce_palette(
+    pixels: &mut PixelBuffer,
+    reserved: Option<u8>,
+) -> (Vec<Reduction>, Option<Vec<Option<u8>>>) {
+    let mut reductions = Vec::new();
+    let palette = match &pixels.palette {
+        Some(palette) => palette.clone(),
+        None => return (reductions, None),
+    };
+
+    let mut used = vec![false; 256];
+    if let Some(reserved) = reserved {
+        used[reserved as usize] = true;
+    }
+    for y in 0..pixels.height() {
+        for x in 0..pixels.width() {
+            used[pixels.sample(x, y, 0) as usize] = true;
+        }
+    }
+
+    // Map every used index to its place in the smaller palette, keeping the order
+    let mut mapping = vec![0u16; 256];
+    let mut new_palette = Vec::new();
+    for (index, entry) in palette.iter().enumerate() {
+        if used[index] {
+            mapping[index] = new_palette.len() as u16;
+            new_palette.push(*entry);
+        }
+    }
+
+    // Indices past the end of the palette are an error, leave such images alone
+    if used.iter().skip(palette.len()).any(|&used| used) {
+        return (reductions, None);
+    }
+
+    let removed = palette.len() - new_palette.len();
+    let bit_depth = [1u8, 2, 4, 8]
+        .into_iter()
+        .find(|&depth| new_palette.len() <= 1 << depth)
+        .unwrap();
+
+    if removed == 0 && bit_depth >= pixels.header.bit_depth {
+        return (reductions, None);
+    }
+
+    let from = pixels.header.bit_depth;
+    let to = bit_depth.min(from);
+    let mut reduced = convert(pixels, ColorType::Indexed, to, |index| {
+        mapping[index as usize]
+    });
+    reduced.palette = Some(new_palette);
+
+    let mut moved = None;
+    if removed > 0 {
+        reductions.push(Reduction::UnusedPaletteEntries { removed });
+        moved = Some(
+            (0..palette.len())
+                .map(|index| used[index].then_some(mapping[index] as u8))
+                .collect(),
+        );
+    }
+    if to < from {
+        reductions.push(Reduction::PaletteBitDepth { from, to });
+    }
+
+    *pixels = reduced;
+    (reductions, moved)
+}
+
+fn all_samples(pixels: &PixelBuffer, check: impl Fn(u16, usize) -> bool) -> bool {
+    let channels = pixels.header.color_type.channels();
+
+    (0..pixels.height()).all(|y| {
+        (0..pixels.width())
+            .all(|x| (0..channels).all(|channel| check(pixels.sample(x, y, channel), channel)))
+    })
+}
+
+fn all_gray(pixels: &PixelBuffer) -> bool {
+    (0..pixels.height()).all(|y| {
+        (0..pixels.width()).all(|x| {
+            let red = pixels.sample(x, y, 0);
+            red == pixels.sample(x, y, 1) && red == pixels.sample(x, y, 2)
+        })
+    })
+}
+
+/// Copies pixels into a new format. Channels that don't exist in the new format are
+/// dropped and every sample goes through `map`. The palette only stays for the color types
+/// that may have a PLTE chunk.
+fn convert(
+    pixels: &PixelBuffer,
+    color_type: ColorType,
+    bit_depth: u8,
+    map: impl Fn(u16) -> u16,
+) -> PixelBuffer {
+    let header = ImageHeader {
+        bit_depth,
+        color_type,
+        ..pixels.header
+    };
+
+    let mut converted = PixelBuffer {
+        header,
+        data: vec![0; header.stride(pixels.width()) * pixels.height()],
+        palette: match color_type {
+            ColorType::Rgb | ColorType::Indexed | ColorType::Rgba => pixels.palette.clone(),
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => None,
+        },
+    };
+
+    for y in 0..pixels.height() {
+        for x in 0..pixels.width() {
+            for channel in 0..color_type.channels() {
+                converted.set_sample(x, y, channel, map(pixels.sample(x, y, channel)));
+            }
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod optimize_tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn pixels(color_type: ColorType, bit_depth: u8, samples: &[u16]) -> PixelBuffer {
+        let header = ImageHeader {
+            width: (samples.len() / color_type.channels()) as u32,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+
+        let mut pixels = PixelBuffer {
+            header,
+            data: vec![0; header.stride(header.width as usize)],
+            palette: None,
+        };
+        for (index, &sample) in samples.iter().enumerate() {
+            let channels = color_type.channels();
+            pixels.set_sample(index / channels, 0, index % channels, sample);
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_reduce_16_bit_rgba() {
+        let mut pixels = pixels(ColorType::Rgba, 16, &[257, 514, 257, 65535, 0, 0, 0, 65535]);
+        let reductions = reduce(&mut pixels, false);
+
+        assert_eq!(
+            reductions,
+            vec![Reduction::SixteenToEightBits, Reduction::DropAlpha]
+        );
+        assert_eq!(pixels.header.color_type, ColorType::Rgb);
+        assert_eq!(pixels.data, vec![1, 2, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_reduce_gray_rgb() {
+        let mut pixels = pixels(ColorType::Rgb, 8, &[5, 5, 5, 9, 9, 9]);
+        assert_eq!(reduce(&mut pixels, false), vec![Reduction::RgbToGrayscale]);
+        assert_eq!(pixels.data, vec![5, 9]);
+    }
+
+    #[test]
+    fn test_reduce_gray_rgb_drops_suggested_palette() {
+        let mut pixels = pixels(ColorType::Rgb, 8, &[5, 5, 5, 9, 9, 9]);
+        pixels.palette = Some(vec![[5, 5, 5], [9, 9, 9]]);
+
+        assert_eq!(reduce(&mut pixels, false), vec![Reduction::RgbToGrayscale]);
+        assert_eq!(pixels.palette, None);
+    }
+
+    #[test]
+    fn test_reduce_keeps_color() {
+        let mut pixels = pixels(ColorType::Rgb, 8, &[5, 5, 5, 9, 9, 9]);
+        assert!(reduce(&mut pixels, true).is_empty());
+        assert_eq!(pixels.header.color_type, ColorType::Rgb);
+    }
+
+    #[test]
+    fn test_optimize_gray_rgb_with_palette_and_profile() {
+        let pixels = pixels(ColorType::Rgb, 8, &[5, 5, 5, 9, 9, 9]);
+        let image_data =
+            encoder::encode_image_data(&pixels.header, &pixels.data, &EncoderOptions::default())
+                .unwrap();
+        let chunks = vec![
+            pixels.header.to_chunk(),
+            Chunk::new(ChunkType::new(*b"PLTE"), vec![5, 5, 5, 9, 9, 9]),
+            Chunk::new(ChunkType::new(*b"IDAT"), image_data),
+            Chunk::new(ChunkType::new(*b"IEND"), Vec::new()),
+        ];
+
+        // The suggested palette goes with the color
+        let (optimized, report) = optimize(&Png::from_chunks(chunks.clone()), &[]).unwrap();
+        assert_eq!(report.reductions, vec![Reduction::RgbToGrayscale]);
+        assert!(optimized.chunk_by_type("PLTE").is_none());
+        assert!(optimized.validate_order().is_ok());
+
+        // An RGB profile keeps the image in color
+        let mut with_profile = Png::from_chunks(chunks);
+        with_profile
+            .insert_chunk(1, Chunk::iccp("RGB", &[0; 128]).unwrap())
+            .unwrap();
+        let (optimized, report) = optimize(&with_profile, &[]).unwrap();
+        assert!(report.reductions.is_empty());
+        assert_eq!(
+            ImageHeader::from_png(&optimized).unwrap().color_type,
+            ColorType::Rgb
+        );
+    }
+
+    #[test]
+    fn test_reduce_palette() {
+        let mut pixels = pixels(ColorType::Indexed, 8, &[3, 1, 3]);
+        pixels.palette = Some(vec![[0, 0, 0], [1, 1, 1], [2, 2, 2], [3, 3, 3]]);
+
+        let before: Vec<[u16; 4]> = (0..3).map(|x| pixels.rgba16(x, 0)).collect();
+        let reductions = reduce(&mut pixels, false);
+        let after: Vec<[u16; 4]> = (0..3).map(|x| pixels.rgba16(x, 0)).collect();
+
+        assert_eq!(
+            reductions,
+            vec![
+                Reduction::UnusedPaletteEntries { removed: 2 },
+                Reduction::PaletteBitDepth { from: 8, to: 1 }
+            ]
+        );
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_optimize_reports_unsafe_to_copy_chunks() {
+        let mut png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::new(*b"abCD"), b"raw".to_vec()))
+            .unwrap();
+        png.append_chunk(Chunk::new(ChunkType::new(*b"xyZW"), b"kept".to_vec()))
+            .unwrap();
+
+        let (optimized, report) = optimize(&png, &[ChunkType::new(*b"xyZW")]).unwrap();
+        assert_eq!(report.unsafe_to_copy, vec!["abCD".to_string()]);
+        assert!(optimized.chunk_by_type("abCD").is_none());
+        assert!(optimized.chunk_by_type("xyZW").is_some());
+    }
+
+    #[test]
+    fn test_optimize_keeps_payload_chunks() {
+        let png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        let (optimized, report) = optimize(&png, &[]).unwrap();
+
+        let payloads = |png: &Png| -> Vec<Vec<u8>> {
+            png.chunks()
+                .iter()
+                .filter(|chunk| !chunk.chunk_type().is_standard())
+                .map(|chunk| chunk.as_bytes())
+                .collect()
+        };
+
+        assert_eq!(payloads(&png), payloads(&optimized));
+        assert_eq!(
+            decoder::decode(&png).unwrap().rgba16(3, 3),
+            decoder::decode(&optimized).unwrap().rgba16(3, 3)
+        );
+        assert_eq!(report.optimized_size, optimized.as_bytes().len());
+    }
+
+    #[test]
+    fn test_drop_redundant_chunks() {
+        let text = Chunk::new(ChunkType::new(*b"tEXt"), b"a\0b".to_vec());
+        let png = Png::from_chunks(vec![text.clone(), text.clone(), text]);
+
+        let (cleaned, dropped) = drop_redundant_chunks(&png);
+        assert_eq!(cleaned.chunks().len(), 1);
+        assert_eq!(dropped, vec!["tEXt", "tEXt"]);
+    }
+
+    #[test]
+    fn test_optimize_remaps_palette_chunks() {
+        let header = ImageHeader {
+            width: 3,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let stream =
+            encoder::encode_image_data(&header, &[3, 1, 3], &EncoderOptions::default()).unwrap();
+
+        let png = Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::new(*b"PLTE"), (0..12).collect()),
+            Chunk::new(ChunkType::new(*b"tRNS"), vec![0, 50, 100, 150]),
+            Chunk::new(ChunkType::new(*b"bKGD"), vec![2]),
+            Chunk::new(ChunkType::new(*b"hIST"), vec![0, 1, 0, 2, 0, 3, 0, 4]),
+            Chunk::new(ChunkType::new(*b"IDAT"), stream),
+            Chunk::new(ChunkType::new(*b"IEND"), Vec::new()),
+        ]);
+
+        let (optimized, report) = optimize(&png, &[]).unwrap();
+        assert!(report
+            .reductions
+            .contains(&Reduction::UnusedPaletteEntries { removed: 1 }));
+        palette::validate(&optimized).unwrap();
+
+        // The pixels look the same and the background is still the same color
+        let before = decoder::decode(&png).unwrap();
+        let after = decoder::decode(&optimized).unwrap();
+        for x in 0..3 {
+            assert_eq!(before.rgba16(x, 0), after.rgba16(x, 0));
+        }
+
+        let palette = palette::Palette::from_png(&optimized).unwrap().unwrap();
+        let background = match Background::from_png(&optimized).unwrap() {
+            Some(Background::Indexed(index)) => index as usize,
+            _ => panic!("The background was lost"),
+        };
+        assert_eq!(palette.entries[background], [6, 7, 8]);
+
+        let alphas = palette::Transparency::from_png(&optimized)
+            .unwrap()
+            .unwrap();
+        assert_eq!(alphas, palette::Transparency::Indexed(vec![50, 100, 150]));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::chunk::Chunk;
+
+/// Where a chunk type is allowed to appear relative to the other chunks
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Placement {
+    /// Must be the very first chunk (IHDR)
+    First,
+    /// Must be the very last chunk (IEND)
+    Last,
+    /// Must come before PLTE and before the first IDAT
+    BeforePlte,
+    /// Must come after PLTE (if there is one) and before the first IDAT
+    AfterPlte,
+    /// Must come before the first IDAT
+    BeforeIdat,
+    /// Image data, all IDAT chunks must be consecutive
+    Idat,
+    /// Must come after the IDAT chunks (APNG frame data)
+    AfterIdat,
+    /// Can go anywhere between IHDR and IEND
+    Anywhere,
+}
+
+/// The ordering rules of a single chunk type
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChunkRule {
+    pub placement: Placement,
+    pub multiple: bool,
+}
+
+/// Returns the ordering rule for a chunk type, unknown chunk types may go anywhere
+pub fn rule_for(chunk_type: &[u8; 4]) -> ChunkRule {
+    let (placement, multiple) = match chunk_type {
+        b"IHDR" => (Placement::First, false),
+        b"IEND" => (Placement::Last, false),
+        b"PLTE" => (Placement::BeforeIdat, false),
+        b"IDAT" => (Placement::Idat, true),
+
+        b"cHRM" | b"gAMA" | b"iCCP" | b"sBIT" | b"sRGB" | b"cICP" | b"mDCV" | b"cLLI" => {
+            (Placement::BeforePlte, false)
+        }
+
+        b"bKGD" | b"hIST" | b"tRNS" => (Placement::AfterPlte, false),
+
+        b"pHYs" | b"oFFs" | b"pCAL" | b"sCAL" | b"eXIf" | b"acTL" => {
+            (Placement::BeforeIdat, false)
+        }
+        b"sPLT" => (Placement::BeforeIdat, true),
+
+        b"fcTL" => (Placement::Anywhere, true),
+        b"fdAT" => (Placement::AfterIdat, true),
+
+        b"tIME" => (Placement::Anywhere, false),
+        _ => (Placement::Anywhere, true),
+    };
+
+    ChunkRule {
+        placement,
+        multiple,
+    }
+}
+
+/// Checks a sequence of chunks against the ordering rules of the PNG specification
+/// and returns the first violation found.
+pub fn validate(chunks: &[Chunk]) -> Result<(), OrderingError> {
+    match violations(chunks).into_iter().next() {
+        Some(violation) => Err(violation),
+        None => Ok(()),
+    }
+}
+
+/// Returns every ordering violation in a sequence of chunks
+pub fn violations(chunks: &[Chunk]) -> Vec<OrderingError> {
+    let types: Vec<[u8; 4]> = chunks
+        .iter()
+        .map(|chunk| chunk.chunk_type().bytes())
+        .collect();
+    type_violations(&types)
+}
+
+/// Returns every ordering violation in a sequence of chunk types
+pub fn type_violations(types: &[[u8; 4]]) -> Vec<OrderingError> {
+    let mut violations = Vec::new();
+
+    let name = |chunk_type: &[u8; 4]| String::from_utf8_lossy(chunk_type).to_string();
+    let position = |wanted: &[u8; 4]| types.iter().position(|chunk_type| chunk_type == wanted);
+
+    let plte = position(b"PLTE");
+    let first_idat = position(b"IDAT");
+    let last_idat = types.iter().rposition(|chunk_type| chunk_type == b"IDAT");
+    let iend = position(b"IEND");
+
+    if types.first() != Some(b"IHDR") {
+        violations.push(OrderingError::IhdrNotFirst);
+    }
+
+    match iend {
+        None => violations.push(OrderingError::MissingIend),
+        Some(iend) => {
+            for chunk_type in &types[iend + 1..] {
+                violations.push(OrderingError::AfterIend(name(chunk_type)));
+            }
+        }
+    }
+
+    if first_idat.is_none() {
+        violations.push(OrderingError::MissingIdat);
+    }
+
+    // Every chunk between the first and last IDAT must be an IDAT as well
+    if let (Some(first), Some(last)) = (first_idat, last_idat) {
+        for chunk_type in &types[first..=last] {
+            if chunk_type != b"IDAT" {
+                violations.push(OrderingError::SplitsIdat(name(chunk_type)));
+            }
+        }
+    }
+
+    // Nothing may sit between the frames of an animation once they start after the IDATs
+    if let Some(last_idat) = last_idat {
+        let is_frame = |chunk_type: &[u8; 4]| chunk_type == b"fcTL" || chunk_type == b"fdAT";
+        let first_frame = types[last_idat..].iter().position(is_frame);
+        let last_frame = types.iter().rposition(|chunk_type| chunk_type == b"fdAT");
+
+        if let (Some(first), Some(last)) = (first_frame, last_frame) {
+            for chunk_type in types.iter().take(last + 1).skip(last_idat + first) {
+                if !is_frame(chunk_type) {
+                    violations.push(OrderingError::SplitsFrames(name(chunk_type)));
+                }
+            }
+        }
+    }
+
+    for (index, chunk_type) in types.iter().enumerate() {
+        let rule = rule_for(chunk_type);
+
+        if !rule.multiple && position(chunk_type) != Some(index) {
+            violations.push(OrderingError::Duplicate(name(chunk_type)));
+            continue;
+        }
+
+        let after_plte = plte.is_some_and(|plte| index > plte);
+        let after_idat = first_idat.is_some_and(|idat| index > idat);
+
+        match rule.placement {
+            Placement::BeforePlte => {
+                if after_plte {
+                    violations.push(OrderingError::MustPrecede(name(chunk_type), "PLTE"));
+                } else if after_idat {
+                    violations.push(OrderingError::MustPrecede(name(chunk_type), "IDAT"));
+                }
+            }
+            Placement::AfterPlte => {
+                if plte.is_some_and(|plte| index < plte) {
+                    violations.push(OrderingError::MustFollow(name(chunk_type), "PLTE"));
+                } else if after_idat {
+                    violations.push(OrderingError::MustPrecede(name(chunk_type), "IDAT"));
+                }
+            }
+            Placement::BeforeIdat => {
+                if after_idat {
+                    violations.push(OrderingError::MustPrecede(name(chunk_type), "IDAT"));
+                }
+            }
+            Placement::AfterIdat => {
+                if first_idat.is_none_or(|idat| index <= idat) {
+                    violations.push(OrderingError::MustFollow(name(chunk_type), "IDAT"));
+                }
+            }
+            Placement::First | Placement::Last | Placement::Idat | Placement::Anywhere => {}
+        }
+    }
+
+    violations
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum OrderingError {
+    #[error("The first chunk must be IHDR.")]
+    IhdrNotFirst,
+
+    #[error("The file has no IEND chunk.")]
+    MissingIend,
+
+    #[error("The file has no IDAT chunk.")]
+    MissingIdat,
+
+    #[error("The '{0}' chunk comes after IEND.")]
+    AfterIend(String),
+
+    #[error("The '{0}' chunk splits up the IDAT chunks, they must be consecutive.")]
+    SplitsIdat(String),
+
+    #[error("The '{0}' chunk splits up the animation frames.")]
+    SplitsFrames(String),
+
+    #[error("The '{0}' chunk may only appear once.")]
+    Duplicate(String),
+
+    #[error("The '{0}' chunk must come before {1}.")]
+    MustPrecede(String, &'static str),
+
+    #[error("The '{0}' chunk must come after {1}.")]
+    MustFollow(String, &'static str),
+}
+
+#[cfg(test)]
+mod ordering_tests {
+    use super::*;
+
+    fn types(names: &[&str]) -> Vec<[u8; 4]> {
+        names
+            .iter()
+            .map(|name| name.as_bytes().try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_order() {
+        let types = types(&[
+            "IHDR", "sRGB", "gAMA", "PLTE", "tRNS", "pHYs", "IDAT", "IDAT", "tEXt", "IEND",
+        ]);
+        assert!(type_violations(&types).is_empty());
+    }
+
+    #[test]
+    fn test_ihdr_not_first() {
+        let types = types(&["gAMA", "IHDR", "IDAT", "IEND"]);
+        assert!(type_violations(&types).contains(&OrderingError::IhdrNotFirst));
+    }
+
+    #[test]
+    fn test_plte_after_idat() {
+        let types = types(&["IHDR", "IDAT", "PLTE", "IEND"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::MustPrecede("PLTE".to_string(), "IDAT")]
+        );
+    }
+
+    #[test]
+    fn test_split_idat() {
+        let types = types(&["IHDR", "IDAT", "RuSt", "IDAT", "IEND"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::SplitsIdat("RuSt".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_color_chunk_after_plte() {
+        let types = types(&["IHDR", "PLTE", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::MustPrecede("gAMA".to_string(), "PLTE")]
+        );
+    }
+
+    #[test]
+    fn test_trns_before_plte() {
+        let types = types(&["IHDR", "tRNS", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::MustFollow("tRNS".to_string(), "PLTE")]
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let types = types(&["IHDR", "gAMA", "gAMA", "IDAT", "tEXt", "tEXt", "IEND"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::Duplicate("gAMA".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_chunk_after_iend() {
+        let types = types(&["IHDR", "IDAT", "IEND", "RuSt"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::AfterIend("RuSt".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_animation() {
+        let valid = types(&[
+            "IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fdAT", "fcTL", "fdAT", "IEND",
+        ]);
+        assert!(type_violations(&valid).is_empty());
+
+        let split = types(&["IHDR", "acTL", "IDAT", "fcTL", "RuSt", "fdAT", "IEND"]);
+        assert_eq!(
+            type_violations(&split),
+            vec![OrderingError::SplitsFrames("RuSt".to_string())]
+        );
+
+        let early = types(&["IHDR", "fcTL", "fdAT", "IDAT", "IEND"]);
+        assert_eq!(
+            type_violations(&early),
+            vec![OrderingError::MustFollow("fdAT".to_string(), "IDAT")]
+        );
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let types = types(&["IHDR"]);
+        assert_eq!(
+            type_violations(&types),
+            vec![OrderingError::MissingIend, OrderingError::MissingIdat]
+        );
+    }
+}
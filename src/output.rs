@@ -0,0 +1,335 @@
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    apng::ApngError, batch::BatchError, camouflage::CamouflageError, chunk::ChunkError,
+    chunk_type::ChunkTypeError, color::ColorError, decoder::DecodeError,
+    deflate_slack::DeflateSlackError, deniable::DeniableError, dump::DumpError, ecc::EccError,
+    encoder::EncodeError, envelope::EnvelopeError, exif::ExifError, filter::FilterError,
+    ihdr::HeaderError, meta::MetaError, ordering::OrderingError, palette::PaletteError,
+    palette_stego::PaletteStegoError, payload::PayloadError, png::PngError, server::ServerError,
+    stream::StreamError, xmp::XmpError, Error,
+};
+
+// Commands print through `emit!` instead of `println!`. A batch run captures the output of
+// every file on the thread that processes it, so the output of files processed at the same
+// time doesn't get mixed up.
+//
+// With `--format json` the whole command is captured: the emitted lines become messages
+// and the values commands `record` become the data of a single JSON document. Batch runs
+// stream a JSON line per file instead.
+
+/// How the results of a command are printed
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Colored text for people
+    #[default]
+    Text,
+    /// A JSON document, or a JSON line per file for directories and patterns
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Set once a JSON line was printed, the command's own document is left out then
+static STREAMED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+/// What a command emitted and recorded while it was captured
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Captured {
+    pub text: String,
+    pub data: Map<String, Value>,
+}
+
+/// Prints a line, or adds it to the output being captured on this thread
+#[macro_export]
+macro_rules! emit {
+    () => {
+        $crate::output::write(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+pub fn format() -> Format {
+    match JSON.load(Ordering::Relaxed) {
+        true => Format::Json,
+        false => Format::Text,
+    }
+}
+
+pub fn write(text: std::fmt::Arguments) {
+    CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(captured) => {
+            let _ = captured.text.write_fmt(text);
+        }
+        None => print!("{}", text),
+    });
+}
+
+/// Adds a value to the data of the JSON document, text output leaves it out
+pub fn record(key: &str, value: Value) {
+    CAPTURED.with(|captured| {
+        if let Some(captured) = captured.borrow_mut().as_mut() {
+            captured.data.insert(key.to_string(), value);
+        }
+    });
+}
+
+/// Runs a function and returns what it emitted and recorded instead of printing it
+pub fn capture<T>(function: impl FnOnce() -> T) -> (T, Captured) {
+    let previous = CAPTURED.with(|captured| captured.replace(Some(Captured::default())));
+    let result = function();
+    let captured = CAPTURED.with(|captured| captured.replace(previous));
+
+    (result, captured.unwrap_or_default())
+}
+
+/// Prints a JSON line right away, even while output is being captured
+pub fn stream(value: &Value) {
+    STREAMED.store(true, Ordering::Relaxed);
+    println!("{}", value);
+}
+
+pub fn streamed() -> bool {
+    STREAMED.load(Ordering::Relaxed)
+}
+
+/// The JSON document of a finished command
+pub fn document(command: &str, result: &Result<(), Error>, captured: Captured) -> Value {
+    let mut document = json!({
+        "command": command,
+        "ok": result.is_ok(),
+        "messages": messages(&captured.text),
+        "data": captured.data,
+    });
+
+    if let Err(error) = result {
+        document["error"] = error_json(error.as_ref());
+    }
+    document
+}
+
+/// The lines of emitted text, without the empty ones
+pub fn messages(text: &str) -> Vec<&str> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect()
+}
+
+pub fn error_json(error: &(dyn std::error::Error + 'static)) -> Value {
+    json!({
+        "code": error_code(error),
+        "message": error.to_string(),
+    })
+}
+
+/// A stable name for an error: the error type and its variant, like
+/// `PngError::NonExistentChunk`, or `Error` for errors without a type of their own
+pub fn error_code(error: &(dyn std::error::Error + 'static)) -> String {
+    macro_rules! typed {
+        ($($error_type:ident),* $(,)?) => {
+            $(
+                if let Some(error) = error.downcast_ref::<$error_type>() {
+                    return format!("{}::{}", stringify!($error_type), variant(error));
+                }
+            )*
+        };
+    }
+
+    typed!(
+        ApngError,
+        BatchError,
+        CamouflageError,
+        ChunkError,
+        ChunkTypeError,
+        ColorError,
+        DecodeError,
+        DeflateSlackError,
+        DeniableError,
+        DumpError,
+        EccError,
+        EncodeError,
+        EnvelopeError,
+        ExifError,
+        FilterError,
+        HeaderError,
+        MetaError,
+        OrderingError,
+        PaletteError,
+        PaletteStegoError,
+        PayloadError,
+        PngError,
+        ServerError,
+        StreamError,
+        XmpError,
+    );
+
+    if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        return format!("IoError::{:?}", error.kind());
+    }
+    if error.is::<serde_json::Error>() {
+        return "JsonError".to_string();
+    }
+
+    "Error".to_string()
+}
+
+/// The variant name from the debug output of an error enum
+fn variant(error: &impl std::fmt::Debug) -> String {
+    let debug = format!("{:?}", error);
+    debug
+        .split(|char: char| !char.is_alphanumeric() && char != '_')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let (result, captured) = capture(|| {
+            emit!("first {}", 1);
+            emit!();
+            let (_, inner) = capture(|| emit!("inner"));
+            assert_eq!(inner.text, "inner\n");
+            record("answer", json!(42));
+            emit!("second");
+            42
+        });
+
+        assert_eq!(result, 42);
+        assert_eq!(captured.text, "first 1\n\nsecond\n");
+        assert_eq!(captured.data["answer"], 42);
+    }
+
+    #[test]
+    fn test_capture_is_per_thread() {
+        let (_, captured) = capture(|| {
+            std::thread::spawn(|| capture(|| emit!("other thread")).1)
+                .join()
+                .unwrap()
+        });
+        assert_eq!(captured.text, "");
+    }
+
+    #[test]
+    fn test_error_code() {
+        let error: Error = PngError::NonExistentChunk.into();
+        assert_eq!(error_code(error.as_ref()), "PngError::NonExistentChunk");
+
+        let error: Error = MetaError::UnknownUnit("pHYs", 7).into();
+        assert_eq!(error_code(error.as_ref()), "MetaError::UnknownUnit");
+
+        let error: Error = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        assert_eq!(error_code(error.as_ref()), "IoError::NotFound");
+
+        let error: Error = "plain".into();
+        assert_eq!(error_code(error.as_ref()), "Error");
+    }
+
+    #[test]
+    fn test_document() {
+        let (result, captured) = capture(|| {
+            emit!("Found it");
+            record("size", json!(3));
+            Err::<(), Error>(PngError::NonExistentChunk.into())
+        });
+
+        let document = document("remove", &result, captured);
+        assert_eq!(document["command"], "remove");
+        assert_eq!(document["ok"], false);
+        assert_eq!(document["messages"], json!(["Found it"]));
+        assert_eq!(document["data"]["size"], 3);
+        assert_eq!(document["error"]["code"], "PngError::NonExistentChunk");
+    }
+
+    #[test]
+    fn test_document_without_payload() {
+        let file = std::env::temp_dir().join(format!("sspngme_output_{}.png", std::process::id()));
+        std::fs::write(&file, include_bytes!("../sss.png")).unwrap();
+        let file_name = file.to_string_lossy();
+        let options = crate::args::DecodeOptions {
+            ecc: false,
+            raw: false,
+            output: None,
+            passphrase: None,
+        };
+
+        let target = |method| crate::payload::Target {
+            method,
+            chunk_type: Some("noNe"),
+            carrier: crate::camouflage::Carrier::Comment,
+            frame: None,
+        };
+
+        let (result, captured) = capture(|| {
+            crate::args::decode(&file_name, &target(crate::payload::Method::Chunk), &options)
+        });
+        let document = document("decode", &result, captured);
+        assert_eq!(document["ok"], false);
+        assert_eq!(document["data"]["found"], false);
+        assert_eq!(document["error"]["code"], "PngError::NonExistentChunk");
+
+        let (result, captured) = capture(|| {
+            crate::args::decode(
+                &file_name,
+                &target(crate::payload::Method::DeflateSlack),
+                &options,
+            )
+        });
+        let slack = super::document("decode", &result, captured);
+        assert_eq!(slack["ok"], false);
+        assert_eq!(slack["error"]["code"], "DeflateSlackError::NoPayload");
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_scan_and_sanitize_documents() {
+        let file = std::env::temp_dir().join(format!("sspngme_scan_{}.png", std::process::id()));
+        let mut png = crate::png::Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        png.append_trailing_bytes(b"after IEND").unwrap();
+        std::fs::write(&file, png.as_bytes()).unwrap();
+        let file_name = file.to_string_lossy();
+
+        let (result, captured) = capture(|| crate::args::scan(&file_name));
+        let scanned = document("scan", &result, captured);
+        assert_eq!(scanned["data"]["suspicious"], true);
+        assert!(scanned["data"]["findings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|finding| finding["method"] == "trailer" && finding["size"] == 10));
+
+        let (result, captured) = capture(|| crate::args::sanitize(&file_name));
+        let sanitized = document("sanitize", &result, captured);
+        assert_eq!(sanitized["ok"], true);
+        assert!(!sanitized["data"]["removed"].as_array().unwrap().is_empty());
+
+        let (result, captured) = capture(|| crate::args::scan(&file_name));
+        assert_eq!(
+            document("scan", &result, captured)["data"]["suspicious"],
+            false
+        );
+
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,753 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    Error,
+};
+
+/// The `PLTE` chunk: the colors of an indexed image
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Palette {
+    pub entries: Vec<[u8; 3]>,
+}
+
+/// The `tRNS` chunk, its layout depends on the color type of the image
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Transparency {
+    /// The gray level that is fully transparent
+    Grayscale(u16),
+    /// The color that is fully transparent
+    Rgb(u16, u16, u16),
+    /// The alpha of every palette entry, missing entries are fully opaque
+    Indexed(Vec<u8>),
+}
+
+/// The `bKGD` chunk, its layout depends on the color type of the image
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Background {
+    Grayscale(u16),
+    Rgb(u16, u16, u16),
+    /// A palette entry
+    Indexed(u8),
+}
+
+/// The `sBIT` chunk: the number of significant bits of every channel. Indexed images
+/// give the bits of the red, green and blue of the palette entries.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignificantBits {
+    pub bits: Vec<u8>,
+}
+
+/// The `hIST` chunk: roughly how often every palette entry is used
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Histogram {
+    pub frequencies: Vec<u16>,
+}
+
+/// The `sPLT` chunk: a palette suggested for displays that can't show every color
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// 8 or 16, the entries are stored with this many bits per sample
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SuggestedEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+#[allow(dead_code)]
+impl Palette {
+    /// Reads the palette of a PNG file, if it has one
+    pub fn from_png(png: &Png) -> Result<Option<Palette>, Error> {
+        png.chunk_by_type("PLTE").map(Palette::try_from).transpose()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::new(*b"PLTE"), self.entries.concat())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl TryFrom<&Chunk> for Palette {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+
+        if &chunk.chunk_type().bytes() != b"PLTE" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+            return Err(PaletteError::InvalidPaletteLength(data.len()).into());
+        }
+
+        Ok(Palette {
+            entries: data
+                .chunks_exact(3)
+                .map(|entry| [entry[0], entry[1], entry[2]])
+                .collect(),
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl Transparency {
+    /// Reads the tRNS chunk of a PNG file, if it has one, and checks it against IHDR and PLTE
+    pub fn from_png(png: &Png) -> Result<Option<Transparency>, Error> {
+        let chunk = match png.chunk_by_type("tRNS") {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        let header = ImageHeader::from_png(png)?;
+        let transparency = Transparency::from_chunk(chunk, header.color_type)?;
+        transparency.validate(&header, Palette::from_png(png)?.as_ref())?;
+        Ok(Some(transparency))
+    }
+
+    pub fn from_chunk(chunk: &Chunk, color_type: ColorType) -> Result<Transparency, Error> {
+        let data = chunk.data();
+        let sample = |index: usize| u16::from_be_bytes([data[index * 2], data[index * 2 + 1]]);
+
+        if &chunk.chunk_type().bytes() != b"tRNS" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        match color_type {
+            ColorType::Grayscale if data.len() == 2 => Ok(Transparency::Grayscale(sample(0))),
+            ColorType::Rgb if data.len() == 6 => {
+                Ok(Transparency::Rgb(sample(0), sample(1), sample(2)))
+            }
+            ColorType::Indexed if data.len() <= 256 => Ok(Transparency::Indexed(data.to_vec())),
+            ColorType::GrayscaleAlpha | ColorType::Rgba => {
+                Err(PaletteError::TransparencyWithAlpha.into())
+            }
+            _ => Err(PaletteError::InvalidTransparencyLength(data.len()).into()),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = match self {
+            Transparency::Grayscale(gray) => gray.to_be_bytes().to_vec(),
+            Transparency::Rgb(red, green, blue) => {
+                [red.to_be_bytes(), green.to_be_bytes(), blue.to_be_bytes()].concat()
+            }
+            Transparency::Indexed(alphas) => alphas.clone(),
+        };
+
+        Chunk::new(ChunkType::new(*b"tRNS"), data)
+    }
+
+    /// The alpha of a palette entry, 255 for entries without one
+    pub fn alpha(&self, index: usize) -> u8 {
+        match self {
+            Transparency::Indexed(alphas) => alphas.get(index).copied().unwrap_or(255),
+            _ => 255,
+        }
+    }
+
+    /// Checks that the samples fit the bit depth and that there is an alpha for at most
+    /// every palette entry
+    pub fn validate(&self, header: &ImageHeader, palette: Option<&Palette>) -> Result<(), Error> {
+        match self {
+            Transparency::Grayscale(gray) => check_samples(&[*gray], header.bit_depth),
+            Transparency::Rgb(red, green, blue) => {
+                check_samples(&[*red, *green, *blue], header.bit_depth)
+            }
+            Transparency::Indexed(alphas) => {
+                let entries = palette_length(palette)?;
+                if alphas.len() > entries {
+                    return Err(PaletteError::TooManyAlphas(alphas.len(), entries).into());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Background {
+    /// Reads the bKGD chunk of a PNG file, if it has one, and checks it against IHDR and PLTE
+    pub fn from_png(png: &Png) -> Result<Option<Background>, Error> {
+        let chunk = match png.chunk_by_type("bKGD") {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        let header = ImageHeader::from_png(png)?;
+        let background = Background::from_chunk(chunk, header.color_type)?;
+        background.validate(&header, Palette::from_png(png)?.as_ref())?;
+        Ok(Some(background))
+    }
+
+    pub fn from_chunk(chunk: &Chunk, color_type: ColorType) -> Result<Background, Error> {
+        let data = chunk.data();
+        let sample = |index: usize| u16::from_be_bytes([data[index * 2], data[index * 2 + 1]]);
+
+        if &chunk.chunk_type().bytes() != b"bKGD" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        match (color_type, data.len()) {
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, 2) => {
+                Ok(Background::Grayscale(sample(0)))
+            }
+            (ColorType::Rgb | ColorType::Rgba, 6) => {
+                Ok(Background::Rgb(sample(0), sample(1), sample(2)))
+            }
+            (ColorType::Indexed, 1) => Ok(Background::Indexed(data[0])),
+            _ => Err(PaletteError::InvalidLength("bKGD", data.len()).into()),
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let data = match self {
+            Background::Grayscale(gray) => gray.to_be_bytes().to_vec(),
+            Background::Rgb(red, green, blue) => {
+                [red.to_be_bytes(), green.to_be_bytes(), blue.to_be_bytes()].concat()
+            }
+            Background::Indexed(index) => vec![index],
+        };
+
+        Chunk::new(ChunkType::new(*b"bKGD"), data)
+    }
+
+    /// Checks that the samples fit the bit depth or that the palette has the entry
+    pub fn validate(&self, header: &ImageHeader, palette: Option<&Palette>) -> Result<(), Error> {
+        match *self {
+            Background::Grayscale(gray) => check_samples(&[gray], header.bit_depth),
+            Background::Rgb(red, green, blue) => {
+                check_samples(&[red, green, blue], header.bit_depth)
+            }
+            Background::Indexed(index) => {
+                let entries = palette_length(palette)?;
+                if index as usize >= entries {
+                    return Err(PaletteError::BackgroundOutOfRange(index, entries).into());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl SignificantBits {
+    /// Reads the sBIT chunk of a PNG file, if it has one, and checks it against IHDR
+    pub fn from_png(png: &Png) -> Result<Option<SignificantBits>, Error> {
+        let chunk = match png.chunk_by_type("sBIT") {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        let header = ImageHeader::from_png(png)?;
+        let significant_bits = SignificantBits::from_chunk(chunk, header.color_type)?;
+        significant_bits.validate(&header)?;
+        Ok(Some(significant_bits))
+    }
+
+    pub fn from_chunk(chunk: &Chunk, color_type: ColorType) -> Result<SignificantBits, Error> {
+        if &chunk.chunk_type().bytes() != b"sBIT" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        if chunk.data().len() != SignificantBits::channels(color_type) {
+            return Err(PaletteError::InvalidLength("sBIT", chunk.data().len()).into());
+        }
+
+        Ok(SignificantBits {
+            bits: chunk.data().to_vec(),
+        })
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::new(*b"sBIT"), self.bits.clone())
+    }
+
+    /// Checks that every channel has at least one and at most the bit depth significant
+    /// bits, palette entries always have 8 bit samples
+    pub fn validate(&self, header: &ImageHeader) -> Result<(), Error> {
+        let depth = match header.color_type {
+            ColorType::Indexed => 8,
+            _ => header.bit_depth,
+        };
+
+        if self.bits.len() != SignificantBits::channels(header.color_type) {
+            return Err(PaletteError::InvalidLength("sBIT", self.bits.len()).into());
+        }
+
+        match self.bits.iter().find(|&&bits| bits == 0 || bits > depth) {
+            Some(&bits) => Err(PaletteError::InvalidSignificantBits(bits, depth).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The number of values the chunk holds, indexed images describe the palette's RGB
+    fn channels(color_type: ColorType) -> usize {
+        match color_type {
+            ColorType::Indexed => 3,
+            _ => color_type.channels(),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Histogram {
+    /// Reads the hIST chunk of a PNG file, if it has one, and checks it against PLTE
+    pub fn from_png(png: &Png) -> Result<Option<Histogram>, Error> {
+        let chunk = match png.chunk_by_type("hIST") {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        let histogram = Histogram::try_from(chunk)?;
+        histogram.validate(Palette::from_png(png)?.as_ref())?;
+        Ok(Some(histogram))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = self
+            .frequencies
+            .iter()
+            .flat_map(|frequency| frequency.to_be_bytes())
+            .collect();
+        Chunk::new(ChunkType::new(*b"hIST"), data)
+    }
+
+    /// Checks that there is a frequency for exactly every palette entry
+    pub fn validate(&self, palette: Option<&Palette>) -> Result<(), Error> {
+        let entries = palette_length(palette)?;
+        if self.frequencies.len() != entries {
+            return Err(PaletteError::HistogramMismatch(self.frequencies.len(), entries).into());
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&Chunk> for Histogram {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+
+        if &chunk.chunk_type().bytes() != b"hIST" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        if data.is_empty() || !data.len().is_multiple_of(2) || data.len() > 256 * 2 {
+            return Err(PaletteError::InvalidLength("hIST", data.len()).into());
+        }
+
+        Ok(Histogram {
+            frequencies: data
+                .chunks_exact(2)
+                .map(|frequency| u16::from_be_bytes([frequency[0], frequency[1]]))
+                .collect(),
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl SuggestedPalette {
+    /// Reads every sPLT chunk of a PNG file, a file can have several with different names
+    pub fn all_from_png(png: &Png) -> Result<Vec<SuggestedPalette>, Error> {
+        png.chunks()
+            .iter()
+            .filter(|chunk| &chunk.chunk_type().bytes() == b"sPLT")
+            .map(SuggestedPalette::try_from)
+            .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data: Vec<u8> = self.name.chars().map(|char| char as u8).collect();
+        data.push(0);
+        data.push(self.sample_depth);
+
+        for entry in &self.entries {
+            let samples = [entry.red, entry.green, entry.blue, entry.alpha];
+            match self.sample_depth {
+                8 => data.extend(samples.iter().map(|&sample| sample as u8)),
+                _ => data.extend(samples.iter().flat_map(|sample| sample.to_be_bytes())),
+            }
+            data.extend_from_slice(&entry.frequency.to_be_bytes());
+        }
+
+        Chunk::new(ChunkType::new(*b"sPLT"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for SuggestedPalette {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+
+        if &chunk.chunk_type().bytes() != b"sPLT" {
+            return Err(PaletteError::WrongChunkType(chunk.chunk_type().to_string()).into());
+        }
+
+        // The name is Latin-1 and 1 to 79 bytes long
+        let end = match data.iter().position(|&byte| byte == 0) {
+            Some(end) if (1..80).contains(&end) => end,
+            _ => return Err(PaletteError::InvalidSuggestedPalette.into()),
+        };
+        let name = data[..end].iter().map(|&byte| byte as char).collect();
+
+        let sample_depth = match data.get(end + 1) {
+            Some(&depth) if depth == 8 || depth == 16 => depth,
+            _ => return Err(PaletteError::InvalidSuggestedPalette.into()),
+        };
+
+        // Four samples and a 16 bit frequency
+        let size = sample_depth as usize / 2 + 2;
+        let entries = &data[end + 2..];
+        if !entries.len().is_multiple_of(size) {
+            return Err(PaletteError::InvalidSuggestedPalette.into());
+        }
+
+        let entries = entries
+            .chunks_exact(size)
+            .map(|entry| {
+                let value = |index: usize| match sample_depth {
+                    8 => entry[index] as u16,
+                    _ => u16::from_be_bytes([entry[index * 2], entry[index * 2 + 1]]),
+                };
+                SuggestedEntry {
+                    red: value(0),
+                    green: value(1),
+                    blue: value(2),
+                    alpha: value(3),
+                    frequency: u16::from_be_bytes([entry[size - 2], entry[size - 1]]),
+                }
+            })
+            .collect();
+
+        Ok(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+}
+
+/// Reads and checks every palette auxiliary chunk (tRNS, bKGD, sBIT, hIST and sPLT) of a
+/// PNG file, returning the first problem
+pub fn validate(png: &Png) -> Result<(), Error> {
+    Transparency::from_png(png)?;
+    Background::from_png(png)?;
+    SignificantBits::from_png(png)?;
+    Histogram::from_png(png)?;
+    SuggestedPalette::all_from_png(png)?;
+    Ok(())
+}
+
+/// Rewrites tRNS, bKGD and hIST of an indexed image after its palette entries moved.
+/// `mapping` gives the new index of every old entry, `None` for removed entries, and
+/// `entries` is the length of the new palette.
+pub fn remap(png: &mut Png, mapping: &[Option<u8>], entries: usize) -> Result<(), Error> {
+    let new_index = |old: usize| mapping.get(old).copied().flatten().map(usize::from);
+
+    if let Some(chunk) = png.chunk_by_type("tRNS") {
+        let transparency = Transparency::from_chunk(chunk, ColorType::Indexed)?;
+
+        let mut alphas = vec![255; entries];
+        if let Transparency::Indexed(old_alphas) = &transparency {
+            for (old, &alpha) in old_alphas.iter().enumerate() {
+                if let Some(new) = new_index(old) {
+                    alphas[new] = alpha;
+                }
+            }
+        }
+
+        // Missing alphas are fully opaque
+        while alphas.len() > 1 && alphas.last() == Some(&255) {
+            alphas.pop();
+        }
+        png.set_chunk(Transparency::Indexed(alphas).to_chunk())?;
+    }
+
+    if let Some(chunk) = png.chunk_by_type("bKGD") {
+        if let Background::Indexed(index) = Background::from_chunk(chunk, ColorType::Indexed)? {
+            let new = new_index(index as usize).ok_or(PaletteError::BackgroundRemoved(index))?;
+            png.set_chunk(Background::Indexed(new as u8).to_chunk())?;
+        }
+    }
+
+    if let Some(chunk) = png.chunk_by_type("hIST") {
+        let histogram = Histogram::try_from(chunk)?;
+
+        let mut frequencies = vec![0; entries];
+        for (old, &frequency) in histogram.frequencies.iter().enumerate() {
+            if let Some(new) = new_index(old) {
+                frequencies[new] = frequency;
+            }
+        }
+        png.set_chunk(Histogram { frequencies }.to_chunk())?;
+    }
+
+    Ok(())
+}
+
+/// The length of the palette that tRNS, bKGD and hIST refer to
+fn palette_length(palette: Option<&Palette>) -> Result<usize, Error> {
+    palette
+        .map(Palette::len)
+        .ok_or_else(|| PaletteError::MissingPalette.into())
+}
+
+/// Checks that gray or RGB samples fit in the bit depth of the image
+fn check_samples(samples: &[u16], bit_depth: u8) -> Result<(), Error> {
+    let max = ((1u32 << bit_depth) - 1) as u16;
+    match samples.iter().find(|&&sample| sample > max) {
+        Some(&sample) => Err(PaletteError::SampleOutOfRange(sample, bit_depth).into()),
+        None => Ok(()),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PaletteError {
+    #[error("Expected a different chunk type than '{0}'.")]
+    WrongChunkType(String),
+
+    #[error("A PLTE chunk of {0} bytes is invalid, it must hold 1 to 256 RGB entries.")]
+    InvalidPaletteLength(usize),
+
+    #[error("A tRNS chunk of {0} bytes does not match the color type.")]
+    InvalidTransparencyLength(usize),
+
+    #[error("Images with an alpha channel can't have a tRNS chunk.")]
+    TransparencyWithAlpha,
+
+    #[error("A {0} chunk of {1} bytes does not match the color type.")]
+    InvalidLength(&'static str, usize),
+
+    #[error("The image has no PLTE chunk to refer to.")]
+    MissingPalette,
+
+    #[error("The tRNS chunk has {0} alphas but the palette only has {1} entries.")]
+    TooManyAlphas(usize, usize),
+
+    #[error("The background is palette entry {0} but the palette only has {1} entries.")]
+    BackgroundOutOfRange(u8, usize),
+
+    #[error("The background palette entry {0} was removed.")]
+    BackgroundRemoved(u8),
+
+    #[error("The hIST chunk has {0} frequencies but the palette has {1} entries.")]
+    HistogramMismatch(usize, usize),
+
+    #[error("The sample {0} does not fit in {1} bits.")]
+    SampleOutOfRange(u16, u8),
+
+    #[error("{0} significant bits is invalid for samples of {1} bits.")]
+    InvalidSignificantBits(u8, u8),
+
+    #[error("The sPLT chunk is invalid.")]
+    InvalidSuggestedPalette,
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_round_trip() {
+        let palette = Palette {
+            entries: vec![[1, 2, 3], [4, 5, 6]],
+        };
+        let parsed = Palette::try_from(&palette.to_chunk()).unwrap();
+        assert_eq!(palette, parsed);
+    }
+
+    #[test]
+    fn test_invalid_palette() {
+        let chunk = Chunk::new(ChunkType::new(*b"PLTE"), vec![1, 2, 3, 4]);
+        assert!(Palette::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_transparency_round_trip() {
+        let rgb = Transparency::Rgb(1, 2, 300);
+        let parsed = Transparency::from_chunk(&rgb.to_chunk(), ColorType::Rgb).unwrap();
+        assert_eq!(rgb, parsed);
+
+        let indexed = Transparency::Indexed(vec![0, 128]);
+        let parsed = Transparency::from_chunk(&indexed.to_chunk(), ColorType::Indexed).unwrap();
+        assert_eq!(parsed.alpha(1), 128);
+        assert_eq!(parsed.alpha(5), 255);
+    }
+
+    #[test]
+    fn test_transparency_with_alpha() {
+        let chunk = Transparency::Grayscale(0).to_chunk();
+        assert!(Transparency::from_chunk(&chunk, ColorType::Rgba).is_err());
+        assert!(Transparency::from_chunk(&chunk, ColorType::Rgb).is_err());
+    }
+
+    fn indexed_header() -> ImageHeader {
+        ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_too_many_alphas() {
+        let palette = Palette {
+            entries: vec![[0, 0, 0], [1, 1, 1]],
+        };
+        let header = indexed_header();
+
+        assert!(Transparency::Indexed(vec![0, 0])
+            .validate(&header, Some(&palette))
+            .is_ok());
+        assert!(Transparency::Indexed(vec![0, 0, 0])
+            .validate(&header, Some(&palette))
+            .is_err());
+        assert!(Transparency::Indexed(vec![0])
+            .validate(&header, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_background() {
+        let rgb = Background::Rgb(1, 2, 3);
+        assert_eq!(
+            Background::from_chunk(&rgb.to_chunk(), ColorType::Rgba).unwrap(),
+            rgb
+        );
+        assert!(Background::from_chunk(&rgb.to_chunk(), ColorType::Indexed).is_err());
+
+        let mut header = indexed_header();
+        let palette = Palette {
+            entries: vec![[0, 0, 0]],
+        };
+        assert!(Background::Indexed(0)
+            .validate(&header, Some(&palette))
+            .is_ok());
+        assert!(Background::Indexed(1)
+            .validate(&header, Some(&palette))
+            .is_err());
+
+        header.color_type = ColorType::Grayscale;
+        header.bit_depth = 4;
+        assert!(Background::Grayscale(15).validate(&header, None).is_ok());
+        assert!(Background::Grayscale(16).validate(&header, None).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let mut header = indexed_header();
+        let bits = SignificantBits {
+            bits: vec![5, 6, 5],
+        };
+        let parsed = SignificantBits::from_chunk(&bits.to_chunk(), ColorType::Indexed).unwrap();
+        assert_eq!(parsed, bits);
+        assert!(bits.validate(&header).is_ok());
+
+        header.color_type = ColorType::Grayscale;
+        header.bit_depth = 4;
+        assert!(SignificantBits::from_chunk(&bits.to_chunk(), ColorType::Grayscale).is_err());
+        assert!(SignificantBits { bits: vec![4] }.validate(&header).is_ok());
+        assert!(SignificantBits { bits: vec![5] }.validate(&header).is_err());
+        assert!(SignificantBits { bits: vec![0] }.validate(&header).is_err());
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram {
+            frequencies: vec![10, 0, 300],
+        };
+        assert_eq!(
+            Histogram::try_from(&histogram.to_chunk()).unwrap(),
+            histogram
+        );
+
+        let palette = Palette {
+            entries: vec![[0, 0, 0]; 3],
+        };
+        assert!(histogram.validate(Some(&palette)).is_ok());
+        assert!(Histogram {
+            frequencies: vec![1]
+        }
+        .validate(Some(&palette))
+        .is_err());
+    }
+
+    #[test]
+    fn test_suggested_palette_round_trip() {
+        for sample_depth in [8, 16] {
+            let suggested = SuggestedPalette {
+                name: "Few colors".to_string(),
+                sample_depth,
+                entries: vec![
+                    SuggestedEntry {
+                        red: 255,
+                        green: 0,
+                        blue: 128,
+                        alpha: 255,
+                        frequency: 1000,
+                    },
+                    SuggestedEntry {
+                        red: 1,
+                        green: 2,
+                        blue: 3,
+                        alpha: 4,
+                        frequency: 5,
+                    },
+                ],
+            };
+            let parsed = SuggestedPalette::try_from(&suggested.to_chunk()).unwrap();
+            assert_eq!(parsed, suggested);
+        }
+
+        let chunk = Chunk::new(ChunkType::new(*b"sPLT"), b"name\0\x07".to_vec());
+        assert!(SuggestedPalette::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_remap() {
+        let mut png = Png::from_chunks(vec![
+            Transparency::Indexed(vec![10, 20, 30]).to_chunk(),
+            Background::Indexed(2).to_chunk(),
+            Histogram {
+                frequencies: vec![1, 2, 3, 4],
+            }
+            .to_chunk(),
+        ]);
+
+        // Entry 1 is removed and the others are reversed
+        remap(&mut png, &[Some(2), None, Some(1), Some(0)], 3).unwrap();
+
+        let transparency = Transparency::from_chunk(&png.chunks()[0], ColorType::Indexed).unwrap();
+        assert_eq!(transparency, Transparency::Indexed(vec![255, 30, 10]));
+        assert_eq!(
+            Background::from_chunk(&png.chunks()[1], ColorType::Indexed).unwrap(),
+            Background::Indexed(1)
+        );
+        assert_eq!(
+            Histogram::try_from(&png.chunks()[2]).unwrap().frequencies,
+            vec![4, 3, 1]
+        );
+
+        assert!(remap(&mut png, &[Some(0), None], 1).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{
+    apng,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    decoder,
+    encoder::{self, EncoderOptions},
+    ihdr::{ColorType, ImageHeader},
+    palette::{self, Palette, Transparency},
+    png::Png,
+    Error,
+};
+
+// The payload is hidden in the order of the palette entries. Sorting the entries gives a
+// canonical order, and the permutation from the canonical order to the stored order is a
+// number below n! (using the factorial number system). That number holds a length byte
+// followed by the payload. The pixels (and tRNS, hIST and bKGD) are remapped to the new
+// order, so the image looks exactly the same.
+
+/// Big enough for 256! which is the largest palette
+const NUMBER_WIDTH: usize = 256;
+
+/// The number of payload bytes a palette with `entries` distinct entries can hold
+pub fn capacity(entries: usize) -> usize {
+    let mut factorial = vec![0u8; NUMBER_WIDTH];
+    factorial[NUMBER_WIDTH - 1] = 1;
+    for factor in 2..=entries as u32 {
+        multiply_add(&mut factorial, factor, 0);
+    }
+
+    // floor(log2(n!)) whole bits, minus the length byte
+    let bits = bit_length(&factorial).saturating_sub(1);
+    (bits / 8).saturating_sub(1)
+}
+
+/// Returns the payload capacity of a PNG file
+pub fn png_capacity(png: &Png) -> Result<usize, Error> {
+    let colors = palette_colors(png)?;
+    Ok(capacity(colors.len()))
+}
+
+/// Hides a payload in the order of the palette entries. The copy rules for modified image
+/// data apply, unsafe-to-copy chunks are dropped unless listed in `keep`. Returns the new
+/// file and the dropped chunks.
+pub fn embed(png: &Png, payload: &[u8], keep: &[ChunkType]) -> Result<(Png, Vec<Chunk>), Error> {
+    // Only the default image is remapped, the frames would point at the wrong colors
+    if apng::is_animated(png) {
+        return Err(PaletteStegoError::Animated.into());
+    }
+
+    let colors = palette_colors(png)?;
+    let entries = colors.len();
+    let capacity = capacity(entries);
+
+    if payload.len() > capacity || payload.len() > 255 {
+        return Err(PaletteStegoError::PayloadTooLarge(capacity.min(255)).into());
+    }
+
+    // The number to store: a length byte, the payload and zero padding
+    let mut number = vec![0u8; NUMBER_WIDTH];
+    let message = &mut number[NUMBER_WIDTH - capacity - 1..];
+    message[0] = payload.len() as u8;
+    message[1..=payload.len()].copy_from_slice(payload);
+
+    // Convert the number to the factorial number system, digit i goes from 0 to n - 1 - i
+    let mut digits = vec![0usize; entries];
+    for radix in 1..=entries {
+        digits[entries - radix] = divide(&mut number, radix as u32) as usize;
+    }
+
+    // Turn the digits into a permutation of the canonical order
+    let canonical = canonical_order(&colors);
+    let mut available: Vec<usize> = (0..entries).collect();
+    let mut new_index = vec![0u16; 256];
+    let mut new_colors = Vec::with_capacity(entries);
+
+    for (position, &digit) in digits.iter().enumerate() {
+        let old = canonical[available.remove(digit)];
+        new_index[old] = position as u16;
+        new_colors.push(colors[old]);
+    }
+
+    remap(png, &new_colors, &new_index, keep)
+}
+
+/// Reads a payload hidden in the order of the palette entries
+pub fn extract(png: &Png) -> Result<Vec<u8>, Error> {
+    let colors = palette_colors(png)?;
+    let entries = colors.len();
+    let capacity = capacity(entries);
+
+    // Where every stored entry sits in the canonical order
+    let canonical = canonical_order(&colors);
+    let mut ranks = vec![0usize; entries];
+    for (rank, &index) in canonical.iter().enumerate() {
+        ranks[index] = rank;
+    }
+
+    let mut number = vec![0u8; NUMBER_WIDTH];
+    for (position, &rank) in ranks.iter().enumerate() {
+        let digit = ranks[position + 1..]
+            .iter()
+            .filter(|&&later| later < rank)
+            .count();
+        multiply_add(&mut number, (entries - position) as u32, digit as u32);
+    }
+
+    let message = &number[NUMBER_WIDTH - capacity - 1..];
+    let length = message[0] as usize;
+
+    if capacity == 0
+        || number[..NUMBER_WIDTH - capacity - 1]
+            .iter()
+            .any(|&byte| byte != 0)
+        || length > capacity
+    {
+        return Err(PaletteStegoError::NoPayload.into());
+    }
+
+    Ok(message[1..=length].to_vec())
+}
+
+/// The palette entries with their alpha from tRNS, these have to be distinct since the
+/// order of identical entries can't be told apart.
+fn palette_colors(png: &Png) -> Result<Vec<[u8; 4]>, Error> {
+    let header = ImageHeader::from_png(png)?;
+    if header.color_type != ColorType::Indexed {
+        return Err(PaletteStegoError::NotIndexed.into());
+    }
+
+    let palette = match Palette::from_png(png)? {
+        Some(palette) => palette,
+        None => return Err(PaletteStegoError::NotIndexed.into()),
+    };
+    let transparency = Transparency::from_png(png)?;
+
+    let colors: Vec<[u8; 4]> = palette
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(index, [red, green, blue])| {
+            let alpha = transparency.as_ref().map_or(255, |trns| trns.alpha(index));
+            [*red, *green, *blue, alpha]
+        })
+        .collect();
+
+    let mut sorted = colors.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    if sorted.len() != colors.len() {
+        return Err(PaletteStegoError::DuplicateEntries.into());
+    }
+
+    Ok(colors)
+}
+
+/// The indices of the entries sorted by their color
+fn canonical_order(colors: &[[u8; 4]]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..colors.len()).collect();
+    order.sort_by_key(|&index| colors[index]);
+    order
+}
+
+/// Rewrites the pixels and the palette dependent chunks for a new palette order
+fn remap(
+    png: &Png,
+    new_colors: &[[u8; 4]],
+    new_index: &[u16],
+    keep: &[ChunkType],
+) -> Result<(Png, Vec<Chunk>), Error> {
+    let mut pixels = decoder::decode(png)?;
+
+    for y in 0..pixels.height() {
+        for x in 0..pixels.width() {
+            let index = pixels.sample(x, y, 0) as usize;
+            if index >= new_colors.len() {
+                return Err(PaletteStegoError::IndexOutOfRange(index).into());
+            }
+            pixels.set_sample(x, y, 0, new_index[index]);
+        }
+    }
+
+    pixels.palette = Some(
+        new_colors
+            .iter()
+            .map(|&[red, green, blue, _]| [red, green, blue])
+            .collect(),
+    );
+
+    let (mut remapped, dropped) = encoder::encode(png, &pixels, &EncoderOptions::default(), keep)?;
+
+    let mapping: Vec<Option<u8>> = (0..new_colors.len())
+        .map(|old| Some(new_index[old] as u8))
+        .collect();
+    palette::remap(&mut remapped, &mapping, new_colors.len())?;
+
+    Ok((remapped, dropped))
+}
+
+/// Divides a big endian number by a small divisor in place and returns the remainder
+fn divide(number: &mut [u8], divisor: u32) -> u32 {
+    let mut remainder: u32 = 0;
+    for byte in number.iter_mut() {
+        let value = (remainder << 8) | *byte as u32;
+        *byte = (value / divisor) as u8;
+        remainder = value % divisor;
+    }
+    remainder
+}
+
+/// Computes `number * factor + add` in place on a big endian number
+fn multiply_add(number: &mut [u8], factor: u32, add: u32) {
+    let mut carry = add;
+    for byte in number.iter_mut().rev() {
+        let value = *byte as u32 * factor + carry;
+        *byte = value as u8;
+        carry = value >> 8;
+    }
+}
+
+fn bit_length(number: &[u8]) -> usize {
+    match number.iter().position(|&byte| byte != 0) {
+        Some(index) => (number.len() - index) * 8 - number[index].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PaletteStegoError {
+    #[error("Only indexed color images (color type 3) have a palette to hide data in.")]
+    NotIndexed,
+
+    #[error("The palette has duplicate entries, their order can't carry any data.")]
+    DuplicateEntries,
+
+    #[error("The payload is too large, the palette can hold {0} bytes.")]
+    PayloadTooLarge(usize),
+
+    #[error("The image uses palette index {0} which is outside of the palette.")]
+    IndexOutOfRange(usize),
+
+    #[error("The palette order does not hold a payload.")]
+    NoPayload,
+
+    #[error("The frames of an animated PNG file share the palette, its order can't be changed.")]
+    Animated,
+}
+
+#[cfg(test)]
+mod palette_stego_tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    /// A 4x4 image with 32 palette entries, some of them transparent
+    fn testing_png() -> Png {
+        let header = ImageHeader {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+
+        let palette: Vec<u8> = (0..32u8).flat_map(|i| [i * 8, 255 - i * 8, i]).collect();
+        let alphas: Vec<u8> = (0..8u8).map(|i| i * 30).collect();
+        let histogram: Vec<u8> = (0..32u16).flat_map(|i| i.to_be_bytes()).collect();
+        let pixels: Vec<u8> = (0..16u8).map(|i| i * 2).collect();
+
+        let stream =
+            encoder::encode_image_data(&header, &pixels, &EncoderOptions::default()).unwrap();
+
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::new(*b"PLTE"), palette),
+            Chunk::new(ChunkType::new(*b"tRNS"), alphas),
+            Chunk::new(ChunkType::new(*b"hIST"), histogram),
+            Chunk::new(ChunkType::new(*b"bKGD"), vec![5]),
+            Chunk::new(ChunkType::new(*b"IDAT"), stream),
+            Chunk::new(ChunkType::new(*b"RuSt"), b"payload chunk".to_vec()),
+            Chunk::new(ChunkType::new(*b"IEND"), Vec::new()),
+        ])
+    }
+
+    /// Every pixel as RGBA including the alpha from tRNS
+    fn colors(png: &Png) -> Vec<[u8; 4]> {
+        let colors = palette_colors(png).unwrap();
+        let pixels = decoder::decode(png).unwrap();
+
+        (0..16)
+            .map(|i| colors[pixels.sample(i % 4, i / 4, 0) as usize])
+            .collect()
+    }
+
+    fn chunk_data(png: &Png, chunk_type: &str) -> Vec<u8> {
+        png.chunk_by_type(chunk_type).unwrap().data().to_vec()
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 32! is about 2^117.7
+        assert_eq!(capacity(32), 13);
+        assert_eq!(capacity(2), 0);
+        assert_eq!(capacity(256), 209);
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let png = testing_png();
+        let (embedded, _) = embed(&png, b"hello", &[]).unwrap();
+
+        assert_eq!(extract(&embedded).unwrap(), b"hello".to_vec());
+        assert_eq!(colors(&png), colors(&embedded));
+        assert_eq!(chunk_data(&embedded, "RuSt"), b"payload chunk".to_vec());
+    }
+
+    #[test]
+    fn test_remaps_auxiliary_chunks() {
+        let png = testing_png();
+        let (embedded, _) = embed(&png, b"remap", &[]).unwrap();
+
+        let background = chunk_data(&embedded, "bKGD")[0] as usize;
+        let palette = Palette::from_png(&embedded).unwrap().unwrap();
+        assert_eq!(palette.entries[background], [40, 215, 5]);
+
+        let histogram = chunk_data(&embedded, "hIST");
+        let frequency =
+            u16::from_be_bytes([histogram[background * 2], histogram[background * 2 + 1]]);
+        assert_eq!(frequency, 5);
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let png = testing_png();
+        assert!(embed(&png, &[0; 14], &[]).is_err());
+        assert!(embed(&png, &[0; 13], &[]).is_ok());
+    }
+
+    #[test]
+    fn test_not_indexed() {
+        let png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        let mut header = ImageHeader::from_png(&png).unwrap();
+        header.color_type = ColorType::Rgb;
+
+        let mut chunks = png.chunks().to_vec();
+        chunks[0] = header.to_chunk();
+        assert!(embed(&Png::from_chunks(chunks), b"hi", &[]).is_err());
+    }
+
+    #[test]
+    fn test_animated() {
+        let mut png = testing_png();
+        let animation = crate::apng::AnimationControl {
+            num_frames: 1,
+            num_plays: 0,
+        };
+        png.insert_chunk(1, animation.to_chunk()).unwrap();
+
+        let error = embed(&png, b"hi", &[]).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<PaletteStegoError>(),
+            Some(PaletteStegoError::Animated)
+        ));
+    }
+
+    #[test]
+    fn test_big_number_arithmetic() {
+        let mut number = vec![0u8; 4];
+        multiply_add(&mut number, 1000, 7);
+        multiply_add(&mut number, 1000, 9);
+        assert_eq!(u32::from_be_bytes(number.clone().try_into().unwrap()), 7009);
+        assert_eq!(divide(&mut number, 10), 9);
+        assert_eq!(u32::from_be_bytes(number.try_into().unwrap()), 700);
+    }
+}
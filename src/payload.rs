@@ -0,0 +1,727 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{
+    apng,
+    camouflage::{self, Carrier},
+    chunk::{Chunk, ChunkError},
+    chunk_type::ChunkType,
+    color::{self, ColorState},
+    decoder,
+    deflate_slack::{self, DeflateSlackError},
+    deniable, ecc, encoder,
+    envelope::{self, EnvelopeError, Metadata, SealOptions},
+    ihdr::{ColorType, ImageHeader},
+    optimize,
+    palette_stego::{self, PaletteStegoError},
+    png::{Png, PngError},
+    Error,
+};
+
+// The way from a payload to the bytes hidden in an image and back, the same for the command
+// line and the server:
+//
+//   prepare  wraps the payload in an envelope, unless it is raw, encrypts it into a slot of
+//            a deniable container if there is a passphrase and adds error correction
+//   hide     puts those bytes in the image with one of the methods
+//   find     reads them back out of the image
+//   reveal   undoes what `prepare` did
+//
+// `scan` looks for payloads hidden with any of the methods and `sanitize` removes whatever
+// could hold one.
+
+/// Where the payload is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+    /// In a chunk of its own
+    Chunk,
+    /// In the order of the palette entries of an indexed color image
+    Palette,
+    /// In the padding bits of the stored blocks of the compressed image data
+    DeflateSlack,
+    /// As raw bytes after the IEND chunk
+    Trailer,
+    /// In a chunk that looks like ordinary metadata, see --carrier
+    Camouflage,
+}
+
+/// Where a payload is hidden in an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target<'a> {
+    pub method: Method,
+    /// The chunk type of the chunk method
+    pub chunk_type: Option<&'a str>,
+    /// The carrier of the camouflage method
+    pub carrier: Carrier,
+    /// The frame of an animation the deflate-slack method hides the payload in
+    pub frame: Option<usize>,
+}
+
+/// A payload with what its envelope tells about it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Payload {
+    pub bytes: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+/// How payloads become the bytes to hide
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PrepareOptions {
+    /// Reed-Solomon parity bytes per block, no error correction if `None`
+    pub parity: Option<usize>,
+    /// Compress the payload inside the envelope
+    pub compress: bool,
+    /// Hide the bytes as they are, without an envelope
+    pub raw: bool,
+    /// Use a compact envelope, for methods that hide only a few bytes
+    pub compact: bool,
+    /// The number of slots of a deniable container
+    pub slots: usize,
+}
+
+/// How the bytes found in an image become the payload
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RevealOptions {
+    /// Correct a raw payload, or a deniable container, hidden with error correction
+    pub ecc: bool,
+    /// Don't look for an envelope
+    pub raw: bool,
+    /// Open the slot of a deniable container this passphrase belongs to
+    pub passphrase: Option<String>,
+    /// The most bytes the payload of an envelope may inflate to
+    pub limit: usize,
+}
+
+/// A payload found in an image, and what its envelope told about it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Revealed {
+    pub payload: Vec<u8>,
+    /// The metadata of the envelope, `None` for a raw payload
+    pub metadata: Option<Metadata>,
+    /// The flags of the envelope
+    pub flags: u8,
+    /// A compact envelope, its metadata has no file name or creation time
+    pub compact: bool,
+    /// The number of bytes error correction fixed, `None` if there was nothing to correct
+    pub corrected: Option<usize>,
+}
+
+/// An image with a payload hidden in it
+#[derive(Clone)]
+pub struct Hidden {
+    pub png: Png,
+    /// The unsafe-to-copy chunks the copy rules dropped because the image data changed
+    pub dropped: Vec<Chunk>,
+    /// The number of bytes the palette order or the deflate slack can hold
+    pub capacity: Option<usize>,
+}
+
+/// Something that may be a hidden payload
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Finding {
+    pub method: Method,
+    /// The type of the chunk that holds it, for the chunk and camouflage methods
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub carrier: Option<Carrier>,
+    pub size: usize,
+    /// Whether it is an envelope
+    pub envelope: bool,
+}
+
+impl Method {
+    /// The palette order and the deflate slack hold too few bytes for a full envelope
+    pub fn compact(self) -> bool {
+        matches!(self, Method::Palette | Method::DeflateSlack)
+    }
+}
+
+impl Target<'_> {
+    /// The chunk method can't do without a chunk type
+    pub fn chunk_type(&self) -> Result<&str, Error> {
+        match self.chunk_type {
+            Some(chunk_type) => Ok(chunk_type),
+            None => Err(PayloadError::MissingChunkType.into()),
+        }
+    }
+}
+
+impl Payload {
+    /// A text payload
+    pub fn text(text: &str) -> Payload {
+        Payload {
+            bytes: text.as_bytes().to_vec(),
+            metadata: Metadata::new("text/plain", None),
+        }
+    }
+
+    /// The contents of a file, its content type is guessed from the name
+    pub fn file(file_name: &str, bytes: Vec<u8>) -> Payload {
+        let content_type = envelope::guess_content_type(file_name);
+        Payload {
+            bytes,
+            metadata: Metadata::new(content_type, Some(file_name.to_string())),
+        }
+    }
+}
+
+impl Default for PrepareOptions {
+    fn default() -> Self {
+        PrepareOptions {
+            parity: None,
+            compress: false,
+            raw: false,
+            compact: false,
+            slots: deniable::DEFAULT_SLOTS,
+        }
+    }
+}
+
+impl Default for RevealOptions {
+    fn default() -> Self {
+        RevealOptions {
+            ecc: false,
+            raw: false,
+            passphrase: None,
+            limit: decoder::MAX_INFLATED_SIZE,
+        }
+    }
+}
+
+impl Revealed {
+    /// Whether the payload is text, raw payloads are taken for text
+    pub fn is_text(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .is_none_or(|metadata| metadata.content_type.starts_with("text/"))
+    }
+}
+
+/// Splits an extra payload given as `PASSPHRASE=PAYLOAD`
+pub fn split_also(also: &str) -> Result<(&str, &str), Error> {
+    match also.split_once('=') {
+        Some(parts) => Ok(parts),
+        None => Err(PayloadError::MissingPassphrase(also.to_string()).into()),
+    }
+}
+
+/// Turns a payload into the bytes to hide. With a passphrase it is encrypted into a slot of
+/// a deniable container, along with the `also` payloads under passphrases of their own.
+/// Error correction then goes around the whole container: damage inside a slot would fail
+/// its authentication before it could be corrected.
+pub fn prepare(
+    payload: &Payload,
+    passphrase: Option<&str>,
+    also: &[(&str, Payload)],
+    options: &PrepareOptions,
+) -> Result<Vec<u8>, Error> {
+    let passphrase = match passphrase {
+        Some(passphrase) => passphrase,
+        None if also.is_empty() => return wrap(payload, options, options.parity),
+        None => return Err(PayloadError::AlsoWithoutPassphrase.into()),
+    };
+
+    let mut secrets = vec![(passphrase, wrap(payload, options, None)?)];
+    for (passphrase, payload) in also {
+        secrets.push((passphrase, wrap(payload, options, None)?));
+    }
+    let secrets: Vec<(&str, &[u8])> = secrets
+        .iter()
+        .map(|(passphrase, wrapped)| (*passphrase, wrapped.as_slice()))
+        .collect();
+
+    let container = deniable::seal(&secrets, options.slots)?;
+    match options.parity {
+        Some(parity) => ecc::encode(&container, parity),
+        None => Ok(container),
+    }
+}
+
+/// Wraps a single payload in an envelope unless it is raw, with `parity` bytes of error
+/// correction per block
+fn wrap(
+    payload: &Payload,
+    options: &PrepareOptions,
+    parity: Option<usize>,
+) -> Result<Vec<u8>, Error> {
+    if options.raw {
+        return match parity {
+            Some(parity) => ecc::encode(&payload.bytes, parity),
+            None => Ok(payload.bytes.clone()),
+        };
+    }
+
+    let seal_options = SealOptions {
+        compress: options.compress,
+        parity,
+        compact: options.compact,
+    };
+    envelope::seal(&payload.bytes, &payload.metadata, &seal_options)
+}
+
+/// Hides prepared bytes with the method of the target. Unsafe-to-copy chunks that hold an
+/// envelope, or are listed in `keep`, survive a change of the image data.
+pub fn hide(png: &Png, target: &Target, bytes: &[u8], keep: &[ChunkType]) -> Result<Hidden, Error> {
+    let keep = encoder::payload_chunk_types(png, keep);
+    let mut hidden = Hidden {
+        png: png.clone(),
+        dropped: Vec::new(),
+        capacity: None,
+    };
+
+    match target.method {
+        Method::Chunk => {
+            let chunk_type = ChunkType::from_str(target.chunk_type()?)?;
+            hidden
+                .png
+                .append_chunk(Chunk::new(chunk_type, bytes.to_vec()))?;
+        }
+        Method::Palette => {
+            (hidden.png, hidden.dropped) = palette_stego::embed(png, bytes, &keep)?;
+            hidden.capacity = Some(palette_stego::png_capacity(&hidden.png)?);
+        }
+        Method::DeflateSlack => {
+            let stream = deflate_slack::SlackStream::new(png, target.frame)?;
+            hidden.capacity = Some(stream.capacity()?);
+            (hidden.png, hidden.dropped) = stream.embed(png, bytes, &keep)?;
+        }
+        Method::Trailer => hidden.png.append_trailing_bytes(bytes)?,
+        Method::Camouflage => hidden.png = camouflage::embed(png, target.carrier, bytes)?,
+    }
+
+    Ok(hidden)
+}
+
+/// The bytes hidden in an image with the method of the target. `damaged` are the indices of
+/// the chunks whose CRC doesn't match, a chunk payload in one of those is only read if it
+/// can stand some damage.
+pub fn find(png: &Png, damaged: &[usize], target: &Target, ecc: bool) -> Result<Vec<u8>, Error> {
+    match target.method {
+        Method::Chunk => {
+            let chunk_type = target.chunk_type()?;
+            let index = png
+                .chunks()
+                .iter()
+                .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+                .ok_or(PngError::NonExistentChunk)?;
+
+            // A payload with error correction (or a digest) can stand some damage
+            let data = png.chunks()[index].data();
+            if damaged.contains(&index) && !ecc && !envelope::is_envelope(data) {
+                return Err(ChunkError::InvalidCRC.into());
+            }
+            Ok(data.to_vec())
+        }
+        Method::Palette => palette_stego::extract(png),
+        Method::DeflateSlack => deflate_slack::extract(png, target.frame),
+        Method::Trailer => match png.trailing_bytes() {
+            [] => Err(PngError::NoTrailingData.into()),
+            trailing_bytes => Ok(trailing_bytes.to_vec()),
+        },
+        Method::Camouflage => camouflage::extract(png, target.carrier),
+    }
+}
+
+/// Undoes what `prepare` did to the bytes found in an image: the slot of the passphrase is
+/// decrypted, an envelope is opened and a raw payload hidden with error correction is
+/// corrected. Any palette order and any deflate slack read as some bytes, so without a
+/// passphrase those only hold a payload if they hold an envelope, unless it is raw.
+pub fn reveal(found: &[u8], method: Method, options: &RevealOptions) -> Result<Revealed, Error> {
+    let mut corrected = None;
+
+    let payload = match &options.passphrase {
+        Some(passphrase) => {
+            // Error correction goes around the whole container
+            let container = match options.ecc {
+                true => {
+                    let (decoded, fixed) = ecc::decode(found)?;
+                    corrected = Some(fixed);
+                    decoded
+                }
+                false => found.to_vec(),
+            };
+            deniable::open(&container, passphrase)?
+        }
+        None => found.to_vec(),
+    };
+
+    let noise = !options.raw
+        && options.passphrase.is_none()
+        && matches!(method, Method::Palette | Method::DeflateSlack);
+
+    if !options.raw && envelope::is_envelope(&payload) {
+        let opened = match envelope::open_limited(&payload, options.limit) {
+            Ok(opened) => opened,
+            Err(error)
+                if noise && !matches!(error.downcast_ref(), Some(EnvelopeError::TooLarge(..))) =>
+            {
+                return Err(no_payload(method))
+            }
+            Err(error) => return Err(error),
+        };
+        return Ok(Revealed {
+            payload: opened.payload,
+            metadata: Some(opened.metadata),
+            flags: opened.flags,
+            compact: opened.compact,
+            corrected: Some(corrected.unwrap_or(0) + opened.corrected),
+        });
+    }
+
+    if noise {
+        return Err(no_payload(method));
+    }
+
+    let payload = match options.ecc && options.passphrase.is_none() {
+        true => {
+            let (decoded, fixed) = ecc::decode(&payload)?;
+            corrected = Some(fixed);
+            decoded
+        }
+        false => payload,
+    };
+
+    Ok(Revealed {
+        payload,
+        metadata: None,
+        flags: 0,
+        compact: false,
+        corrected,
+    })
+}
+
+/// The error of a method that found no payload
+fn no_payload(method: Method) -> Error {
+    match method {
+        Method::Palette => PaletteStegoError::NoPayload.into(),
+        _ => DeflateSlackError::NoPayload.into(),
+    }
+}
+
+/// Whether an error says the method found no payload, rather than one it couldn't open
+pub fn is_no_payload(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(error.downcast_ref(), Some(PaletteStegoError::NoPayload))
+        || matches!(error.downcast_ref(), Some(DeflateSlackError::NoPayload))
+}
+
+/// Looks for payloads hidden with every method. Chunks of unknown types and data after
+/// IEND are reported whatever they hold. The palette order and the deflate slack of any
+/// image read as some payload, so they are only reported when that is an envelope that
+/// opens.
+pub fn scan(png: &Png) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for chunk in png.chunks() {
+        if !chunk.chunk_type().is_standard() {
+            findings.push(Finding {
+                method: Method::Chunk,
+                chunk_type: Some(chunk.chunk_type().to_string()),
+                carrier: None,
+                size: chunk.data().len(),
+                envelope: envelope::is_envelope(chunk.data()),
+            });
+        }
+    }
+
+    if !png.trailing_bytes().is_empty() {
+        findings.push(Finding {
+            method: Method::Trailer,
+            chunk_type: None,
+            carrier: None,
+            size: png.trailing_bytes().len(),
+            envelope: envelope::is_envelope(png.trailing_bytes()),
+        });
+    }
+
+    for carrier in <Carrier as clap::ValueEnum>::value_variants() {
+        if let Ok(payload) = camouflage::extract(png, *carrier) {
+            findings.push(Finding {
+                method: Method::Camouflage,
+                chunk_type: Some(ChunkType::new(carrier.chunk_type()).to_string()),
+                carrier: Some(*carrier),
+                size: payload.len(),
+                envelope: envelope::is_envelope(&payload),
+            });
+        }
+    }
+
+    let hidden = [
+        (Method::Palette, palette_stego::extract(png)),
+        (Method::DeflateSlack, deflate_slack::extract(png, None)),
+    ];
+    for (method, payload) in hidden {
+        if let Some(payload) = payload
+            .ok()
+            .filter(|payload| envelope::open(payload).is_ok())
+        {
+            findings.push(Finding {
+                method,
+                chunk_type: None,
+                carrier: None,
+                size: payload.len(),
+                envelope: true,
+            });
+        }
+    }
+
+    findings
+}
+
+/// Removes whatever could hold a payload: chunks of unknown types, carrier chunks that hold
+/// one and the data after IEND. The palette is put in its canonical order and the image
+/// data deflated anew, which leaves none of the slack it had. Of the color chunks only
+/// those that agree with the one that applies are kept, see `ColorState::consistent`.
+pub fn sanitize(png: &Png) -> Result<Png, Error> {
+    let chunks = png
+        .chunks()
+        .iter()
+        .filter(|chunk| {
+            chunk.chunk_type().is_standard()
+                && <Carrier as clap::ValueEnum>::value_variants()
+                    .iter()
+                    .all(|&carrier| !matches!(camouflage::unwrap(carrier, chunk), Ok(Some(_))))
+        })
+        .cloned()
+        .collect();
+    let mut sanitized = Png::from_chunks(chunks);
+
+    let header = ImageHeader::from_png(&sanitized)?;
+    if header.color_type == ColorType::Indexed && !apng::is_animated(&sanitized) {
+        let keep = encoder::payload_chunk_types(&sanitized, &[]);
+        sanitized = palette_stego::embed(&sanitized, &[], &keep)?.0;
+    }
+
+    let keep = encoder::payload_chunk_types(&sanitized, &[]);
+    let optimized = optimize::optimize(&sanitized, &keep)?.0;
+    sanitize_color(&optimized)
+}
+
+/// Writes the color chunks anew from their typed state: one of each type, without the
+/// malformed ones and those that contradict the chunk that applies
+fn sanitize_color(png: &Png) -> Result<Png, Error> {
+    let header = ImageHeader::from_png(png)?;
+    let state = ColorState::from_png_lenient(png).consistent(Some(&header));
+
+    let mut chunks: Vec<Chunk> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| !color::CHUNK_TYPES.contains(&chunk.chunk_type().to_string().as_str()))
+        .cloned()
+        .collect();
+    // They all go before PLTE and IDAT, right after IHDR will do
+    chunks.splice(1..1, state.to_chunks()?);
+
+    let mut sanitized = Png::from_chunks(chunks);
+    sanitized.set_trailing_bytes(png.trailing_bytes().to_vec());
+    Ok(sanitized)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PayloadError {
+    #[error("The chunk method needs a chunk type.")]
+    MissingChunkType,
+
+    #[error("Expected PASSPHRASE=PAYLOAD, got '{0}'.")]
+    MissingPassphrase(String),
+
+    #[error("Extra payloads are only hidden along with a passphrase.")]
+    AlsoWithoutPassphrase,
+}
+
+#[cfg(test)]
+mod payload_tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::try_from(&include_bytes!("../sss.png")[..]).unwrap()
+    }
+
+    fn target(method: Method) -> Target<'static> {
+        Target {
+            method,
+            chunk_type: Some("ruSt"),
+            carrier: Carrier::Comment,
+            frame: None,
+        }
+    }
+
+    #[test]
+    fn test_hide_and_find() {
+        let png = testing_png();
+        let payload = Payload::text("round trip");
+
+        for method in [
+            Method::Chunk,
+            Method::DeflateSlack,
+            Method::Trailer,
+            Method::Camouflage,
+        ] {
+            let options = PrepareOptions {
+                compact: method.compact(),
+                ..Default::default()
+            };
+            let bytes = prepare(&payload, None, &[], &options).unwrap();
+            let hidden = hide(&png, &target(method), &bytes, &[]).unwrap();
+            let found = find(&hidden.png, &[], &target(method), false).unwrap();
+            let revealed = reveal(&found, method, &RevealOptions::default()).unwrap();
+            assert_eq!(revealed.payload, b"round trip");
+            assert!(revealed.is_text());
+            assert_eq!(revealed.corrected, Some(0));
+        }
+    }
+
+    #[test]
+    fn test_deniable_slots() {
+        let options = PrepareOptions {
+            parity: Some(8),
+            ..Default::default()
+        };
+        let also = [("other", Payload::file("notes.txt", b"other".to_vec()))];
+        let bytes = prepare(&Payload::text("first"), Some("one"), &also, &options).unwrap();
+
+        let reveal_with = |passphrase: &str| {
+            let options = RevealOptions {
+                ecc: true,
+                passphrase: Some(passphrase.to_string()),
+                ..Default::default()
+            };
+            reveal(&bytes, Method::Trailer, &options)
+        };
+        assert_eq!(reveal_with("one").unwrap().payload, b"first");
+        let other = reveal_with("other").unwrap();
+        assert_eq!(other.payload, b"other");
+        assert_eq!(
+            other.metadata.unwrap().file_name.as_deref(),
+            Some("notes.txt")
+        );
+        assert!(reveal_with("three").is_err());
+
+        assert!(prepare(&Payload::text("first"), None, &also, &options).is_err());
+        assert_eq!(split_also("pass=word=s").unwrap(), ("pass", "word=s"));
+        assert!(split_also("password").is_err());
+    }
+
+    #[test]
+    fn test_raw_with_ecc() {
+        let options = PrepareOptions {
+            parity: Some(8),
+            raw: true,
+            ..Default::default()
+        };
+        let mut bytes = prepare(&Payload::text("raw"), None, &[], &options).unwrap();
+        bytes[0] ^= 0xFF;
+
+        let options = RevealOptions {
+            ecc: true,
+            raw: true,
+            ..Default::default()
+        };
+        let revealed = reveal(&bytes, Method::Trailer, &options).unwrap();
+        assert_eq!(revealed.payload, b"raw");
+        assert_eq!(revealed.metadata, None);
+        assert_eq!(revealed.corrected, Some(1));
+    }
+
+    #[test]
+    fn test_noise_is_no_payload() {
+        let options = PrepareOptions {
+            compact: true,
+            ..Default::default()
+        };
+        let bytes = prepare(&Payload::text("hi"), None, &[], &options).unwrap();
+        let mut damaged = bytes.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+
+        for method in [Method::Palette, Method::DeflateSlack] {
+            let options = RevealOptions::default();
+            assert_eq!(reveal(&bytes, method, &options).unwrap().payload, b"hi");
+
+            for noise in [&b"random order"[..], &damaged] {
+                let error = reveal(noise, method, &options).unwrap_err();
+                assert!(is_no_payload(error.as_ref()));
+            }
+
+            let raw = RevealOptions {
+                raw: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                reveal(b"random order", method, &raw).unwrap().payload,
+                b"random order"
+            );
+        }
+
+        // Other methods show what they found
+        let revealed = reveal(b"plain", Method::Trailer, &RevealOptions::default()).unwrap();
+        assert_eq!(revealed.payload, b"plain");
+    }
+
+    #[test]
+    fn test_scan_and_sanitize() {
+        let png = testing_png();
+        let clean = scan(&png).len();
+
+        let bytes = prepare(
+            &Payload::text("look"),
+            None,
+            &[],
+            &PrepareOptions::default(),
+        )
+        .unwrap();
+        let hidden = hide(&png, &target(Method::Trailer), &bytes, &[]).unwrap();
+        let hidden = hide(&hidden.png, &target(Method::Camouflage), &bytes, &[]).unwrap();
+
+        let findings = scan(&hidden.png);
+        assert_eq!(findings.len(), clean + 2);
+        assert!(findings
+            .iter()
+            .any(|finding| finding.method == Method::Trailer && finding.envelope));
+        assert!(findings
+            .iter()
+            .any(|finding| finding.carrier == Some(Carrier::Comment)));
+
+        let sanitized = sanitize(&hidden.png).unwrap();
+        assert!(scan(&sanitized).is_empty());
+        assert!(find(&sanitized, &[], &target(Method::Camouflage), false).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_color_chunks() {
+        use crate::color::{ContentLightLevel, Gamma};
+
+        // The testing image has an sRGB chunk, a gAMA that disagrees with it can hold
+        // anything, and so can a cLLI without cICP
+        let png = testing_png();
+        let mut chunks = png.chunks().to_vec();
+        chunks.splice(
+            1..1,
+            [
+                Gamma(12345).to_chunk(),
+                ContentLightLevel {
+                    max_content: 1000,
+                    max_frame_average: 400,
+                }
+                .to_chunk(),
+            ],
+        );
+        let sanitized = sanitize(&Png::from_chunks(chunks)).unwrap();
+
+        let state = ColorState::from_png(&sanitized).unwrap();
+        assert!(state.srgb.is_some());
+        assert_eq!(state.gamma, None);
+        assert_eq!(state.light_level, None);
+        assert!(crate::ordering::violations(sanitized.chunks()).is_empty());
+
+        // A gAMA that agrees with sRGB is kept for decoders that don't know sRGB
+        let mut chunks = png.chunks().to_vec();
+        chunks.insert(1, Gamma(45455).to_chunk());
+        let sanitized = sanitize(&Png::from_chunks(chunks)).unwrap();
+        assert_eq!(
+            ColorState::from_png(&sanitized).unwrap().gamma,
+            Some(Gamma(45455))
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use std::io::Cursor;
-
-use crate::{chunk::Chunk, Error};
-
+use crate::{
+    apng,
+    chunk::{Chunk, ChunkError},
+    chunk_type::ChunkType,
+    ordering, Error,
+};
+
+#[derive(Clone)]
 pub struct Png {
     data: Vec<Chunk>,
+    /// Bytes after the last chunk that can't be read as a chunk, kept as they are
+    trailing_bytes: Vec<u8>,
 }
 
 #[allow(dead_code)]
@@ -11,31 +17,248 @@ impl Png {
     const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { data: chunks }
+        Png {
+            data: chunks,
+            trailing_bytes: Vec::new(),
+        }
     }
 
-    pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.data.push(chunk);
+    /// Appends a chunk as late in the file as the ordering rules allow, so custom
+    /// chunks end up before IEND instead of after it.
+    pub fn append_chunk(&mut self, chunk: Chunk) -> Result<(), Error> {
+        let types = self.chunk_types();
+        let existing = ordering::type_violations(&types).len();
+
+        for index in (0..=self.data.len()).rev() {
+            let mut candidate = types.clone();
+            candidate.insert(index, chunk.chunk_type().bytes());
+
+            if ordering::type_violations(&candidate).len() <= existing {
+                let snapshot = self.frame_snapshot(std::slice::from_ref(&chunk));
+                self.data.insert(index, chunk);
+                return self.renumber_frames(snapshot);
+            }
+        }
+
+        // Nowhere works, so report what breaks where the fewest rules break, before IEND
+        let end = types
+            .iter()
+            .position(|chunk_type| chunk_type == b"IEND")
+            .unwrap_or(types.len());
+        let best = (0..=end)
+            .rev()
+            .min_by_key(|&index| {
+                let mut candidate = types.clone();
+                candidate.insert(index, chunk.chunk_type().bytes());
+                ordering::type_violations(&candidate).len()
+            })
+            .unwrap_or(end);
+
+        Err(self.new_violation(types, best, chunk.chunk_type().bytes()))
     }
 
+    /// Inserts a chunk at the given index, refusing placements that break the ordering rules
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<(), Error> {
+        if index > self.data.len() {
+            return Err(PngError::IndexOutOfBounds.into());
+        }
+
+        let types = self.chunk_types();
+        let existing = ordering::type_violations(&types).len();
+
+        let mut candidate = types.clone();
+        candidate.insert(index, chunk.chunk_type().bytes());
+
+        if ordering::type_violations(&candidate).len() > existing {
+            return Err(self.new_violation(types, index, chunk.chunk_type().bytes()));
+        }
+
+        let snapshot = self.frame_snapshot(std::slice::from_ref(&chunk));
+        self.data.insert(index, chunk);
+        self.renumber_frames(snapshot)
+    }
+
+    /// Replaces the first chunk of the same type in place, or appends the chunk if there
+    /// is none
+    pub fn set_chunk(&mut self, chunk: Chunk) -> Result<(), Error> {
+        match self
+            .data
+            .iter()
+            .position(|existing| existing.chunk_type() == chunk.chunk_type())
+        {
+            Some(index) => {
+                self.splice_chunks(index..index + 1, vec![chunk])?;
+                Ok(())
+            }
+            None => self.append_chunk(chunk),
+        }
+    }
+
+    /// Removes the first chunk of a type, refusing if that would break the ordering rules
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
+        let index = match self
+            .data
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+        {
+            Some(index) => index,
+            None => return Err(PngError::NonExistentChunk.into()),
+        };
+
+        let types = self.chunk_types();
+        let mut candidate = types.clone();
+        candidate.remove(index);
+
+        let existing = ordering::type_violations(&types);
+        if let Some(violation) = ordering::type_violations(&candidate)
+            .into_iter()
+            .find(|violation| !existing.contains(violation))
+        {
+            return Err(violation.into());
+        }
 
+        let snapshot = self.frame_snapshot(&self.data[index..=index]);
+        let removed = self.data.remove(index);
+        self.renumber_frames(snapshot)?;
 
+        Ok(removed)
+    }
 
-        for (index, chunk) in self.data.iter().enumerate() {
-            if chunk.chunk_type().to_string() == chunk_type {
-                // println!("{}\t{}", chunk.chunk_type().to_string(), chunk_type);
+    /// Replaces a range of chunks with new ones, refusing if the result breaks the ordering
+    /// rules. Returns the replaced chunks.
+    pub fn splice_chunks(
+        &mut self,
+        range: std::ops::Range<usize>,
+        chunks: Vec<Chunk>,
+    ) -> Result<Vec<Chunk>, Error> {
+        if range.start > range.end || range.end > self.data.len() {
+            return Err(PngError::IndexOutOfBounds.into());
+        }
+
+        let types = self.chunk_types();
+        let mut candidate = types.clone();
+        candidate.splice(
+            range.clone(),
+            chunks.iter().map(|chunk| chunk.chunk_type().bytes()),
+        );
+
+        let existing = ordering::type_violations(&types);
+        if let Some(violation) = ordering::type_violations(&candidate)
+            .into_iter()
+            .find(|violation| !existing.contains(violation))
+        {
+            return Err(violation.into());
+        }
+
+        let mut snapshot = self.frame_snapshot(&chunks);
+        if snapshot.is_none() {
+            snapshot = self.frame_snapshot(&self.data[range.clone()]);
+        }
+
+        let replaced = self.data.splice(range, chunks).collect();
+        self.renumber_frames(snapshot)?;
+
+        Ok(replaced)
+    }
 
-                let removed = self.data.remove(index);
-                return Ok(removed);
+    /// Swaps the chunk at an index for another of the same type, which can't break the
+    /// ordering rules
+    pub(crate) fn replace_chunk(&mut self, index: usize, chunk: Chunk) {
+        debug_assert!(self.data[index].chunk_type() == chunk.chunk_type());
+        self.data[index] = chunk;
+    }
+
+    /// A copy of the chunks to go back to if `chunks` are frame chunks of an animation,
+    /// whose sequence numbers have to be renumbered once they are added or removed
+    fn frame_snapshot(&self, chunks: &[Chunk]) -> Option<Vec<Chunk>> {
+        let frame_chunks = chunks
+            .iter()
+            .any(|chunk| matches!(&chunk.chunk_type().bytes(), b"fcTL" | b"fdAT"));
+
+        match frame_chunks && apng::is_animated(self) {
+            true => Some(self.data.clone()),
+            false => None,
+        }
+    }
+
+    /// Renumbers the frame chunks after a change `frame_snapshot` took a copy for, the
+    /// change is undone if that fails
+    fn renumber_frames(&mut self, snapshot: Option<Vec<Chunk>>) -> Result<(), Error> {
+        if let Some(snapshot) = snapshot {
+            if let Err(error) = apng::renumber(self) {
+                self.data = snapshot;
+                return Err(error);
             }
+        }
+        Ok(())
+    }
 
-            if index == self.data.len() - 1 {
-                return Err(PngError::NonExistentChunk.into());
+    /// Replaces the image data (and optionally the palette) and applies the copy rules of
+    /// the PNG specification: unknown ancillary chunks that are unsafe to copy are dropped
+    /// unless their type is listed in `keep`. Returns the dropped chunks.
+    pub fn replace_image_data(
+        &mut self,
+        palette: Option<Chunk>,
+        image_data: Vec<Chunk>,
+        keep: &[ChunkType],
+    ) -> Result<Vec<Chunk>, Error> {
+        if self.chunk_by_type("IDAT").is_none() {
+            return Err(ordering::OrderingError::MissingIdat.into());
+        }
+
+        let mut palette = palette;
+        let mut image_data = Some(image_data);
+
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut dropped: Vec<Chunk> = Vec::new();
+
+        for chunk in self.data.drain(..) {
+            match &chunk.chunk_type().bytes() {
+                b"IDAT" => {
+                    // The new image data replaces the whole run of IDAT chunks
+                    if let Some(image_data) = image_data.take() {
+                        if let Some(palette) = palette.take() {
+                            chunks.push(palette);
+                        }
+                        chunks.extend(image_data);
+                    }
+                }
+                b"PLTE" => chunks.push(palette.take().unwrap_or(chunk)),
+                _ if chunk.chunk_type().survives_critical_edit(keep) => chunks.push(chunk),
+                _ => dropped.push(chunk),
             }
         }
 
-        Err(PngError::NonExistentChunk.into())
+        self.data = chunks;
+
+        Ok(dropped)
+    }
+
+    /// Checks the chunks against the ordering rules of the PNG specification
+    pub fn validate_order(&self) -> Result<(), Error> {
+        ordering::validate(&self.data)?;
+        Ok(())
+    }
+
+    fn chunk_types(&self) -> Vec<[u8; 4]> {
+        self.data
+            .iter()
+            .map(|chunk| chunk.chunk_type().bytes())
+            .collect()
+    }
+
+    /// Finds the violation that inserting a chunk type at an index would introduce
+    fn new_violation(&self, mut types: Vec<[u8; 4]>, index: usize, chunk_type: [u8; 4]) -> Error {
+        let existing = ordering::type_violations(&types);
+        types.insert(index, chunk_type);
+
+        match ordering::type_violations(&types)
+            .into_iter()
+            .find(|violation| !existing.contains(violation))
+        {
+            Some(violation) => violation.into(),
+            None => PngError::InvalidPlacement.into(),
+        }
     }
 
     pub fn header(&self) -> &[u8; 8] {
@@ -56,9 +279,45 @@ impl Png {
         None
     }
 
+    /// Reads the first chunk of a type as a typed chunk, `None` if there is none
+    pub fn typed_chunk<'a, T: TryFrom<&'a Chunk, Error = Error>>(
+        &'a self,
+        chunk_type: &str,
+    ) -> Result<Option<T>, Error> {
+        self.chunk_by_type(chunk_type).map(T::try_from).transpose()
+    }
+
+    /// Concatenates the data of every IDAT chunk into a single zlib stream
+    pub fn image_data(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .filter(|chunk| &chunk.chunk_type().bytes() == b"IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+
+    /// The bytes after IEND that aren't chunks
+    pub fn trailing_bytes(&self) -> &[u8] {
+        &self.trailing_bytes
+    }
+
+    pub fn set_trailing_bytes(&mut self, trailing_bytes: Vec<u8>) {
+        self.trailing_bytes = trailing_bytes;
+    }
+
+    /// Appends raw bytes after IEND. Without an IEND chunk the bytes would be read back as
+    /// a broken chunk, so the file must have one.
+    pub fn append_trailing_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.chunk_by_type("IEND").is_none() {
+            return Err(ordering::OrderingError::MissingIend.into());
+        }
+
+        self.trailing_bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut data: Vec<u8> = vec![137, 80, 78, 71, 13, 10, 26, 10];
-        
 
         for chunk in &self.data {
             data.extend_from_slice(&chunk.length.to_be_bytes());
@@ -68,10 +327,11 @@ impl Png {
                 data.push(*byte);
             }
 
-
             data.extend_from_slice(&chunk.crc.to_be_bytes());
         }
 
+        data.extend_from_slice(&self.trailing_bytes);
+
         data
     }
 }
@@ -80,36 +340,79 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Png::parse(value, false)?.0)
+    }
+}
+
+impl Png {
+    /// Reads a PNG file like `try_from`, but keeps chunks whose CRC doesn't match instead of
+    /// failing, so a damaged payload can still reach error correction. Returns the indices
+    /// of those chunks too.
+    pub fn try_from_lenient(value: &[u8]) -> Result<(Png, Vec<usize>), Error> {
+        Png::parse(value, true)
+    }
+
+    fn parse(value: &[u8], lenient: bool) -> Result<(Png, Vec<usize>), Error> {
         // Validate header
-        if value[0..8] != [137, 80, 78, 71, 13, 10, 26, 10] {
+        if value.len() < 8 || value[0..8] != [137, 80, 78, 71, 13, 10, 26, 10] {
             return Err("Invalid header.".into());
         }
 
-        // Create a cursor
-        let mut cursor = Cursor::new(value);
-
         // Skip over the header and to the chunks
-        cursor.set_position(8);
+        let mut position = 8;
+        let mut ended = false;
 
         let mut chunks: Vec<Chunk> = Vec::new();
-
-        loop {
-            let a = cursor.remaining_slice();
-
-            let chunk = Chunk::try_from(a);
+        let mut damaged = Vec::new();
+
+        while position < value.len() {
+            let remaining = &value[position..];
+
+            // Don't trust the length of something that might not be a chunk
+            let length = match remaining.get(0..4) {
+                Some(length) => u32::from_be_bytes(length.try_into()?) as usize,
+                None => 0,
+            };
+            let chunk = if remaining.len() < length + 12 {
+                Err(PngError::TruncatedChunk.into())
+            } else {
+                Chunk::try_from_lenient(remaining)
+            };
+
+            // A bad CRC after IEND is more likely trailing data than a damaged chunk
+            let chunk = match chunk {
+                Ok((chunk, valid)) if valid || (lenient && !ended) => {
+                    if !valid {
+                        damaged.push(chunks.len());
+                    }
+                    Ok(chunk)
+                }
+                Ok(_) => Err(ChunkError::InvalidCRC.into()),
+                Err(error) => Err(error),
+            };
 
             match chunk {
                 Ok(chunk) => {
-                    cursor.set_position(cursor.position() + chunk.length as u64 + 12);
+                    position += chunk.length as usize + 12;
+                    ended |= &chunk.chunk_type().bytes() == b"IEND";
                     chunks.push(chunk);
                 }
-                Err(_) => {
-                    break;
-                }
+
+                // Chunks older versions wrote after IEND are still read as chunks, whatever
+                // follows them is trailing data
+                Err(_) if ended => break,
+
+                Err(error) => return Err(PngError::InvalidChunk(position, error.to_string()).into()),
             }
         }
 
-        Ok(Png { data: chunks })
+        Ok((
+            Png {
+                data: chunks,
+                trailing_bytes: value[position..].to_vec(),
+            },
+            damaged,
+        ))
     }
 }
 
@@ -123,6 +426,21 @@ impl std::fmt::Display for Png {
 pub enum PngError {
     #[error("The chunk code does not exist.")]
     NonExistentChunk,
+
+    #[error("The chunk index is out of bounds.")]
+    IndexOutOfBounds,
+
+    #[error("The chunk cannot be placed anywhere without breaking the chunk order.")]
+    InvalidPlacement,
+
+    #[error("The chunk is cut off before its end.")]
+    TruncatedChunk,
+
+    #[error("There is no data after the IEND chunk.")]
+    NoTrailingData,
+
+    #[error("The chunk at byte {0} is invalid: {1}")]
+    InvalidChunk(usize, String),
 }
 
 #[cfg(test)]
@@ -130,16 +448,16 @@ mod png_tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
+    use crate::ordering::OrderingError;
     use std::convert::TryFrom;
+    use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -240,7 +558,7 @@ mod png_tests {
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
         let chunk = png.chunk_by_type("TeSt").unwrap();
         assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
@@ -249,12 +567,94 @@ mod png_tests {
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
         png.remove_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("RuSt", "Message").unwrap()).unwrap();
+
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "RuSt");
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+        assert!(png.validate_order().is_ok());
+    }
+
+    #[test]
+    fn test_append_chunk_before_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("cHRM", "chromaticities").unwrap()).unwrap();
+
+        let index = png.chunks().iter().position(|chunk| chunk.chunk_type().to_string() == "cHRM");
+        let idat = png.chunks().iter().position(|chunk| chunk.chunk_type().to_string() == "IDAT");
+        assert!(index < idat);
+        assert!(png.validate_order().is_ok());
+    }
+
+    #[test]
+    fn test_append_duplicate_chunk() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let error = png
+            .append_chunk(chunk_from_strings("gAMA", "gamma").unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OrderingError>(),
+            Some(OrderingError::Duplicate(chunk_type)) if chunk_type == "gAMA"
+        ));
+    }
+
+    #[test]
+    fn test_insert_chunk_after_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let end = png.chunks().len();
+        assert!(png.insert_chunk(end, chunk_from_strings("RuSt", "Message").unwrap()).is_err());
+        assert!(png.insert_chunk(end - 1, chunk_from_strings("RuSt", "Message").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_remove_critical_chunk() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.remove_chunk("IDAT").is_err());
+        assert!(png.remove_chunk("IHDR").is_err());
+        assert!(png.remove_chunk("gAMA").is_ok());
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("ruSt", "safe").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("ruST", "unsafe").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("peRS", "kept").unwrap()).unwrap();
+
+        let image_data = vec![
+            chunk_from_strings("IDAT", "first").unwrap(),
+            chunk_from_strings("IDAT", "second").unwrap(),
+        ];
+        let keep = [ChunkType::from_str("peRS").unwrap()];
+
+        let dropped = png.replace_image_data(None, image_data, &keep).unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(&dropped[0].chunk_type().to_string(), "ruST");
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "IDAT", "RuSt", "ruSt", "peRS", "IEND"
+            ]
+        );
+        assert!(png.validate_order().is_ok());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -265,10 +665,101 @@ mod png_tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_trailing_bytes_round_trip() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"after the end");
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.trailing_bytes(), b"after the end");
+        assert_eq!(&chunks_types(&png)[..], &chunks_types(&Png::try_from(&PNG_FILE[..]).unwrap())[..]);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(chunk_from_strings("RuSt", "old payload").unwrap().as_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 200, 1, 2]);
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(chunks_types(&png).last().unwrap(), "RuSt");
+        assert_eq!(png.trailing_bytes(), &[0, 0, 0, 200, 1, 2]);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_invalid_chunk_before_iend() {
+        let mut bytes = PNG_FILE[..PNG_FILE.len() - 12].to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 4, 1, 2]);
+
+        assert!(Png::try_from(&bytes[..]).is_err());
+        assert!(Png::try_from(&PNG_FILE[..4]).is_err());
+    }
+
+    #[test]
+    fn test_lenient_keeps_damaged_chunks() {
+        let payload = b"survives a flipped byte";
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let encoded = crate::ecc::encode(payload, 8).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), encoded.clone()))
+            .unwrap();
+
+        // Damage the payload and leave the original CRC in place
+        let mut bytes = png.as_bytes();
+        let start = bytes
+            .windows(encoded.len())
+            .position(|window| window == &encoded[..])
+            .unwrap();
+        bytes[start + 3] ^= 0xFF;
+
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let (damaged, indices) = Png::try_from_lenient(&bytes[..]).unwrap();
+        let index = damaged
+            .chunks()
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == "ruSt")
+            .unwrap();
+        assert_eq!(indices, vec![index]);
+
+        let (decoded, corrected) = crate::ecc::decode(damaged.chunks()[index].data()).unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(corrected, 1);
+
+        // Damage after IEND is still trailing data
+        let mut bytes = PNG_FILE.to_vec();
+        let mut chunk = chunk_from_strings("RuSt", "old payload").unwrap().as_bytes();
+        chunk[9] ^= 0xFF;
+        bytes.extend(&chunk);
+        let (png, indices) = Png::try_from_lenient(&bytes[..]).unwrap();
+        assert!(indices.is_empty());
+        assert_eq!(png.trailing_bytes(), &chunk[..]);
+    }
+
+    #[test]
+    fn test_append_trailing_bytes() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_trailing_bytes(b"first").unwrap();
+        png.append_trailing_bytes(b" second").unwrap();
+
+        let parsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(parsed.trailing_bytes(), b"first second");
+
+        assert!(testing_png().append_trailing_bytes(b"no IEND").is_err());
+    }
+
+    fn chunks_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
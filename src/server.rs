@@ -0,0 +1,1218 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::{mpsc, Mutex},
+};
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::{
+    apng,
+    camouflage::Carrier,
+    color::ColorState,
+    decoder, deniable, emit,
+    ihdr::ImageHeader,
+    ordering,
+    output::{self, Captured},
+    palette,
+    payload::{self, Method, Payload, PrepareOptions, RevealOptions, Target},
+    png::Png,
+    Error,
+};
+
+// A local HTTP API over the same commands as the command line, and `scan` and `sanitize`.
+// A fixed number of workers answer the requests, the others wait in the server until one
+// is free. Everything happens in memory: the uploaded image is parsed, the library does the
+// work and the response is a JSON document like the one `--format json` prints, or the
+// changed image for encode, remove and sanitize.
+//
+// The image is the raw request body, with the options in the query string, or the `image`
+// part of a multipart/form-data body, whose other parts are options as well. URLs end up
+// in logs, so a passphrase comes in the `X-Passphrase` header or a `passphrase` part, and
+// extra payloads under passphrases of their own in `also` parts, as `PASSPHRASE=PAYLOAD`.
+//
+// Uploads can't be trusted: images whose data inflates to more than `max_inflated` bytes,
+// and payloads larger than that, are refused before anything is inflated.
+
+pub const DEFAULT_MAX_BODY: usize = 16 * 1024 * 1024;
+
+pub const DEFAULT_MAX_INFLATED: usize = 64 * 1024 * 1024;
+
+pub const DEFAULT_WORKERS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServeOptions {
+    /// The largest request body accepted, in bytes
+    pub max_body: usize,
+    /// The most bytes the image data of an upload, or a payload, may inflate to
+    pub max_inflated: usize,
+    /// The number of requests handled at the same time
+    pub workers: usize,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            max_body: DEFAULT_MAX_BODY,
+            max_inflated: DEFAULT_MAX_INFLATED,
+            workers: DEFAULT_WORKERS,
+        }
+    }
+}
+
+/// The commands served, each at `POST /<name>`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Command {
+    Inspect,
+    Encode,
+    Decode,
+    Remove,
+    Scan,
+    Sanitize,
+}
+
+/// What a request sent: the image, an optional binary payload and the options
+#[derive(Debug, Default, PartialEq)]
+struct Upload {
+    image: Vec<u8>,
+    /// A payload part, with the file name it was sent with
+    payload: Option<(Option<String>, Vec<u8>)>,
+    passphrase: Option<String>,
+    /// Extra payloads for other slots, as `PASSPHRASE=PAYLOAD`
+    also: Vec<String>,
+    params: HashMap<String, String>,
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+/// Listens on an address until the process is stopped
+pub fn serve(bind: &str, options: &ServeOptions) -> Result<(), Error> {
+    let server = match Server::http(bind) {
+        Ok(server) => server,
+        Err(error) => return Err(ServerError::Bind(bind.to_string(), error.to_string()).into()),
+    };
+
+    emit!(
+        "Listening on http://{} with {} workers",
+        server.server_addr(),
+        options.workers
+    );
+    run(&server, options);
+
+    Ok(())
+}
+
+/// Answers requests on `options.workers` threads until the server is unblocked
+pub fn run(server: &Server, options: &ServeOptions) {
+    // A request is only handed over once a worker takes it
+    let (sender, receiver) = mpsc::sync_channel::<Request>(0);
+    let receiver = Mutex::new(receiver);
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.workers.max(1) {
+            scope.spawn(|| loop {
+                let request = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => return,
+                };
+                match request {
+                    Ok(request) => handle(request, options),
+                    Err(_) => return,
+                }
+            });
+        }
+
+        for request in server.incoming_requests() {
+            if sender.send(request).is_err() {
+                break;
+            }
+        }
+        drop(sender);
+    });
+}
+
+fn handle(mut request: Request, options: &ServeOptions) {
+    // A bug that panics on some upload fails that request, not the worker
+    let reply = std::panic::catch_unwind(AssertUnwindSafe(|| respond(&mut request, options)));
+
+    let reply = match reply {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(error)) => {
+            let status = match error.downcast_ref::<ServerError>() {
+                Some(error) => error.status(),
+                None => 400,
+            };
+            let document = json!({ "ok": false, "error": output::error_json(error.as_ref()) });
+            Reply::json(status, &document)
+        }
+        Err(_) => {
+            let error = ServerError::Internal;
+            let document = json!({ "ok": false, "error": output::error_json(&error) });
+            Reply::json(error.status(), &document)
+        }
+    };
+
+    let response = Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(header("Content-Type", reply.content_type));
+    let _ = request.respond(response);
+}
+
+fn respond(request: &mut Request, options: &ServeOptions) -> Result<Reply, Error> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    let command = match path.trim_end_matches('/') {
+        "/inspect" => Command::Inspect,
+        "/encode" => Command::Encode,
+        "/decode" => Command::Decode,
+        "/remove" => Command::Remove,
+        "/scan" => Command::Scan,
+        "/sanitize" => Command::Sanitize,
+        _ => return Err(ServerError::NotFound(path.to_string()).into()),
+    };
+
+    if *request.method() != tiny_http::Method::Post {
+        return Err(ServerError::MethodNotAllowed(request.method().to_string()).into());
+    }
+
+    let body = read_body(request, options.max_body)?;
+    let content_type = header_value(request, "Content-Type").unwrap_or_default();
+
+    let mut upload = Upload::parse(&body, &content_type, query)?;
+    if upload.passphrase.is_none() {
+        upload.passphrase = header_value(request, "X-Passphrase");
+    }
+    run_command(command, &upload, options)
+}
+
+/// Reads the whole body, as long as it stays within the limit
+fn read_body(request: &mut Request, max_body: usize) -> Result<Vec<u8>, Error> {
+    if request
+        .body_length()
+        .is_some_and(|length| length > max_body)
+    {
+        return Err(ServerError::TooLarge(max_body).into());
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(max_body as u64 + 1)
+        .read_to_end(&mut body)?;
+
+    if body.len() > max_body {
+        return Err(ServerError::TooLarge(max_body).into());
+    }
+    Ok(body)
+}
+
+/// Runs a command on the uploaded image. Bad requests are errors of their own, a command
+/// that fails answers with its document.
+fn run_command(command: Command, upload: &Upload, options: &ServeOptions) -> Result<Reply, Error> {
+    let target = Target {
+        method: upload.value::<Method>("method")?.unwrap_or(Method::Chunk),
+        chunk_type: upload.params.get("chunk_type").map(String::as_str),
+        carrier: upload
+            .value::<Carrier>("carrier")?
+            .unwrap_or(Carrier::Comment),
+        frame: upload.number("frame")?,
+    };
+    if target.frame.is_some() && target.method != Method::DeflateSlack {
+        return Err(
+            ServerError::BadParameter("frame", "only with deflate-slack".to_string()).into(),
+        );
+    }
+
+    let mut data = Map::new();
+    let result = match command {
+        Command::Inspect => inspect(upload, options, &mut data).map(|_| None),
+        Command::Encode => encode(upload, &target, options).map(Some),
+        Command::Decode => decode(upload, &target, options, &mut data).map(|_| None),
+        Command::Remove => remove(upload, &target, options).map(Some),
+        Command::Scan => scan(upload, options, &mut data).map(|_| None),
+        Command::Sanitize => sanitize(upload, options).map(Some),
+    };
+
+    let name = format!("{:?}", command).to_lowercase();
+    let captured = Captured {
+        text: String::new(),
+        data,
+    };
+    match result {
+        Ok(Some(png)) => Ok(Reply {
+            status: 200,
+            content_type: "image/png",
+            body: png.as_bytes(),
+        }),
+        Ok(None) => Ok(Reply::json(
+            200,
+            &output::document(&name, &Ok(()), captured),
+        )),
+        Err(error) if error.is::<ServerError>() => Err(error),
+        Err(error) => Ok(Reply::json(
+            422,
+            &output::document(&name, &Err(error), captured),
+        )),
+    }
+}
+
+/// Lists the chunks, the order problems and the data after IEND
+fn inspect(
+    upload: &Upload,
+    options: &ServeOptions,
+    data: &mut Map<String, Value>,
+) -> Result<(), Error> {
+    let png = read_png(&upload.image, options)?;
+
+    if let Ok(header) = ImageHeader::from_png(&png) {
+        data.insert(
+            "header".to_string(),
+            json!({
+                "width": header.width,
+                "height": header.height,
+                "color_type": format!("{:?}", header.color_type),
+                "bit_depth": header.bit_depth,
+                "interlaced": header.interlaced,
+            }),
+        );
+    }
+
+    let mut after_iend = false;
+    let mut chunks = Vec::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        chunks.push(json!({
+            "index": index,
+            "type": chunk_type.to_string(),
+            "length": chunk.length(),
+            "crc": chunk.crc(),
+            "critical": chunk_type.is_critical(),
+            "safe_to_copy": chunk_type.is_safe_to_copy(),
+            "standard": chunk_type.is_standard(),
+            "after_iend": after_iend,
+        }));
+        after_iend |= &chunk_type.bytes() == b"IEND";
+    }
+    data.insert("chunks".to_string(), json!(chunks));
+
+    let mut warnings: Vec<String> = ordering::violations(png.chunks())
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect();
+    if let Err(error) = ColorState::from_png(&png) {
+        warnings.push(error.to_string());
+    }
+    if let Err(error) = palette::validate(&png) {
+        warnings.push(error.to_string());
+    }
+    data.insert("warnings".to_string(), json!(warnings));
+    data.insert(
+        "trailing_bytes".to_string(),
+        json!(png.trailing_bytes().len()),
+    );
+
+    Ok(())
+}
+
+/// Hides the payload with the method asked for
+fn encode(upload: &Upload, target: &Target, options: &ServeOptions) -> Result<Png, Error> {
+    let png = read_png(&upload.image, options)?;
+    if target.method == Method::Chunk {
+        require_chunk_type(target)?;
+    }
+
+    let prepared = upload.prepare_payload(target.method.compact())?;
+    Ok(payload::hide(&png, target, &prepared, &[])?.png)
+}
+
+/// Finds the payload hidden with the method asked for and opens it
+fn decode(
+    upload: &Upload,
+    target: &Target,
+    options: &ServeOptions,
+    data: &mut Map<String, Value>,
+) -> Result<(), Error> {
+    // Chunks with a bad CRC are kept, error correction may still repair them
+    let (png, damaged) = Png::try_from_lenient(&upload.image)?;
+    check_size(&png, options.max_inflated)?;
+    if !damaged.is_empty() {
+        let types: Vec<String> = damaged
+            .iter()
+            .map(|&index| png.chunks()[index].chunk_type().to_string())
+            .collect();
+        data.insert("damaged_chunks".to_string(), json!(types));
+    }
+    if target.method == Method::Chunk {
+        require_chunk_type(target)?;
+    }
+
+    let found = payload::find(&png, &damaged, target, upload.flag("ecc"));
+    data.insert("found".to_string(), json!(found.is_ok()));
+
+    let reveal_options = RevealOptions {
+        ecc: upload.flag("ecc"),
+        raw: upload.flag("raw"),
+        passphrase: upload.passphrase.clone(),
+        limit: options.max_inflated,
+    };
+    let revealed = match payload::reveal(&found?, target.method, &reveal_options) {
+        Ok(revealed) => revealed,
+        Err(error) => {
+            // The palette order or the slack of any image reads as some bytes
+            if payload::is_no_payload(error.as_ref()) {
+                data.insert("found".to_string(), json!(false));
+            }
+            return Err(error);
+        }
+    };
+
+    if let Some(metadata) = &revealed.metadata {
+        data.insert("content_type".to_string(), json!(metadata.content_type));
+        data.insert("file_name".to_string(), json!(metadata.file_name));
+        data.insert(
+            "created".to_string(),
+            json!((!revealed.compact).then_some(metadata.created)),
+        );
+    }
+    if let Some(corrected) = revealed.corrected {
+        data.insert("corrected".to_string(), json!(corrected));
+    }
+    data.insert("size".to_string(), json!(revealed.payload.len()));
+    if revealed.is_text() {
+        data.insert(
+            "message".to_string(),
+            json!(String::from_utf8_lossy(&revealed.payload)),
+        );
+    }
+    Ok(())
+}
+
+fn remove(upload: &Upload, target: &Target, options: &ServeOptions) -> Result<Png, Error> {
+    let mut png = read_png(&upload.image, options)?;
+    png.remove_chunk(require_chunk_type(target)?)?;
+    Ok(png)
+}
+
+/// Looks for payloads hidden with every method, see `payload::scan`
+fn scan(
+    upload: &Upload,
+    options: &ServeOptions,
+    data: &mut Map<String, Value>,
+) -> Result<(), Error> {
+    let png = read_png(&upload.image, options)?;
+    let findings = payload::scan(&png);
+
+    data.insert("suspicious".to_string(), json!(!findings.is_empty()));
+    data.insert("findings".to_string(), json!(findings));
+    Ok(())
+}
+
+/// Removes whatever could hold a payload, see `payload::sanitize`
+fn sanitize(upload: &Upload, options: &ServeOptions) -> Result<Png, Error> {
+    payload::sanitize(&read_png(&upload.image, options)?)
+}
+
+/// Parses an uploaded image that isn't too large to work on
+fn read_png(image: &[u8], options: &ServeOptions) -> Result<Png, Error> {
+    let png = Png::try_from(image)?;
+    check_size(&png, options.max_inflated)?;
+    Ok(png)
+}
+
+/// Refuses images whose data, or that of a frame, inflates to more than `limit` bytes
+fn check_size(png: &Png, limit: usize) -> Result<(), Error> {
+    // Without a header nothing gets inflated
+    let header = match ImageHeader::from_png(png) {
+        Ok(header) => header,
+        Err(_) => return Ok(()),
+    };
+
+    let mut sizes = vec![(header.width, header.height)];
+    if let Ok(frames) = apng::frames(png) {
+        sizes.extend(
+            frames
+                .iter()
+                .map(|frame| (frame.control.width, frame.control.height)),
+        );
+    }
+
+    for (width, height) in sizes {
+        let frame = ImageHeader {
+            width,
+            height,
+            ..header
+        };
+        if decoder::filtered_size(&frame).map_or(true, |size| size > limit) {
+            return Err(ServerError::ImageTooLarge(limit).into());
+        }
+    }
+    Ok(())
+}
+
+/// The chunk type of the chunk method and of remove, a request without one is a bad one
+fn require_chunk_type<'a>(target: &Target<'a>) -> Result<&'a str, Error> {
+    match target.chunk_type {
+        Some(chunk_type) => Ok(chunk_type),
+        None => Err(ServerError::MissingParameter("chunk_type").into()),
+    }
+}
+
+impl Upload {
+    /// Reads a raw or multipart body, along with the options in the query string
+    fn parse(body: &[u8], content_type: &str, query: &str) -> Result<Upload, Error> {
+        let mut upload = Upload {
+            params: query_params(query),
+            ..Default::default()
+        };
+        if upload.params.contains_key("passphrase") || upload.params.contains_key("also") {
+            return Err(ServerError::PassphraseInQuery.into());
+        }
+
+        match multipart_boundary(content_type) {
+            Some(boundary) => {
+                for part in multipart(body, &boundary)? {
+                    match &part.name[..] {
+                        "image" => upload.image = part.data,
+                        "payload" if part.file_name.is_some() => {
+                            upload.payload = Some((part.file_name, part.data))
+                        }
+                        "passphrase" => {
+                            upload.passphrase =
+                                Some(String::from_utf8_lossy(&part.data).to_string())
+                        }
+                        "also" => upload
+                            .also
+                            .push(String::from_utf8_lossy(&part.data).to_string()),
+                        _ => {
+                            let value = String::from_utf8_lossy(&part.data).to_string();
+                            upload.params.insert(part.name, value);
+                        }
+                    }
+                }
+            }
+            None => upload.image = body.to_vec(),
+        }
+
+        if upload.image.is_empty() {
+            return Err(ServerError::MissingParameter("image").into());
+        }
+        Ok(upload)
+    }
+
+    /// An option that names a value of an enum, like `method=deflate-slack`
+    fn value<T: ValueEnum>(&self, name: &'static str) -> Result<Option<T>, Error> {
+        match self.params.get(name) {
+            Some(value) => match T::from_str(value, true) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(ServerError::BadParameter(name, value.clone()).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// An option that is on when it is given, unless it is `false` or `0`
+    fn flag(&self, name: &str) -> bool {
+        self.params
+            .get(name)
+            .is_some_and(|value| value != "false" && value != "0")
+    }
+
+    fn number(&self, name: &'static str) -> Result<Option<usize>, Error> {
+        match self.params.get(name) {
+            Some(value) => match value.parse() {
+                Ok(number) => Ok(Some(number)),
+                Err(_) => Err(ServerError::BadParameter(name, value.clone()).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The bytes to hide, prepared the way `encode` prepares them. A payload part is
+    /// hidden like a file, with its name.
+    fn prepare_payload(&self, compact: bool) -> Result<Vec<u8>, Error> {
+        let payload = match (&self.payload, self.params.get("payload")) {
+            (Some((file_name, data)), _) => {
+                let file_name = file_name
+                    .as_deref()
+                    .and_then(|name| Path::new(name).file_name())
+                    .map_or("payload.bin".to_string(), |name| {
+                        name.to_string_lossy().to_string()
+                    });
+                Payload::file(&file_name, data.clone())
+            }
+            (None, Some(payload)) => Payload::text(payload),
+            (None, None) => return Err(ServerError::MissingParameter("payload").into()),
+        };
+
+        let also = self
+            .also
+            .iter()
+            .map(|also| {
+                let (passphrase, payload) = payload::split_also(also)?;
+                Ok((passphrase, Payload::text(payload)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let options = PrepareOptions {
+            parity: self.number("ecc")?,
+            compress: self.flag("compress"),
+            raw: self.flag("raw"),
+            compact,
+            slots: self.number("slots")?.unwrap_or(deniable::DEFAULT_SLOTS),
+        };
+        payload::prepare(&payload, self.passphrase.as_deref(), &also, &options)
+    }
+}
+
+impl Reply {
+    fn json(status: u16, document: &serde_json::Value) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: document.to_string().into_bytes(),
+        }
+    }
+}
+
+/// The value of a request header, like `X-Passphrase`
+fn header_value(request: &Request, field: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(field))
+        .map(|header| header.value.to_string())
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Header names are ASCII")
+}
+
+/// The options of a query string like `chunk_type=ruSt&raw`
+fn query_params(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes and `+` for spaces
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// A part of a multipart/form-data body
+#[derive(Debug, PartialEq)]
+struct Part {
+    name: String,
+    file_name: Option<String>,
+    data: Vec<u8>,
+}
+
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (mime, parameters) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameter(parameters, "boundary")
+}
+
+/// A parameter of a header value like `form-data; name="image"`
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').find_map(|parameter| {
+        let (key, value) = parameter.trim().split_once('=')?;
+        key.eq_ignore_ascii_case(name)
+            .then(|| value.trim_matches('"').to_string())
+    })
+}
+
+/// Splits a multipart/form-data body into its parts
+fn multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>, Error> {
+    let delimiter = format!("--{}", boundary);
+    let mut sections = split(body, delimiter.as_bytes()).into_iter();
+
+    // Whatever comes before the first delimiter is left out
+    sections.next();
+
+    let mut parts = Vec::new();
+    for section in sections {
+        if section.starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        let section = section
+            .strip_prefix(b"\r\n")
+            .and_then(|section| section.strip_suffix(b"\r\n"))
+            .ok_or(ServerError::BadMultipart)?;
+        let headers_end = find(section, b"\r\n\r\n").ok_or(ServerError::BadMultipart)?;
+        let headers =
+            std::str::from_utf8(&section[..headers_end]).map_err(|_| ServerError::BadMultipart)?;
+
+        let disposition = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(field, _)| field.trim().eq_ignore_ascii_case("Content-Disposition"))
+            .map(|(_, value)| value)
+            .ok_or(ServerError::BadMultipart)?;
+
+        parts.push(Part {
+            name: parameter(disposition, "name").ok_or(ServerError::BadMultipart)?,
+            file_name: parameter(disposition, "filename"),
+            data: section[headers_end + 4..].to_vec(),
+        });
+    }
+
+    // The closing delimiter is missing
+    Err(ServerError::BadMultipart.into())
+}
+
+fn split<'a>(bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut sections = Vec::new();
+    let mut rest = bytes;
+    while let Some(position) = find(rest, delimiter) {
+        sections.push(&rest[..position]);
+        rest = &rest[position + delimiter.len()..];
+    }
+    sections.push(rest);
+    sections
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ServerError {
+    #[error("Can't listen on '{0}': {1}")]
+    Bind(String, String),
+
+    #[error("There is nothing at '{0}'.")]
+    NotFound(String),
+
+    #[error("{0} is not allowed, use POST.")]
+    MethodNotAllowed(String),
+
+    #[error("The request body is larger than {0} bytes.")]
+    TooLarge(usize),
+
+    #[error("The '{0}' parameter is missing.")]
+    MissingParameter(&'static str),
+
+    #[error("'{1}' is not a valid value for '{0}'.")]
+    BadParameter(&'static str, String),
+
+    #[error("The multipart body is malformed.")]
+    BadMultipart,
+
+    #[error("The image data would inflate to more than {0} bytes.")]
+    ImageTooLarge(usize),
+
+    #[error("A passphrase doesn't belong in the URL, send it in the X-Passphrase header, or in an also part along with its payload.")]
+    PassphraseInQuery,
+
+    #[error("The request failed unexpectedly.")]
+    Internal,
+}
+
+impl ServerError {
+    /// The HTTP status code of the response
+    fn status(&self) -> u16 {
+        match self {
+            ServerError::Bind(..) | ServerError::Internal => 500,
+            ServerError::NotFound(_) => 404,
+            ServerError::MethodNotAllowed(_) => 405,
+            ServerError::TooLarge(_) | ServerError::ImageTooLarge(_) => 413,
+            ServerError::MissingParameter(_)
+            | ServerError::BadParameter(..)
+            | ServerError::BadMultipart
+            | ServerError::PassphraseInQuery => 400,
+        }
+    }
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_type::ChunkType,
+        encoder,
+        envelope::{self, Metadata, SealOptions},
+        ihdr::ColorType,
+        png::Png,
+    };
+    use serde_json::Value;
+    use std::{io::Write, net::TcpStream, str::FromStr, sync::Arc};
+
+    const BOUNDARY: &str = "sspngme-boundary";
+
+    fn testing_bytes() -> Vec<u8> {
+        include_bytes!("../sss.png").to_vec()
+    }
+
+    /// A server on a free port of localhost, stopped when the test is done
+    struct TestServer(Arc<Server>);
+
+    impl TestServer {
+        fn start(options: ServeOptions) -> TestServer {
+            let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+            let running = Arc::clone(&server);
+            std::thread::spawn(move || run(&running, &options));
+            TestServer(server)
+        }
+
+        /// Sends a request and returns the status and body of the response
+        fn request(
+            &self,
+            method: &str,
+            path: &str,
+            content_type: &str,
+            body: &[u8],
+        ) -> (u16, Vec<u8>) {
+            self.request_with(method, path, content_type, "", body)
+        }
+
+        /// Sends a request with more header lines, each ending in CRLF
+        fn request_with(
+            &self,
+            method: &str,
+            path: &str,
+            content_type: &str,
+            headers: &str,
+            body: &[u8],
+        ) -> (u16, Vec<u8>) {
+            let address = self.0.server_addr().to_ip().unwrap();
+            let mut stream = TcpStream::connect(address).unwrap();
+
+            write!(
+                stream,
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                method,
+                path,
+                content_type,
+                body.len(),
+                headers
+            )
+            .unwrap();
+            // A rejected body may not be read at all
+            let _ = stream.write_all(body);
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+
+            let headers_end = find(&response, b"\r\n\r\n").unwrap();
+            let status_line = String::from_utf8_lossy(&response[..headers_end]).to_string();
+            let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+            (status, response[headers_end + 4..].to_vec())
+        }
+
+        fn post(&self, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+            self.request("POST", path, "image/png", body)
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            self.0.unblock();
+        }
+    }
+
+    fn json(body: &[u8]) -> Value {
+        serde_json::from_slice(body).unwrap()
+    }
+
+    fn multipart_body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, file_name, data) in parts {
+            write!(
+                body,
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                BOUNDARY, name
+            )
+            .unwrap();
+            if let Some(file_name) = file_name {
+                write!(body, "; filename=\"{}\"", file_name).unwrap();
+            }
+            body.extend_from_slice(b"\r\n\r\n");
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        write!(body, "--{}--\r\n", BOUNDARY).unwrap();
+        body
+    }
+
+    #[test]
+    fn test_inspect() {
+        let server = TestServer::start(ServeOptions::default());
+
+        let (status, body) = server.post("/inspect", &testing_bytes());
+        assert_eq!(status, 200);
+
+        let document = json(&body);
+        assert_eq!(document["command"], "inspect");
+        assert_eq!(document["ok"], true);
+        let png = Png::try_from(&testing_bytes()[..]).unwrap();
+        assert_eq!(
+            document["data"]["chunks"].as_array().unwrap().len(),
+            png.chunks().len()
+        );
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let server = TestServer::start(ServeOptions::default());
+
+        let body = multipart_body(&[
+            ("image", Some("image.png"), &testing_bytes()),
+            ("chunk_type", None, b"ruSt"),
+            ("payload", None, b"hidden over http"),
+        ]);
+        let (status, encoded) = server.request(
+            "POST",
+            "/encode",
+            &format!("multipart/form-data; boundary={}", BOUNDARY),
+            &body,
+        );
+        assert_eq!(status, 200);
+        assert!(Png::try_from(&encoded[..])
+            .unwrap()
+            .chunk_by_type("ruSt")
+            .is_some());
+
+        let (status, body) = server.post("/decode?chunk_type=ruSt", &encoded);
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)["data"]["message"], "hidden over http");
+
+        let (status, removed) = server.post("/remove?chunk_type=ruSt", &encoded);
+        assert_eq!(status, 200);
+        assert!(Png::try_from(&removed[..])
+            .unwrap()
+            .chunk_by_type("ruSt")
+            .is_none());
+    }
+
+    #[test]
+    fn test_encode_file_payload() {
+        let server = TestServer::start(ServeOptions::default());
+
+        let body = multipart_body(&[
+            ("image", None, &testing_bytes()),
+            ("payload", Some("notes.txt"), b"from a file"),
+        ]);
+        let (status, encoded) = server.request(
+            "POST",
+            "/encode?method=trailer",
+            &format!("multipart/form-data; boundary={}", BOUNDARY),
+            &body,
+        );
+        assert_eq!(status, 200);
+
+        let (_, body) = server.post("/decode?method=trailer", &encoded);
+        let document = json(&body);
+        assert_eq!(document["data"]["file_name"], "notes.txt");
+        assert_eq!(document["data"]["message"], "from a file");
+    }
+
+    #[test]
+    fn test_errors() {
+        let server = TestServer::start(ServeOptions::default());
+
+        let (status, body) = server.post("/remove?chunk_type=zzZz", &testing_bytes());
+        assert_eq!(status, 422);
+        assert_eq!(json(&body)["error"]["code"], "PngError::NonExistentChunk");
+
+        let (status, _) = server.post("/decode?method=carrier-pigeon", b"x");
+        assert_eq!(status, 400);
+
+        let (status, _) = server.post("/steganalyze", b"x");
+        assert_eq!(status, 404);
+
+        let (status, _) = server.request("GET", "/inspect", "image/png", b"");
+        assert_eq!(status, 405);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let server = TestServer::start(ServeOptions {
+            max_body: 64,
+            ..Default::default()
+        });
+
+        let (status, body) = server.post("/inspect", &testing_bytes());
+        assert_eq!(status, 413);
+        assert_eq!(json(&body)["error"]["code"], "ServerError::TooLarge");
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let server = TestServer::start(ServeOptions::default());
+        let multipart_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+
+        let body = multipart_body(&[
+            ("image", None, &testing_bytes()),
+            ("payload", None, b"for one reader"),
+            ("passphrase", None, b"open sesame"),
+            ("also", None, b"other=for another reader"),
+            ("ecc", None, b"8"),
+        ]);
+        let (status, encoded) =
+            server.request("POST", "/encode?method=trailer", &multipart_type, &body);
+        assert_eq!(status, 200);
+
+        let (status, body) = server.request_with(
+            "POST",
+            "/decode?method=trailer&ecc",
+            "image/png",
+            "X-Passphrase: open sesame\r\n",
+            &encoded,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)["data"]["message"], "for one reader");
+
+        let (_, body) = server.request_with(
+            "POST",
+            "/decode?method=trailer&ecc",
+            "image/png",
+            "X-Passphrase: other\r\n",
+            &encoded,
+        );
+        assert_eq!(json(&body)["data"]["message"], "for another reader");
+
+        let (status, _) = server.request_with(
+            "POST",
+            "/decode?method=trailer&ecc",
+            "image/png",
+            "X-Passphrase: wrong\r\n",
+            &encoded,
+        );
+        assert_eq!(status, 422);
+
+        // Not in the URL, which ends up in logs
+        let (status, body) = server.post("/decode?method=trailer&passphrase=open+sesame", &encoded);
+        assert_eq!(status, 400);
+        assert_eq!(
+            json(&body)["error"]["code"],
+            "ServerError::PassphraseInQuery"
+        );
+        let (status, _) = server.post("/encode?method=trailer&also=other%3Dhi", &encoded);
+        assert_eq!(status, 400);
+    }
+
+    /// An RGBA image with the given image data
+    fn testing_image(width: u32, height: u32, image_data: Vec<u8>) -> Vec<u8> {
+        let header = ImageHeader {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), image_data);
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new());
+
+        Png::from_chunks(vec![header.to_chunk(), idat, iend]).as_bytes()
+    }
+
+    #[test]
+    fn test_hostile_uploads() {
+        let server = TestServer::start(ServeOptions {
+            max_inflated: 1 << 20,
+            workers: 1,
+            ..Default::default()
+        });
+
+        // A few kilobytes that inflate to 16 MiB, and a header far past any limit
+        let bomb = testing_image(
+            2048,
+            2048,
+            encoder::deflate(&vec![0; 2049 * 2048 * 4], 9).unwrap(),
+        );
+        assert!(bomb.len() < 64 * 1024);
+        let huge = testing_image(i32::MAX as u32, i32::MAX as u32, Vec::new());
+        for (path, image) in [
+            ("/decode?method=deflate-slack", &bomb),
+            ("/encode?method=palette&payload=x", &bomb),
+            ("/sanitize", &bomb),
+            ("/scan", &huge),
+        ] {
+            let (status, body) = server.post(path, image);
+            assert_eq!(status, 413);
+            assert_eq!(json(&body)["error"]["code"], "ServerError::ImageTooLarge");
+        }
+
+        // An envelope that inflates past the limit
+        let options = SealOptions {
+            compress: true,
+            parity: None,
+            compact: false,
+        };
+        let sealed = envelope::seal(
+            &vec![0; 2 << 20],
+            &Metadata::new("text/plain", None),
+            &options,
+        )
+        .unwrap();
+        let mut png = Png::try_from(&testing_bytes()[..]).unwrap();
+        png.append_trailing_bytes(&sealed).unwrap();
+        let (status, body) = server.post("/decode?method=trailer", &png.as_bytes());
+        assert_eq!(status, 422);
+        assert_eq!(json(&body)["error"]["code"], "EnvelopeError::TooLarge");
+
+        // Garbage doesn't take the worker down
+        for garbage in [&b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"[..], &[0xFF; 4096]] {
+            let (status, _) = server.post("/inspect", garbage);
+            assert_eq!(status, 422);
+        }
+        let (status, _) = server.post("/inspect", &testing_bytes());
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn test_workers() {
+        let server = Arc::new(TestServer::start(ServeOptions {
+            workers: 2,
+            ..Default::default()
+        }));
+
+        let clients: Vec<_> = (0..8)
+            .map(|_| {
+                let server = Arc::clone(&server);
+                std::thread::spawn(move || server.post("/inspect", &testing_bytes()).0)
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap(), 200);
+        }
+    }
+
+    #[test]
+    fn test_scan_and_sanitize() {
+        let server = TestServer::start(ServeOptions::default());
+
+        let (status, body) = server.post("/scan", &testing_bytes());
+        assert_eq!(status, 200);
+        let clean = json(&body)["data"]["findings"].as_array().unwrap().len();
+
+        let body = multipart_body(&[
+            ("image", None, &testing_bytes()),
+            ("payload", None, b"look here"),
+        ]);
+        let multipart_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        let (_, encoded) = server.request(
+            "POST",
+            "/encode?method=camouflage&carrier=xmp",
+            &multipart_type,
+            &body,
+        );
+        let (_, encoded) = server.request(
+            "POST",
+            "/encode?method=trailer",
+            &multipart_type,
+            &multipart_body(&[("image", None, &encoded), ("payload", None, b"and here")]),
+        );
+
+        let (status, body) = server.post("/scan", &encoded);
+        assert_eq!(status, 200);
+        let document = json(&body);
+        assert_eq!(document["data"]["suspicious"], true);
+        let findings = document["data"]["findings"].as_array().unwrap();
+        assert_eq!(findings.len(), clean + 2);
+        assert!(findings
+            .iter()
+            .any(|finding| finding["method"] == "trailer" && finding["envelope"] == true));
+        assert!(findings
+            .iter()
+            .any(|finding| finding["method"] == "camouflage"));
+
+        let (status, sanitized) = server.post("/sanitize", &encoded);
+        assert_eq!(status, 200);
+        let png = Png::try_from(&sanitized[..]).unwrap();
+        assert!(png.trailing_bytes().is_empty());
+        assert!(png
+            .chunks()
+            .iter()
+            .all(|chunk| chunk.chunk_type().is_standard()));
+
+        let (_, body) = server.post("/scan", &sanitized);
+        assert_eq!(json(&body)["data"]["suspicious"], false);
+        let (status, _) = server.post("/decode?method=camouflage&carrier=xmp", &sanitized);
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn test_multipart() {
+        let body = multipart_body(&[
+            ("image", Some("a.png"), b"\x89PNG\r\n--"),
+            ("raw", None, b"1"),
+        ]);
+        let parts = multipart(&body, BOUNDARY).unwrap();
+
+        assert_eq!(
+            parts,
+            vec![
+                Part {
+                    name: "image".to_string(),
+                    file_name: Some("a.png".to_string()),
+                    data: b"\x89PNG\r\n--".to_vec()
+                },
+                Part {
+                    name: "raw".to_string(),
+                    file_name: None,
+                    data: b"1".to_vec()
+                },
+            ]
+        );
+
+        assert!(multipart(&body[..body.len() - 8], BOUNDARY).is_err());
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=\"abc\""),
+            Some("abc".to_string())
+        );
+        assert_eq!(multipart_boundary("image/png"), None);
+    }
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params("payload=hello+there%21&raw&chunk_type=ruSt");
+        assert_eq!(params["payload"], "hello there!");
+        assert_eq!(params["raw"], "");
+        assert_eq!(params["chunk_type"], "ruSt");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::{Read, Write};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType};
+
+// Chunks read from and written to a stream one at a time, so a file never has to be held in
+// memory as a whole. The async versions in `async_stream` share the parsing below.
+//
+// The errors are a type of their own rather than `Error`, as they have to be `Send` to cross
+// threads in async services.
+
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Chunk lengths are limited to 2^31 - 1 bytes
+pub const MAX_LENGTH: u32 = i32::MAX as u32;
+
+/// Reads the chunks of a PNG file from a stream. Like `Png::try_from`, whole chunks with a
+/// matching CRC are still read after IEND, whatever follows them is left to
+/// `trailing_bytes`.
+pub struct ChunkReader<R> {
+    reader: R,
+    ended: bool,
+    /// What was read after IEND that isn't a chunk, the start of the trailing bytes
+    rest: Vec<u8>,
+}
+
+/// Writes a PNG file to a stream a chunk at a time
+pub struct ChunkWriter<W> {
+    writer: W,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads and checks the signature
+    pub fn new(mut reader: R) -> Result<ChunkReader<R>, StreamError> {
+        let mut signature = [0; 8];
+        reader.read_exact(&mut signature)?;
+        check_signature(&signature)?;
+
+        Ok(ChunkReader {
+            reader,
+            ended: false,
+            rest: Vec::new(),
+        })
+    }
+
+    /// The next chunk, or `None` once there are no more
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, StreamError> {
+        if self.ended {
+            return self.next_trailing_chunk();
+        }
+
+        let mut head = [0; 8];
+        self.reader.read_exact(&mut head).map_err(missing_iend)?;
+        let (length, chunk_type) = parse_head(&head)?;
+
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        if data.len() < length as usize {
+            return Err(StreamError::Truncated);
+        }
+
+        let mut crc = [0; 4];
+        self.reader.read_exact(&mut crc)?;
+
+        let chunk = finish_chunk(chunk_type, data, crc)?;
+        self.ended = &chunk.chunk_type().bytes() == b"IEND";
+        Ok(Some(chunk))
+    }
+
+    /// Reads whatever is left after the chunks
+    pub fn trailing_bytes(mut self) -> Result<Vec<u8>, StreamError> {
+        let mut trailing_bytes = self.rest;
+        self.reader.read_to_end(&mut trailing_bytes)?;
+        Ok(trailing_bytes)
+    }
+
+    /// A chunk after IEND. It is read into `rest` first, so it stays trailing data if it
+    /// turns out not to be one.
+    fn next_trailing_chunk(&mut self) -> Result<Option<Chunk>, StreamError> {
+        if !self.rest.is_empty() {
+            return Ok(None);
+        }
+
+        (&mut self.reader).take(8).read_to_end(&mut self.rest)?;
+        if let Some(length) = trailing_length(&self.rest) {
+            (&mut self.reader)
+                .take(length as u64 + 4)
+                .read_to_end(&mut self.rest)?;
+        }
+
+        let chunk = trailing_chunk(&self.rest);
+        if chunk.is_some() {
+            self.rest.clear();
+        }
+        Ok(chunk)
+    }
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Writes the signature
+    pub fn new(mut writer: W) -> Result<ChunkWriter<W>, StreamError> {
+        writer.write_all(&SIGNATURE)?;
+        Ok(ChunkWriter { writer })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), StreamError> {
+        self.writer.write_all(&chunk.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes bytes as they are, for data after IEND
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), StreamError> {
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Flushes the stream and gives it back
+    pub fn finish(mut self) -> Result<W, StreamError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub(crate) fn check_signature(signature: &[u8; 8]) -> Result<(), StreamError> {
+    match *signature == SIGNATURE {
+        true => Ok(()),
+        false => Err(StreamError::InvalidSignature),
+    }
+}
+
+/// The length and type at the start of a chunk
+pub(crate) fn parse_head(head: &[u8; 8]) -> Result<(u32, ChunkType), StreamError> {
+    let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+    if length > MAX_LENGTH {
+        return Err(StreamError::TooLong(length));
+    }
+
+    Ok((length, ChunkType::new([head[4], head[5], head[6], head[7]])))
+}
+
+/// A stream that runs out where the next chunk should start has no IEND
+pub(crate) fn missing_iend(error: std::io::Error) -> StreamError {
+    match error.kind() {
+        std::io::ErrorKind::UnexpectedEof => StreamError::MissingIend,
+        _ => StreamError::Io(error),
+    }
+}
+
+/// Builds the chunk and checks it against the CRC that was read
+pub(crate) fn finish_chunk(
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+    crc: [u8; 4],
+) -> Result<Chunk, StreamError> {
+    let chunk = Chunk::new(chunk_type, data);
+    if chunk.crc() != u32::from_be_bytes(crc) {
+        return Err(StreamError::InvalidCrc(chunk.chunk_type().to_string()));
+    }
+    Ok(chunk)
+}
+
+/// The data length of what may be a chunk after IEND, `None` if there isn't a whole head or
+/// the length is too long for a chunk
+pub(crate) fn trailing_length(head: &[u8]) -> Option<u32> {
+    let head: &[u8; 8] = head.try_into().ok()?;
+    parse_head(head).ok().map(|(length, _)| length)
+}
+
+/// The chunk that the bytes after IEND hold, as long as they are a whole chunk with a
+/// matching CRC
+pub(crate) fn trailing_chunk(bytes: &[u8]) -> Option<Chunk> {
+    match Chunk::try_from_lenient(bytes) {
+        Ok((chunk, true)) if chunk.length() as usize + 12 == bytes.len() => Some(chunk),
+        _ => None,
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StreamError {
+    #[error("The stream doesn't start with a PNG signature.")]
+    InvalidSignature,
+
+    #[error("A chunk length of {0} bytes is over the limit of 2^31 - 1.")]
+    TooLong(u32),
+
+    #[error("The stream ended in the middle of a chunk.")]
+    Truncated,
+
+    #[error("The CRC of the {0} chunk doesn't match its data.")]
+    InvalidCrc(String),
+
+    #[error("'{0}' is not a valid chunk type.")]
+    InvalidChunkType(String),
+
+    #[error("A {0} chunk can't be added at the end of the file.")]
+    Placement(String),
+
+    #[error("The stream ended before the IEND chunk.")]
+    MissingIend,
+
+    #[error("The payload envelope failed: {0}")]
+    Envelope(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::png::Png;
+
+    fn testing_bytes() -> Vec<u8> {
+        include_bytes!("../sss.png").to_vec()
+    }
+
+    #[test]
+    fn test_read_and_write() {
+        let bytes = testing_bytes();
+        let png = Png::try_from(&bytes[..]).unwrap();
+
+        let mut reader = ChunkReader::new(&bytes[..]).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+
+        let mut chunks = 0;
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            writer.write_chunk(&chunk).unwrap();
+            chunks += 1;
+        }
+        writer
+            .write_bytes(&reader.trailing_bytes().unwrap())
+            .unwrap();
+
+        // The chunks sss.png has after IEND are read as chunks, like `Png::try_from` does
+        assert_eq!(chunks, png.chunks().len());
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let mut bytes = testing_bytes();
+        let mut corrupted = Png::try_from(&bytes[..]).unwrap().chunks()[7].as_bytes();
+        corrupted[8] ^= 1;
+        bytes.extend_from_slice(&corrupted);
+        bytes.extend_from_slice(b"anything");
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        for end in [
+            bytes.len(),
+            bytes.len() - 8,
+            bytes.len() - 8 - corrupted.len() + 5,
+        ] {
+            let png = Png::try_from(&bytes[..end]).unwrap();
+            let mut reader = ChunkReader::new(&bytes[..end]).unwrap();
+
+            let mut chunks = Vec::new();
+            while let Some(chunk) = reader.next_chunk().unwrap() {
+                chunks.push(chunk.as_bytes());
+            }
+            let expected: Vec<Vec<u8>> = png.chunks().iter().map(Chunk::as_bytes).collect();
+            assert_eq!(chunks, expected);
+            assert_eq!(reader.trailing_bytes().unwrap(), png.trailing_bytes());
+        }
+        assert_eq!(png.trailing_bytes().len(), corrupted.len() + 8);
+    }
+
+    #[test]
+    fn test_invalid_streams() {
+        let bytes = testing_bytes();
+
+        assert!(matches!(
+            ChunkReader::new(&bytes[1..]),
+            Err(StreamError::InvalidSignature)
+        ));
+
+        let mut reader = ChunkReader::new(&bytes[..20]).unwrap();
+        assert!(reader.next_chunk().is_err());
+
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        let mut reader = ChunkReader::new(&corrupted[..]).unwrap();
+        assert!(matches!(
+            reader.next_chunk(),
+            Err(StreamError::InvalidCrc(chunk_type)) if chunk_type == "IHDR"
+        ));
+
+        let mut huge = SIGNATURE.to_vec();
+        huge.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        huge.extend_from_slice(b"IDAT");
+        let mut reader = ChunkReader::new(&huge[..]).unwrap();
+        assert!(matches!(reader.next_chunk(), Err(StreamError::TooLong(_))));
+    }
+}
@@ -0,0 +1,612 @@
+use crate::{chunk::Chunk, png::Png, Error};
+
+// XMP metadata lives in an iTXt chunk with the keyword "XML:com.adobe.xmp", as an XML packet:
+//
+//   <?xpacket begin="..." id="W5M0MpCehiHzreSzNTczkc9d"?>
+//   <x:xmpmeta xmlns:x="adobe:ns:meta/">
+//    <rdf:RDF xmlns:rdf="...">
+//     <rdf:Description rdf:about="" xmlns:dc="...">
+//      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">A title</rdf:li></rdf:Alt></dc:title>
+//      ...
+//
+// Properties are simple text (as elements or as attributes of rdf:Description), arrays
+// (rdf:Bag, rdf:Seq) or language alternatives (rdf:Alt). Anything more structured is kept
+// as the XML it was read as. Every rdf:Description is merged into one when written.
+
+pub const KEYWORD: &str = "XML:com.adobe.xmp";
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML: &str = "http://www.w3.org/XML/1998/namespace";
+
+const DUBLIN_CORE: &str = "http://purl.org/dc/elements/1.1/";
+
+const DEFAULT_LANGUAGE: &str = "x-default";
+
+/// The namespaces that can be used without declaring them
+const KNOWN_NAMESPACES: &[(&str, &str)] = &[
+    ("dc", DUBLIN_CORE),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmpRights", "http://ns.adobe.com/xap/1.0/rights/"),
+    ("xmpMM", "http://ns.adobe.com/xap/1.0/mm/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+];
+
+/// The Dublin Core properties that aren't simple text, and their array type
+const DUBLIN_CORE_ARRAYS: &[(&str, ArrayKind)] = &[
+    ("contributor", ArrayKind::Bag),
+    ("creator", ArrayKind::Seq),
+    ("date", ArrayKind::Seq),
+    ("description", ArrayKind::Alt),
+    ("language", ArrayKind::Bag),
+    ("publisher", ArrayKind::Bag),
+    ("relation", ArrayKind::Bag),
+    ("rights", ArrayKind::Alt),
+    ("subject", ArrayKind::Bag),
+    ("title", ArrayKind::Alt),
+    ("type", ArrayKind::Bag),
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ArrayKind {
+    Bag,
+    Seq,
+    Alt,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PropertyValue {
+    Text(String),
+    /// An unordered array
+    Bag(Vec<String>),
+    /// An ordered array
+    Seq(Vec<String>),
+    /// The same text in several languages, as language and text pairs
+    Alt(Vec<(String, String)>),
+    /// A structure we don't interpret, as the XML of the property element
+    Raw(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Property {
+    pub namespace: String,
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+/// The properties of an XMP packet
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Xmp {
+    /// Prefixes and namespace URIs, in the order they were seen
+    namespaces: Vec<(String, String)>,
+    properties: Vec<Property>,
+}
+
+impl std::fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyValue::Text(text) | PropertyValue::Raw(text) => write!(f, "{}", text),
+            PropertyValue::Bag(items) | PropertyValue::Seq(items) => {
+                write!(f, "{}", items.join("; "))
+            }
+            PropertyValue::Alt(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|(language, text)| match language.as_str() {
+                        DEFAULT_LANGUAGE => text.clone(),
+                        _ => format!("[{}] {}", language, text),
+                    })
+                    .collect();
+                write!(f, "{}", items.join("; "))
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Xmp {
+    pub fn new() -> Xmp {
+        Xmp::default()
+    }
+
+    /// Reads the XMP packet of a PNG file, `None` if there is none
+    pub fn from_png(png: &Png) -> Result<Option<Xmp>, Error> {
+        match packet(png)? {
+            Some(packet) => Ok(Some(Xmp::parse(&packet)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the XMP chunk of a PNG file, or adds one
+    pub fn write_to(&self, png: &mut Png) -> Result<(), Error> {
+        let chunk = self.to_chunk()?;
+
+        match find_chunk(png) {
+            Some(index) => {
+                png.splice_chunks(index..index + 1, vec![chunk])?;
+            }
+            None => png.append_chunk(chunk)?,
+        }
+
+        Ok(())
+    }
+
+    /// The packet in an uncompressed iTXt chunk, as readers of XMP expect it
+    pub fn to_chunk(&self) -> Result<Chunk, Error> {
+        Chunk::itxt(KEYWORD, &self.to_xml())
+    }
+
+    pub fn parse(packet: &str) -> Result<Xmp, Error> {
+        let packet = packet.trim_start_matches('\u{feff}');
+        let document = roxmltree::Document::parse(packet)?;
+
+        let mut xmp = Xmp::new();
+
+        for node in document.descendants().filter(|node| node.is_element()) {
+            for namespace in node.namespaces() {
+                if let Some(prefix) = namespace.name() {
+                    xmp.declare(prefix, namespace.uri());
+                }
+            }
+        }
+
+        let descriptions = document
+            .descendants()
+            .filter(|node| node.has_tag_name((RDF, "Description")));
+
+        for description in descriptions {
+            for attribute in description.attributes() {
+                match attribute.namespace() {
+                    Some(RDF) | Some(XML) | None => continue,
+                    Some(namespace) => xmp.properties.push(Property {
+                        namespace: namespace.to_string(),
+                        name: attribute.name().to_string(),
+                        value: PropertyValue::Text(attribute.value().to_string()),
+                    }),
+                }
+            }
+
+            for element in description.children().filter(|node| node.is_element()) {
+                let namespace = match element.tag_name().namespace() {
+                    Some(namespace) => namespace,
+                    None => return Err(XmpError::MissingNamespace.into()),
+                };
+
+                xmp.properties.push(Property {
+                    namespace: namespace.to_string(),
+                    name: element.tag_name().name().to_string(),
+                    value: read_value(element, packet),
+                });
+            }
+        }
+
+        if document
+            .descendants()
+            .all(|node| !node.has_tag_name((RDF, "RDF")))
+        {
+            return Err(XmpError::MissingRdf.into());
+        }
+
+        Ok(xmp)
+    }
+
+    /// Writes the properties as an XMP packet
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+             <rdf:Description rdf:about=\"\"",
+        );
+
+        for (prefix, uri) in &self.namespaces {
+            if !matches!(prefix.as_str(), "x" | "rdf" | "xml") {
+                xml.push_str(&format!("\n    xmlns:{}=\"{}\"", prefix, escape(uri)));
+            }
+        }
+        xml.push_str(">\n");
+
+        for property in &self.properties {
+            let key = format!("{}:{}", self.prefix(&property.namespace), property.name);
+
+            let items = |kind: &str, items: Vec<(Option<&str>, &str)>| {
+                let mut xml = format!("   <{}>\n    <rdf:{}>\n", key, kind);
+                for (language, text) in items {
+                    match language {
+                        Some(language) => xml.push_str(&format!(
+                            "     <rdf:li xml:lang=\"{}\">{}</rdf:li>\n",
+                            escape(language),
+                            escape(text)
+                        )),
+                        None => xml.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape(text))),
+                    }
+                }
+                xml.push_str(&format!("    </rdf:{}>\n   </{}>\n", kind, key));
+                xml
+            };
+
+            match &property.value {
+                PropertyValue::Text(text) => {
+                    xml.push_str(&format!("   <{}>{}</{}>\n", key, escape(text), key))
+                }
+                PropertyValue::Bag(values) => xml.push_str(&items(
+                    "Bag",
+                    values.iter().map(|value| (None, value.as_str())).collect(),
+                )),
+                PropertyValue::Seq(values) => xml.push_str(&items(
+                    "Seq",
+                    values.iter().map(|value| (None, value.as_str())).collect(),
+                )),
+                PropertyValue::Alt(values) => xml.push_str(&items(
+                    "Alt",
+                    values
+                        .iter()
+                        .map(|(language, text)| (Some(language.as_str()), text.as_str()))
+                        .collect(),
+                )),
+                PropertyValue::Raw(raw) => xml.push_str(&format!("   {}\n", raw)),
+            }
+        }
+
+        xml.push_str("  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>");
+        xml
+    }
+
+    pub fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+
+    /// The `prefix:name` key of a property
+    pub fn key(&self, property: &Property) -> String {
+        format!("{}:{}", self.prefix(&property.namespace), property.name)
+    }
+
+    /// Declares a namespace prefix, a prefix that is already declared keeps its URI
+    pub fn declare(&mut self, prefix: &str, uri: &str) {
+        if self.namespace(prefix).is_none() {
+            self.namespaces.push((prefix.to_string(), uri.to_string()));
+        }
+    }
+
+    /// The URI of a prefix, declared in the packet or well known
+    pub fn namespace(&self, prefix: &str) -> Option<&str> {
+        self.namespaces
+            .iter()
+            .find(|(declared, _)| declared == prefix)
+            .map(|(_, uri)| uri.as_str())
+    }
+
+    /// The prefix of a namespace, made up if it isn't declared
+    fn prefix(&self, uri: &str) -> String {
+        match self.namespaces.iter().find(|(_, declared)| declared == uri) {
+            Some((prefix, _)) => prefix.clone(),
+            None => "ns".to_string(),
+        }
+    }
+
+    /// Splits a `prefix:name` key and finds the namespace of the prefix
+    fn resolve(&self, key: &str) -> Result<(String, String), Error> {
+        let (prefix, name) = match key.split_once(':') {
+            Some((prefix, name)) if !prefix.is_empty() && !name.is_empty() => (prefix, name),
+            _ => return Err(XmpError::InvalidKey(key.to_string()).into()),
+        };
+
+        let namespace = self.namespace(prefix).or_else(|| {
+            KNOWN_NAMESPACES
+                .iter()
+                .find(|(known, _)| *known == prefix)
+                .map(|(_, uri)| *uri)
+        });
+
+        match namespace {
+            Some(namespace) => Ok((namespace.to_string(), name.to_string())),
+            None => Err(XmpError::UnknownPrefix(prefix.to_string()).into()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<&PropertyValue>, Error> {
+        let (namespace, name) = self.resolve(key)?;
+
+        Ok(self
+            .properties
+            .iter()
+            .find(|property| property.namespace == namespace && property.name == name)
+            .map(|property| &property.value))
+    }
+
+    /// Sets a property, replacing the value it had
+    pub fn set(&mut self, key: &str, value: PropertyValue) -> Result<(), Error> {
+        let (namespace, name) = self.resolve(key)?;
+        let prefix = key.split_once(':').map_or("", |(prefix, _)| prefix);
+        self.declare(prefix, &namespace);
+
+        match self
+            .properties
+            .iter_mut()
+            .find(|property| property.namespace == namespace && property.name == name)
+        {
+            Some(property) => property.value = value,
+            None => self.properties.push(Property {
+                namespace,
+                name,
+                value,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Sets a property from text, in the shape it has or the one Dublin Core gives it.
+    /// Array items are separated by semicolons, for language alternatives only the
+    /// default language is replaced.
+    pub fn set_text(&mut self, key: &str, text: &str) -> Result<(), Error> {
+        let (namespace, name) = self.resolve(key)?;
+
+        let kind = match self.get(key)? {
+            Some(PropertyValue::Bag(_)) => Some(ArrayKind::Bag),
+            Some(PropertyValue::Seq(_)) => Some(ArrayKind::Seq),
+            Some(PropertyValue::Alt(_)) => Some(ArrayKind::Alt),
+            Some(_) => None,
+            None if namespace == DUBLIN_CORE => DUBLIN_CORE_ARRAYS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|&(_, kind)| kind),
+            None => None,
+        };
+
+        let items = || {
+            text.split(';')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        };
+
+        let value = match kind {
+            None => PropertyValue::Text(text.to_string()),
+            Some(ArrayKind::Bag) => PropertyValue::Bag(items()),
+            Some(ArrayKind::Seq) => PropertyValue::Seq(items()),
+            Some(ArrayKind::Alt) => {
+                let mut alternatives = match self.get(key)? {
+                    Some(PropertyValue::Alt(alternatives)) => alternatives.clone(),
+                    _ => Vec::new(),
+                };
+                match alternatives
+                    .iter_mut()
+                    .find(|(language, _)| language == DEFAULT_LANGUAGE)
+                {
+                    Some((_, existing)) => *existing = text.to_string(),
+                    // The default goes first, readers that only look at one take it
+                    None => {
+                        alternatives.insert(0, (DEFAULT_LANGUAGE.to_string(), text.to_string()))
+                    }
+                }
+                PropertyValue::Alt(alternatives)
+            }
+        };
+
+        self.set(key, value)
+    }
+
+    /// Removes a property, returns whether it was there
+    pub fn remove(&mut self, key: &str) -> Result<bool, Error> {
+        let (namespace, name) = self.resolve(key)?;
+
+        let length = self.properties.len();
+        self.properties
+            .retain(|property| property.namespace != namespace || property.name != name);
+        Ok(self.properties.len() != length)
+    }
+}
+
+/// The XMP packet of a PNG file as it is stored, `None` if there is none
+pub fn packet(png: &Png) -> Result<Option<String>, Error> {
+    match find_chunk(png) {
+        Some(index) => {
+            let (_, text) = png.chunks()[index].text()?;
+            Ok(Some(String::from_utf8(text)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The index of the iTXt chunk holding XMP
+fn find_chunk(png: &Png) -> Option<usize> {
+    png.chunks().iter().position(|chunk| {
+        &chunk.chunk_type().bytes() == b"iTXt" && chunk.data().starts_with(KEYWORD.as_bytes())
+    })
+}
+
+/// Reads the value of a property element
+fn read_value(element: roxmltree::Node, packet: &str) -> PropertyValue {
+    let raw = || PropertyValue::Raw(packet[element.range()].to_string());
+
+    let children: Vec<roxmltree::Node> = element
+        .children()
+        .filter(|node| node.is_element())
+        .collect();
+    if element.attributes().len() > 0 && children.is_empty() {
+        return raw();
+    }
+
+    let array = match children.as_slice() {
+        [] => return PropertyValue::Text(element.text().unwrap_or("").to_string()),
+        [array] if array.tag_name().namespace() == Some(RDF) => array,
+        _ => return raw(),
+    };
+
+    let items: Vec<roxmltree::Node> = array.children().filter(|node| node.is_element()).collect();
+    let simple = items.iter().all(|item| {
+        item.has_tag_name((RDF, "li")) && item.children().all(|node| !node.is_element())
+    });
+    if !simple {
+        return raw();
+    }
+
+    let text = |item: &roxmltree::Node| item.text().unwrap_or("").to_string();
+
+    match array.tag_name().name() {
+        "Bag" => PropertyValue::Bag(items.iter().map(text).collect()),
+        "Seq" => PropertyValue::Seq(items.iter().map(text).collect()),
+        "Alt" => PropertyValue::Alt(
+            items
+                .iter()
+                .map(|item| {
+                    let language = item.attribute((XML, "lang")).unwrap_or(DEFAULT_LANGUAGE);
+                    (language.to_string(), text(item))
+                })
+                .collect(),
+        ),
+        _ => raw(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum XmpError {
+    #[error("The XMP packet has no rdf:RDF element.")]
+    MissingRdf,
+
+    #[error("An XMP property has no namespace.")]
+    MissingNamespace,
+
+    #[error("'{0}' is not a property key, it must look like prefix:name.")]
+    InvalidKey(String),
+
+    #[error("The prefix '{0}' has no namespace, give its URI with --namespace.")]
+    UnknownPrefix(String),
+}
+
+#[cfg(test)]
+mod xmp_tests {
+    use super::*;
+
+    const PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Some Toolkit">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmp:CreatorTool="Squirrel Paint &amp; Co">
+   <xmp:Rating>4</xmp:Rating>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:stEvt="http://ns.adobe.com/xap/1.0/sType/ResourceEvent#"
+    xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/">
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Acorns</rdf:li>
+     <rdf:li xml:lang="de">Eicheln</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+   <dc:subject><rdf:Bag><rdf:li>nuts</rdf:li><rdf:li>autumn</rdf:li></rdf:Bag></dc:subject>
+   <xmpMM:History><rdf:Seq><rdf:li rdf:parseType="Resource"><stEvt:action>saved</stEvt:action></rdf:li></rdf:Seq></xmpMM:History>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn test_parse() {
+        let xmp = Xmp::parse(PACKET).unwrap();
+
+        assert_eq!(
+            xmp.get("xmp:CreatorTool").unwrap(),
+            Some(&PropertyValue::Text("Squirrel Paint & Co".to_string()))
+        );
+        assert_eq!(
+            xmp.get("xmp:Rating").unwrap(),
+            Some(&PropertyValue::Text("4".to_string()))
+        );
+        assert_eq!(
+            xmp.get("dc:subject").unwrap().unwrap().to_string(),
+            "nuts; autumn"
+        );
+        assert_eq!(
+            xmp.get("dc:title").unwrap().unwrap().to_string(),
+            "Acorns; [de] Eicheln"
+        );
+        assert!(matches!(
+            xmp.get("xmpMM:History").unwrap(),
+            Some(PropertyValue::Raw(_))
+        ));
+        assert_eq!(xmp.get("dc:creator").unwrap(), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let xmp = Xmp::parse(PACKET).unwrap();
+        let reparsed = Xmp::parse(&xmp.to_xml()).unwrap();
+
+        assert_eq!(reparsed.properties(), xmp.properties());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut xmp = Xmp::parse(PACKET).unwrap();
+        xmp.set_text("dc:title", "Hazelnuts").unwrap();
+        xmp.set_text("dc:creator", "Squirrel; Chipmunk").unwrap();
+        xmp.set_text("xmp:Rating", "5").unwrap();
+        assert!(xmp.set_text("sspm:Data", "x").is_err());
+        xmp.declare("sspm", "http://ns.softsquirrel.net/sspngme/1.0/");
+        xmp.set_text("sspm:Data", "<x & y>").unwrap();
+        assert!(xmp.remove("dc:subject").unwrap());
+
+        let xmp = Xmp::parse(&xmp.to_xml()).unwrap();
+        assert_eq!(
+            xmp.get("dc:title").unwrap(),
+            Some(&PropertyValue::Alt(vec![
+                ("x-default".to_string(), "Hazelnuts".to_string()),
+                ("de".to_string(), "Eicheln".to_string())
+            ]))
+        );
+        assert_eq!(
+            xmp.get("dc:creator").unwrap(),
+            Some(&PropertyValue::Seq(vec![
+                "Squirrel".to_string(),
+                "Chipmunk".to_string()
+            ]))
+        );
+        assert_eq!(xmp.get("xmp:Rating").unwrap().unwrap().to_string(), "5");
+        assert_eq!(
+            xmp.get("sspm:Data").unwrap().unwrap().to_string(),
+            "<x & y>"
+        );
+        assert_eq!(xmp.get("dc:subject").unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_packets() {
+        assert!(Xmp::parse("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>").is_err());
+        assert!(Xmp::parse("not xml").is_err());
+        assert!(Xmp::new().get("title").is_err());
+    }
+
+    #[test]
+    fn test_png() {
+        let mut png = Png::try_from(&include_bytes!("../sss.png")[..]).unwrap();
+        assert_eq!(Xmp::from_png(&png).unwrap(), None);
+
+        let mut xmp = Xmp::new();
+        xmp.set_text("dc:title", "Acorns").unwrap();
+        xmp.write_to(&mut png).unwrap();
+        xmp.set_text("dc:title", "Hazelnuts").unwrap();
+        xmp.write_to(&mut png).unwrap();
+
+        let reread = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let chunks = reread
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.data().starts_with(KEYWORD.as_bytes()))
+            .count();
+        assert_eq!(chunks, 1);
+        assert_eq!(
+            Xmp::from_png(&reread).unwrap().unwrap().properties(),
+            xmp.properties()
+        );
+    }
+}
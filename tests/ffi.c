@@ -0,0 +1,107 @@
+/*
+ * Uses the C ABI the way a C program would. Built and run by tests/ffi.rs with the path of
+ * a PNG file as its argument, it prints what failed and exits non-zero on the first failure.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "sspngme.h"
+
+#define CHECK(condition)                                                                   \
+    do {                                                                                   \
+        if (!(condition)) {                                                                \
+            fprintf(stderr, "%s:%d: %s (last error: %s)\n", __FILE__, __LINE__, #condition, \
+                    sspngme_last_error_message());                                         \
+            exit(1);                                                                       \
+        }                                                                                  \
+    } while (0)
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    CHECK(file != NULL);
+
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+
+    uint8_t *data = malloc(*len);
+    CHECK(data != NULL);
+    CHECK(fread(data, 1, *len, file) == *len);
+    fclose(file);
+    return data;
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 2);
+
+    size_t len;
+    uint8_t *data = read_file(argv[1], &len);
+
+    SspngmePng *png = NULL;
+    CHECK(sspngme_png_parse(data, len, &png) == SSPNGME_OK);
+    free(data);
+
+    /* The chunks */
+    size_t count = 0;
+    CHECK(sspngme_png_chunk_count(png, &count) == SSPNGME_OK);
+    CHECK(count > 2);
+
+    char chunk_type[5];
+    CHECK(sspngme_png_chunk_type(png, 0, chunk_type) == SSPNGME_OK);
+    CHECK(strcmp(chunk_type, "IHDR") == 0);
+
+    const uint8_t *chunk_data = NULL;
+    size_t chunk_len = 0;
+    CHECK(sspngme_png_get_chunk(png, "IHDR", &chunk_data, &chunk_len) == SSPNGME_OK);
+    CHECK(chunk_len == 13);
+    CHECK(sspngme_png_chunk_data(png, count, &chunk_data, &chunk_len) ==
+          SSPNGME_ERROR_INDEX_OUT_OF_BOUNDS);
+
+    /* A payload through a serialized file */
+    const char *message = "hidden from C";
+    CHECK(sspngme_png_encode(png, "ruSt", (const uint8_t *)message, strlen(message),
+                             "text/plain") == SSPNGME_OK);
+
+    SspngmeBuffer serialized = {NULL, 0};
+    CHECK(sspngme_png_serialize(png, &serialized) == SSPNGME_OK);
+
+    SspngmePng *reparsed = NULL;
+    CHECK(sspngme_png_parse(serialized.data, serialized.len, &reparsed) == SSPNGME_OK);
+    sspngme_buffer_free(&serialized);
+    CHECK(serialized.data == NULL);
+
+    SspngmeBuffer payload = {NULL, 0};
+    CHECK(sspngme_png_decode(reparsed, "ruSt", &payload) == SSPNGME_OK);
+    CHECK(payload.len == strlen(message));
+    CHECK(memcmp(payload.data, message, payload.len) == 0);
+    sspngme_buffer_free(&payload);
+
+    /* Chunks added and removed by hand */
+    const uint8_t raw[] = {1, 2, 3};
+    CHECK(sspngme_png_add_chunk(reparsed, "raWw", raw, sizeof raw) == SSPNGME_OK);
+    CHECK(sspngme_png_get_chunk(reparsed, "raWw", &chunk_data, &chunk_len) == SSPNGME_OK);
+    CHECK(chunk_len == 3 && chunk_data[2] == 3);
+    CHECK(sspngme_png_remove_chunk(reparsed, "raWw") == SSPNGME_OK);
+
+    /* Errors come back as codes with a message */
+    CHECK(sspngme_png_remove_chunk(reparsed, "raWw") == SSPNGME_ERROR_NOT_FOUND);
+    CHECK(strlen(sspngme_last_error_message()) > 0);
+    CHECK(sspngme_png_add_chunk(reparsed, "ra1w", raw, sizeof raw) ==
+          SSPNGME_ERROR_INVALID_CHUNK_TYPE);
+    CHECK(sspngme_png_add_chunk(reparsed, "IHDR", raw, sizeof raw) == SSPNGME_ERROR_ORDERING);
+    CHECK(sspngme_png_chunk_count(NULL, &count) == SSPNGME_ERROR_NULL_POINTER);
+
+    SspngmePng *invalid = NULL;
+    CHECK(sspngme_png_parse(raw, sizeof raw, &invalid) == SSPNGME_ERROR_INVALID_PNG);
+    CHECK(invalid == NULL);
+
+    sspngme_png_free(reparsed);
+    sspngme_png_free(png);
+    sspngme_png_free(NULL);
+    sspngme_buffer_free(NULL);
+
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "cdylib")]
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// The C ABI as a C program sees it: the shared library is built with the cdylib feature,
+// tests/ffi.c is compiled against include/sspngme.h, linked against the library and run on
+// sss.png.
+
+fn manifest_directory() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Builds the shared library the way the README says to and returns its directory. It gets
+/// a target directory of its own, the one of this test is locked while the test runs.
+fn build_library() -> PathBuf {
+    let target_directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cdylib");
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "cdylib",
+            "--crate-type",
+            "cdylib",
+        ])
+        .arg("--manifest-path")
+        .arg(manifest_directory().join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_directory)
+        .status()
+        .unwrap();
+    assert!(status.success(), "the shared library doesn't build");
+
+    target_directory.join("debug")
+}
+
+#[test]
+fn test_c_program() {
+    let library_directory = build_library();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sspngme_ffi_test");
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(compiler)
+        .arg(manifest_directory().join("tests/ffi.c"))
+        .arg("-I")
+        .arg(manifest_directory().join("include"))
+        .arg("-L")
+        .arg(&library_directory)
+        .arg(format!("-Wl,-rpath,{}", library_directory.display()))
+        .args(["-lsspngme", "-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .status()
+        .expect("A C compiler is needed to run this test");
+    assert!(status.success(), "tests/ffi.c doesn't compile");
+
+    let output = Command::new(&program)
+        .arg(manifest_directory().join("sss.png"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
+
+#[test]
+fn test_header_declares_every_export() {
+    let source = std::fs::read_to_string(manifest_directory().join("src/ffi.rs")).unwrap();
+    let header = std::fs::read_to_string(manifest_directory().join("include/sspngme.h")).unwrap();
+
+    for line in source.lines() {
+        if let Some(function) = line
+            .strip_prefix("pub unsafe extern \"C\" fn ")
+            .or_else(|| line.strip_prefix("pub extern \"C\" fn "))
+        {
+            let name = &function[..function.find('(').unwrap()];
+            assert!(
+                header.contains(&format!(" {}(", name)),
+                "{} is missing from the header",
+                name
+            );
+        }
+
+        if let Some(constant) = line.strip_prefix("pub const ") {
+            let (name, value) = constant.split_once(": c_int = ").unwrap();
+            let define = format!("#define {} {}", name, value.trim_end_matches(';'));
+            assert!(
+                header.contains(&define),
+                "{} is missing from the header",
+                define
+            );
+        }
+    }
+}
@@ -0,0 +1,372 @@
+// include/sspngme.h is generated from src/ffi.rs rather than written by hand, so the two
+// can't drift apart. The exports are read line by line the way rustfmt lays them out: the
+// `c_int` constants become defines, `#[repr(C)]` structs are declared with their fields and
+// the other structs are opaque. Doc comments become C comments, up to their `# Safety`
+// section.
+
+/// Lines of the header are kept within this many columns
+const WIDTH: usize = 100;
+
+const PREAMBLE: &str = "\
+/*
+ * The C ABI of sspngme, generated from src/ffi.rs by `cargo xtask header`. Don't edit it by
+ * hand, change src/ffi.rs and run that again.
+ *
+ * The library is built into libsspngme.so (libsspngme.dylib, sspngme.dll) with
+ * `cargo rustc --release --lib --features cdylib --crate-type cdylib`.
+ *
+ * Every function but the free functions returns SSPNGME_OK or an error code, the message of
+ * the last error on the calling thread comes from sspngme_last_error_message().
+ */
+
+#ifndef SSPNGME_H
+#define SSPNGME_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+";
+
+const POSTAMBLE: &str = "\
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SSPNGME_H */
+";
+
+/// The header declaring everything src/ffi.rs exports
+pub fn generate(source: &str) -> Result<String, HeaderError> {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut defines = String::new();
+
+    let mut docs: Vec<String> = Vec::new();
+    let mut safety = false;
+    let mut repr_c = false;
+
+    let mut lines = source.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with("#[cfg(test)]") {
+            break;
+        }
+
+        if let Some(doc) = line.strip_prefix("///") {
+            let doc = doc.strip_prefix(' ').unwrap_or(doc);
+            safety |= doc == "# Safety";
+            if !safety {
+                docs.push(doc.to_string());
+            }
+            continue;
+        }
+        if line == "#[repr(C)]" {
+            repr_c = true;
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+
+        if let Some(constant) = line.strip_prefix("pub const ") {
+            if let Some((name, value)) = constant.split_once(": c_int = ") {
+                defines.push_str(&format!(
+                    "#define {} {}\n",
+                    name,
+                    value.trim_end_matches(';')
+                ));
+            }
+        } else if let Some(name) = line
+            .strip_prefix("pub struct ")
+            .and_then(|rest| rest.strip_suffix(" {"))
+        {
+            if !defines.is_empty() {
+                blocks.push(std::mem::take(&mut defines));
+            }
+            let mut fields = Vec::new();
+            for line in lines.by_ref().take_while(|line| *line != "}") {
+                fields.push(line.trim().trim_end_matches(','));
+            }
+            blocks.push(comment(&docs) + &structure(name, &fields, repr_c)?);
+        } else if line.starts_with("pub unsafe extern \"C\" fn ")
+            || line.starts_with("pub extern \"C\" fn ")
+        {
+            if !defines.is_empty() {
+                blocks.push(std::mem::take(&mut defines));
+            }
+            let mut signature = line.to_string();
+            while !signature.ends_with('{') {
+                let line = lines
+                    .next()
+                    .ok_or_else(|| HeaderError::Unfinished(line.to_string()))?;
+                signature.push(' ');
+                signature.push_str(line.trim());
+            }
+            blocks.push(comment(&docs) + &function(&signature)?);
+        }
+
+        docs.clear();
+        safety = false;
+        repr_c = false;
+    }
+    if !defines.is_empty() {
+        blocks.push(defines);
+    }
+
+    let body: Vec<&str> = blocks.iter().map(|block| block.trim_end()).collect();
+    Ok(format!(
+        "{}\n{}\n\n{}",
+        PREAMBLE,
+        body.join("\n\n"),
+        POSTAMBLE
+    ))
+}
+
+/// A doc comment as a C comment, on a line of its own if it fits
+fn comment(docs: &[String]) -> String {
+    let mut docs: Vec<String> = docs.iter().map(|doc| references(doc)).collect();
+    while docs.last().is_some_and(|doc| doc.is_empty()) {
+        docs.pop();
+    }
+
+    match docs.len() {
+        0 => String::new(),
+        1 if docs[0].len() + 6 <= WIDTH => format!("/* {} */\n", docs[0]),
+        _ => {
+            let mut comment = "/*\n".to_string();
+            for doc in docs {
+                match doc.is_empty() {
+                    true => comment.push_str(" *\n"),
+                    false => comment.push_str(&format!(" * {}\n", doc)),
+                }
+            }
+            comment + " */\n"
+        }
+    }
+}
+
+/// Drops the backticks around names, exported functions are written as calls
+fn references(doc: &str) -> String {
+    let mut text = String::new();
+    for (index, part) in doc.split('`').enumerate() {
+        text.push_str(part);
+        if index % 2 == 1 && part.starts_with("sspngme_") {
+            text.push_str("()");
+        }
+    }
+    text
+}
+
+fn structure(name: &str, fields: &[&str], repr_c: bool) -> Result<String, HeaderError> {
+    if !repr_c {
+        return Ok(format!("typedef struct {0} {0};\n", name));
+    }
+
+    let mut structure = format!("typedef struct {} {{\n", name);
+    for field in fields {
+        let (field, rust_type) = field
+            .strip_prefix("pub ")
+            .and_then(|field| field.split_once(": "))
+            .ok_or_else(|| HeaderError::PrivateField(name.to_string(), field.to_string()))?;
+        structure.push_str(&format!("    {};\n", declaration(rust_type, field)?));
+    }
+    Ok(structure + &format!("}} {};\n", name))
+}
+
+/// The declaration of a function from its signature, up to the opening brace of its body
+fn function(signature: &str) -> Result<String, HeaderError> {
+    let unfinished = || HeaderError::Unfinished(signature.to_string());
+
+    let (_, rest) = signature.split_once(" fn ").ok_or_else(unfinished)?;
+    let (name, rest) = rest.split_once('(').ok_or_else(unfinished)?;
+    let (parameters, rest) = rest.rsplit_once(')').ok_or_else(unfinished)?;
+    let return_type = rest
+        .trim()
+        .trim_end_matches('{')
+        .trim()
+        .strip_prefix("-> ")
+        .unwrap_or("()");
+
+    let mut pieces = Vec::new();
+    for parameter in parameters.split(',').map(str::trim) {
+        if parameter.is_empty() {
+            continue;
+        }
+        let (parameter, rust_type) = parameter.split_once(": ").ok_or_else(unfinished)?;
+        pieces.push(declaration(rust_type, parameter)?);
+    }
+    if pieces.is_empty() {
+        pieces.push("void".to_string());
+    }
+
+    let start = format!("{}(", declaration(return_type, name)?);
+    let mut lines = vec![start.clone()];
+    for (index, piece) in pieces.iter().enumerate() {
+        let piece = match index + 1 == pieces.len() {
+            true => format!("{});", piece),
+            false => format!("{},", piece),
+        };
+
+        let line = lines.last_mut().unwrap();
+        if line.ends_with('(') {
+            line.push_str(&piece);
+        } else if line.len() + 1 + piece.len() > WIDTH {
+            lines.push(format!("{}{}", " ".repeat(start.len()), piece));
+        } else {
+            line.push(' ');
+            line.push_str(&piece);
+        }
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// A name declared with the C version of a Rust type, like `const uint8_t *data`
+fn declaration(rust_type: &str, name: &str) -> Result<String, HeaderError> {
+    let c_type = c_type(rust_type)?;
+    match c_type.ends_with('*') {
+        true => Ok(format!("{}{}", c_type, name)),
+        false => Ok(format!("{} {}", c_type, name)),
+    }
+}
+
+/// The C type of a Rust type. Pointers can only be const where they point at the value
+/// itself, the way `*mut *const u8` is `const uint8_t **`.
+fn c_type(rust_type: &str) -> Result<String, HeaderError> {
+    let mut rest = rust_type;
+    let mut pointers = 0;
+    let mut constant = false;
+
+    loop {
+        if let Some(inner) = rest.strip_prefix("*mut ") {
+            if constant {
+                return Err(HeaderError::UnknownType(rust_type.to_string()));
+            }
+            rest = inner;
+        } else if let Some(inner) = rest.strip_prefix("*const ") {
+            if constant {
+                return Err(HeaderError::UnknownType(rust_type.to_string()));
+            }
+            constant = true;
+            rest = inner;
+        } else {
+            break;
+        }
+        pointers += 1;
+    }
+
+    let base = match rest {
+        "()" if pointers == 0 => "void",
+        "c_int" => "int",
+        "c_char" => "char",
+        "u8" => "uint8_t",
+        "u32" => "uint32_t",
+        "usize" => "size_t",
+        name if name.starts_with("Sspngme") => name,
+        _ => return Err(HeaderError::UnknownType(rust_type.to_string())),
+    };
+
+    let qualifier = match constant {
+        true => "const ",
+        false => "",
+    };
+    match pointers {
+        0 => Ok(base.to_string()),
+        _ => Ok(format!("{}{} {}", qualifier, base, "*".repeat(pointers))),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HeaderError {
+    #[error("{0} has no C type in the header.")]
+    UnknownType(String),
+
+    #[error("The field {1} of {0} is not public.")]
+    PrivateField(String, String),
+
+    #[error("'{0}' doesn't end where it should.")]
+    Unfinished(String),
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    #[test]
+    fn test_c_type() {
+        assert_eq!(c_type("c_int").unwrap(), "int");
+        assert_eq!(c_type("*const u8").unwrap(), "const uint8_t *");
+        assert_eq!(c_type("*mut *const u8").unwrap(), "const uint8_t **");
+        assert_eq!(c_type("*mut *mut SspngmePng").unwrap(), "SspngmePng **");
+        assert!(c_type("*const *mut u8").is_err());
+        assert!(c_type("String").is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let source = "\
+pub const SSPNGME_OK: c_int = 0;
+
+/// Bytes owned by the library
+#[repr(C)]
+pub struct SspngmeBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Frees a buffer, `sspngme_png_free` frees handles
+///
+/// # Safety
+/// `buffer` must be valid.
+#[no_mangle]
+pub unsafe extern \"C\" fn sspngme_buffer_free(buffer: *mut SspngmeBuffer) {
+}
+
+#[no_mangle]
+pub extern \"C\" fn sspngme_last_error_message() -> *const c_char {
+}
+
+/// Not exported
+fn private() {}
+";
+        let header = generate(source).unwrap();
+        let body = header
+            .strip_prefix(PREAMBLE)
+            .and_then(|header| header.strip_suffix(POSTAMBLE))
+            .unwrap();
+
+        assert_eq!(
+            body,
+            "
+#define SSPNGME_OK 0
+
+/* Bytes owned by the library */
+typedef struct SspngmeBuffer {
+    uint8_t *data;
+    size_t len;
+} SspngmeBuffer;
+
+/* Frees a buffer, sspngme_png_free() frees handles */
+void sspngme_buffer_free(SspngmeBuffer *buffer);
+
+const char *sspngme_last_error_message(void);
+
+"
+        );
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let declaration = function(
+            "pub unsafe extern \"C\" fn sspngme_png_get_chunk(png: *const SspngmePng, \
+             chunk_type: *const c_char, data: *mut *const u8, len: *mut usize, ) -> c_int {",
+        )
+        .unwrap();
+
+        assert_eq!(
+            declaration,
+            "int sspngme_png_get_chunk(const SspngmePng *png, const char *chunk_type, \
+             const uint8_t **data,\n                          size_t *len);\n"
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+mod header;
+
+// Tasks for working on sspngme itself, run with `cargo xtask <TASK>`:
+//
+// - `header` writes include/sspngme.h from the exports in src/ffi.rs
+// - `header --check` fails if include/sspngme.h isn't what `header` would write
+
+type Error = Box<dyn std::error::Error>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["header"] => write_header(),
+        ["header", "--check"] => check_header(),
+        _ => Err("Usage: cargo xtask header [--check]".into()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The directory of the sspngme crate, the parent of this one
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap()
+}
+
+fn header_path() -> PathBuf {
+    root().join("include/sspngme.h")
+}
+
+fn generated_header() -> Result<String, Error> {
+    let source = std::fs::read_to_string(root().join("src/ffi.rs"))?;
+    Ok(header::generate(&source)?)
+}
+
+fn write_header() -> Result<(), Error> {
+    std::fs::write(header_path(), generated_header()?)?;
+    println!("Wrote {}", header_path().display());
+    Ok(())
+}
+
+fn check_header() -> Result<(), Error> {
+    let header = std::fs::read_to_string(header_path())?;
+    match header == generated_header()? {
+        true => Ok(()),
+        false => Err(
+            "include/sspngme.h is out of date with src/ffi.rs, run `cargo xtask header`.".into(),
+        ),
+    }
+}